@@ -1,10 +1,44 @@
-use std::path::PathBuf;
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    ops::Deref,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use bitcask::{db::Engine, options::Options};
+use bitcask::{
+    db::Engine,
+    options::{IteratorOptions, Options},
+};
 use bytes::Bytes;
 use criterion::{criterion_group, criterion_main, Criterion};
 use rand::Rng;
 
+// 统计内存分配次数的分配器，用于对比不同接口的分配次数
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+// 统计执行一次 f 的内存分配次数
+fn count_allocations<F: FnOnce()>(f: F) -> usize {
+    let start = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - start
+}
+
 #[allow(dead_code)]
 pub fn get_test_key(i: u32) -> Bytes {
     Bytes::from(std::format!("bitcask-rs-key-{:09}", i))
@@ -18,11 +52,50 @@ pub fn get_test_value(i: u32) -> Bytes {
     ))
 }
 
+// 在独立的临时目录中打开的存储引擎，drop 时先关闭存储引擎再删除目录，
+// 每次运行都从空目录开始，数据量不会随着多次运行而增长
+struct BenchEngine {
+    engine: Option<Engine>,
+    dir_path: PathBuf,
+}
+
+impl Deref for BenchEngine {
+    type Target = Engine;
+
+    fn deref(&self) -> &Engine {
+        self.engine.as_ref().unwrap()
+    }
+}
+
+impl Drop for BenchEngine {
+    fn drop(&mut self) {
+        std::mem::drop(self.engine.take());
+        let _ = std::fs::remove_dir_all(&self.dir_path);
+    }
+}
+
+fn open_bench_engine(name: &str, mut options: Options) -> BenchEngine {
+    let dir_path = std::env::temp_dir().join(format!("bitcask-rs-bench-{}", name));
+    let _ = std::fs::remove_dir_all(&dir_path);
+
+    options.dir_path = dir_path.clone();
+    BenchEngine {
+        engine: Some(Engine::open(options).unwrap()),
+        dir_path,
+    }
+}
+
+// 写入 key 为 0..count 的测试数据
+fn put_test_data(engine: &Engine, count: u32) {
+    for i in 0..count {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+}
+
 fn benchmark_put(c: &mut Criterion) {
     // 打开存储引擎
-    let mut options = Options::default();
-    options.dir_path = PathBuf::from("/tmp/bitcask-rs-bench");
-    let engine = Engine::open(options).unwrap();
+    let engine = open_bench_engine("put", Options::default());
 
     let mut rnd: rand::rngs::ThreadRng = rand::thread_rng();
 
@@ -35,16 +108,46 @@ fn benchmark_put(c: &mut Criterion) {
     });
 }
 
-fn benchmark_get(c: &mut Criterion) {
-    // 打开存储引擎
-    let mut options = Options::default();
-    options.dir_path = PathBuf::from("/tmp/bitcask-rs-bench");
-    let engine = Engine::open(options).unwrap();
+fn benchmark_put_concurrent(c: &mut Criterion) {
+    // 8 个线程同时写入不同的 key，每次迭代共写入 8 * 100 条数据
+    let mut group = c.benchmark_group("bitcask-put-concurrent-bench");
+    for sync_writes in [false, true] {
+        // 打开存储引擎
+        let options = Options {
+            sync_writes,
+            ..Default::default()
+        };
+        let engine = open_bench_engine("put-concurrent", options);
 
-    for i in 0..100000 {
-        let res = engine.put(get_test_key(i), get_test_value(i));
-        assert!(res.is_ok());
+        let name = if sync_writes {
+            "sync-writes"
+        } else {
+            "no-sync"
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                std::thread::scope(|s| {
+                    for _ in 0..8 {
+                        s.spawn(|| {
+                            let mut rnd = rand::thread_rng();
+                            for _ in 0..100 {
+                                let i = rnd.gen_range(0..u32::MAX);
+                                let res = engine.put(get_test_key(i), get_test_value(i));
+                                assert!(res.is_ok());
+                            }
+                        });
+                    }
+                });
+            })
+        });
     }
+    group.finish();
+}
+
+fn benchmark_get(c: &mut Criterion) {
+    // 打开存储引擎
+    let engine = open_bench_engine("get", Options::default());
+    put_test_data(&engine, 100000);
 
     let mut rnd: rand::rngs::ThreadRng = rand::thread_rng();
 
@@ -58,25 +161,204 @@ fn benchmark_get(c: &mut Criterion) {
 
 fn benchmark_delete(c: &mut Criterion) {
     // 打开存储引擎
-    let mut options = Options::default();
-    options.dir_path = PathBuf::from("/tmp/bitcask-rs-bench");
-    let engine = Engine::open(options).unwrap();
+    let engine = open_bench_engine("delete", Options::default());
+    put_test_data(&engine, 100000);
+
+    let mut rnd: rand::rngs::ThreadRng = rand::thread_rng();
+
+    c.bench_function("bitcask-delete-bench", |b| {
+        b.iter(|| {
+            let i = rnd.gen_range(0..std::u32::MAX);
+            let res = engine.delete(get_test_key(i));
+            assert!(res.is_ok());
+        })
+    });
+}
+
+fn benchmark_mixed(c: &mut Criterion) {
+    // 打开存储引擎
+    let engine = open_bench_engine("mixed", Options::default());
+    put_test_data(&engine, 100000);
+
+    let mut rnd: rand::rngs::ThreadRng = rand::thread_rng();
 
+    // 80% 读 20% 写的混合负载，读写的 key 都落在已写入的数据范围内
+    c.bench_function("bitcask-mixed-bench", |b| {
+        b.iter(|| {
+            let i = rnd.gen_range(0..100000);
+            if rnd.gen_range(0..100) < 80 {
+                let res = engine.get(get_test_key(i));
+                assert!(!res.unwrap().is_empty());
+            } else {
+                let res = engine.put(get_test_key(i), get_test_value(i));
+                assert!(res.is_ok());
+            }
+        })
+    });
+}
+
+fn benchmark_get_with(c: &mut Criterion) {
+    // 打开存储引擎
+    let engine = open_bench_engine("get-with", Options::default());
+    put_test_data(&engine, 100000);
+
+    let mut rnd: rand::rngs::ThreadRng = rand::thread_rng();
+
+    // 对比 get 和 get_with 读取已存在的 key，get_with 不会为 value 额外分配 Bytes
+    let mut group = c.benchmark_group("bitcask-get-with-bench");
+    group.bench_function("get", |b| {
+        b.iter(|| {
+            let i = rnd.gen_range(0..100000);
+            let res = engine.get(get_test_key(i));
+            assert!(!res.unwrap().is_empty());
+        })
+    });
+    group.bench_function("get_with", |b| {
+        b.iter(|| {
+            let i = rnd.gen_range(0..100000);
+            let res = engine.get_with(get_test_key(i), |value| value.len());
+            assert!(res.unwrap() > 0);
+        })
+    });
+    group.finish();
+}
+
+fn benchmark_slice_api(c: &mut Criterion) {
+    // 打开存储引擎
+    let engine = open_bench_engine("slice-api", Options::default());
+
+    // 数据保存在 Vec<u8> 中，使用 Bytes 接口时需要先拷贝一次
+    let keys: Vec<Vec<u8>> = (0..100000).map(|i| get_test_key(i).to_vec()).collect();
+    let values: Vec<Vec<u8>> = (0..100000).map(|i| get_test_value(i).to_vec()).collect();
     for i in 0..100000 {
-        let res = engine.put(get_test_key(i), get_test_value(i));
+        let res = engine.put_slice(&keys[i], &values[i]);
         assert!(res.is_ok());
     }
 
     let mut rnd: rand::rngs::ThreadRng = rand::thread_rng();
 
-    c.bench_function("bitcask-delete-bench", |b| {
+    // 对比 Bytes 接口和切片接口，切片接口不需要为 key/value 额外分配 Bytes
+    let mut group = c.benchmark_group("bitcask-slice-bench");
+    group.bench_function("put", |b| {
         b.iter(|| {
-            let i = rnd.gen_range(0..std::u32::MAX);
-            let res = engine.delete(get_test_key(i));
+            let i = rnd.gen_range(0..100000);
+            let res = engine.put(
+                Bytes::from(keys[i].to_vec()),
+                Bytes::from(values[i].to_vec()),
+            );
+            assert!(res.is_ok());
+        })
+    });
+    group.bench_function("put_slice", |b| {
+        b.iter(|| {
+            let i = rnd.gen_range(0..100000);
+            let res = engine.put_slice(&keys[i], &values[i]);
+            assert!(res.is_ok());
+        })
+    });
+    group.bench_function("get", |b| {
+        b.iter(|| {
+            let i = rnd.gen_range(0..100000);
+            let res = engine.get(Bytes::from(keys[i].to_vec()));
+            assert!(!res.unwrap().is_empty());
+        })
+    });
+    let mut buf = Vec::new();
+    group.bench_function("get_into", |b| {
+        b.iter(|| {
+            let i = rnd.gen_range(0..100000);
+            let res = engine.get_into(&keys[i], &mut buf);
             assert!(res.is_ok());
+            assert!(!buf.is_empty());
         })
     });
+    group.finish();
+}
+
+fn benchmark_key_scan(c: &mut Criterion) {
+    // 打开存储引擎
+    let engine = open_bench_engine("key-scan", Options::default());
+    put_test_data(&engine, 100000);
+
+    // 只遍历 key 的场景，fold 每个 key 都会拷贝一次并读取 value，next_key_with 直接借用索引快照中的 key
+    let fold_scan = || {
+        let total = std::cell::Cell::new(0);
+        engine.fold(|key, _| {
+            total.set(total.get() + key.len());
+            true
+        });
+        total.get()
+    };
+    let next_with_scan = || {
+        let iter = engine.iter(IteratorOptions::default());
+        let mut total = 0;
+        while iter.next_with(|key, _| total += key.len()).is_some() {}
+        total
+    };
+    let next_key_with_scan = || {
+        let iter = engine.iter(IteratorOptions::default());
+        let mut total = 0;
+        while iter.next_key_with(|key| total += key.len()).is_some() {}
+        total
+    };
+
+    println!(
+        "key scan allocations: fold {}, next_with {}, next_key_with {}",
+        count_allocations(|| assert!(fold_scan() > 0)),
+        count_allocations(|| assert!(next_with_scan() > 0)),
+        count_allocations(|| assert!(next_key_with_scan() > 0)),
+    );
+
+    let mut group = c.benchmark_group("bitcask-key-scan-bench");
+    group.sample_size(10);
+    group.bench_function("fold", |b| b.iter(|| assert!(fold_scan() > 0)));
+    group.bench_function("next_with", |b| b.iter(|| assert!(next_with_scan() > 0)));
+    group.bench_function("next_key_with", |b| {
+        b.iter(|| assert!(next_key_with_scan() > 0))
+    });
+    group.finish();
+}
+
+fn benchmark_reuse_read_buffers(c: &mut Criterion) {
+    // 对比读取时复用缓冲区和每次读取都重新分配缓冲区的 get 性能
+    let mut group = c.benchmark_group("bitcask-reuse-read-buffers-bench");
+    for reuse_read_buffers in [true, false] {
+        // 打开存储引擎
+        let options = Options {
+            reuse_read_buffers,
+            ..Default::default()
+        };
+        let engine = open_bench_engine("reuse-read-buffers", options);
+        put_test_data(&engine, 100000);
+
+        let mut rnd: rand::rngs::ThreadRng = rand::thread_rng();
+
+        let name = if reuse_read_buffers {
+            "reuse"
+        } else {
+            "no-reuse"
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                let i = rnd.gen_range(0..100000);
+                let res = engine.get(get_test_key(i));
+                assert!(!res.unwrap().is_empty());
+            })
+        });
+    }
+    group.finish();
 }
 
-criterion_group!(benches, benchmark_put, benchmark_get, benchmark_delete);
+criterion_group!(
+    benches,
+    benchmark_put,
+    benchmark_put_concurrent,
+    benchmark_get,
+    benchmark_delete,
+    benchmark_mixed,
+    benchmark_get_with,
+    benchmark_slice_api,
+    benchmark_key_scan,
+    benchmark_reuse_read_buffers
+);
 criterion_main!(benches);
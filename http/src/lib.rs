@@ -0,0 +1,178 @@
+use std::{collections::HashMap, net::ToSocketAddrs, sync::Arc};
+
+use actix_web::{
+    delete, get, post,
+    web::{self, Bytes},
+    App, HttpResponse, HttpServer, Responder, Scope,
+};
+use bitcask::db::Engine;
+
+#[post("/put")]
+async fn put_handler(
+    eng: web::Data<Arc<Engine>>,
+    data: web::Json<HashMap<String, String>>,
+) -> impl Responder {
+    for (key, value) in data.iter() {
+        if eng
+            .put(Bytes::from(key.to_string()), Bytes::from(value.to_string()))
+            .is_err()
+        {
+            return HttpResponse::InternalServerError().body("failed to put value in engine");
+        }
+    }
+
+    HttpResponse::Ok().body("OK")
+}
+
+#[get("/get/{key}")]
+async fn get_handler(eng: web::Data<Arc<Engine>>, key: web::Path<String>) -> impl Responder {
+    let value = match eng.get(Bytes::from(key.to_string())) {
+        Ok(val) => val,
+        Err(e) => {
+            if e != bitcask::errors::Errors::KeyIsNotFound {
+                return HttpResponse::InternalServerError().body("failed to get value in engine");
+            }
+            return HttpResponse::Ok().body("key not found");
+        }
+    };
+    HttpResponse::Ok().body(value)
+}
+
+#[delete("/delete/{key}")]
+async fn delete_handler(eng: web::Data<Arc<Engine>>, key: web::Path<String>) -> impl Responder {
+    if let Err(e) = eng.delete(Bytes::from(key.to_string())) {
+        if e != bitcask::errors::Errors::KeyIsEmpty {
+            return HttpResponse::InternalServerError().body("failed to delete value in engine");
+        }
+    }
+    HttpResponse::Ok().body("OK")
+}
+
+#[get("/listkeys")]
+async fn listkeys_handler(eng: web::Data<Arc<Engine>>) -> impl Responder {
+    let keys = eng.list_keys();
+
+    let keys = keys
+        .into_iter()
+        .map(|key| String::from_utf8(key.to_vec()).unwrap())
+        .collect::<Vec<String>>();
+
+    let result = serde_json::to_string(&keys).unwrap();
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(result)
+}
+
+#[get("/stat")]
+async fn stat_handler(eng: web::Data<Arc<Engine>>) -> impl Responder {
+    let stat = match eng.stat() {
+        Ok(stat) => stat,
+        Err(_) => return HttpResponse::InternalServerError().body("failed to get stat in engine"),
+    };
+
+    let mut result = HashMap::new();
+    result.insert("key_num", stat.key_num);
+    result.insert("data_file_num", stat.data_file_num);
+    result.insert("reclaim_size", stat.reclaim_size);
+    result.insert("disk_size", stat.disk_size as usize);
+    HttpResponse::Ok().body(serde_json::to_string(&result).unwrap())
+}
+
+/// 使用传入的 Engine 实例启动 http 服务，阻塞直到服务退出
+/// 可以在自己的程序中使用自定义配置的 Engine 嵌入 http 接口
+pub fn run_server(engine: Arc<Engine>, addr: impl ToSocketAddrs) -> std::io::Result<()> {
+    actix_web::rt::System::new().block_on(async move {
+        HttpServer::new(move || {
+            App::new().app_data(web::Data::new(engine.clone())).service(
+                Scope::new("/bitcask")
+                    .service(put_handler)
+                    .service(get_handler)
+                    .service(delete_handler)
+                    .service(listkeys_handler)
+                    .service(stat_handler),
+            )
+        })
+        .bind(addr)?
+        .run()
+        .await
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{Read, Write},
+        net::{TcpListener, TcpStream},
+        path::PathBuf,
+        time::Duration,
+    };
+
+    use bitcask::options::Options;
+
+    use super::*;
+
+    // 发送 http 请求，返回完整的响应
+    fn send_request(addr: &str, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn test_run_server() {
+        let opts = Options {
+            dir_path: PathBuf::from("/tmp/bitcask-rs-http-run-server"),
+            ..Default::default()
+        };
+        let engine = Arc::new(Engine::open(opts.clone()).unwrap());
+
+        // 获取一个空闲端口启动服务
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addr = format!("127.0.0.1:{}", port);
+        let server_addr = addr.clone();
+        std::thread::spawn(move || run_server(engine, server_addr));
+
+        // 等待服务启动
+        let mut started = false;
+        for _ in 0..100 {
+            if TcpStream::connect(&addr).is_ok() {
+                started = true;
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        assert!(started);
+
+        let body = r#"{"name":"bitcask-rs"}"#;
+        let put_res = send_request(
+            &addr,
+            &format!(
+                "POST /bitcask/put HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                addr,
+                body.len(),
+                body
+            ),
+        );
+        assert!(put_res.starts_with("HTTP/1.1 200 OK"));
+        assert!(put_res.ends_with("OK"));
+
+        let get_res = send_request(
+            &addr,
+            &format!(
+                "GET /bitcask/get/name HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                addr
+            ),
+        );
+        assert!(get_res.starts_with("HTTP/1.1 200 OK"));
+        assert!(get_res.ends_with("bitcask-rs"));
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.dir_path).expect("failed to remove path");
+    }
+}
@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     sync::{atomic::Ordering, Arc},
 };
 
@@ -11,7 +11,7 @@ use crate::{
     data::log_record::{LogRecord, LogRecordType},
     db::Engine,
     errors::Errors,
-    options::{IndexType, WriteBatchOptions},
+    options::WriteBatchOptions,
 };
 
 const TXN_FIN_KEY: &[u8] = "txn-fin".as_bytes();
@@ -26,11 +26,9 @@ pub struct WriteBatch<'a> {
 
 impl Engine {
     pub fn new_write_batch(&self, options: WriteBatchOptions) -> Result<WriteBatch, Errors> {
-        if self.options.index_type == IndexType::BPlusTree
-            && !self.seq_file_exists
-            && !self.is_initial
-        {
-            return Err(Errors::UnableToUseWriteBatch);
+        self.check_open()?;
+        if self.options.read_only {
+            return Err(Errors::ReadOnlyMode);
         }
         Ok(WriteBatch {
             prending_writes: Arc::new(Mutex::new(HashMap::new())),
@@ -38,11 +36,50 @@ impl Engine {
             options,
         })
     }
+
+    /// 原子地执行一批写操作，Some(value) 表示写入，None 表示删除
+    pub fn write_batch<I>(&self, ops: I) -> Result<(), Errors>
+    where
+        I: IntoIterator<Item = (Bytes, Option<Bytes>)>,
+    {
+        let wb = self.new_write_batch(WriteBatchOptions::default())?;
+        for (key, value) in ops {
+            match value {
+                Some(value) => wb.put(key, value)?,
+                None => wb.delete(key)?,
+            }
+        }
+        wb.commit()?;
+        Ok(())
+    }
+
+    /// 原子地删除一批 key，返回删除前存在的 key 的数量
+    /// 空的 key 和不存在的 key 会被跳过，不写入删除标记
+    pub fn delete_batch(&self, keys: &[Bytes]) -> Result<usize, Errors> {
+        let wb = self.new_write_batch(WriteBatchOptions::default())?;
+        let mut deleted = HashSet::new();
+        for key in keys.iter().filter(|key| !key.is_empty()) {
+            // 转换后相同的 key 只删除一次
+            let normalized = self.normalize_key(key);
+            match self.get_log_record_pos(&normalized) {
+                Ok(_) => {
+                    if deleted.insert(normalized.to_vec()) {
+                        wb.delete(key.clone())?;
+                    }
+                }
+                Err(Errors::KeyIsNotFound) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        wb.commit()?;
+        Ok(deleted.len())
+    }
 }
 
 impl WriteBatch<'_> {
     /// 批量操作写数据
     pub fn put(&self, key: Bytes, value: Bytes) -> Result<(), Errors> {
+        let key = self.engine.normalize_key(&key);
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
@@ -61,6 +98,7 @@ impl WriteBatch<'_> {
 
     /// 批量操作删除数据
     pub fn delete(&self, key: Bytes) -> Result<(), Errors> {
+        let key = self.engine.normalize_key(&key);
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
@@ -68,11 +106,9 @@ impl WriteBatch<'_> {
         let mut pending_writes = self.prending_writes.lock();
 
         // key 不存在直接返回
-        let pos = self.engine.index.get(key.to_vec());
-        if pos.is_none() {
-            if pending_writes.contains_key(&key.to_vec()) {
-                pending_writes.remove(&key.to_vec());
-            }
+        let pos = self.engine.get_log_record_pos(&key);
+        if pos.is_err() && pending_writes.contains_key(&key.to_vec()) {
+            pending_writes.remove(&key.to_vec());
         }
 
         let record = LogRecord {
@@ -86,17 +122,22 @@ impl WriteBatch<'_> {
     }
 
     /// 提交数据，将数据写到文件，更新内存索引
-    pub fn commit(&self) -> Result<(), Errors> {
+    /// 返回本次事务的序列号，没有待提交的数据时不分配序列号，返回 0
+    pub fn commit(&self) -> Result<usize, Errors> {
         let mut pending_writes = self.prending_writes.lock();
         if pending_writes.len() == 0 {
-            return Ok(());
+            return Ok(NON_TXN_SEQ_NO);
         }
         if pending_writes.len() > self.options.max_batch_num as usize {
             return Err(Errors::ExceedMaxBatchNum);
         }
 
         // 加锁保证事务提交串行化
-        let _lock = self.engine.batch_commit_lock.lock();
+        let batch_lock = self.engine.batch_commit_lock.lock();
+        // 和单独的写入、删除操作互斥，保证同一个 key 的索引更新顺序
+        let _key_locks = self
+            .engine
+            .lock_keys(pending_writes.keys().map(|k| k.as_slice()));
 
         // 获取全局事务序列号
         let seq_no = self.engine.seq_no.fetch_add(1, Ordering::SeqCst);
@@ -119,27 +160,33 @@ impl WriteBatch<'_> {
             value: Default::default(),
             rec_type: LogRecordType::TXNFINISHED,
         };
-        self.engine.append_log_record(fin_record)?;
+        let fin_pos = self.engine.append_log_record(fin_record)?;
 
         if self.options.sync_writes {
-            self.engine.sync()?;
+            match self.engine.options.group_commit_window {
+                // 组提交，数据写入完成后释放提交锁，其他事务可以在等待期间写入，之后共享一次 sync
+                // 仍然持有 key 的锁，相同 key 的事务按序列号的顺序更新索引
+                Some(window) => {
+                    drop(batch_lock);
+                    self.engine.group_sync(&fin_pos, window)?;
+                }
+                None => self.engine.sync()?,
+            }
         }
 
         // 所有数据写入成功后更新索引
         for (key, record) in pending_writes.iter() {
+            let (index_key, exists) = self
+                .engine
+                .resolve_index_key(key.clone(), &|pos| self.engine.read_key_at(pos))?;
             if record.rec_type == LogRecordType::NOAMAL {
                 let pos = positons.get(key).unwrap();
-                if let Some(old_pos) = self.engine.index.put(key.clone(), *pos) {
-                    self.engine
-                        .reclaim_size
-                        .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+                if let Some(old_pos) = self.engine.index.put(index_key, *pos) {
+                    self.engine.add_reclaim(&old_pos);
                 }
-            }
-            if record.rec_type == LogRecordType::DELETED {
-                if let Some(old_pos) = self.engine.index.delete(key.clone()) {
-                    self.engine
-                        .reclaim_size
-                        .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+            } else if record.rec_type == LogRecordType::DELETED && exists {
+                if let Some(old_pos) = self.engine.index.delete(index_key) {
+                    self.engine.add_reclaim(&old_pos);
                 }
             }
         }
@@ -147,7 +194,7 @@ impl WriteBatch<'_> {
         // 清空暂存数据
         pending_writes.clear();
 
-        Ok(())
+        Ok(seq_no)
     }
 }
 
@@ -160,19 +207,28 @@ pub(crate) fn log_record_key_with_seq(key: Vec<u8>, seq_no: usize) -> Vec<u8> {
     enc_key.to_vec()
 }
 
-pub(crate) fn parse_log_record_key(key: Vec<u8>) -> (Vec<u8>, usize) {
+/// 解析数据文件中存储的 key，返回实际的 key 和事务序列号
+/// key 开头的事务序列号不是合法的变长编码时（数据损坏或者格式不匹配）返回 InvalidLogRecordKey
+pub fn parse_log_record_key(key: Vec<u8>) -> Result<(Vec<u8>, usize), Errors> {
     let mut buf = BytesMut::new();
     buf.put_slice(&key);
-    let seq_no = decode_length_delimiter(&mut buf).unwrap();
+    let seq_no = match decode_length_delimiter(&mut buf) {
+        Ok(seq_no) => seq_no,
+        Err(_) => return Err(Errors::InvalidLogRecordKey),
+    };
 
-    (buf.to_vec(), seq_no)
+    Ok((buf.to_vec(), seq_no))
 }
 
 #[cfg(test)]
 mod tests {
     use std::{path::PathBuf, sync::atomic::Ordering};
 
-    use crate::{options::Options, util};
+    use crate::{
+        data::data_file::MANIFEST_FILE_NAME,
+        options::{IndexType, Options},
+        util,
+    };
 
     use super::*;
 
@@ -285,4 +341,387 @@ mod tests {
 
     //     // wb.commit();
     // }
+
+    #[test]
+    fn test_engine_write_batch() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-engine-write-batch");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let put_res1 = engine.put(
+            util::rand_kv::get_test_key(1),
+            util::rand_kv::get_test_value(10),
+        );
+        assert!(put_res1.is_ok());
+
+        // 写入和删除混合
+        let ops = vec![
+            (
+                util::rand_kv::get_test_key(2),
+                Some(util::rand_kv::get_test_value(20)),
+            ),
+            (
+                util::rand_kv::get_test_key(3),
+                Some(util::rand_kv::get_test_value(30)),
+            ),
+            (util::rand_kv::get_test_key(1), None),
+        ];
+        let wb_res1 = engine.write_batch(ops);
+        assert!(wb_res1.is_ok());
+
+        let res1 = engine.get(util::rand_kv::get_test_key(1));
+        assert_eq!(Errors::KeyIsNotFound, res1.err().unwrap());
+        let res2 = engine.get(util::rand_kv::get_test_key(2));
+        assert_eq!(util::rand_kv::get_test_value(20), res2.unwrap());
+        let res3 = engine.get(util::rand_kv::get_test_key(3));
+        assert_eq!(util::rand_kv::get_test_value(30), res3.unwrap());
+
+        // 其中一个操作失败，所有操作都不生效
+        let ops = vec![
+            (
+                util::rand_kv::get_test_key(4),
+                Some(util::rand_kv::get_test_value(40)),
+            ),
+            (util::rand_kv::get_test_key(2), None),
+            (Bytes::new(), Some(util::rand_kv::get_test_value(50))),
+        ];
+        let wb_res2 = engine.write_batch(ops);
+        assert_eq!(Errors::KeyIsEmpty, wb_res2.err().unwrap());
+
+        let res4 = engine.get(util::rand_kv::get_test_key(4));
+        assert_eq!(Errors::KeyIsNotFound, res4.err().unwrap());
+        let res5 = engine.get(util::rand_kv::get_test_key(2));
+        assert_eq!(util::rand_kv::get_test_value(20), res5.unwrap());
+
+        // 空的操作集合
+        let wb_res3 = engine.write_batch(Vec::new());
+        assert!(wb_res3.is_ok());
+
+        // 重启之后进行校验
+        engine.close().expect("failed to close");
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = engine2.list_keys();
+        assert_eq!(2, keys.len());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_delete_batch() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-engine-delete-batch");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..10 {
+            let put_res = engine.put(
+                util::rand_kv::get_test_key(i),
+                util::rand_kv::get_test_value(10),
+            );
+            assert!(put_res.is_ok());
+        }
+
+        // 存在的 key、不存在的 key、重复的 key 和空的 key 混合
+        let keys = vec![
+            util::rand_kv::get_test_key(1),
+            util::rand_kv::get_test_key(3),
+            util::rand_kv::get_test_key(100),
+            Bytes::new(),
+            util::rand_kv::get_test_key(3),
+            util::rand_kv::get_test_key(5),
+        ];
+        let del_res = engine.delete_batch(&keys);
+        assert_eq!(Ok(3), del_res);
+
+        // 没有需要删除的 key
+        let del_res = engine.delete_batch(&[Bytes::new(), util::rand_kv::get_test_key(1)]);
+        assert_eq!(Ok(0), del_res);
+
+        // 重启之后进行校验
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = engine2.list_keys();
+        assert_eq!(7, keys.len());
+        for i in [0, 2, 4, 6, 7, 8, 9] {
+            assert!(engine2.get(util::rand_kv::get_test_key(i)).is_ok());
+        }
+        for i in [1, 3, 5] {
+            let get_res = engine2.get(util::rand_kv::get_test_key(i));
+            assert_eq!(Errors::KeyIsNotFound, get_res.err().unwrap());
+        }
+        std::mem::drop(engine2);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_commit_seq_no() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-batch-commit-seq-no");
+        opts.data_file_size = 64 * 1024 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+
+        // 没有数据时不分配序列号
+        let commit_res1 = wb.commit();
+        assert_eq!(NON_TXN_SEQ_NO, commit_res1.unwrap());
+
+        let put_res1 = wb.put(
+            util::rand_kv::get_test_key(1),
+            util::rand_kv::get_test_value(10),
+        );
+        assert!(put_res1.is_ok());
+        let seq_no1 = wb.commit().unwrap();
+        assert!(seq_no1 > NON_TXN_SEQ_NO);
+
+        let put_res2 = wb.put(
+            util::rand_kv::get_test_key(2),
+            util::rand_kv::get_test_value(10),
+        );
+        assert!(put_res2.is_ok());
+        let seq_no2 = wb.commit().unwrap();
+        assert!(seq_no2 > seq_no1);
+
+        // 空的提交不会增加序列号
+        let commit_res2 = wb.commit();
+        assert_eq!(NON_TXN_SEQ_NO, commit_res2.unwrap());
+
+        let wb2 = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        let del_res = wb2.delete(util::rand_kv::get_test_key(1));
+        assert!(del_res.is_ok());
+        let seq_no3 = wb2.commit().unwrap();
+        assert_eq!(seq_no3, seq_no2 + 1);
+
+        // 序列号和数据文件中的记录对应
+        let records: Vec<_> = engine
+            .scan_raw()
+            .filter(|r| r.key == util::rand_kv::get_test_key(2))
+            .collect();
+        assert_eq!(1, records.len());
+        assert_eq!(seq_no2, records[0].seq_no);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_write_batch_reopen_without_seq_no_file() {
+        for (index_type, name) in [
+            (IndexType::BPlusTree, "bptree"),
+            (IndexType::BTree, "btree"),
+        ] {
+            let mut opts = Options::default();
+            opts.dir_path = PathBuf::from(format!("/tmp/bitcask-rs-batch-no-seq-no-{}", name));
+            opts.data_file_size = 64 * 1024 * 1024;
+            opts.index_type = index_type;
+            let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+            let wb = engine
+                .new_write_batch(WriteBatchOptions::default())
+                .unwrap();
+            let mut seq_no = NON_TXN_SEQ_NO;
+            for i in 0..3 {
+                let put_res = wb.put(
+                    util::rand_kv::get_test_key(i),
+                    util::rand_kv::get_test_value(10),
+                );
+                assert!(put_res.is_ok());
+                seq_no = wb.commit().unwrap();
+            }
+            drop(wb);
+            drop(engine);
+
+            // 删除 MANIFEST，模拟没有正常关闭
+            std::fs::remove_file(opts.dir_path.join(MANIFEST_FILE_NAME))
+                .expect("failed to remove manifest file");
+
+            let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+            let wb2 = engine2
+                .new_write_batch(WriteBatchOptions::default())
+                .expect("failed to create write batch");
+            let put_res = wb2.put(
+                util::rand_kv::get_test_key(3),
+                util::rand_kv::get_test_value(10),
+            );
+            assert!(put_res.is_ok());
+            assert_eq!(seq_no + 1, wb2.commit().unwrap());
+
+            for i in 0..4 {
+                assert!(engine2.get(util::rand_kv::get_test_key(i)).is_ok());
+            }
+            drop(wb2);
+            drop(engine2);
+
+            // 删除测试的文件夹
+            std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        }
+    }
+
+    #[test]
+    fn test_write_batch_replay_mixed_state() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-batch-replay-mixed");
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        let append = |key: &str, value: &str, seq_no: usize, rec_type: LogRecordType| {
+            let record = LogRecord {
+                key: log_record_key_with_seq(key.as_bytes().to_vec(), seq_no),
+                value: value.as_bytes().to_vec(),
+                rec_type,
+            };
+            engine.append_log_record(record).unwrap();
+        };
+
+        // 1.事务的数据写入之后、完成标识写入之前，key1 被非事务数据覆盖
+        append("key1", "old", NON_TXN_SEQ_NO, LogRecordType::NOAMAL);
+        append("key1", "txn", 100, LogRecordType::NOAMAL);
+        append("key2", "txn", 100, LogRecordType::NOAMAL);
+        append("key1", "newer", NON_TXN_SEQ_NO, LogRecordType::NOAMAL);
+        append("key3", "old", NON_TXN_SEQ_NO, LogRecordType::NOAMAL);
+        append("key3", "", 100, LogRecordType::DELETED);
+        append("key3", "newer", NON_TXN_SEQ_NO, LogRecordType::NOAMAL);
+        append("txn-fin", "", 100, LogRecordType::TXNFINISHED);
+
+        // 2.没有完成标识的事务数据
+        append("key2", "dangling", 101, LogRecordType::NOAMAL);
+        append("key4", "dangling", 101, LogRecordType::NOAMAL);
+
+        // 3.事务的数据已经在 merge 后的文件中，只剩下完成标识
+        append("txn-fin", "", 102, LogRecordType::TXNFINISHED);
+        std::mem::drop(engine);
+
+        let check = |engine: &Engine| {
+            assert_eq!(
+                Bytes::from("newer"),
+                engine.get(Bytes::from("key1")).unwrap()
+            );
+            assert_eq!(Bytes::from("txn"), engine.get(Bytes::from("key2")).unwrap());
+            assert_eq!(
+                Bytes::from("newer"),
+                engine.get(Bytes::from("key3")).unwrap()
+            );
+            assert_eq!(
+                Errors::KeyIsNotFound,
+                engine.get(Bytes::from("key4")).err().unwrap()
+            );
+        };
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine2);
+        // merge 之后重新打开，结果保持不变
+        assert!(engine2.merge().is_ok());
+        std::mem::drop(engine2);
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        check(&engine3);
+        std::mem::drop(engine3);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    // 多个线程并发提交小事务，返回提交期间持久化的次数
+    fn commit_tiny_batches(engine: &Engine) -> usize {
+        let sync_count = engine.sync_count.load(Ordering::SeqCst);
+        std::thread::scope(|s| {
+            for t in 0..8 {
+                s.spawn(move || {
+                    for i in 0..25 {
+                        let wb = engine
+                            .new_write_batch(WriteBatchOptions::default())
+                            .unwrap();
+                        let key = t * 1000 + i * 2;
+                        assert!(wb
+                            .put(util::rand_kv::get_test_key(key), Bytes::from("a"))
+                            .is_ok());
+                        assert!(wb
+                            .put(util::rand_kv::get_test_key(key + 1), Bytes::from("b"))
+                            .is_ok());
+                        assert!(wb.commit().unwrap() > NON_TXN_SEQ_NO);
+                    }
+                });
+            }
+        });
+        engine.sync_count.load(Ordering::SeqCst) - sync_count
+    }
+
+    #[test]
+    fn test_write_batch_group_commit() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-batch-group-commit");
+        opts.data_file_size = 64 * 1024 * 1024;
+
+        // 不开启组提交时每个事务单独持久化
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(200, commit_tiny_batches(&engine));
+        std::mem::drop(engine);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+
+        // 开启组提交后多个事务共享一次持久化
+        opts.group_commit_window = Some(std::time::Duration::from_millis(2));
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let sync_count = commit_tiny_batches(&engine2);
+        assert!(sync_count > 0 && sync_count < 100);
+        assert_eq!(400, engine2.list_keys().len());
+
+        // 每个事务的数据和事务完成标识使用同一个序列号，序列号按写入顺序递增
+        let mut txns: HashMap<usize, (usize, usize)> = HashMap::new();
+        let mut last_seq_no = NON_TXN_SEQ_NO;
+        for record in engine2.scan_raw() {
+            let txn = txns.entry(record.seq_no).or_default();
+            if record.rec_type == LogRecordType::TXNFINISHED {
+                assert!(record.seq_no > last_seq_no);
+                last_seq_no = record.seq_no;
+                txn.1 += 1;
+            } else {
+                assert_eq!(0, txn.1);
+                txn.0 += 1;
+            }
+        }
+        assert_eq!(200, txns.len());
+        assert!(txns.values().all(|txn| *txn == (2, 1)));
+        std::mem::drop(engine2);
+
+        // 重启后所有事务的数据都能正常读取
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(400, engine3.list_keys().len());
+        assert_eq!(
+            Bytes::from("b"),
+            engine3.get(util::rand_kv::get_test_key(7001)).unwrap()
+        );
+        std::mem::drop(engine3);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_parse_log_record_key() {
+        let enc_key = log_record_key_with_seq(b"key".to_vec(), 300);
+        assert_eq!(Ok((b"key".to_vec(), 300)), parse_log_record_key(enc_key));
+        assert_eq!(Ok((Vec::new(), 0)), parse_log_record_key(vec![0]));
+
+        // 开头不是合法的变长编码
+        let mut bad_key = vec![0xff; 11];
+        bad_key.extend_from_slice(b"key");
+        assert_eq!(
+            Err(Errors::InvalidLogRecordKey),
+            parse_log_record_key(bad_key)
+        );
+        assert_eq!(
+            Err(Errors::InvalidLogRecordKey),
+            parse_log_record_key(Vec::new())
+        );
+    }
 }
@@ -0,0 +1,243 @@
+use std::{
+    fs,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use parking_lot::{RwLock, RwLockReadGuard};
+
+use crate::{
+    errors::Errors,
+    options::{IOType, RetryPolicy},
+};
+
+use super::{
+    data_file::{get_data_file_path, DataFile},
+    file_cache::FileCache,
+    log_record::{LogRecord, LogRecordPos, LogRecordType},
+};
+
+pub(crate) const BLOB_FILE_NAME_SUFFIX: &str = ".blob";
+
+/// 保存从数据文件中分离出来的大 value，开启 separate_values_over 时使用
+/// blob 文件和数据文件一样按 id 顺序追加写入，记录格式和 LogRecord 相同，保存的 key 用于 GC 时判断 value 是否仍然有效；
+/// 数据文件中只保存 value 在 blob 文件中的位置，merge 不会移动 blob 文件中的数据
+pub(crate) struct BlobStore {
+    dir_path: PathBuf,
+    file_size: u64, // 单个 blob 文件的大小上限，和 data_file_size 一致
+    active_file: RwLock<Option<DataFile>>, // 当前写入的 blob 文件，第一次写入时才创建
+    older_files: RwLock<FileCache>, // 已经写满的 blob 文件
+    dirty: AtomicBool, // 上次持久化之后是否写入了新的数据
+    remove_lock: RwLock<()>, // GC 删除 blob 文件时持有写锁
+    io_retry: Option<RetryPolicy>, // 写入和持久化失败时的重试策略
+    read_only: bool, // 只读模式下无法写入的 blob 文件也可以打开
+}
+
+impl BlobStore {
+    /// 打开数据目录中的 blob 文件，id 最大的文件作为当前写入的文件
+    pub fn open(
+        dir_path: PathBuf,
+        file_size: u64,
+        max_open_files: usize,
+        io_retry: Option<RetryPolicy>,
+        read_only: bool,
+        file_ids: Vec<u32>,
+    ) -> Result<Self, Errors> {
+        let mut older_files = FileCache::new(
+            dir_path.clone(),
+            BLOB_FILE_NAME_SUFFIX,
+            max_open_files,
+            IOType::FileIO,
+        );
+        older_files.set_read_only(read_only);
+        let mut active_file = None;
+        if let Some((active_fid, older_file_ids)) = file_ids.split_last() {
+            for file_id in older_file_ids.iter() {
+                older_files.add_file_id(*file_id);
+            }
+            // 末尾没有写完整的 value 没有被任何数据文件引用，直接在其后追加写入
+            let mut data_file = open_blob_file(dir_path.clone(), *active_fid, io_retry, read_only)?;
+            data_file.set_write_off(data_file.file_size());
+            active_file = Some(data_file);
+        }
+
+        Ok(BlobStore {
+            dir_path,
+            file_size,
+            active_file: RwLock::new(active_file),
+            older_files: RwLock::new(older_files),
+            dirty: AtomicBool::new(false),
+            remove_lock: RwLock::new(()),
+            io_retry,
+            read_only,
+        })
+    }
+
+    /// 追加写入 value，返回 value 在 blob 文件中的位置
+    pub fn write(&self, key: &[u8], value: &[u8]) -> Result<LogRecordPos, Errors> {
+        let enc_record = LogRecord {
+            key: key.to_vec(),
+            value: value.to_vec(),
+            rec_type: LogRecordType::NOAMAL,
+        }
+        .encode();
+        let record_len = enc_record.len() as u64;
+
+        let mut active_file = self.active_file.write();
+        if active_file.is_none() {
+            *active_file = Some(open_blob_file(
+                self.dir_path.clone(),
+                0,
+                self.io_retry,
+                false,
+            )?);
+        }
+        let data_file = active_file.as_mut().unwrap();
+
+        // 当前 blob 文件写满时持久化并加入旧文件列表，超过文件大小上限的 value 单独占用一个文件
+        let write_off = data_file.get_write_off();
+        if write_off > 0 && write_off + record_len > self.file_size {
+            data_file.sync()?;
+            let file_id = data_file.get_file_id();
+            *data_file = open_blob_file(self.dir_path.clone(), file_id + 1, self.io_retry, false)?;
+            self.older_files.write().add_file_id(file_id);
+        }
+
+        let offset = data_file.get_write_off();
+        data_file.write(&enc_record)?;
+        self.dirty.store(true, Ordering::SeqCst);
+
+        Ok(LogRecordPos {
+            file_id: data_file.get_file_id(),
+            offset,
+            size: record_len as u32,
+        })
+    }
+
+    /// 读取 blob 位置对应的 value，并交给回调函数处理
+    pub fn read_value_with<R, F>(&self, pos: &LogRecordPos, f: F) -> Result<R, Errors>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        self.read_with(pos.file_id, |data_file| {
+            data_file.read_value_with(pos.offset, |_, value| f(value))
+        })
+    }
+
+    /// 找到 id 对应的 blob 文件，交给回调函数读取
+    pub fn read_with<R, F>(&self, file_id: u32, f: F) -> Result<R, Errors>
+    where
+        F: FnOnce(&DataFile) -> Result<R, Errors>,
+    {
+        let active_file = self.active_file.read();
+        if let Some(data_file) = active_file.as_ref() {
+            if data_file.get_file_id() == file_id {
+                return f(data_file);
+            }
+        }
+
+        let data_file = self.older_files.read().get(file_id)?;
+        drop(active_file);
+        match data_file {
+            Some(data_file) => f(&data_file),
+            None => Err(Errors::DataFileIsNotFound),
+        }
+    }
+
+    /// 获取 blob 文件的引用，当前写入的 blob 文件在锁外仍然会追加写入，单独打开一个只用于读取的文件
+    pub fn get_file(&self, file_id: u32) -> Result<Arc<DataFile>, Errors> {
+        let active_file = self.active_file.read();
+        if let Some(data_file) = active_file.as_ref() {
+            if data_file.get_file_id() == file_id {
+                return Ok(Arc::new(open_blob_file(
+                    self.dir_path.clone(),
+                    file_id,
+                    None,
+                    self.read_only,
+                )?));
+            }
+        }
+
+        let data_file = self.older_files.read().get(file_id)?;
+        data_file.ok_or(Errors::DataFileIsNotFound)
+    }
+
+    /// 读取 blob 位置和 value 期间需要持有的锁，保证 GC 不会在读取 value 之前删除 blob 文件
+    /// 回调函数中可能再次读取，使用可重入的读锁
+    pub fn read_guard(&self) -> RwLockReadGuard<'_, ()> {
+        self.remove_lock.read_recursive()
+    }
+
+    /// 持久化当前写入的 blob 文件，没有新写入的数据时不做处理
+    pub fn sync(&self) -> Result<(), Errors> {
+        let active_file = self.active_file.read();
+        if !self.dirty.swap(false, Ordering::SeqCst) {
+            return Ok(());
+        }
+        if let Some(data_file) = active_file.as_ref() {
+            if let Err(e) = data_file.sync() {
+                self.dirty.store(true, Ordering::SeqCst);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// 已经写满的 blob 文件的 id，按从小到大的顺序排列，GC 只处理这些文件
+    pub fn sealed_file_ids(&self) -> Vec<u32> {
+        self.older_files.read().file_ids()
+    }
+
+    /// 删除已经写满的 blob 文件，等待正在读取 blob 的线程完成
+    pub fn remove_file(&self, file_id: u32) -> Result<(), Errors> {
+        let _remove_lock = self.remove_lock.write();
+        self.older_files.write().remove(file_id);
+        fs::remove_file(get_data_file_path(
+            self.dir_path.clone(),
+            file_id,
+            BLOB_FILE_NAME_SUFFIX,
+        ))?;
+        Ok(())
+    }
+
+    /// 删除所有 blob 文件，清空存储引擎时使用
+    pub fn clear(&self) -> Result<(), Errors> {
+        let _remove_lock = self.remove_lock.write();
+        let mut active_file = self.active_file.write();
+        let mut older_files = self.older_files.write();
+        let mut file_ids = older_files.file_ids();
+        if let Some(data_file) = active_file.take() {
+            file_ids.push(data_file.get_file_id());
+        }
+        older_files.clear();
+        self.dirty.store(false, Ordering::SeqCst);
+
+        for file_id in file_ids {
+            let file_path =
+                get_data_file_path(self.dir_path.clone(), file_id, BLOB_FILE_NAME_SUFFIX);
+            fs::remove_file(file_path)?;
+        }
+        Ok(())
+    }
+}
+
+// 打开数据目录中指定 id 的 blob 文件，文件不存在时新建
+fn open_blob_file(
+    dir_path: PathBuf,
+    file_id: u32,
+    io_retry: Option<RetryPolicy>,
+    read_only: bool,
+) -> Result<DataFile, Errors> {
+    let mut data_file = DataFile::new_with_suffix(
+        dir_path,
+        file_id,
+        IOType::FileIO,
+        BLOB_FILE_NAME_SUFFIX,
+        read_only,
+    )?;
+    data_file.set_io_retry(io_retry);
+    Ok(data_file)
+}
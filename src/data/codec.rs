@@ -0,0 +1,421 @@
+//! 数据文件的磁盘格式定义，所有记录的编码和解码都经过这里
+//!
+//! v1 格式下 LogRecord 的布局：
+//!
+//! ```text
+//! +------+----------+------------+-----+-------+-------+
+//! | type | key size | value size | key | value |  crc  |
+//! +------+----------+------------+-----+-------+-------+
+//!  1 字节   变长       变长                         4 字节（大端）
+//! ```
+//!
+//! key size 和 value size 使用 prost 的变长编码，crc 为 header、key、value 的 crc32 校验值。
+//! crc32 固定使用 IEEE 802.3 多项式（0x04C11DB7，和 zlib、gzip 相同），属于磁盘格式的一部分，
+//! 外部工具可以通过 [`verify_crc`] 按同样的方式校验记录。
+//! 数据文件中的 key 前面还带有变长编码的事务序列号，见 `batch::parse_log_record_key`。
+//! hint 文件、seq-no 文件等也使用同样的记录格式：
+//! hint 记录的 value 是依次变长编码的 file_id、offset、size；
+//! seq-no 记录的 value 是十进制字符串形式的事务序列号，
+//! 检查点写入的 seq-no 文件在其后还有一条记录，value 是十进制字符串形式的检查点时活跃文件的 id。
+//! index-type 文件中依次是索引类型名称、数据文件大小、累计写入字节数和累计写入记录数四条记录，
+//! 后三条的 value 都是十进制字符串，读取时按 key 识别，旧版本只有前面的部分记录。
+//! 现在这些元数据和事务序列号一起保存在 MANIFEST 文件中，每条记录同样按 key 识别，
+//! 另外还有一条记录格式版本的记录；index-type 和 seq-no 文件只在没有 MANIFEST 时读取。
+//!
+//! v1 格式已经冻结，修改布局时需要增加新的版本，不能直接修改这里的函数。
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use prost::{
+    decode_length_delimiter, encode_length_delimiter,
+    encoding::{decode_varint, encode_varint},
+    length_delimiter_len,
+};
+
+use crate::errors::Errors;
+
+use super::log_record::{LogRecord, LogRecordPos, LogRecordType, ReadLogRecord};
+
+/// seq-no 文件中记录的 key
+pub(crate) const SEQ_NO_KEY: &str = "seq.no";
+/// seq-no 文件中检查点记录的 key
+pub(crate) const CHECKPOINT_FID_KEY: &str = "checkpoint.fid";
+
+/// 数据文件的格式版本
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FormatVersion {
+    V1 = 1,
+}
+
+impl FormatVersion {
+    /// 当前写入使用的格式版本
+    pub const CURRENT: FormatVersion = FormatVersion::V1;
+
+    pub fn from_u8(v: u8) -> Option<Self> {
+        match v {
+            1 => Some(FormatVersion::V1),
+            _ => None,
+        }
+    }
+}
+
+/// 解码后的 LogRecord header 信息
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecordHeader {
+    pub rec_type: LogRecordType,
+    pub key_size: usize,
+    pub value_size: usize,
+    pub header_size: usize,
+}
+
+impl RecordHeader {
+    /// 整条记录编码后的长度
+    pub fn record_size(&self) -> usize {
+        // 损坏的数据可能解码出很大的长度，避免计算溢出
+        self.header_size
+            .saturating_add(self.key_size)
+            .saturating_add(self.value_size)
+            .saturating_add(4)
+    }
+}
+
+/// 对 LogRecord 编码，返回编码后的数据和 crc 校验值
+pub fn encode_v1(record: &LogRecord) -> (Vec<u8>, u32) {
+    let mut buf = BytesMut::new();
+    buf.reserve(encoded_size_v1(&record.key, &record.value));
+
+    buf.put_u8(record.rec_type as u8);
+
+    encode_length_delimiter(record.key.len(), &mut buf).unwrap();
+    encode_length_delimiter(record.value.len(), &mut buf).unwrap();
+
+    buf.extend_from_slice(&record.key);
+    buf.extend_from_slice(&record.value);
+
+    let crc = record_crc_v1(&buf, &[]);
+    buf.put_u32(crc);
+
+    (buf.to_vec(), crc)
+}
+
+/// 从完整的记录数据中解码 LogRecord
+pub fn decode_v1(buf: &[u8]) -> Result<ReadLogRecord, Errors> {
+    let header = decode_header_v1(buf)?;
+    if buf.len() < header.record_size() {
+        return Err(Errors::ReadDataFileEOF);
+    }
+
+    let kv_end = header.header_size + header.key_size + header.value_size;
+    let mut crc_buf = &buf[kv_end..kv_end + 4];
+    if crc_buf.get_u32()
+        != record_crc_v1(&buf[..header.header_size], &buf[header.header_size..kv_end])
+    {
+        return Err(Errors::InvalidLogRecordCrc);
+    }
+
+    let key_end = header.header_size + header.key_size;
+    Ok(ReadLogRecord {
+        record: LogRecord {
+            key: buf[header.header_size..key_end].to_vec(),
+            value: buf[key_end..kv_end].to_vec(),
+            rec_type: header.rec_type,
+        },
+        size: header.record_size() as u64,
+    })
+}
+
+/// 解码 LogRecord 的 header，buf 可以比 header 长
+/// header 不完整或者 key 和 value 都为空时说明已经读到了文件末尾
+pub fn decode_header_v1(buf: &[u8]) -> Result<RecordHeader, Errors> {
+    if buf.is_empty() {
+        return Err(Errors::ReadDataFileEOF);
+    }
+    let mut header_buf = &buf[1..];
+    let key_size = match decode_length_delimiter(&mut header_buf) {
+        Ok(size) => size,
+        Err(_) => return Err(Errors::ReadDataFileEOF),
+    };
+    let value_size = match decode_length_delimiter(&mut header_buf) {
+        Ok(size) => size,
+        Err(_) => return Err(Errors::ReadDataFileEOF),
+    };
+    if key_size == 0 && value_size == 0 {
+        return Err(Errors::ReadDataFileEOF);
+    }
+    let rec_type = LogRecordType::try_from(buf[0])?;
+
+    Ok(RecordHeader {
+        rec_type,
+        key_size,
+        value_size,
+        header_size: length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1,
+    })
+}
+
+/// 计算 CRC-32（IEEE 802.3 多项式）校验值，和数据文件中记录的校验方式相同
+/// 对一条编码后的记录去掉最后 4 字节计算，结果应等于最后 4 字节按大端序解出的值
+///
+/// ```
+/// use bitcask::data::codec::verify_crc;
+///
+/// assert_eq!(verify_crc(b"123456789"), 0xCBF43926);
+/// ```
+pub fn verify_crc(bytes: &[u8]) -> u32 {
+    crc32fast::hash(bytes)
+}
+
+/// 计算记录的 crc 校验值，header 为编码后的 header 部分，kv 为 key 和 value 部分
+pub fn record_crc_v1(header: &[u8], kv: &[u8]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(header);
+    hasher.update(kv);
+    hasher.finalize()
+}
+
+/// LogRecord header 部分最大长度
+pub fn max_header_size_v1() -> usize {
+    std::mem::size_of::<u8>() + length_delimiter_len(u32::MAX as usize) * 2
+}
+
+/// 计算 key/value 编码为 LogRecord 后占用的字节数
+pub fn encoded_size_v1(key: &[u8], value: &[u8]) -> usize {
+    std::mem::size_of::<u8>()
+        + length_delimiter_len(key.len())
+        + length_delimiter_len(value.len())
+        + key.len()
+        + value.len()
+        + 4
+}
+
+/// 对 LogRecordPos 编码
+pub fn encode_pos_v1(pos: &LogRecordPos) -> Vec<u8> {
+    let mut buf = BytesMut::new();
+    encode_varint(pos.file_id as u64, &mut buf);
+    encode_varint(pos.offset, &mut buf);
+    encode_varint(pos.size as u64, &mut buf);
+    buf.to_vec()
+}
+
+/// 解码 LogRecordPos
+pub fn decode_pos_v1(pos: &[u8]) -> LogRecordPos {
+    let mut buf = Bytes::copy_from_slice(pos);
+
+    let file_id = match decode_varint(&mut buf) {
+        Ok(fid) => fid as u32,
+        Err(e) => panic!("decode log record pos err: {}", e),
+    };
+    let offset = match decode_varint(&mut buf) {
+        Ok(n) => n,
+        Err(e) => panic!("decode log record pos err: {}", e),
+    };
+    let size = match decode_varint(&mut buf) {
+        Ok(size) => size as u32,
+        Err(e) => panic!("decode log record pos err: {}", e),
+    };
+
+    LogRecordPos {
+        file_id,
+        offset,
+        size,
+    }
+}
+
+/// 编码 hint 文件中的记录，key 为数据文件中的 key，value 为编码后的位置信息
+pub fn encode_hint_v1(key: Vec<u8>, pos: &LogRecordPos) -> Vec<u8> {
+    let hint_record = LogRecord {
+        key,
+        value: encode_pos_v1(pos),
+        rec_type: LogRecordType::NOAMAL,
+    };
+    encode_v1(&hint_record).0
+}
+
+/// 从 hint 文件中读出的记录拿到 key 和位置信息
+pub fn decode_hint_v1(record: LogRecord) -> (Vec<u8>, LogRecordPos) {
+    let pos = decode_pos_v1(&record.value);
+    (record.key, pos)
+}
+
+/// 编码 seq-no 文件中的记录
+pub fn encode_seq_no_v1(seq_no: usize) -> Vec<u8> {
+    let record = LogRecord {
+        key: SEQ_NO_KEY.as_bytes().to_vec(),
+        value: seq_no.to_string().into_bytes(),
+        rec_type: LogRecordType::NOAMAL,
+    };
+    encode_v1(&record).0
+}
+
+/// 从 seq-no 文件中读出的记录拿到事务序列号
+pub fn decode_seq_no_v1(record: &LogRecord) -> usize {
+    let v = String::from_utf8(record.value.clone()).unwrap();
+    v.parse::<usize>().unwrap()
+}
+
+/// 编码检查点的 seq-no 文件内容，依次为事务序列号和检查点时活跃文件的 id
+pub fn encode_checkpoint_v1(seq_no: usize, file_id: u32) -> Vec<u8> {
+    let mut buf = encode_seq_no_v1(seq_no);
+    let record = LogRecord {
+        key: CHECKPOINT_FID_KEY.as_bytes().to_vec(),
+        value: file_id.to_string().into_bytes(),
+        rec_type: LogRecordType::NOAMAL,
+    };
+    buf.extend_from_slice(&encode_v1(&record).0);
+    buf
+}
+
+/// 从 seq-no 文件中事务序列号之后的记录拿到检查点时活跃文件的 id，不是检查点记录时返回 None
+pub fn decode_checkpoint_fid_v1(record: &LogRecord) -> Option<u32> {
+    if record.key != CHECKPOINT_FID_KEY.as_bytes() {
+        return None;
+    }
+    let v = String::from_utf8(record.value.clone()).ok()?;
+    v.parse::<u32>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_round_trip(rec: LogRecord) {
+        let (enc, crc) = encode_v1(&rec);
+        assert_eq!(enc.len(), encoded_size_v1(&rec.key, &rec.value));
+        assert_eq!(crc, (&enc[enc.len() - 4..]).get_u32());
+
+        let header = decode_header_v1(&enc).unwrap();
+        assert_eq!(header.rec_type, rec.rec_type);
+        assert_eq!(header.key_size, rec.key.len());
+        assert_eq!(header.value_size, rec.value.len());
+        assert_eq!(header.record_size(), enc.len());
+
+        let dec = decode_v1(&enc).unwrap();
+        assert_eq!(dec.size, enc.len() as u64);
+        assert_eq!(dec.record.key, rec.key);
+        assert_eq!(dec.record.value, rec.value);
+        assert_eq!(dec.record.rec_type, rec.rec_type);
+    }
+
+    #[test]
+    fn test_codec_round_trip_normal() {
+        assert_round_trip(LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "hsy".as_bytes().to_vec(),
+            rec_type: LogRecordType::NOAMAL,
+        });
+        assert_round_trip(LogRecord {
+            key: vec![b'k'; 20000],
+            value: vec![b'v'; 1024 * 1024],
+            rec_type: LogRecordType::NOAMAL,
+        });
+    }
+
+    #[test]
+    fn test_codec_round_trip_deleted() {
+        assert_round_trip(LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETED,
+        });
+    }
+
+    #[test]
+    fn test_codec_round_trip_txn_finished() {
+        assert_round_trip(LogRecord {
+            key: "txn-fin".as_bytes().to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::TXNFINISHED,
+        });
+    }
+
+    #[test]
+    fn test_codec_round_trip_hint() {
+        let pos = LogRecordPos {
+            file_id: 12,
+            offset: 1 << 40,
+            size: 1024,
+        };
+        let enc = encode_hint_v1("name".as_bytes().to_vec(), &pos);
+        let dec = decode_v1(&enc).unwrap();
+        assert_eq!(dec.size, enc.len() as u64);
+
+        let (key, dec_pos) = decode_hint_v1(dec.record);
+        assert_eq!(key, "name".as_bytes().to_vec());
+        assert_eq!(dec_pos.file_id, pos.file_id);
+        assert_eq!(dec_pos.offset, pos.offset);
+        assert_eq!(dec_pos.size, pos.size);
+    }
+
+    #[test]
+    fn test_codec_round_trip_seq_no() {
+        let enc = encode_seq_no_v1(123456);
+        let dec = decode_v1(&enc).unwrap();
+        assert_eq!(dec.record.key, SEQ_NO_KEY.as_bytes().to_vec());
+        assert_eq!(decode_seq_no_v1(&dec.record), 123456);
+    }
+
+    #[test]
+    fn test_codec_round_trip_checkpoint() {
+        let enc = encode_checkpoint_v1(42, 7);
+        let dec1 = decode_v1(&enc).unwrap();
+        assert_eq!(decode_seq_no_v1(&dec1.record), 42);
+        assert_eq!(decode_checkpoint_fid_v1(&dec1.record), None);
+
+        let dec2 = decode_v1(&enc[dec1.size as usize..]).unwrap();
+        assert_eq!(decode_checkpoint_fid_v1(&dec2.record), Some(7));
+        assert_eq!(dec1.size + dec2.size, enc.len() as u64);
+    }
+
+    #[test]
+    fn test_codec_decode_invalid() {
+        let rec = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "hsy".as_bytes().to_vec(),
+            rec_type: LogRecordType::NOAMAL,
+        };
+        let mut enc = encode_v1(&rec).0;
+
+        // 数据不完整
+        let res1 = decode_v1(&enc[..enc.len() - 1]);
+        assert_eq!(res1.err().unwrap(), Errors::ReadDataFileEOF);
+        let res2 = decode_v1(&[]);
+        assert_eq!(res2.err().unwrap(), Errors::ReadDataFileEOF);
+
+        // 数据被修改
+        let len = enc.len();
+        enc[len - 5] ^= 0xff;
+        let res3 = decode_v1(&enc);
+        assert_eq!(res3.err().unwrap(), Errors::InvalidLogRecordCrc);
+
+        // 未知的记录类型
+        enc[0] = 0xff;
+        let res4 = decode_v1(&enc);
+        assert_eq!(res4.err().unwrap(), Errors::InvalidLogRecordCrc);
+    }
+
+    #[test]
+    fn test_format_version() {
+        assert_eq!(FormatVersion::CURRENT, FormatVersion::V1);
+        assert_eq!(FormatVersion::from_u8(1), Some(FormatVersion::V1));
+        assert_eq!(FormatVersion::from_u8(0), None);
+    }
+
+    #[test]
+    fn test_verify_crc() {
+        // CRC-32/IEEE 的标准测试向量
+        assert_eq!(verify_crc(b""), 0);
+        assert_eq!(verify_crc(b"123456789"), 0xCBF43926);
+        assert_eq!(
+            verify_crc(b"The quick brown fox jumps over the lazy dog"),
+            0x414FA339
+        );
+
+        // 和记录中保存的校验值一致
+        let rec = LogRecord {
+            key: b"name".to_vec(),
+            value: b"bitcask-rs".to_vec(),
+            rec_type: LogRecordType::NOAMAL,
+        };
+        let (enc, crc) = encode_v1(&rec);
+        assert_eq!(crc, verify_crc(&enc[..enc.len() - 4]));
+        assert_eq!(crc, (&enc[enc.len() - 4..]).get_u32());
+    }
+}
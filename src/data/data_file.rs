@@ -1,13 +1,20 @@
 use std::path::PathBuf;
 
-use bytes::{Buf, BytesMut};
-use prost::{decode_length_delimiter, length_delimiter_len};
+use bytes::Buf;
+use log::warn;
+use parking_lot::Mutex;
 
 use crate::{
-    data::log_record::{LogRecord, LogRecordType},
+    data::{
+        codec::{self, RecordHeader},
+        log_record::{LogRecord, LogRecordType},
+    },
     errors::Errors,
-    fio::{new_io_manager, IOManager},
-    options::IOType,
+    fio::{
+        buffer_pool::ReadBuffer, file_io::with_retry, new_archive_io_manager, new_io_manager,
+        IOManager,
+    },
+    options::{ArchiveReader, IOType, RetryPolicy},
 };
 
 use super::log_record::{max_log_record_header_size, LogRecordPos, ReadLogRecord};
@@ -16,26 +23,71 @@ pub const DATA_FILE_NAME_SUFFIX: &str = ".data";
 pub(crate) const HINT_FILE_NAME: &str = "hint-index";
 pub(crate) const MERGE_FINISHED_FILE_NAME: &str = "merge-finished";
 pub const SEQ_NO_FILE_NAME: &str = "seq-no";
+pub(crate) const INDEX_TYPE_FILE_NAME: &str = "index-type";
+pub(crate) const MANIFEST_FILE_NAME: &str = "MANIFEST";
 
 /// 存储引擎数据文件实例
 pub struct DataFile {
     file_id: u32,                   // 数据文件 id
     write_off: u64,                 // 当前写偏移
     io_manager: Box<dyn IOManager>, // IO 管理接口
+    file_path: PathBuf,             // 文件路径
+    reuse_read_buffers: bool,       // 读取时是否复用当前线程缓存的缓冲区
+    io_retry: Option<RetryPolicy>,  // 写入和持久化失败时的重试策略
+    write_buf: Mutex<Vec<u8>>,      // 已经分配了写偏移但还没有写入文件的数据
+    write_buffer_size: usize,       // 缓冲的数据达到该大小时写入文件，0 表示不缓冲
 }
 
 impl DataFile {
     pub fn new(dir_path: PathBuf, file_id: u32, io_type: IOType) -> Result<DataFile, Errors> {
-        let file_path = get_data_file_path(dir_path, file_id);
-        let io_manager = new_io_manager(file_path, io_type);
+        Self::new_with_suffix(dir_path, file_id, io_type, DATA_FILE_NAME_SUFFIX, false)
+    }
+
+    /// 使用指定的文件后缀新建或打开数据文件，read_only 为 true 时已经存在的文件无法写入也可以打开
+    pub fn new_with_suffix(
+        dir_path: PathBuf,
+        file_id: u32,
+        io_type: IOType,
+        file_suffix: &str,
+        read_only: bool,
+    ) -> Result<DataFile, Errors> {
+        let file_path = get_data_file_path(dir_path, file_id, file_suffix);
+        let io_manager = new_io_manager(file_path.clone(), io_type, read_only)?;
 
         Ok(DataFile {
             file_id,
             write_off: 0,
             io_manager,
+            file_path,
+            reuse_read_buffers: true,
+            io_retry: None,
+            write_buf: Mutex::new(Vec::new()),
+            write_buffer_size: 0,
         })
     }
 
+    /// 打开压缩归档的数据文件，第一次读取时才会解压，只能读取
+    pub fn new_archived(
+        dir_path: PathBuf,
+        file_id: u32,
+        file_suffix: &str,
+        reader: ArchiveReader,
+    ) -> DataFile {
+        let file_path = get_data_file_path(dir_path, file_id, file_suffix);
+        let io_manager = new_archive_io_manager(file_path.clone(), reader);
+
+        DataFile {
+            file_id,
+            write_off: 0,
+            io_manager,
+            file_path,
+            reuse_read_buffers: true,
+            io_retry: None,
+            write_buf: Mutex::new(Vec::new()),
+            write_buffer_size: 0,
+        }
+    }
+
     pub fn get_file_id(&self) -> u32 {
         self.file_id
     }
@@ -48,114 +100,400 @@ impl DataFile {
         self.write_off = offset
     }
 
+    /// 设置读取时是否复用当前线程缓存的缓冲区，默认开启
+    pub fn set_reuse_read_buffers(&mut self, reuse_read_buffers: bool) {
+        self.reuse_read_buffers = reuse_read_buffers;
+    }
+
+    /// 设置写入和持久化失败时的重试策略，默认不重试
+    pub fn set_io_retry(&mut self, io_retry: Option<RetryPolicy>) {
+        self.io_retry = io_retry;
+    }
+
+    /// 设置写缓冲区的大小，默认为 0，每次写入都直接写入文件
+    /// 开启后写入的数据先追加到缓冲区，达到该大小、sync 或者 flush 时一次写入文件，读取时可以读到缓冲区中的数据
+    pub fn set_write_buffer_size(&mut self, write_buffer_size: usize) {
+        self.write_buffer_size = write_buffer_size;
+    }
+
+    /// 文件的大小，包括写缓冲区中还没有写入文件的数据
     pub fn file_size(&self) -> u64 {
-        self.io_manager.size()
+        if self.write_buffer_size == 0 {
+            return self.io_manager.size();
+        }
+        let write_buf = self.write_buf.lock();
+        self.io_manager.size() + write_buf.len() as u64
     }
 
     /// 从数据文件中读取 LogRecord
     pub fn read(&self, offset: u64) -> Result<ReadLogRecord, Errors> {
-        // 先读出 header 部分的数据，header = LogRecord类型 + key长度 + value长度
-        let mut header_buf = BytesMut::zeroed(max_log_record_header_size());
-        self.io_manager.read(&mut header_buf, offset)?;
-        let rec_type = header_buf.get_u8();
-        let key_size = decode_length_delimiter(&mut header_buf).unwrap();
-        let value_size = decode_length_delimiter(&mut header_buf).unwrap();
-        if key_size == 0 && value_size == 0 {
-            return Err(Errors::ReadDataFileEOF);
-        }
-        let header_size = length_delimiter_len(key_size) + length_delimiter_len(value_size) + 1;
-
-        // 读取 key/value 数据和最后 4 字节 CRC 校验值
-        let mut kv_buf = BytesMut::zeroed(key_size + value_size + 4);
-        self.io_manager
-            .read(&mut kv_buf, offset + header_size as u64)?;
+        let raw = self.read_raw(offset)?;
 
         // 构造 LogRecord
         let log_record = LogRecord {
-            key: kv_buf.get(..key_size).unwrap().to_vec(),
-            value: kv_buf.get(key_size..kv_buf.len() - 4).unwrap().to_vec(),
-            rec_type: LogRecordType::from_u8(rec_type),
+            key: raw.key().to_vec(),
+            value: raw.value().to_vec(),
+            rec_type: raw.rec_type,
         };
 
+        Ok(ReadLogRecord {
+            record: log_record,
+            size: raw.size(),
+        })
+    }
+
+    /// 从 offset 开始读取原始数据到 buf 中，返回读取的字节数，不解析记录
+    /// 写缓冲区中的数据紧跟在文件末尾之后，超出文件末尾的部分从写缓冲区中读取
+    pub fn read_at(&self, buf: &mut [u8], offset: u64) -> Result<usize, Errors> {
+        if self.write_buffer_size == 0 {
+            return self.io_manager.read(buf, offset);
+        }
+
+        // 持有写缓冲区的锁时文件大小不会变化，只读取文件中的数据时不需要继续持有锁
+        let write_buf = self.write_buf.lock();
+        let flushed = self.io_manager.size();
+        if write_buf.is_empty() || offset + buf.len() as u64 <= flushed {
+            drop(write_buf);
+            return self.io_manager.read(buf, offset);
+        }
+
+        let mut n_bytes = 0;
+        if offset < flushed {
+            let len = (flushed - offset) as usize;
+            n_bytes = self.io_manager.read(&mut buf[..len], offset)?;
+            if n_bytes < len {
+                return Ok(n_bytes);
+            }
+        }
+        let buf_off = (offset + n_bytes as u64 - flushed) as usize;
+        if buf_off < write_buf.len() {
+            let len = std::cmp::min(buf.len() - n_bytes, write_buf.len() - buf_off);
+            buf[n_bytes..n_bytes + len].copy_from_slice(&write_buf[buf_off..buf_off + len]);
+            n_bytes += len;
+        }
+        Ok(n_bytes)
+    }
+
+    /// 从头遍历数据文件中的所有 LogRecord，返回记录和它在文件中的偏移
+    /// 读到文件末尾时结束，遇到其他错误时返回该错误并结束遍历
+    pub fn iter_records(&self) -> impl Iterator<Item = Result<(LogRecord, u64), Errors>> + '_ {
+        let mut offset = 0;
+        let mut finished = false;
+        std::iter::from_fn(move || {
+            if finished {
+                return None;
+            }
+            match self.read(offset) {
+                Ok(read_res) => {
+                    let item = (read_res.record, offset);
+                    offset += read_res.size;
+                    Some(Ok(item))
+                }
+                Err(e) => {
+                    finished = true;
+                    if e == Errors::ReadDataFileEOF {
+                        return None;
+                    }
+                    Some(Err(e))
+                }
+            }
+        })
+    }
+
+    /// 从数据文件中读取 LogRecord，将类型和 value 切片交给回调函数处理，不额外拷贝 value
+    pub fn read_value_with<R, F>(&self, offset: u64, f: F) -> Result<R, Errors>
+    where
+        F: FnOnce(LogRecordType, &[u8]) -> R,
+    {
+        let raw = self.read_raw(offset)?;
+        Ok(f(raw.rec_type, raw.value()))
+    }
+
+    /// 只读取 LogRecord 的 header 部分，不读取 key/value，也不校验 CRC
+    pub fn read_header_only(&self, offset: u64) -> Result<RecordHeader, Errors> {
+        let (header, _) = self.read_header(offset)?;
+        Ok(header)
+    }
+
+    // 读取并解码 LogRecord 的 header，同时返回 header 部分的原始数据用于计算 CRC
+    fn read_header(&self, offset: u64) -> Result<(RecordHeader, ReadBuffer), Errors> {
+        // header = LogRecord类型 + key长度 + value长度
+        // 文件末尾的数据可能不足最大 header 长度，读取长度不能超过文件剩余部分
+        let file_size = self.file_size();
+        if offset >= file_size {
+            return Err(Errors::ReadDataFileEOF);
+        }
+        let header_len = std::cmp::min(max_log_record_header_size() as u64, file_size - offset);
+        let mut header_buf = ReadBuffer::zeroed(header_len as usize, self.reuse_read_buffers);
+        let n_bytes = self.read_at(&mut header_buf, offset)?;
+        header_buf.truncate(n_bytes);
+        if header_buf.is_empty() {
+            return Err(Errors::ReadDataFileEOF);
+        }
+        let header = codec::decode_header_v1(&header_buf)?;
+        // 记录超出了文件末尾，说明数据不完整，同时避免按照损坏的长度分配内存
+        if header.record_size() as u64 > file_size - offset {
+            return Err(Errors::ReadDataFileEOF);
+        }
+        Ok((header, header_buf))
+    }
+
+    // 读取 LogRecord 的原始数据并校验 CRC
+    fn read_raw(&self, offset: u64) -> Result<RawLogRecord, Errors> {
+        // 先读出 header 部分的数据
+        let (header, header_buf) = self.read_header(offset)?;
+        let (key_size, value_size, header_size) =
+            (header.key_size, header.value_size, header.header_size);
+
+        // 读取 key/value 数据和最后 4 字节 CRC 校验值
+        let mut kv_buf = ReadBuffer::zeroed(key_size + value_size + 4, self.reuse_read_buffers);
+        if self.read_at(&mut kv_buf, offset + header_size as u64)? < kv_buf.len() {
+            return Err(Errors::ReadDataFileEOF);
+        }
+
         // 校验 CRC 验证数据完整性
-        kv_buf.advance(key_size + value_size);
-        if kv_buf.get_u32() != log_record.get_crc() {
+        let crc =
+            codec::record_crc_v1(&header_buf[..header_size], &kv_buf[..key_size + value_size]);
+        let mut crc_buf = &kv_buf[key_size + value_size..];
+        if crc_buf.get_u32() != crc {
             return Err(Errors::InvalidLogRecordCrc);
         }
 
-        Ok(ReadLogRecord {
-            record: log_record,
-            size: (header_size + key_size + value_size + 4) as u64,
+        Ok(RawLogRecord {
+            rec_type: header.rec_type,
+            header_size,
+            key_size,
+            value_size,
+            kv_buf,
         })
     }
 
     pub fn write(&mut self, buf: &[u8]) -> Result<usize, Errors> {
-        let n_bytes = self.io_manager.write(buf)?;
-        self.write_off += n_bytes as u64;
-        Ok(n_bytes)
+        if self.write_buffer_size == 0 {
+            let n_bytes = with_retry(self.io_retry.as_ref(), || self.io_manager.write(buf))?;
+            self.write_off += n_bytes as u64;
+            return Ok(n_bytes);
+        }
+
+        let write_buf = self.write_buf.get_mut();
+        write_buf.extend_from_slice(buf);
+        if write_buf.len() >= self.write_buffer_size {
+            // 写入文件失败时从缓冲区中去掉这次写入的数据，和不缓冲时写入失败的结果一致
+            if let Err(e) = self.flush() {
+                let write_buf = self.write_buf.get_mut();
+                write_buf.truncate(write_buf.len() - buf.len());
+                return Err(e);
+            }
+        }
+        self.write_off += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    /// 将写缓冲区中的数据写入文件，不持久化，缓冲区为空时不做处理
+    pub fn flush(&self) -> Result<(), Errors> {
+        let mut write_buf = self.write_buf.lock();
+        if write_buf.is_empty() {
+            return Ok(());
+        }
+        with_retry(self.io_retry.as_ref(), || self.io_manager.write(&write_buf))?;
+        write_buf.clear();
+        Ok(())
     }
 
+    /// 先将写缓冲区中的数据写入文件，再持久化
     pub fn sync(&self) -> Result<(), Errors> {
-        self.io_manager.sync()
+        self.flush()?;
+        with_retry(self.io_retry.as_ref(), || self.io_manager.sync())
+    }
+
+    /// 从头读取数据文件，返回最后一条完整数据的结束位置
+    pub fn valid_size(&self) -> Result<u64, Errors> {
+        let mut offset = 0;
+        loop {
+            match self.read_raw(offset) {
+                Ok(raw) => offset += raw.size(),
+                Err(Errors::ReadDataFileEOF) => return Ok(offset),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// 查找 offset 处损坏的数据之后，下一条完整并且 CRC 校验通过的数据的偏移，之后没有完整的数据时返回 None
+    /// 先按 header 中记录的长度跳过损坏的数据，header 中的长度也损坏时，逐字节向后查找
+    pub fn next_valid_offset(&self, offset: u64) -> Option<u64> {
+        let file_size = self.file_size();
+        if let Ok((header, _)) = self.read_header(offset) {
+            let next = offset + header.record_size() as u64;
+            if next == file_size {
+                return None;
+            }
+            if self.read_raw(next).is_ok() {
+                return Some(next);
+            }
+        }
+        (offset + 1..file_size).find(|off| self.read_raw(*off).is_ok())
+    }
+
+    /// 将数据文件截断到指定长度，并把写偏移设置为该长度
+    pub fn truncate(&mut self, size: u64) -> Result<(), Errors> {
+        self.flush()?;
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(&self.file_path)?;
+        file.set_len(size)?;
+        file.sync_all()?;
+        self.write_off = size;
+        Ok(())
     }
 
-    pub fn set_io_manager(&mut self, dir_path: PathBuf, io_type: IOType) {
-        self.io_manager = new_io_manager(get_data_file_path(dir_path, self.file_id), io_type)
+    pub fn set_io_manager(&mut self, io_type: IOType, read_only: bool) -> Result<(), Errors> {
+        self.flush()?;
+        self.io_manager = new_io_manager(self.file_path.clone(), io_type, read_only)?;
+        Ok(())
+    }
+
+    // 测试中替换为自定义的 IO 管理接口
+    #[cfg(test)]
+    pub(crate) fn replace_io_manager(&mut self, io_manager: Box<dyn IOManager>) {
+        self.io_manager = io_manager;
     }
 
     // 创建 hint 索引文件，用于启动时快速构建索引
-    pub fn new_hint_file(dir_path: PathBuf) -> Result<DataFile, Errors> {
+    pub fn new_hint_file(dir_path: PathBuf, read_only: bool) -> Result<DataFile, Errors> {
         let file_path = dir_path.join(HINT_FILE_NAME);
-        let io_manager = new_io_manager(file_path, IOType::FileIO);
+        let io_manager = new_io_manager(file_path.clone(), IOType::FileIO, read_only)?;
 
         Ok(DataFile {
             file_id: 0,
             write_off: 0,
             io_manager,
+            file_path,
+            reuse_read_buffers: true,
+            io_retry: None,
+            write_buf: Mutex::new(Vec::new()),
+            write_buffer_size: 0,
         })
     }
 
     // 写入 key 的索引信息
     pub fn write_hint_record(&mut self, key: Vec<u8>, pos: LogRecordPos) -> Result<(), Errors> {
-        let hint_record = LogRecord {
-            key,
-            value: pos.encode(),
-            rec_type: LogRecordType::NOAMAL,
-        };
-        let enc_record = hint_record.encode();
+        let enc_record = codec::encode_hint_v1(key, &pos);
         self.write(&enc_record)?;
 
         Ok(())
     }
 
     // 标识 merge 完成的文件
-    pub fn new_merge_finished_file(dir_path: PathBuf) -> Result<DataFile, Errors> {
+    pub fn new_merge_finished_file(dir_path: PathBuf, read_only: bool) -> Result<DataFile, Errors> {
         let file_path = dir_path.join(MERGE_FINISHED_FILE_NAME);
-        let io_manager = new_io_manager(file_path, IOType::FileIO);
+        let io_manager = new_io_manager(file_path.clone(), IOType::FileIO, read_only)?;
 
         Ok(DataFile {
             file_id: 0,
             write_off: 0,
             io_manager,
+            file_path,
+            reuse_read_buffers: true,
+            io_retry: None,
+            write_buf: Mutex::new(Vec::new()),
+            write_buffer_size: 0,
         })
     }
 
     /// 新建或打开存储事务序列号的文件
     pub fn new_seq_no_file(dir_path: PathBuf) -> Result<DataFile, Errors> {
         let file_path = dir_path.join(SEQ_NO_FILE_NAME);
-        let io_manager = new_io_manager(file_path, IOType::FileIO);
+        let io_manager = new_io_manager(file_path.clone(), IOType::FileIO, false)?;
 
         Ok(DataFile {
             file_id: 0,
             write_off: 0,
             io_manager,
+            file_path,
+            reuse_read_buffers: true,
+            io_retry: None,
+            write_buf: Mutex::new(Vec::new()),
+            write_buffer_size: 0,
         })
     }
+
+    /// 新建或打开记录索引类型的文件
+    pub fn new_index_type_file(dir_path: PathBuf) -> Result<DataFile, Errors> {
+        let file_path = dir_path.join(INDEX_TYPE_FILE_NAME);
+        let io_manager = new_io_manager(file_path.clone(), IOType::FileIO, false)?;
+
+        Ok(DataFile {
+            file_id: 0,
+            write_off: 0,
+            io_manager,
+            file_path,
+            reuse_read_buffers: true,
+            io_retry: None,
+            write_buf: Mutex::new(Vec::new()),
+            write_buffer_size: 0,
+        })
+    }
+
+    /// 新建或打开数据目录中保存元数据的文件，包括 MANIFEST 和写入 MANIFEST 时使用的临时文件
+    pub fn new_metadata_file(
+        dir_path: PathBuf,
+        file_name: &str,
+        read_only: bool,
+    ) -> Result<DataFile, Errors> {
+        let file_path = dir_path.join(file_name);
+        let io_manager = new_io_manager(file_path.clone(), IOType::FileIO, read_only)?;
+
+        Ok(DataFile {
+            file_id: 0,
+            write_off: 0,
+            io_manager,
+            file_path,
+            reuse_read_buffers: true,
+            io_retry: None,
+            write_buf: Mutex::new(Vec::new()),
+            write_buffer_size: 0,
+        })
+    }
+}
+
+impl Drop for DataFile {
+    // 关闭文件之前写入缓冲区中剩余的数据，没有开启写缓冲时缓冲区始终为空
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            warn!(
+                "failed to flush write buffer of data file {}: {}",
+                self.file_id, e
+            );
+        }
+    }
+}
+
+// 从数据文件中读取的原始 LogRecord 数据
+struct RawLogRecord {
+    rec_type: LogRecordType,
+    header_size: usize,
+    key_size: usize,
+    value_size: usize,
+    kv_buf: ReadBuffer, // key + value + CRC
+}
+
+impl RawLogRecord {
+    fn key(&self) -> &[u8] {
+        &self.kv_buf[..self.key_size]
+    }
+
+    fn value(&self) -> &[u8] {
+        &self.kv_buf[self.key_size..self.key_size + self.value_size]
+    }
+
+    fn size(&self) -> u64 {
+        (self.header_size + self.key_size + self.value_size + 4) as u64
+    }
 }
 
-// 根据 dir_path 和 file_id 构建数据文件路径
-pub(crate) fn get_data_file_path(dir_path: PathBuf, file_id: u32) -> PathBuf {
-    let file_name = std::format!("{:09}", file_id) + DATA_FILE_NAME_SUFFIX;
+// 根据 dir_path、file_id 和文件后缀构建数据文件路径
+pub(crate) fn get_data_file_path(dir_path: PathBuf, file_id: u32, file_suffix: &str) -> PathBuf {
+    let file_name = std::format!("{:09}", file_id) + file_suffix;
     dir_path.join(file_name)
 }
 
@@ -177,6 +515,7 @@ mod tests {
         let remove_res1 = remove_file(get_data_file_path(
             dir_path.clone(),
             data_file1.get_file_id(),
+            DATA_FILE_NAME_SUFFIX,
         ));
         assert!(remove_res1.is_ok());
 
@@ -188,6 +527,7 @@ mod tests {
         let remove_res2 = remove_file(get_data_file_path(
             dir_path.clone(),
             data_file2.get_file_id(),
+            DATA_FILE_NAME_SUFFIX,
         ));
         assert!(remove_res2.is_ok());
 
@@ -199,6 +539,7 @@ mod tests {
         let remove_res3 = remove_file(get_data_file_path(
             dir_path.clone(),
             data_file3.get_file_id(),
+            DATA_FILE_NAME_SUFFIX,
         ));
         assert!(remove_res3.is_ok());
     }
@@ -224,6 +565,7 @@ mod tests {
         let remove_res1 = remove_file(get_data_file_path(
             dir_path.clone(),
             data_file1.get_file_id(),
+            DATA_FILE_NAME_SUFFIX,
         ));
         assert!(remove_res1.is_ok());
     }
@@ -248,6 +590,7 @@ mod tests {
         let remove_res1 = remove_file(get_data_file_path(
             dir_path.clone(),
             data_file1.get_file_id(),
+            DATA_FILE_NAME_SUFFIX,
         ));
         assert!(remove_res1.is_ok());
     }
@@ -320,6 +663,116 @@ mod tests {
         let remove_res1 = remove_file(get_data_file_path(
             dir_path.clone(),
             data_file1.get_file_id(),
+            DATA_FILE_NAME_SUFFIX,
+        ));
+        assert!(remove_res1.is_ok());
+    }
+
+    #[test]
+    fn test_data_file_read_at_file_end_with_mmap() {
+        let dir_path = std::env::temp_dir();
+        let data_file_res1 = DataFile::new(dir_path.clone(), 5, IOType::FileIO);
+        assert!(data_file_res1.is_ok());
+        let mut data_file1 = data_file_res1.unwrap();
+
+        // 记录长度小于最大 header 长度，header 的读取范围会超过文件末尾
+        let rec1 = LogRecord {
+            key: "a".as_bytes().to_vec(),
+            value: "b".as_bytes().to_vec(),
+            rec_type: LogRecordType::NOAMAL,
+        };
+        let enc_rec1 = rec1.encode();
+        assert!(enc_rec1.len() < max_log_record_header_size());
+        let write_res1 = data_file1.write(&enc_rec1);
+        assert!(write_res1.is_ok());
+
+        let mmap_file_res1 = DataFile::new(dir_path.clone(), 5, IOType::MMapIO);
+        assert!(mmap_file_res1.is_ok());
+        let mmap_file1 = mmap_file_res1.unwrap();
+        let read_res1 = mmap_file1.read(0);
+        assert!(read_res1.is_ok());
+        let read_res1 = read_res1.unwrap();
+        assert_eq!(read_res1.size, enc_rec1.len() as u64);
+        assert_eq!(read_res1.record.key, rec1.key);
+        assert_eq!(read_res1.record.value, rec1.value);
+
+        // 读到文件末尾
+        let read_res2 = mmap_file1.read(read_res1.size);
+        assert_eq!(read_res2.err().unwrap(), Errors::ReadDataFileEOF);
+
+        // 文件末尾的 Deleted 数据
+        let rec2 = LogRecord {
+            key: "a".as_bytes().to_vec(),
+            value: Default::default(),
+            rec_type: LogRecordType::DELETED,
+        };
+        let write_res2 = data_file1.write(&rec2.encode());
+        assert!(write_res2.is_ok());
+
+        let mmap_file_res2 = DataFile::new(dir_path.clone(), 5, IOType::MMapIO);
+        assert!(mmap_file_res2.is_ok());
+        let mmap_file2 = mmap_file_res2.unwrap();
+        let read_res3 = mmap_file2.read(read_res1.size);
+        assert!(read_res3.is_ok());
+        let read_res3 = read_res3.unwrap();
+        assert_eq!(read_res3.record.key, rec2.key);
+        assert_eq!(read_res3.record.rec_type, LogRecordType::DELETED);
+
+        let remove_res1 = remove_file(get_data_file_path(
+            dir_path.clone(),
+            data_file1.get_file_id(),
+            DATA_FILE_NAME_SUFFIX,
+        ));
+        assert!(remove_res1.is_ok());
+    }
+
+    #[test]
+    fn test_data_file_write_buffer() {
+        let dir_path = std::env::temp_dir();
+
+        let data_file_res1 = DataFile::new(dir_path.clone(), 6, IOType::FileIO);
+        assert!(data_file_res1.is_ok());
+        let mut data_file1 = data_file_res1.unwrap();
+        let rec = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs-kv".as_bytes().to_vec(),
+            rec_type: LogRecordType::NOAMAL,
+        };
+        let enc_rec = rec.encode();
+        data_file1.set_write_buffer_size(enc_rec.len() * 2 + 1);
+
+        // 没有写满缓冲区时不写入文件，但可以读取
+        let write_res1 = data_file1.write(&enc_rec);
+        assert!(write_res1.is_ok());
+        let write_res2 = data_file1.write(&enc_rec);
+        assert!(write_res2.is_ok());
+        assert_eq!(0, data_file1.io_manager.size());
+        assert_eq!(enc_rec.len() as u64 * 2, data_file1.file_size());
+        let read_res1 = data_file1.read(enc_rec.len() as u64);
+        assert!(read_res1.is_ok());
+        assert_eq!(read_res1.unwrap().record.value, rec.value);
+
+        // 写满缓冲区时一次写入文件，之后的数据一部分在文件中，一部分在缓冲区中
+        let write_res3 = data_file1.write(&enc_rec);
+        assert!(write_res3.is_ok());
+        assert_eq!(enc_rec.len() as u64 * 3, data_file1.io_manager.size());
+        let write_res4 = data_file1.write(&enc_rec);
+        assert!(write_res4.is_ok());
+        let mut buf = vec![0u8; enc_rec.len() * 2];
+        let read_res2 = data_file1.read_at(&mut buf, enc_rec.len() as u64 * 2);
+        assert_eq!(buf.len(), read_res2.unwrap());
+        assert_eq!([enc_rec.clone(), enc_rec.clone()].concat(), buf);
+
+        // sync 时写入缓冲区中剩余的数据
+        let sync_res = data_file1.sync();
+        assert!(sync_res.is_ok());
+        assert_eq!(enc_rec.len() as u64 * 4, data_file1.io_manager.size());
+        assert_eq!(data_file1.get_write_off(), data_file1.file_size());
+
+        let remove_res1 = remove_file(get_data_file_path(
+            dir_path.clone(),
+            data_file1.get_file_id(),
+            DATA_FILE_NAME_SUFFIX,
         ));
         assert!(remove_res1.is_ok());
     }
@@ -0,0 +1,293 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use parking_lot::Mutex;
+
+use crate::{
+    errors::Errors,
+    options::{ArchiveReader, IOType},
+};
+
+use super::data_file::{get_data_file_path, DataFile};
+
+/// 旧数据文件缓存，保存所有旧数据文件的 id，读取时按需打开数据文件
+/// 打开的文件数量超过上限时，关闭最近最少使用的文件，避免数据文件很多时耗尽文件描述符
+/// 读取时返回数据文件的引用计数，文件被淘汰或移出列表后，正在进行的读取结束时才会真正关闭文件
+pub struct FileCache {
+    dir_path: PathBuf,
+    file_suffix: String,
+    max_open_files: usize, // 同时打开的文件数量上限，0 表示不限制
+    io_type: IOType,       // 打开文件时使用的 IO 类型
+    archive_reader: Option<ArchiveReader>, // 设置后按压缩归档的文件打开，忽略 io_type
+    reuse_read_buffers: bool, // 读取时是否复用当前线程缓存的缓冲区
+    read_only: bool,       // 只读模式下无法写入的文件也可以打开
+    file_ids: BTreeSet<u32>,
+    lru: Mutex<LruFiles>,
+}
+
+// 已经打开的文件，按最近一次访问的顺序排列
+#[derive(Default)]
+struct LruFiles {
+    files: HashMap<u32, (Arc<DataFile>, u64)>, // 文件 id -> (数据文件，最近一次访问的序号)
+    order: BTreeMap<u64, u32>,                 // 访问序号 -> 文件 id
+    tick: u64,
+}
+
+impl LruFiles {
+    // 记录文件被访问
+    fn touch(&mut self, file_id: u32) -> Option<Arc<DataFile>> {
+        self.tick += 1;
+        let tick = self.tick;
+        let (data_file, last) = self.files.get_mut(&file_id)?;
+        self.order.remove(last);
+        self.order.insert(tick, file_id);
+        *last = tick;
+        Some(data_file.clone())
+    }
+
+    // 加入新打开的文件，超过上限时关闭最近最少使用的文件
+    fn insert(&mut self, data_file: Arc<DataFile>, max_open_files: usize) {
+        let file_id = data_file.get_file_id();
+        self.remove(file_id);
+        self.tick += 1;
+        self.order.insert(self.tick, file_id);
+        self.files.insert(file_id, (data_file, self.tick));
+
+        while max_open_files > 0 && self.files.len() > max_open_files {
+            let (_, evicted) = self.order.pop_first().unwrap();
+            // 正在读取该文件的线程持有 Arc，读取结束后才会真正关闭文件
+            self.files.remove(&evicted);
+        }
+    }
+
+    fn remove(&mut self, file_id: u32) {
+        if let Some((_, tick)) = self.files.remove(&file_id) {
+            self.order.remove(&tick);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.files.clear();
+        self.order.clear();
+    }
+}
+
+impl FileCache {
+    pub fn new(
+        dir_path: PathBuf,
+        file_suffix: &str,
+        max_open_files: usize,
+        io_type: IOType,
+    ) -> Self {
+        Self {
+            dir_path,
+            file_suffix: file_suffix.to_string(),
+            max_open_files,
+            io_type,
+            archive_reader: None,
+            reuse_read_buffers: true,
+            read_only: false,
+            file_ids: BTreeSet::new(),
+            lru: Mutex::new(LruFiles::default()),
+        }
+    }
+
+    /// 获取旧数据文件，文件没有打开时重新打开
+    /// 文件 id 不在旧文件列表中，或者文件已经从磁盘上删除时返回 None
+    pub fn get(&self, file_id: u32) -> Result<Option<Arc<DataFile>>, Errors> {
+        if !self.file_ids.contains(&file_id) {
+            return Ok(None);
+        }
+
+        let mut lru = self.lru.lock();
+        if let Some(data_file) = lru.touch(file_id) {
+            return Ok(Some(data_file));
+        }
+
+        // 打开文件时如果文件不存在会新建文件，需要先判断文件是否还在
+        let file_path = get_data_file_path(self.dir_path.clone(), file_id, &self.file_suffix);
+        if !file_path.is_file() {
+            return Ok(None);
+        }
+        let mut data_file = match self.archive_reader.as_ref() {
+            Some(reader) => DataFile::new_archived(
+                self.dir_path.clone(),
+                file_id,
+                &self.file_suffix,
+                reader.clone(),
+            ),
+            None => DataFile::new_with_suffix(
+                self.dir_path.clone(),
+                file_id,
+                self.io_type,
+                &self.file_suffix,
+                self.read_only,
+            )?,
+        };
+        data_file.set_reuse_read_buffers(self.reuse_read_buffers);
+        // 旧数据文件不会再写入，写偏移即文件的实际大小
+        data_file.set_write_off(data_file.file_size());
+        let data_file = Arc::new(data_file);
+        lru.insert(data_file.clone(), self.max_open_files);
+
+        Ok(Some(data_file))
+    }
+
+    /// 加入一个旧数据文件，传入的文件作为最近访问的文件保持打开，已经打开的同 id 文件会被替换
+    pub fn insert(&mut self, data_file: DataFile) {
+        self.file_ids.insert(data_file.get_file_id());
+        self.lru
+            .get_mut()
+            .insert(Arc::new(data_file), self.max_open_files);
+    }
+
+    /// 只记录旧数据文件的 id，读取时再打开文件
+    pub fn add_file_id(&mut self, file_id: u32) {
+        self.file_ids.insert(file_id);
+    }
+
+    /// 从旧文件列表中移除文件，返回文件是否存在
+    pub fn remove(&mut self, file_id: u32) -> bool {
+        self.lru.get_mut().remove(file_id);
+        self.file_ids.remove(&file_id)
+    }
+
+    pub fn contains(&self, file_id: u32) -> bool {
+        self.file_ids.contains(&file_id)
+    }
+
+    /// 所有旧数据文件的 id，按从小到大的顺序排列
+    pub fn file_ids(&self) -> Vec<u32> {
+        self.file_ids.iter().copied().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.file_ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.file_ids.is_empty()
+    }
+
+    /// 当前打开的文件数量
+    pub fn open_files(&self) -> usize {
+        self.lru.lock().files.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.file_ids.clear();
+        self.lru.get_mut().clear();
+    }
+
+    /// 设置读取压缩归档文件的函数，之后打开的文件都按压缩归档的文件读取
+    pub fn set_archive_reader(&mut self, archive_reader: Option<ArchiveReader>) {
+        self.archive_reader = archive_reader;
+        self.lru.get_mut().clear();
+    }
+
+    /// 设置打开的文件读取时是否复用缓冲区，已经打开的文件会被关闭
+    pub fn set_reuse_read_buffers(&mut self, reuse_read_buffers: bool) {
+        self.reuse_read_buffers = reuse_read_buffers;
+        self.lru.get_mut().clear();
+    }
+
+    /// 设置是否以只读模式打开文件，已经打开的文件会被关闭
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+        self.lru.get_mut().clear();
+    }
+
+    /// 切换打开文件使用的 IO 类型，已经打开的文件会被关闭，之后读取时使用新的 IO 类型重新打开
+    pub fn set_io_type(&mut self, io_type: IOType) {
+        self.io_type = io_type;
+        self.lru.get_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::log_record::{LogRecord, LogRecordType};
+
+    #[test]
+    fn test_file_cache_evict_lru() {
+        let dir_path = std::env::temp_dir().join("bitcask-rs-file-cache-evict");
+        std::fs::create_dir_all(dir_path.clone()).unwrap();
+        for fid in 0..5 {
+            DataFile::new(dir_path.clone(), fid, IOType::FileIO).unwrap();
+        }
+
+        let mut cache = FileCache::new(dir_path.clone(), ".data", 2, IOType::FileIO);
+        for fid in 0..5 {
+            cache.add_file_id(fid);
+        }
+        assert_eq!(cache.len(), 5);
+        assert_eq!(cache.open_files(), 0);
+
+        assert!(cache.get(0).unwrap().is_some());
+        assert!(cache.get(1).unwrap().is_some());
+        assert_eq!(cache.open_files(), 2);
+
+        // 访问 0 之后，最近最少使用的是 1
+        assert!(cache.get(0).unwrap().is_some());
+        assert!(cache.get(2).unwrap().is_some());
+        assert_eq!(cache.open_files(), 2);
+        assert!(cache.lru.lock().files.contains_key(&0));
+        assert!(!cache.lru.lock().files.contains_key(&1));
+
+        // 不在旧文件列表中的文件
+        assert!(cache.get(10).unwrap().is_none());
+
+        // 移除后不再返回该文件
+        assert!(cache.remove(0));
+        assert!(cache.get(0).unwrap().is_none());
+        assert_eq!(cache.file_ids(), vec![1, 2, 3, 4]);
+
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
+
+    #[test]
+    fn test_file_cache_deleted_file() {
+        let dir_path = std::env::temp_dir().join("bitcask-rs-file-cache-deleted");
+        std::fs::create_dir_all(dir_path.clone()).unwrap();
+        DataFile::new(dir_path.clone(), 0, IOType::FileIO).unwrap();
+
+        let mut cache = FileCache::new(dir_path.clone(), ".data", 0, IOType::FileIO);
+        cache.add_file_id(0);
+
+        // 文件没有打开并且已经从磁盘上删除，不会重新创建文件
+        std::fs::remove_file(get_data_file_path(dir_path.clone(), 0, ".data")).unwrap();
+        assert!(cache.get(0).unwrap().is_none());
+        assert!(!get_data_file_path(dir_path.clone(), 0, ".data").exists());
+
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
+
+    #[test]
+    fn test_file_cache_read_after_remove() {
+        let dir_path = std::env::temp_dir().join("bitcask-rs-file-cache-read-after-remove");
+        std::fs::create_dir_all(dir_path.clone()).unwrap();
+        let mut data_file = DataFile::new(dir_path.clone(), 0, IOType::FileIO).unwrap();
+        let rec = LogRecord {
+            key: "name".as_bytes().to_vec(),
+            value: "bitcask-rs".as_bytes().to_vec(),
+            rec_type: LogRecordType::NOAMAL,
+        };
+        data_file.write(&rec.encode()).unwrap();
+
+        let mut cache = FileCache::new(dir_path.clone(), ".data", 1, IOType::FileIO);
+        cache.add_file_id(0);
+        let in_use = cache.get(0).unwrap().unwrap();
+
+        // 正在使用的文件被移出列表并从磁盘删除后仍然可以读取
+        assert!(cache.remove(0));
+        std::fs::remove_file(get_data_file_path(dir_path.clone(), 0, ".data")).unwrap();
+        let read_res = in_use.read(0).unwrap();
+        assert_eq!(read_res.record.value, rec.value);
+
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
+}
@@ -1,11 +1,6 @@
-use std::u8;
+use crate::errors::Errors;
 
-use bytes::{BufMut, Bytes, BytesMut};
-use prost::{
-    encode_length_delimiter,
-    encoding::{decode_varint, encode_varint},
-    length_delimiter_len,
-};
+use super::codec;
 
 // 数据位置索引信息，描述数据存储的位置
 #[derive(Clone, Copy, Debug)]
@@ -18,36 +13,13 @@ pub struct LogRecordPos {
 impl LogRecordPos {
     // 对 LogRecordPos 编码
     pub fn encode(&self) -> Vec<u8> {
-        let mut buf = BytesMut::new();
-        encode_varint(self.file_id as u64, &mut buf);
-        encode_varint(self.offset, &mut buf);
-        encode_varint(self.size as u64, &mut buf);
-        buf.to_vec()
+        codec::encode_pos_v1(self)
     }
 }
 
 // 解码 LogRecordPos
 pub fn decode_log_record_pos(pos: Vec<u8>) -> LogRecordPos {
-    let mut buf = Bytes::from(pos);
-
-    let file_id = match decode_varint(&mut buf) {
-        Ok(fid) => fid as u32,
-        Err(e) => panic!("decode log record pos err: {}", e),
-    };
-    let offset = match decode_varint(&mut buf) {
-        Ok(n) => n,
-        Err(e) => panic!("decode log record pos err: {}", e),
-    };
-    let size = match decode_varint(&mut buf) {
-        Ok(size) => size as u32,
-        Err(e) => panic!("decode log record pos err: {}", e),
-    };
-
-    LogRecordPos {
-        file_id,
-        offset,
-        size,
-    }
+    codec::decode_pos_v1(&pos)
 }
 
 // LogRecord 写入到数据文件的记录
@@ -61,60 +33,66 @@ pub struct LogRecord {
 impl LogRecord {
     // 对 LogRecord 编码
     pub fn encode(&self) -> Vec<u8> {
-        let (enc_buf, _) = self.encoder_and_get_crc();
+        let (enc_buf, _) = codec::encode_v1(self);
         enc_buf
     }
 
     pub fn get_crc(&self) -> u32 {
-        let (_, crc) = self.encoder_and_get_crc();
+        let (_, crc) = codec::encode_v1(self);
         crc
     }
 
-    fn encoder_and_get_crc(&self) -> (Vec<u8>, u32) {
-        let mut buf = BytesMut::new();
-        buf.reserve(self.encoded_length());
-
-        buf.put_u8(self.rec_type as u8);
-
-        encode_length_delimiter(self.key.len(), &mut buf).unwrap();
-        encode_length_delimiter(self.value.len(), &mut buf).unwrap();
-
-        buf.extend_from_slice(&self.key);
-        buf.extend_from_slice(&self.value);
-
-        let mut hasher = crc32fast::Hasher::new();
-        hasher.update(&buf);
-        let crc = hasher.finalize();
-        buf.put_u32(crc);
+    /// 数据文件中存储的 key，包含编码的事务序列号，可以使用 batch::parse_log_record_key 解析
+    pub fn key(&self) -> &[u8] {
+        &self.key
+    }
 
-        (buf.to_vec(), crc)
+    pub fn value(&self) -> &[u8] {
+        &self.value
     }
 
-    // LogRecord 编码后的长度
-    fn encoded_length(&self) -> usize {
-        std::mem::size_of::<u8>()
-            + length_delimiter_len(self.key.len())
-            + length_delimiter_len(self.value.len())
-            + self.key.len()
-            + self.value.len()
-            + 4
+    pub fn rec_type(&self) -> LogRecordType {
+        self.rec_type
     }
 }
 
+/// 计算 key/value 编码为 LogRecord 后在磁盘上占用的字节数
+/// 包括 header（类型 + key长度 + value长度）、key、value 和 4 字节 CRC 校验值
+pub fn record_encoded_size(key: &[u8], value: &[u8]) -> usize {
+    codec::encoded_size_v1(key, value)
+}
+
+/// 数据文件中记录的类型，之后可能增加新的类型，匹配时需要处理未知的类型
 #[derive(Clone, Copy, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum LogRecordType {
     NOAMAL = 1,      // 正常写入的数据
     DELETED = 2,     // 删除数据的标记，墓碑值
     TXNFINISHED = 3, // 标记事务完成的数据
+    BLOBPOINTER = 4, // value 保存在 blob 文件中，记录的 value 为 blob 位置
+}
+
+impl TryFrom<u8> for LogRecordType {
+    type Error = Errors;
+
+    // 类型也在 crc 校验的范围内，未知的类型说明数据已经损坏
+    fn try_from(v: u8) -> Result<Self, Self::Error> {
+        match v {
+            1 => Ok(LogRecordType::NOAMAL),
+            2 => Ok(LogRecordType::DELETED),
+            3 => Ok(LogRecordType::TXNFINISHED),
+            4 => Ok(LogRecordType::BLOBPOINTER),
+            _ => Err(Errors::InvalidLogRecordCrc),
+        }
+    }
 }
 
 impl LogRecordType {
+    #[deprecated(note = "use LogRecordType::try_from instead")]
     pub fn from_u8(v: u8) -> Self {
-        match v {
-            1 => LogRecordType::NOAMAL,
-            2 => LogRecordType::DELETED,
-            3 => LogRecordType::TXNFINISHED,
-            _ => panic!("unknown log record type"),
+        match Self::try_from(v) {
+            Ok(rec_type) => rec_type,
+            Err(_) => panic!("unknown log record type"),
         }
     }
 }
@@ -134,7 +112,7 @@ pub struct TransactionLogRecord {
 
 // LogRecord header 部分最大长度
 pub fn max_log_record_header_size() -> usize {
-    std::mem::size_of::<u8>() + length_delimiter_len(std::u32::MAX as usize) * 2
+    codec::max_header_size_v1()
 }
 
 #[cfg(test)]
@@ -170,4 +148,37 @@ mod tests {
         let enc3 = rec3.encode();
         assert!(enc3.len() > 9)
     }
+
+    #[test]
+    fn test_record_encoded_size() {
+        let cases: Vec<(Vec<u8>, Vec<u8>)> = vec![
+            (b"name".to_vec(), b"hsy".to_vec()),
+            (b"name".to_vec(), Vec::new()),
+            (vec![b'k'; 200], vec![b'v'; 1000]),
+            (vec![b'k'; 20000], vec![b'v'; 1024 * 1024]),
+        ];
+
+        for (key, value) in cases {
+            let size = record_encoded_size(&key, &value);
+            let rec = LogRecord {
+                key,
+                value,
+                rec_type: LogRecordType::NOAMAL,
+            };
+            assert_eq!(size, rec.encode().len());
+        }
+    }
+
+    #[test]
+    fn test_log_record_type_try_from() {
+        assert_eq!(LogRecordType::try_from(1), Ok(LogRecordType::NOAMAL));
+        assert_eq!(LogRecordType::try_from(2), Ok(LogRecordType::DELETED));
+        assert_eq!(LogRecordType::try_from(3), Ok(LogRecordType::TXNFINISHED));
+        assert_eq!(LogRecordType::try_from(4), Ok(LogRecordType::BLOBPOINTER));
+
+        // 未知的类型返回错误，不会 panic
+        for v in [0u8, 5, 255] {
+            assert_eq!(LogRecordType::try_from(v), Err(Errors::InvalidLogRecordCrc));
+        }
+    }
 }
@@ -0,0 +1,232 @@
+use std::{
+    fs::{self, remove_file},
+    path::PathBuf,
+};
+
+use log::warn;
+
+use crate::{errors::Errors, util::file::sync_dir};
+
+use super::{
+    codec::{FormatVersion, CHECKPOINT_FID_KEY, SEQ_NO_KEY},
+    data_file::{DataFile, INDEX_TYPE_FILE_NAME, MANIFEST_FILE_NAME, SEQ_NO_FILE_NAME},
+    log_record::{LogRecord, LogRecordType},
+};
+
+/// 写入 MANIFEST 时使用的临时文件
+pub(crate) const MANIFEST_TMP_FILE_NAME: &str = "MANIFEST.tmp";
+
+const FORMAT_VERSION_KEY: &str = "format.version";
+const INDEX_TYPE_KEY: &str = "index.type";
+const DATA_FILE_SIZE_KEY: &str = "data.file.size";
+const TOTAL_BYTES_WRITTEN_KEY: &str = "total.bytes.written";
+const TOTAL_RECORDS_WRITTEN_KEY: &str = "total.records.written";
+
+/// 数据目录的元数据，保存在 MANIFEST 文件中
+/// 每次更新都完整地写入临时文件再重命名为 MANIFEST，读取时要么是更新前的内容，要么是更新后的内容；
+/// 之前的版本把这些元数据分别保存在 index-type 和 seq-no 文件中，没有 MANIFEST 时从这两个文件读取
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Manifest {
+    pub format_version: Option<u8>,  // 数据文件的格式版本
+    pub index_type: Option<String>,  // 使用的索引类型名称
+    pub data_file_size: Option<u64>, // 使用过的最大的数据文件大小
+    pub total_bytes_written: u64,    // 累计写入的字节数
+    pub total_records_written: u64,  // 累计写入的记录数
+    pub seq_no: Option<usize>,       // 关闭或检查点时的事务序列号，没有时需要扫描数据文件恢复
+    pub checkpoint_fid: Option<u32>, // 检查点时活跃文件的 id，正常关闭时为 None
+}
+
+impl Manifest {
+    /// 读取数据目录中的元数据，没有 MANIFEST 时读取之前版本的 index-type 和 seq-no 文件，都不存在时返回空的元数据
+    /// read_only 为 true 时元数据文件无法写入也可以读取
+    pub fn read(dir_path: PathBuf, read_only: bool) -> Result<Self, Errors> {
+        let mut manifest = Manifest::default();
+        if dir_path.join(MANIFEST_FILE_NAME).is_file() {
+            let manifest_file =
+                DataFile::new_metadata_file(dir_path, MANIFEST_FILE_NAME, read_only)?;
+            manifest.read_records(&manifest_file)?;
+        } else {
+            for file_name in [INDEX_TYPE_FILE_NAME, SEQ_NO_FILE_NAME] {
+                if dir_path.join(file_name).is_file() {
+                    let legacy_file =
+                        DataFile::new_metadata_file(dir_path.clone(), file_name, read_only)?;
+                    manifest.read_records(&legacy_file)?;
+                }
+            }
+        }
+
+        if let Some(version) = manifest.format_version {
+            if FormatVersion::from_u8(version).is_none() {
+                return Err(Errors::UnsupportedFormatVersion);
+            }
+        }
+        Ok(manifest)
+    }
+
+    /// 原子地写入 MANIFEST，之前的内容会被替换，写入完成后删除之前版本的 index-type 和 seq-no 文件
+    pub fn write(&self, dir_path: PathBuf, sync_dir_enabled: bool) -> Result<(), Errors> {
+        let tmp_path = dir_path.join(MANIFEST_TMP_FILE_NAME);
+        if tmp_path.is_file() {
+            remove_file(tmp_path.clone())?;
+        }
+
+        let mut tmp_file =
+            DataFile::new_metadata_file(dir_path.clone(), MANIFEST_TMP_FILE_NAME, false)?;
+        for (key, value) in self.records() {
+            let record = LogRecord {
+                key: key.as_bytes().to_vec(),
+                value: value.into_bytes(),
+                rec_type: LogRecordType::NOAMAL,
+            };
+            tmp_file.write(&record.encode())?;
+        }
+        tmp_file.sync()?;
+        drop(tmp_file);
+
+        fs::rename(tmp_path, dir_path.join(MANIFEST_FILE_NAME))?;
+        if sync_dir_enabled {
+            sync_dir(&dir_path)?;
+        }
+
+        // MANIFEST 已经包含了所有元数据，删除失败不影响之后的读取
+        for file_name in [INDEX_TYPE_FILE_NAME, SEQ_NO_FILE_NAME] {
+            let file_path = dir_path.join(file_name);
+            if file_path.is_file() {
+                if let Err(e) = remove_file(file_path) {
+                    warn!("failed to remove legacy metadata file {}: {}", file_name, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // 按 key 识别文件中的记录，不认识的 key 直接忽略
+    fn read_records(&mut self, data_file: &DataFile) -> Result<(), Errors> {
+        for item in data_file.iter_records() {
+            let (record, _) = item?;
+            let value = String::from_utf8(record.value).unwrap();
+            match String::from_utf8(record.key).unwrap().as_str() {
+                FORMAT_VERSION_KEY => self.format_version = value.parse().ok(),
+                INDEX_TYPE_KEY => self.index_type = Some(value),
+                DATA_FILE_SIZE_KEY => self.data_file_size = value.parse().ok(),
+                TOTAL_BYTES_WRITTEN_KEY => self.total_bytes_written = value.parse().unwrap_or(0),
+                TOTAL_RECORDS_WRITTEN_KEY => {
+                    self.total_records_written = value.parse().unwrap_or(0)
+                }
+                SEQ_NO_KEY => self.seq_no = value.parse().ok(),
+                CHECKPOINT_FID_KEY => self.checkpoint_fid = value.parse().ok(),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    // 需要写入的记录，格式版本总是记录为当前写入使用的版本
+    fn records(&self) -> Vec<(&'static str, String)> {
+        let mut records = vec![(
+            FORMAT_VERSION_KEY,
+            (FormatVersion::CURRENT as u8).to_string(),
+        )];
+        if let Some(index_type) = self.index_type.as_ref() {
+            records.push((INDEX_TYPE_KEY, index_type.clone()));
+        }
+        if let Some(data_file_size) = self.data_file_size {
+            records.push((DATA_FILE_SIZE_KEY, data_file_size.to_string()));
+        }
+        records.push((
+            TOTAL_BYTES_WRITTEN_KEY,
+            self.total_bytes_written.to_string(),
+        ));
+        records.push((
+            TOTAL_RECORDS_WRITTEN_KEY,
+            self.total_records_written.to_string(),
+        ));
+        if let Some(seq_no) = self.seq_no {
+            records.push((SEQ_NO_KEY, seq_no.to_string()));
+        }
+        if let Some(checkpoint_fid) = self.checkpoint_fid {
+            records.push((CHECKPOINT_FID_KEY, checkpoint_fid.to_string()));
+        }
+        records
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-manifest-round-trip");
+        fs::create_dir_all(dir_path.clone()).unwrap();
+
+        let manifest = Manifest {
+            format_version: Some(FormatVersion::CURRENT as u8),
+            index_type: Some("bptree".to_string()),
+            data_file_size: Some(64 * 1024 * 1024),
+            total_bytes_written: 12345,
+            total_records_written: 678,
+            seq_no: Some(42),
+            checkpoint_fid: Some(7),
+        };
+        let res = manifest.write(dir_path.clone(), true);
+        assert!(res.is_ok());
+        assert!(!dir_path.join(MANIFEST_TMP_FILE_NAME).exists());
+        assert_eq!(manifest, Manifest::read(dir_path.clone(), false).unwrap());
+
+        // 重新写入会覆盖之前的内容
+        let closed = Manifest {
+            checkpoint_fid: None,
+            ..manifest.clone()
+        };
+        let res = closed.write(dir_path.clone(), true);
+        assert!(res.is_ok());
+        assert_eq!(closed, Manifest::read(dir_path.clone(), false).unwrap());
+
+        fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_manifest_read_legacy_files() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-manifest-legacy");
+        fs::create_dir_all(dir_path.clone()).unwrap();
+
+        // 空目录返回空的元数据
+        assert_eq!(
+            Manifest::default(),
+            Manifest::read(dir_path.clone(), false).unwrap()
+        );
+
+        let mut index_type_file = DataFile::new_index_type_file(dir_path.clone()).unwrap();
+        for (key, value) in [(INDEX_TYPE_KEY, "btree"), (TOTAL_BYTES_WRITTEN_KEY, "100")] {
+            let record = LogRecord {
+                key: key.as_bytes().to_vec(),
+                value: value.as_bytes().to_vec(),
+                rec_type: LogRecordType::NOAMAL,
+            };
+            index_type_file.write(&record.encode()).unwrap();
+        }
+        let mut seq_no_file = DataFile::new_seq_no_file(dir_path.clone()).unwrap();
+        seq_no_file
+            .write(&crate::data::codec::encode_checkpoint_v1(9, 3))
+            .unwrap();
+
+        let manifest = Manifest::read(dir_path.clone(), false).unwrap();
+        assert_eq!(None, manifest.format_version);
+        assert_eq!(Some("btree".to_string()), manifest.index_type);
+        assert_eq!(100, manifest.total_bytes_written);
+        assert_eq!(Some(9), manifest.seq_no);
+        assert_eq!(Some(3), manifest.checkpoint_fid);
+
+        // 写入 MANIFEST 之后删除之前版本的文件
+        let res = manifest.write(dir_path.clone(), false);
+        assert!(res.is_ok());
+        assert!(!dir_path.join(INDEX_TYPE_FILE_NAME).exists());
+        assert!(!dir_path.join(SEQ_NO_FILE_NAME).exists());
+        let read = Manifest::read(dir_path.clone(), false).unwrap();
+        assert_eq!(Some(FormatVersion::CURRENT as u8), read.format_version);
+        assert_eq!(Some(9), read.seq_no);
+
+        fs::remove_dir_all(dir_path).expect("failed to remove path");
+    }
+}
@@ -1,2 +1,7 @@
+pub mod blob_store;
+pub mod codec;
+pub mod data_file;
+pub mod file_cache;
 pub mod log_record;
-pub mod data_file;
\ No newline at end of file
+pub(crate) mod manifest;
+pub mod value_reader;
@@ -0,0 +1,95 @@
+use std::{io, sync::Arc};
+
+use bytes::Buf;
+
+use crate::errors::Errors;
+
+use super::data_file::DataFile;
+
+/// 按位置流式读取数据文件中一条记录的 value，不会将整个 value 加载到内存中
+/// 每次 read 按调用方缓冲区的大小从数据文件中读取，读到 value 末尾时校验整条记录的 CRC，
+/// 校验失败时返回 InvalidData 错误
+pub struct ValueReader {
+    data_file: Arc<DataFile>,  // value 所在的数据文件
+    offset: u64,               // 下一次读取的位置
+    end: u64,                  // value 的结束位置，之后是 4 字节 CRC
+    hasher: crc32fast::Hasher, // 已经读取的 header、key 和 value 部分的 CRC
+    verified: bool,            // 是否已经校验过 CRC
+}
+
+impl ValueReader {
+    /// 从 offset 开始读取长度为 len 的 value，hasher 中是这条记录 header 和 key 部分的 CRC
+    pub(crate) fn new(
+        data_file: Arc<DataFile>,
+        offset: u64,
+        len: usize,
+        hasher: crc32fast::Hasher,
+    ) -> Self {
+        Self {
+            data_file,
+            offset,
+            end: offset + len as u64,
+            hasher,
+            verified: false,
+        }
+    }
+
+    /// 还没有读取的 value 长度
+    pub fn remaining(&self) -> u64 {
+        self.end - self.offset
+    }
+
+    // 读取 value 之后的 4 字节 CRC，和读取过程中计算的 CRC 比较
+    fn verify_crc(&mut self) -> io::Result<()> {
+        let mut crc_buf = [0u8; 4];
+        let n = self
+            .data_file
+            .read_at(&mut crc_buf, self.end)
+            .map_err(to_io_error)?;
+        if n < crc_buf.len() {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        if (&crc_buf[..]).get_u32() != self.hasher.clone().finalize() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                Errors::InvalidLogRecordCrc,
+            ));
+        }
+        self.verified = true;
+        Ok(())
+    }
+}
+
+impl io::Read for ValueReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.offset == self.end {
+            if !self.verified {
+                self.verify_crc()?;
+            }
+            return Ok(0);
+        }
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let len = std::cmp::min(buf.len() as u64, self.remaining()) as usize;
+        let n = self
+            .data_file
+            .read_at(&mut buf[..len], self.offset)
+            .map_err(to_io_error)?;
+        if n == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+        }
+        self.hasher.update(&buf[..n]);
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+// io 错误直接返回原始的 io::Error，其他错误包装为 io::Error
+fn to_io_error(e: Errors) -> io::Error {
+    match e {
+        Errors::Io(e) => io::Error::new(e.kind(), e),
+        e => io::Error::other(e),
+    }
+}
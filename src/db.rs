@@ -1,32 +1,48 @@
 use bytes::Bytes;
 use fs2::FileExt;
 use log::warn;
-use parking_lot::{Mutex, RwLock};
+use parking_lot::{Mutex, MutexGuard, RwLock};
 use std::{
+    borrow::Cow,
     collections::HashMap,
     fs::{self, create_dir_all, read_dir, remove_file, File},
+    io::{ErrorKind, Read},
     path::PathBuf,
     sync::{
-        atomic::{AtomicUsize, Ordering},
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
         Arc,
     },
+    time::Duration,
 };
 
 use crate::{
     batch::{log_record_key_with_seq, parse_log_record_key, NON_TXN_SEQ_NO},
     data::{
-        data_file::{DataFile, DATA_FILE_NAME_SUFFIX, MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME},
+        blob_store::{BlobStore, BLOB_FILE_NAME_SUFFIX},
+        codec,
+        data_file::{
+            get_data_file_path, DataFile, HINT_FILE_NAME, INDEX_TYPE_FILE_NAME, MANIFEST_FILE_NAME,
+            MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME,
+        },
+        file_cache::FileCache,
         log_record::{LogRecord, LogRecordPos, LogRecordType, TransactionLogRecord},
+        manifest::{Manifest, MANIFEST_TMP_FILE_NAME},
+        value_reader::ValueReader,
     },
     errors::Errors,
-    index::{self, Indexer},
-    merge::load_merge_files,
-    options::{IOType, IndexType, Options},
-    util::file::{copy_dir, dir_disk_size},
+    index::{self, bptree::BPTREE_INDEX_FILE_NAME, Indexer},
+    merge::{get_non_merge_file_id, load_merge_files},
+    options::{CorruptionPolicy, IOType, IndexType, IteratorOptions, Options},
+    util::{
+        file::{copy_dir, dir_disk_size, dir_disk_size_with_suffix, sync_dir},
+        hash::key_hash,
+    },
 };
 
-const SEQ_NO_KEY: &str = "seq.no";
 pub(crate) const FILE_LOCK_NAME: &str = "flock";
+const KEY_LOCK_STRIPES: usize = 64;
+// 预热时每次读取的数据大小
+const WARMUP_READ_SIZE: usize = 1024 * 1024;
 
 /// bitcask 存储引擎实例
 pub struct Engine {
@@ -34,8 +50,8 @@ pub struct Engine {
     pub(crate) options: Arc<Options>,
     /// 当前活跃文件，用于写入新的数据
     pub(crate) active_file: Arc<RwLock<DataFile>>,
-    /// 旧文件列表，保存文件 id 和 DafaFile 的映射关系
-    pub(crate) older_files: Arc<RwLock<HashMap<u32, DataFile>>>,
+    /// 旧文件列表，保存所有旧数据文件的 id，读取时按需打开数据文件
+    pub(crate) older_files: Arc<RwLock<FileCache>>,
     /// 数据内存索引
     pub(crate) index: Box<dyn Indexer>,
     /// 数据文件列表，保存所有文件 id
@@ -46,16 +62,36 @@ pub struct Engine {
     pub(crate) seq_no: Arc<AtomicUsize>,
     /// 防止多个线程同时 merge
     pub(crate) merging_lock: Mutex<()>,
-    /// 不存在则禁止 WriteBatch 使用
-    pub(crate) seq_file_exists: bool,
-    /// 是否是第一次初始化该目录
-    pub(crate) is_initial: bool,
     /// 文件锁，保证单进程使用
     lock_file: File,
-    /// 累计写入多少字节
-    bytes_write: Arc<AtomicUsize>,
+    /// 累计写入多少字节，即上次持久化之后未持久化的数据量
+    pub(crate) bytes_write: Arc<AtomicUsize>,
     /// 累计可以 merge 的数据量
     pub(crate) reclaim_size: Arc<AtomicUsize>,
+    /// 每个数据文件中可以 merge 的数据量，和 reclaim_size 同时更新，提供给 merge 策略
+    reclaim_by_file: Mutex<HashMap<u32, usize>>,
+    /// 按 key 分段的锁，同一个 key 的写入和索引更新串行执行，不同 key 之间可以并发
+    key_locks: Vec<Mutex<()>>,
+    /// 已经持久化到的位置（文件 id，偏移），用于合并多个写入的 sync
+    synced_pos: Mutex<(u32, u64)>,
+    /// 开启 hash_keys_in_index 时计算索引 key 的 hash 函数
+    pub(crate) key_hasher: fn(&[u8]) -> [u8; 16],
+    /// 开启 collect_read_stats 时，读取活跃文件和旧数据文件的次数
+    active_file_hits: AtomicUsize,
+    older_file_hits: AtomicUsize,
+    /// 数据目录创建以来累计写入数据文件的字节数和记录数，关闭和检查点时记录到元数据文件中
+    total_bytes_written: AtomicU64,
+    total_records_written: AtomicU64,
+    /// 数据目录占用的磁盘空间，追加写入时累加，打开、清空、检查点时重新统计目录大小
+    disk_size: AtomicU64,
+    /// 数据文件的总大小，和 disk_size 一样追加写入时累加，重新统计目录大小时一起更新
+    pub(crate) data_files_size: AtomicU64,
+    /// 持久化活跃文件的次数
+    pub(crate) sync_count: AtomicUsize,
+    /// 开启 separate_values_over 时保存大 value 的 blob 文件
+    pub(crate) blobs: BlobStore,
+    /// 是否已经调用 close 关闭，关闭之后的读写操作返回 EngineClosed
+    closed: AtomicBool,
 }
 
 /// 存储引擎相关统计信息
@@ -67,8 +103,20 @@ pub struct Stat {
     pub data_file_num: usize,
     /// 可以回收的数据量
     pub reclaim_size: usize,
-    /// 占据磁盘空间大小
+    /// 占据磁盘空间大小，写入数据文件时累加的缓存值，辅助文件的变化在 reconcile_disk_size 之后体现
     pub disk_size: u64,
+    /// 去掉可以回收的数据量之后占据的磁盘空间大小，即 merge 之后大约占据的空间
+    pub live_size: u64,
+    /// 数据文件的总大小，不包括 hint 文件、blob 文件等其他文件，和 disk_size 一样是缓存的值
+    pub data_files_size: u64,
+    /// 从活跃文件中读取数据的次数，未开启 collect_read_stats 时为 None
+    pub active_file_hits: Option<usize>,
+    /// 从旧数据文件中读取数据的次数，未开启 collect_read_stats 时为 None
+    pub older_file_hits: Option<usize>,
+    /// 数据目录创建以来累计写入数据文件的字节数，跨越多次打开
+    pub total_bytes_written: u64,
+    /// 数据目录创建以来累计写入数据文件的记录数，包括删除和事务完成的标识记录
+    pub total_records_written: u64,
 }
 
 impl Engine {
@@ -79,11 +127,16 @@ impl Engine {
             return Err(e);
         }
 
-        let mut is_initial = false;
-        // 如果数据目录不存在则新建
+        // 如果数据目录不存在则新建，路径已经存在但不是目录时单独返回错误，便于发现路径写错的问题
+        // 只读模式下数据目录可能在只读的文件系统上，不会新建数据目录和数据文件
         let dir_path = options.dir_path.clone();
+        if dir_path.exists() && !dir_path.is_dir() {
+            return Err(Errors::DirPathIsNotADirectory);
+        }
+        if !dir_path.is_dir() && (!options.create_if_missing || options.read_only) {
+            return Err(Errors::DatabaseNotFound);
+        }
         if !dir_path.is_dir() {
-            is_initial = true;
             if let Err(e) = create_dir_all(dir_path.clone()) {
                 warn!("create database directory err: {}", e);
                 return Err(Errors::FailedCreateDatabaseDir);
@@ -91,138 +144,471 @@ impl Engine {
         }
 
         // 判断数据目录是否已经被使用了
-        let lock_file = fs::OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(dir_path.join(FILE_LOCK_NAME))
-            .unwrap();
+        let lock_file = open_lock_file(&dir_path, &options)?;
         if let Err(_) = lock_file.try_lock_exclusive() {
             return Err(Errors::DatabaseIsUsing);
         }
 
-        let entries = read_dir(dir_path.clone()).unwrap();
-        if entries.count() == 0 {
-            is_initial = true;
+        // 持有文件锁之后再判断数据目录中是否已经有数据，打开过的存储引擎至少有一个数据文件
+        let exists = !load_data_files(dir_path.clone(), &options.file_suffix)?.is_empty();
+        if exists && options.error_if_exists {
+            return Err(Errors::DatabaseAlreadyExists);
+        }
+        if !exists && (!options.create_if_missing || options.read_only) {
+            return Err(Errors::DatabaseNotFound);
         }
 
-        // 加载 merge 目录
-        load_merge_files(dir_path.clone())?;
+        // 校验索引类型和数据文件大小是否和之前打开时一致，并拿到之前记录的累计写入量
+        let manifest = check_metadata(dir_path.clone(), &options)?;
+
+        // 加载 merge 目录，只读模式下忽略未完成加载的 merge 目录，避免修改数据目录
+        let mut merged = false;
+        if !options.read_only {
+            merged = load_merge_files(
+                dir_path.clone(),
+                &options.file_suffix,
+                &options.lock_file_name,
+                options.sync_dir_enabled(),
+            )?;
+        }
 
-        // 加载数据文件
-        let mut data_files = load_data_files(dir_path.clone(), options.mmap_at_startup)?;
+        // 加载数据文件列表
+        let file_ids = load_data_files(dir_path.clone(), &options.file_suffix)?;
 
-        // 创建数据文件列表
-        let mut file_ids = Vec::new();
-        for data_file in data_files.iter() {
-            file_ids.push(data_file.get_file_id())
+        let mut io_type = IOType::FileIO;
+        if options.mmap_at_startup {
+            io_type = IOType::MMapIO;
         }
 
-        // 将旧数据文件加入到 older_files
-        data_files.reverse();
-        let mut older_files = HashMap::new();
-        if data_files.len() > 1 {
-            for _ in 0..=data_files.len() - 2 {
-                let data_file = data_files.pop().unwrap();
-                older_files.insert(data_file.get_file_id(), data_file);
+        // 将旧数据文件加入到 older_files，读取时再打开，同时打开的文件数量不超过 max_open_files
+        let mut older_files = FileCache::new(
+            dir_path.clone(),
+            &options.file_suffix,
+            options.max_open_files,
+            io_type,
+        );
+        older_files.set_archive_reader(options.archive_reader.clone());
+        older_files.set_reuse_read_buffers(options.reuse_read_buffers);
+        older_files.set_read_only(options.read_only);
+        if let Some((_, older_file_ids)) = file_ids.split_last() {
+            for file_id in older_file_ids.iter() {
+                older_files.add_file_id(*file_id);
             }
         }
 
+        // 加载 blob 文件，关闭 separate_values_over 之后仍然需要读取之前分离的 value
+        let blob_file_ids = load_data_files(dir_path.clone(), BLOB_FILE_NAME_SUFFIX)?;
+        let blobs = BlobStore::open(
+            dir_path.clone(),
+            options.data_file_size,
+            options.max_open_files,
+            options.io_retry,
+            options.read_only,
+            blob_file_ids,
+        )?;
+
         // 获取当前活跃文件
-        let active_file = match data_files.pop() {
-            Some(file) => file,
-            None => DataFile::new(dir_path.clone(), 0, IOType::FileIO)?,
+        let active_file = match file_ids.last() {
+            Some(file_id) => open_data_file(&options, *file_id, io_type)?,
+            None => open_data_file(&options, options.initial_file_id, IOType::FileIO)?,
+        };
+
+        // 打开索引，B+ 树索引文件损坏时根据配置项决定是否从数据文件中重建索引
+        let mut rebuild_index = false;
+        let index = match index::new_indexer(options.index_type.clone(), dir_path.clone()) {
+            Ok(index) => index,
+            Err(Errors::BPlusTreeIndexCorrupted)
+                if options.rebuild_index_on_corruption && !options.read_only =>
+            {
+                warn!("bptree index is corrupted, rebuild it from data files");
+                remove_file(dir_path.join(BPTREE_INDEX_FILE_NAME))?;
+                rebuild_index = true;
+                index::new_indexer(options.index_type.clone(), dir_path.clone())?
+            }
+            Err(e) => return Err(e),
         };
 
         let mut engine = Engine {
             options: Arc::new(options.clone()),
             active_file: Arc::new(RwLock::new(active_file)),
             older_files: Arc::new(RwLock::new(older_files)),
-            index: index::new_indexer(options.index_type, dir_path.clone()),
+            index,
             file_ids,
             batch_commit_lock: Mutex::new(()),
             seq_no: Arc::new(AtomicUsize::new(1)),
             merging_lock: Mutex::new(()),
-            seq_file_exists: false,
-            is_initial,
             lock_file,
             bytes_write: Arc::new(AtomicUsize::new(0)),
             reclaim_size: Arc::new(AtomicUsize::new(0)),
+            reclaim_by_file: Mutex::new(HashMap::new()),
+            key_locks: (0..KEY_LOCK_STRIPES).map(|_| Mutex::new(())).collect(),
+            synced_pos: Mutex::new((0, 0)),
+            key_hasher: key_hash,
+            active_file_hits: AtomicUsize::new(0),
+            older_file_hits: AtomicUsize::new(0),
+            total_bytes_written: AtomicU64::new(manifest.total_bytes_written),
+            total_records_written: AtomicU64::new(manifest.total_records_written),
+            disk_size: AtomicU64::new(0),
+            data_files_size: AtomicU64::new(0),
+            sync_count: AtomicUsize::new(0),
+            blobs,
+            closed: AtomicBool::new(false),
         };
 
-        // b+树索引存放在磁盘上，不需要加载数据文件建立索引
-        if engine.options.index_type != IndexType::BPlusTree {
-            // 从 hint 文件中快速建立索引
-            engine.load_index_from_hint_file()?;
-
-            // 从数据文件中加载内存索引
-            engine.load_index()?;
-
-            // 重置 IO 类型
-            engine.reset_io_type();
+        // b+树索引存放在磁盘上，不需要加载数据文件建立索引，重建 b+树索引时和其他索引一样加载
+        if engine.options.index_type != IndexType::BPlusTree || rebuild_index {
+            // 从 hint 文件中快速建立索引，再从数据文件中加载内存索引
+            let load_res = engine
+                .load_index_from_hint_file()
+                .and_then(|_| engine.load_index());
+            // 加载失败时不持久化任何数据，Drop 时只释放文件锁
+            if let Err(e) = load_res {
+                Arc::make_mut(&mut engine.options).sync_on_drop = false;
+                return Err(e);
+            }
+        } else if merged {
+            // merge 后旧数据文件已被替换，需要从 hint 文件中更新 b+树索引
+            engine.load_bptree_index_from_hint_file()?;
         }
 
-        if engine.options.index_type == IndexType::BTree {
-            let (exists, seq_no) = engine.load_seq_no();
-            engine.seq_file_exists = exists;
+        // 重置 IO 类型，mmap 只用于启动时加载索引
+        engine.reset_io_type()?;
+
+        // b+树索引不从数据文件加载，事务序列号从 MANIFEST 中恢复；
+        // 没有正常关闭时 MANIFEST 中是检查点，从检查点之后的数据文件中恢复，没有记录事务序列号时扫描所有数据文件
+        if engine.options.index_type == IndexType::BPlusTree {
+            let (seq_no, clean_shutdown) = match (manifest.seq_no, manifest.checkpoint_fid) {
+                (Some(seq_no), None) => (seq_no, true),
+                // 检查点之后提交的事务只会出现在检查点时的活跃文件及之后的文件中
+                (Some(seq_no), Some(file_id)) => (engine.recover_seq_no(seq_no, file_id)?, false),
+                (None, _) => (engine.recover_seq_no(NON_TXN_SEQ_NO + 1, 0)?, false),
+            };
             engine.seq_no.store(seq_no, Ordering::SeqCst);
 
-            // 设置当前活跃文件的偏移
+            // 设置当前活跃文件的偏移，没有正常关闭时活跃文件末尾可能有没写完整的数据，以最后一条完整数据的结束位置为准
+            let mut active_file = engine.active_file.write();
+            let write_off = match clean_shutdown {
+                true => active_file.file_size(),
+                false => active_file.valid_size()?,
+            };
+            active_file.set_write_off(write_off);
+            let active_fid = active_file.get_file_id();
+            drop(active_file);
+
+            // MANIFEST 中改写为以当前活跃文件为起点的检查点，正常关闭时再写入最新的事务序列号；
+            // 之后没有正常关闭时，只需要扫描当前活跃文件及之后的文件恢复事务序列号
+            if !engine.options.read_only {
+                engine.update_manifest(seq_no, Some(active_fid))?;
+            }
+        }
+
+        // 活跃文件写偏移之后是没写完整的数据，截断这部分数据，否则之后追加写入的数据会落在这部分数据之后，和写偏移对不上
+        if !engine.options.read_only {
             let mut active_file = engine.active_file.write();
+            let write_off = active_file.get_write_off();
             let file_size = active_file.file_size();
-            active_file.set_write_off(file_size);
+            if file_size > write_off {
+                warn!(
+                    "truncate {} bytes of incomplete data at the end of data file {}",
+                    file_size - write_off,
+                    active_file.get_file_id()
+                );
+                active_file.truncate(write_off)?;
+            }
         }
 
+        // 加载完成后统计一次目录大小，之后 stat 直接返回缓存的值
+        engine.reconcile_disk_size();
+
         Ok(engine)
     }
 
+    /// 新建存储引擎，数据目录中已经有数据时返回 DatabaseAlreadyExists
+    /// 和 open 的区别只在于开启了 create_if_missing 和 error_if_exists，适合初始化新的数据库，
+    /// 避免误用已经存在的数据目录；打开已经存在的数据库使用 open
+    pub fn create(options: Options) -> Result<Self, Errors> {
+        let mut options = options;
+        options.create_if_missing = true;
+        options.error_if_exists = true;
+        Self::open(options)
+    }
+
     /// 关闭存储引擎，释放相关资源
+    /// 关闭之后读写数据、持久化、merge 等操作都返回 EngineClosed，迭代器和 list_keys 等遍历接口不返回任何数据；
+    /// 重复关闭不做处理，直接返回成功
     pub fn close(&self) -> Result<(), Errors> {
+        if self.closed.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
         // 如果数据目录不存在则返回
         if !self.options.dir_path.is_dir() {
             return Ok(());
         }
-        // 记录当前的事务序列号
-        let mut seq_no_file = DataFile::new_seq_no_file(self.options.dir_path.clone())?;
-        let seq_no = self.seq_no.load(Ordering::SeqCst);
-        let record = LogRecord {
-            key: SEQ_NO_KEY.as_bytes().to_vec(),
-            value: seq_no.to_string().into_bytes(),
-            rec_type: LogRecordType::NOAMAL,
-        };
-        seq_no_file.write(&record.encode())?;
-        seq_no_file.sync()?;
 
-        let read_guard = self.active_file.read();
-        read_guard.sync()?;
+        // 持久化数据，无论是否成功都需要释放文件锁，只读模式下不需要持久化
+        let mut res = Ok(());
+        if !self.options.read_only {
+            res = self.persist_on_close();
+        }
+
+        // 关闭索引，B+ 树索引会释放索引文件的锁，之后可以在同一进程中重新打开
+        self.index.close();
 
         // 释放文件锁
-        self.lock_file.unlock().unwrap();
+        if let Err(e) = self.lock_file.unlock() {
+            log::error!("failed to unlock database directory: {}", e);
+        }
+
+        res
+    }
 
+    // 已经关闭时返回 EngineClosed，此时文件锁已经释放，数据目录可能已经被其他实例打开
+    pub(crate) fn check_open(&self) -> Result<(), Errors> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(Errors::EngineClosed);
+        }
         Ok(())
     }
 
-    /// 持久化当前活跃文件
+    // 关闭时持久化当前活跃文件，并记录当前的事务序列号和累计写入量
+    fn persist_on_close(&self) -> Result<(), Errors> {
+        self.blobs.sync()?;
+        let read_guard = self.active_file.read();
+        read_guard.sync()?;
+
+        let seq_no = self.seq_no.load(Ordering::SeqCst);
+        self.update_manifest(seq_no, None)
+    }
+
+    // 在 MANIFEST 中记录事务序列号、检查点时活跃文件的 id 和累计写入量，其他元数据保持不变
+    fn update_manifest(&self, seq_no: usize, checkpoint_fid: Option<u32>) -> Result<(), Errors> {
+        let dir_path = self.options.dir_path.clone();
+        let mut manifest = Manifest::read(dir_path.clone(), false)?;
+        manifest.seq_no = Some(seq_no);
+        manifest.checkpoint_fid = checkpoint_fid;
+        manifest.total_bytes_written = self.total_bytes_written.load(Ordering::SeqCst);
+        manifest.total_records_written = self.total_records_written.load(Ordering::SeqCst);
+        manifest.write(dir_path, self.options.sync_dir_enabled())
+    }
+
+    /// 持久化当前活跃文件，数据文件引用的 blob 先于数据文件持久化
     pub fn sync(&self) -> Result<(), Errors> {
+        self.check_open()?;
+        self.blobs.sync()?;
         let read_guard = self.active_file.read();
-        read_guard.sync()
+        read_guard.sync()?;
+        self.bytes_write.store(0, Ordering::SeqCst);
+        self.sync_count.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// 检查点，持久化当前活跃文件，并将当前的事务序列号写入 MANIFEST，存储引擎保持打开
+    /// 没有正常关闭时，b+树索引只需要扫描检查点之后的数据文件恢复事务序列号
+    pub fn checkpoint(&self) -> Result<(), Errors> {
+        self.check_open()?;
+        if self.options.read_only {
+            return Err(Errors::ReadOnlyMode);
+        }
+
+        // 检查点期间不允许事务提交，保证序列号和活跃文件对应
+        let _batch_lock = self.batch_commit_lock.lock();
+        self.blobs.sync()?;
+        let active_file = self.active_file.read();
+        active_file.sync()?;
+        self.bytes_write.store(0, Ordering::SeqCst);
+
+        let seq_no = self.seq_no.load(Ordering::SeqCst);
+        self.update_manifest(seq_no, Some(active_file.get_file_id()))?;
+        self.reconcile_disk_size();
+        Ok(())
+    }
+
+    /// 当前的事务序列号，即下一个提交的事务将使用的序列号
+    pub fn current_seq_no(&self) -> usize {
+        self.seq_no.load(Ordering::SeqCst)
+    }
+
+    /// 返回所有数据文件的 id，按从小到大的顺序排列
+    pub fn data_file_ids(&self) -> Vec<u32> {
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        let mut file_ids = older_files.file_ids();
+        file_ids.push(active_file.get_file_id());
+        file_ids.sort();
+        file_ids
+    }
+
+    /// 当前活跃文件的写偏移，即已经写入活跃文件的字节数
+    pub fn active_file_offset(&self) -> u64 {
+        self.active_file.read().get_write_off()
+    }
+
+    /// 当前活跃文件达到 data_file_size 之前还可以写入的字节数
+    /// 写入的数据超过剩余空间时会切换到新的活跃文件
+    pub fn active_file_remaining(&self) -> u64 {
+        let write_off = self.active_file.read().get_write_off();
+        self.options.data_file_size.saturating_sub(write_off)
+    }
+
+    /// 内存索引占用内存的估计值，可以用于选择索引类型
+    /// B+ 树索引的数据保存在磁盘上，只返回很小的固定值
+    pub fn index_memory_usage(&self) -> usize {
+        self.index.memory_usage()
     }
 
     /// 获取统计信息
     pub fn stat(&self) -> Result<Stat, Errors> {
+        self.check_open()?;
         let keys = self.list_keys();
         let older_files = self.older_files.read();
+        let read_stat = |hits: &AtomicUsize| {
+            self.options
+                .collect_read_stats
+                .then(|| hits.load(Ordering::Relaxed))
+        };
+        let reclaim_size = self.reclaim_size.load(Ordering::SeqCst);
+        let disk_size = self.disk_size.load(Ordering::SeqCst);
         Ok(Stat {
             key_num: keys.len(),
             data_file_num: older_files.len() + 1,
-            reclaim_size: self.reclaim_size.load(Ordering::SeqCst),
-            disk_size: dir_disk_size(self.options.dir_path.clone()),
+            reclaim_size,
+            disk_size,
+            live_size: disk_size.saturating_sub(reclaim_size as u64),
+            data_files_size: self.data_files_size.load(Ordering::SeqCst),
+            active_file_hits: read_stat(&self.active_file_hits),
+            older_file_hits: read_stat(&self.older_file_hits),
+            total_bytes_written: self.total_bytes_written.load(Ordering::SeqCst),
+            total_records_written: self.total_records_written.load(Ordering::SeqCst),
         })
     }
 
+    /// 每个数据文件中可以回收的数据量，key 为数据文件 id，没有可以回收的数据的文件不包含在内
+    /// 使用 B+ 树索引时重新打开后不会重新统计，只包含打开之后的写入产生的无效数据
+    pub fn reclaim_by_file(&self) -> HashMap<u32, usize> {
+        self.reclaim_by_file.lock().clone()
+    }
+
+    /// 去掉可以回收的数据量之后占据的磁盘空间大小，和 disk_size 的差值就是 merge 大约能够释放的空间
+    pub fn live_size(&self) -> u64 {
+        let reclaim_size = self.reclaim_size.load(Ordering::SeqCst) as u64;
+        self.disk_size
+            .load(Ordering::SeqCst)
+            .saturating_sub(reclaim_size)
+    }
+
+    /// 重新统计数据目录占用的磁盘空间并更新 stat 中缓存的 disk_size 和 data_files_size，返回统计的目录大小
+    /// 追加写入数据文件时会直接累加缓存的大小，但 B+ 树索引文件、MANIFEST 等辅助文件的变化不会计入，
+    /// 需要准确的目录大小时可以定期调用；统计时需要遍历整个数据目录
+    pub fn reconcile_disk_size(&self) -> u64 {
+        let dir_path = self.options.dir_path.clone();
+        let data_files_size =
+            dir_disk_size_with_suffix(dir_path.clone(), &self.options.file_suffix);
+        self.data_files_size
+            .store(data_files_size, Ordering::SeqCst);
+        let size = dir_disk_size(dir_path);
+        self.disk_size.store(size, Ordering::SeqCst);
+        size
+    }
+
+    /// 清空存储引擎中的所有数据
+    /// 删除所有数据文件、hint 文件和 merge 完成标识文件，活跃文件重置为 id 为 initial_file_id 的空文件；
+    /// 事务序列号保持不变，保证清空前后的序列号不会重复
+    pub fn clear(&self) -> Result<(), Errors> {
+        self.check_open()?;
+        if self.options.read_only {
+            return Err(Errors::ReadOnlyMode);
+        }
+
+        // 清空期间不允许 merge 和事务提交
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProcess);
+        }
+        let _batch_lock = self.batch_commit_lock.lock();
+
+        // 删除所有 blob 文件，需要等待正在读取 blob 的线程，这些线程会获取数据文件的锁，因此不能在持有数据文件的锁时删除
+        self.blobs.clear()?;
+
+        // 和 sync_to 保持相同的加锁顺序
+        let mut synced_pos = self.synced_pos.lock();
+        let mut active_file = self.active_file.write();
+        let mut older_files = self.older_files.write();
+
+        // 删除所有数据文件
+        older_files.clear();
+        let dir = match read_dir(self.options.dir_path.clone()) {
+            Ok(dir) => dir,
+            Err(e) => {
+                warn!("failed to read database dir: {}", e);
+                return Err(Errors::FailedOpenDatabaseDir);
+            }
+        };
+        for entry in dir.flatten() {
+            let os_string = entry.file_name();
+            let file_name = os_string.to_str().unwrap();
+            if file_name.ends_with(&self.options.file_suffix)
+                || file_name == HINT_FILE_NAME
+                || file_name == MERGE_FINISHED_FILE_NAME
+            {
+                if let Err(e) = remove_file(entry.path()) {
+                    warn!("failed to remove file {}: {}", file_name, e);
+                    return Err(Errors::FailedToRemoveFile);
+                }
+            }
+        }
+
+        // 重置活跃文件
+        *active_file = self.open_data_file(self.options.initial_file_id, IOType::FileIO)?;
+
+        // 清空内存索引和统计信息
+        self.index.clear();
+        self.reclaim_size.store(0, Ordering::SeqCst);
+        self.reclaim_by_file.lock().clear();
+        self.bytes_write.store(0, Ordering::SeqCst);
+        *synced_pos = (0, 0);
+        self.reconcile_disk_size();
+
+        Ok(())
+    }
+
+    /// 预热数据文件，从新到旧依次读取所有数据文件和 b+树索引文件，使其进入操作系统的页缓存
+    /// 在打开之后、开始处理请求之前调用，可以减少刚启动时读取的延迟；不修改任何数据，只读模式下也可以调用
+    pub fn warmup(&self) -> Result<(), Errors> {
+        self.check_open()?;
+        let mut file_ids = self.older_files.read().file_ids();
+        file_ids.push(self.active_file.read().get_file_id());
+
+        let dir_path = self.options.dir_path.clone();
+        let mut file_paths: Vec<PathBuf> = file_ids
+            .iter()
+            .rev()
+            .map(|file_id| {
+                get_data_file_path(dir_path.clone(), *file_id, &self.options.file_suffix)
+            })
+            .collect();
+        file_paths.push(dir_path.join(BPTREE_INDEX_FILE_NAME));
+
+        // 直接读取文件，不经过 older_files，避免挤出已经打开的数据文件
+        let mut buf = vec![0u8; WARMUP_READ_SIZE];
+        for file_path in file_paths {
+            // 数据文件可能在读取之前被 merge 删除，没有使用 b+树索引时索引文件也不存在
+            let mut file = match File::open(&file_path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while file.read(&mut buf)? > 0 {}
+        }
+        Ok(())
+    }
+
     /// 备份数据目录
     pub fn backup(&self, dir_path: PathBuf) -> Result<(), Errors> {
-        let exclude = [FILE_LOCK_NAME];
+        self.check_open()?;
+        // 写缓冲区中的数据先写入文件，备份中包含已经写入的所有数据
+        self.active_file.read().flush()?;
+        let exclude = [self.options.lock_file_name.as_str(), MANIFEST_TMP_FILE_NAME];
         if let Err(e) = copy_dir(self.options.dir_path.clone(), dir_path, &exclude) {
             log::error!("failed to copy dir: {}", e);
             return Err(Errors::FailedToCopyDir);
@@ -231,11 +617,31 @@ impl Engine {
         Ok(())
     }
 
+    /// 将 HashMap 中的所有数据写入存储引擎，已经存在的 key 会被覆盖，可以和 to_map 配合使用
+    pub fn import_from_map(&self, map: &HashMap<Bytes, Bytes>) -> Result<(), Errors> {
+        self.check_open()?;
+        for (key, value) in map.iter() {
+            self.put_slice(key, value)?;
+        }
+        Ok(())
+    }
+
     /// 存储 key/value 数据，key 不能为空
     pub fn put(&self, key: Bytes, value: Bytes) -> Result<(), Errors> {
+        self.put_slice(&key, &value)
+    }
+
+    /// 存储 key/value 数据，直接使用字节切片，不需要先转换为 Bytes
+    pub fn put_slice(&self, key: &[u8], value: &[u8]) -> Result<(), Errors> {
+        self.check_open()?;
+        let key = self.normalize_key(key);
+        let key = key.as_ref();
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
+        if self.options.read_only {
+            return Err(Errors::ReadOnlyMode);
+        }
 
         // 构造 LogRecord 并写入当前活跃文件
         let log_record = LogRecord {
@@ -243,12 +649,15 @@ impl Engine {
             value: value.to_vec(),
             rec_type: LogRecordType::NOAMAL,
         };
+
+        // 同一个 key 的写入和索引更新需要保持顺序，保证索引指向的是最新的数据
+        let _key_lock = self.key_lock(key).lock();
+        let (index_key, _) = self.resolve_index_key(key.to_vec(), &|pos| self.read_key_at(pos))?;
         let log_record_pos = self.append_log_record(log_record)?;
 
         // 更新内存索引
-        if let Some(old_pos) = self.index.put(key.to_vec(), log_record_pos) {
-            self.reclaim_size
-                .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        if let Some(old_pos) = self.index.put(index_key, log_record_pos) {
+            self.add_reclaim(&old_pos);
         }
 
         Ok(())
@@ -256,6 +665,8 @@ impl Engine {
 
     /// 根据 key 获取数据
     pub fn get(&self, key: Bytes) -> Result<Bytes, Errors> {
+        self.check_open()?;
+        let key = self.normalize_key(&key);
         if key.is_empty() {
             return Err(Errors::KeyIsEmpty);
         }
@@ -266,37 +677,323 @@ impl Engine {
         self.get_value_by_position(log_record_pos)
     }
 
+    /// 根据 key 获取数据，key 不存在时返回 None，value 为空时返回 Some(空的 Bytes)
+    /// 和 get 不同，不需要匹配 KeyIsNotFound 判断 key 是否存在，其他错误仍然返回 Err
+    pub fn get_opt(&self, key: Bytes) -> Result<Option<Bytes>, Errors> {
+        match self.get(key) {
+            Ok(value) => Ok(Some(value)),
+            Err(Errors::KeyIsNotFound) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// 返回 key 当前所在的位置（文件 id，偏移，日志记录的字节数），key 不存在或为空时返回 None
+    /// 可用于调试和构建外部的二级索引；位置只在下次写入该 key 之前有效，
+    /// 覆盖写入、删除和 merge 都会使之前返回的位置失效
+    pub fn locate(&self, key: &[u8]) -> Option<(u32, u64, u32)> {
+        let key = self.normalize_key(key);
+        if key.is_empty() {
+            return None;
+        }
+        let pos = self.get_log_record_pos(&key).ok()?;
+        Some((pos.file_id, pos.offset, pos.size))
+    }
+
+    /// 根据 key 获取数据，并将 value 切片交给回调函数处理，不会额外分配 Bytes
+    /// value 切片只在回调函数执行期间有效，需要保留时请在回调中自行拷贝；
+    /// 回调执行期间持有数据文件的读锁，不能在回调中对存储引擎进行写操作
+    pub fn get_with<R, F>(&self, key: Bytes, f: F) -> Result<R, Errors>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        self.check_open()?;
+        let key = self.normalize_key(&key);
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let log_record_pos = self.get_log_record_pos(&key)?;
+
+        // 从数据文件中读取 value 并交给回调函数处理
+        self.get_value_with_position(log_record_pos, f)
+    }
+
+    /// 根据 key 获取数据，将 value 写入 buf，buf 原有的内容会被清空
+    /// 可以在多次读取之间复用同一个 buf，避免每次读取都分配新的内存
+    pub fn get_into(&self, key: &[u8], buf: &mut Vec<u8>) -> Result<(), Errors> {
+        self.check_open()?;
+        let key = self.normalize_key(key);
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let log_record_pos = self.get_log_record_pos(&key)?;
+
+        self.get_value_with_position(log_record_pos, |value| {
+            buf.clear();
+            buf.extend_from_slice(value);
+        })
+    }
+
+    /// 获取 key 当前版本的标识，key 不存在时返回 None
+    /// 版本由 key 在数据文件中的位置生成（高 32 位为文件 id，低 32 位为偏移），
+    /// 每次写入都会追加到新的位置，因此覆盖写入或删除后重新写入都会得到不同的版本；
+    /// merge 会移动数据的位置，版本也会随之改变，适合用于缓存失效判断
+    pub fn key_version(&self, key: &[u8]) -> Option<u64> {
+        let key = self.normalize_key(key);
+        if key.is_empty() {
+            return None;
+        }
+        let pos = self.get_log_record_pos(&key).ok()?;
+        Some((pos.file_id as u64) << 32 | (pos.offset & u32::MAX as u64))
+    }
+
     // 根据 LogRecord 位置信息读取相应的 value
     pub(crate) fn get_value_by_position(&self, pos: LogRecordPos) -> Result<Bytes, Errors> {
+        self.get_value_with_position(pos, Bytes::copy_from_slice)
+    }
+
+    // 根据 LogRecord 位置信息读取相应的 value，并交给回调函数处理
+    pub(crate) fn get_value_with_position<R, F>(&self, pos: LogRecordPos, f: F) -> Result<R, Errors>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        // 直接保存在记录中的 value 在数据文件的锁内交给回调函数处理，
+        // 保存在其他位置的 value 先取出位置，释放数据文件的锁之后再读取
+        let _blob_guard = self.blobs.read_guard();
+        let mut f = Some(f);
+        let read_res = self.read_with_position(pos, |data_file| {
+            data_file.read_value_with(pos.offset, |rec_type, value| {
+                Ok(match ValueSource::of(rec_type, value)? {
+                    ValueSource::Inline => Ok(f.take().unwrap()(value)),
+                    source => Err(source),
+                })
+            })?
+        });
+        // 索引中的位置超出了数据文件的末尾，说明索引和数据文件不一致，例如数据文件在外部被截断
+        let source = match read_res {
+            Err(Errors::ReadDataFileEOF) => return Err(Errors::StaleIndexEntry),
+            res => res?,
+        };
+        match source {
+            Ok(r) => Ok(r),
+            Err(source) => self.resolve_value_with(source, &[], f.take().unwrap()),
+        }
+    }
+
+    // 根据记录的类型解析出实际的 value，被删除的数据返回 KeyIsNotFound
+    pub(crate) fn resolve_value(&self, record: &LogRecord) -> Result<Bytes, Errors> {
+        let source = ValueSource::of(record.rec_type, &record.value)?;
+        self.resolve_value_with(source, &record.value, Bytes::copy_from_slice)
+    }
+
+    // 从 value 实际保存的位置读取，并交给回调函数处理，value 为记录中保存的内容
+    fn resolve_value_with<R, F>(&self, source: ValueSource, value: &[u8], f: F) -> Result<R, Errors>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        match source {
+            ValueSource::Inline => Ok(f(value)),
+            ValueSource::Blob(blob_pos) => self.blobs.read_value_with(&blob_pos, f),
+        }
+    }
+
+    // 找到 LogRecord 位置信息对应的数据文件，交给回调函数读取
+    fn read_with_position<R, F>(&self, pos: LogRecordPos, f: F) -> Result<R, Errors>
+    where
+        F: FnOnce(&DataFile) -> Result<R, Errors>,
+    {
         let active_file = self.active_file.read();
-        let older_files = self.older_files.read();
         let file_id = pos.file_id;
-        let log_record = match file_id == active_file.get_file_id() {
-            true => active_file.read(pos.offset)?.record,
-            false => {
-                let data_file = older_files.get(&file_id);
-                if data_file.is_none() {
-                    return Err(Errors::DataFileIsNotFound);
+        let in_active_file = file_id == active_file.get_file_id();
+        if self.options.collect_read_stats {
+            let hits = match in_active_file {
+                true => &self.active_file_hits,
+                false => &self.older_file_hits,
+            };
+            hits.fetch_add(1, Ordering::Relaxed);
+        }
+        if in_active_file {
+            return f(&active_file);
+        }
+
+        // 拿到旧数据文件的引用后即可释放锁，读取期间文件被移出旧文件列表（淘汰、merge 后替换）
+        // 也不会被关闭，即使磁盘上的文件已经被删除，仍然可以通过打开的文件读取
+        let data_file = self.get_older_file(file_id);
+        drop(active_file);
+        let data_file = data_file?;
+        f(&data_file)
+    }
+
+    // 从旧文件列表中获取数据文件
+    fn get_older_file(&self, file_id: u32) -> Result<Arc<DataFile>, Errors> {
+        match self.older_files.read().get(file_id)? {
+            Some(data_file) => Ok(data_file),
+            // 数据文件缺失时，根据配置项决定是否跳过该 key
+            None if self.options.skip_missing_data_file => {
+                warn!("data file {} is not found, skip the key", file_id);
+                Err(Errors::KeyIsNotFound)
+            }
+            None => Err(Errors::DataFileIsNotFound),
+        }
+    }
+
+    /// 获取流式读取 key 对应 value 的 Reader，不会将整个 value 加载到内存中，适合很大的 value
+    /// 例如可以通过 io::copy 直接写入到网络连接中；读到 value 末尾时校验整条记录的 CRC，失败时返回 InvalidData
+    /// Reader 固定从创建时 key 所在的文件位置读取，之后的覆盖写入不会体现在读取结果中；
+    /// merge 之后重新打开存储引擎会替换旧的数据文件，之前创建的 Reader 不应继续使用
+    pub fn get_reader(&self, key: &[u8]) -> Result<impl std::io::Read, Errors> {
+        self.check_open()?;
+        let key = self.normalize_key(key);
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let pos = self.get_log_record_pos(&key)?;
+        let _blob_guard = self.blobs.read_guard();
+        let data_file = {
+            let active_file = self.active_file.read();
+            match pos.file_id == active_file.get_file_id() {
+                // 活跃文件在锁外仍然会追加写入，先写入缓冲区中的数据，再单独打开一个只用于读取的文件
+                true => {
+                    active_file.flush()?;
+                    Arc::new(self.open_data_file(pos.file_id, IOType::FileIO)?)
+                }
+                false => {
+                    drop(active_file);
+                    self.get_older_file(pos.file_id)?
                 }
-                data_file.unwrap().read(pos.offset)?.record
             }
         };
 
-        match log_record.rec_type {
+        let mut header = data_file.read_header_only(pos.offset)?;
+        let (data_file, offset) = match header.rec_type {
+            LogRecordType::DELETED => return Err(Errors::KeyIsNotFound),
+            // value 保存在 blob 文件中，改为从 blob 文件中流式读取
+            LogRecordType::BLOBPOINTER => {
+                let blob_pos = data_file
+                    .read_value_with(pos.offset, |_, value| codec::decode_pos_v1(value))?;
+                let blob_file = self.blobs.get_file(blob_pos.file_id)?;
+                header = blob_file.read_header_only(blob_pos.offset)?;
+                (blob_file, blob_pos.offset)
+            }
+            _ => (data_file, pos.offset),
+        };
+
+        // header 和 key 部分很小，先读出来计算 CRC，value 部分在读取时继续计算
+        let mut prefix = vec![0u8; header.header_size + header.key_size];
+        if data_file.read_at(&mut prefix, offset)? < prefix.len() {
+            return Err(Errors::ReadDataFileEOF);
+        }
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(&prefix);
+
+        let value_offset = offset + prefix.len() as u64;
+        Ok(ValueReader::new(
+            data_file,
+            value_offset,
+            header.value_size,
+            hasher,
+        ))
+    }
+
+    /// 获取 key 对应的 value 的长度，只读取数据的 header 部分，不读取 value
+    /// 只需要 value 长度时（例如设置 content-length）比 get 开销更小；没有读取整条数据，不会校验 CRC
+    pub fn value_len(&self, key: &[u8]) -> Result<usize, Errors> {
+        self.check_open()?;
+        let key = self.normalize_key(key);
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+
+        let pos = self.get_log_record_pos(&key)?;
+        let _blob_guard = self.blobs.read_guard();
+        let header =
+            self.read_with_position(pos, |data_file| data_file.read_header_only(pos.offset))?;
+        match header.rec_type {
             LogRecordType::DELETED => Err(Errors::KeyIsNotFound),
-            _ => Ok(log_record.value.into()),
+            // value 保存在 blob 文件中，读取 blob 位置之后再读取 blob 的 header
+            LogRecordType::BLOBPOINTER => {
+                let blob_pos = self.read_with_position(pos, |data_file| {
+                    data_file.read_value_with(pos.offset, |_, value| codec::decode_pos_v1(value))
+                })?;
+                let blob_header = self.blobs.read_with(blob_pos.file_id, |blob_file| {
+                    blob_file.read_header_only(blob_pos.offset)
+                })?;
+                Ok(blob_header.value_size)
+            }
+            _ => Ok(header.value_size),
         }
     }
 
+    /// 修复内存索引，移除数据文件已不存在的 key，返回移除的 key 数量
+    pub fn repair_index(&self) -> Result<usize, Errors> {
+        self.check_open()?;
+        let active_file = self.active_file.read();
+        let mut older_files = self.older_files.write();
+
+        // 找出磁盘上已经不存在的旧数据文件，并从旧文件列表中移除
+        let missing_fids: Vec<u32> = older_files
+            .file_ids()
+            .into_iter()
+            .filter(|fid| {
+                !get_data_file_path(
+                    self.options.dir_path.clone(),
+                    *fid,
+                    &self.options.file_suffix,
+                )
+                .is_file()
+            })
+            .collect();
+        for fid in missing_fids.iter() {
+            warn!("data file {} is missing, remove it from older files", fid);
+            older_files.remove(*fid);
+        }
+
+        // 找出索引指向缺失数据文件的 key
+        let mut dangling_keys = Vec::new();
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        while let Some((key, pos)) = index_iter.next() {
+            if pos.file_id != active_file.get_file_id() && !older_files.contains(pos.file_id) {
+                dangling_keys.push(key.clone());
+            }
+        }
+
+        // 从内存索引中删除这些 key
+        for key in dangling_keys.iter() {
+            self.index.delete(key.clone());
+        }
+
+        Ok(dangling_keys.len())
+    }
+
     /// 根据 key 删除数据
     pub fn delete(&self, key: Bytes) -> Result<(), Errors> {
+        self.check_open()?;
         if key.is_empty() {
             return Ok(());
         }
+        self.delete_existing(key).map(|_| ())
+    }
 
-        let res = self.get_log_record_pos(&key);
-        if res.is_err() {
-            return Ok(());
+    /// 根据 key 删除数据，返回是否写入了删除标记
+    /// 和 delete 不同，key 为空时返回 KeyIsEmpty，key 不存在时返回 false
+    pub fn delete_existing(&self, key: Bytes) -> Result<bool, Errors> {
+        self.check_open()?;
+        let key = self.normalize_key(&key);
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        if self.options.read_only {
+            return Err(Errors::ReadOnlyMode);
+        }
+
+        // 查找索引、写入删除标记和删除索引期间持有 key 所在分段的锁，
+        // 同一个 key 的多次删除只有第一次能在索引中找到位置，每个存在的 key 最多写入一个删除标记
+        let _key_lock = self.key_lock(&key).lock();
+        let (index_key, pos) = self.lookup_index(&key, &|pos| self.read_key_at(pos))?;
+        if pos.is_none() {
+            return Ok(false);
         }
 
         // 构造 LogRecord，标识为删除值并写入当前活跃文件
@@ -306,59 +1003,425 @@ impl Engine {
             rec_type: LogRecordType::DELETED,
         };
         let pos = self.append_log_record(log_record)?;
-        self.reclaim_size
-            .fetch_add(pos.size as usize, Ordering::SeqCst);
+        self.add_reclaim(&pos);
+
+        // 更新内存索引
+        if let Some(old_pos) = self.index.delete(index_key) {
+            self.add_reclaim(&old_pos);
+        }
+
+        Ok(true)
+    }
+
+    /// 将 key 的 value 作为小端序的 i64 加上 delta 并写回，返回加之后的值
+    /// key 不存在时从 0 开始，value 不是 8 字节的数字时返回 NotANumber；溢出时按补码回绕
+    pub fn increment(&self, key: Bytes, delta: i64) -> Result<i64, Errors> {
+        self.check_open()?;
+        let key = self.normalize_key(&key);
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        if self.options.read_only {
+            return Err(Errors::ReadOnlyMode);
+        }
+
+        // 读取、计算和写回期间持有 key 所在分段的锁，保证并发的增加操作不会丢失
+        let _key_lock = self.key_lock(&key).lock();
+        let (index_key, pos) = self.lookup_index(&key, &|pos| self.read_key_at(pos))?;
+        let current = match pos {
+            Some(pos) => {
+                let value = self.get_value_by_position(pos)?;
+                let bytes: [u8; 8] = value[..].try_into().map_err(|_| Errors::NotANumber)?;
+                i64::from_le_bytes(bytes)
+            }
+            None => 0,
+        };
+        let new_value = current.wrapping_add(delta);
+
+        let log_record = LogRecord {
+            key: log_record_key_with_seq(key.to_vec(), NON_TXN_SEQ_NO),
+            value: new_value.to_le_bytes().to_vec(),
+            rec_type: LogRecordType::NOAMAL,
+        };
+        let log_record_pos = self.append_log_record(log_record)?;
 
         // 更新内存索引
-        if let Some(old_pos) = self.index.delete(key.to_vec()) {
-            self.reclaim_size
-                .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+        if let Some(old_pos) = self.index.put(index_key, log_record_pos) {
+            self.add_reclaim(&old_pos);
+        }
+
+        Ok(new_value)
+    }
+
+    /// 将 key 当前的 value 重新写入活跃文件并更新索引，key 在旧数据文件中的数据都变为无效数据，下次 merge 时会被清理
+    /// 适合频繁覆盖写入的热点 key，不需要对全部数据进行 merge；key 当前的数据已经在活跃文件中时不做处理
+    pub fn compact_key(&self, key: &[u8]) -> Result<(), Errors> {
+        self.check_open()?;
+        let key = self.normalize_key(key);
+        if key.is_empty() {
+            return Err(Errors::KeyIsEmpty);
+        }
+        if self.options.read_only {
+            return Err(Errors::ReadOnlyMode);
+        }
+        self.rewrite_to_active_file(&key).map(|_| ())
+    }
+
+    /// 将以 prefix 开头的所有 key 当前的 value 重新写入活跃文件，返回重新写入的 key 数量
+    /// 这些 key 在旧数据文件中的数据（包括被删除的数据）都变为无效数据，下次 merge 时会被清理；
+    /// 删除整个前缀下的数据之后不会有需要重新写入的 key，旧数据文件中的数据在删除时已经计入可回收的数据量
+    pub fn compact_prefix(&self, prefix: &[u8]) -> Result<usize, Errors> {
+        self.check_open()?;
+        if self.options.read_only {
+            return Err(Errors::ReadOnlyMode);
+        }
+
+        // 先取出所有 key，重新写入时不持有索引迭代器
+        let iter = self.iter(IteratorOptions::new().prefix(prefix));
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_key_with(|key| key.to_vec()) {
+            keys.push(key);
+        }
+        drop(iter);
+
+        let mut rewritten = 0;
+        for key in keys.iter() {
+            match self.rewrite_to_active_file(key) {
+                Ok(true) => rewritten += 1,
+                Ok(false) => {}
+                // 取出 key 之后被其他线程删除
+                Err(Errors::KeyIsNotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(rewritten)
+    }
+
+    /// 回收 blob 文件中的无效数据，返回删除的 blob 文件数量
+    /// merge 只重写数据文件，覆盖写入和删除之后旧的 value 仍然占据 blob 文件；GC 将已经写满的 blob 文件中
+    /// 仍然被引用的 value 重新写入当前的 blob 文件，在活跃文件中写入新的 blob 位置并更新索引，之后删除这些 blob 文件
+    pub fn gc_blobs(&self) -> Result<usize, Errors> {
+        self.check_open()?;
+        if self.options.read_only {
+            return Err(Errors::ReadOnlyMode);
+        }
+
+        // GC 期间不允许 merge 和清空
+        let lock = self.merging_lock.try_lock();
+        if lock.is_none() {
+            return Err(Errors::MergeInProcess);
+        }
+
+        let file_ids = self.blobs.sealed_file_ids();
+        for file_id in file_ids.iter() {
+            let blob_file = self.blobs.get_file(*file_id)?;
+            for item in blob_file.iter_records() {
+                let (record, offset) = item?;
+                let (key, _) = parse_log_record_key(record.key)?;
+                self.rewrite_blob(&key, *file_id, offset, &record.value)?;
+            }
+        }
+
+        // 新的 blob 和 blob 位置都持久化之后才能删除旧的 blob 文件
+        self.sync()?;
+        for file_id in file_ids.iter() {
+            self.blobs.remove_file(*file_id)?;
+        }
+        self.reconcile_disk_size();
+
+        Ok(file_ids.len())
+    }
+
+    // blob 文件中的 value 仍然被 key 当前的数据引用时，重新写入 blob 文件并更新索引
+    fn rewrite_blob(
+        &self,
+        key: &[u8],
+        file_id: u32,
+        offset: u64,
+        value: &[u8],
+    ) -> Result<(), Errors> {
+        let _key_lock = self.key_lock(key).lock();
+        let (index_key, pos) = self.lookup_index(key, &|pos| self.read_key_at(pos))?;
+        let pos = match pos {
+            Some(pos) => pos,
+            None => return Ok(()),
+        };
+        let record = self.read_record_at(&pos)?;
+        if record.rec_type != LogRecordType::BLOBPOINTER {
+            return Ok(());
+        }
+        let blob_pos = codec::decode_pos_v1(&record.value);
+        if (blob_pos.file_id, blob_pos.offset) != (file_id, offset) {
+            return Ok(());
+        }
+
+        let key = log_record_key_with_seq(key.to_vec(), NON_TXN_SEQ_NO);
+        let new_blob_pos = self.write_blob(&key, value)?;
+        let log_record = LogRecord {
+            key,
+            value: new_blob_pos.encode(),
+            rec_type: LogRecordType::BLOBPOINTER,
+        };
+        let log_record_pos = self.append_log_record(log_record)?;
+
+        // 更新内存索引
+        if let Some(old_pos) = self.index.put(index_key, log_record_pos) {
+            self.add_reclaim(&old_pos);
         }
 
         Ok(())
     }
 
+    // 将 key 当前的数据重新写入活跃文件，返回是否重新写入，已经在活跃文件中时不做处理
+    fn rewrite_to_active_file(&self, key: &[u8]) -> Result<bool, Errors> {
+        let _key_lock = self.key_lock(key).lock();
+        let (index_key, pos) = self.lookup_index(key, &|pos| self.read_key_at(pos))?;
+        let pos = pos.ok_or(Errors::KeyIsNotFound)?;
+        if pos.file_id == self.active_file.read().get_file_id() {
+            return Ok(false);
+        }
+
+        // 读取当前的数据，重新写入活跃文件，value 保存在 blob 文件中时只重新写入 blob 位置
+        let record = self.read_record_at(&pos)?;
+        let log_record = LogRecord {
+            key: log_record_key_with_seq(record.key, NON_TXN_SEQ_NO),
+            value: record.value,
+            rec_type: record.rec_type,
+        };
+        let log_record_pos = self.append_log_record(log_record)?;
+
+        // 更新内存索引
+        if let Some(old_pos) = self.index.put(index_key, log_record_pos) {
+            self.add_reclaim(&old_pos);
+        }
+
+        Ok(true)
+    }
+
+    // 获取 key 所在分段的锁
+    fn key_lock(&self, key: &[u8]) -> &Mutex<()> {
+        &self.key_locks[self.key_lock_index(key)]
+    }
+
+    // 按分段下标的顺序获取多个 key 所在分段的锁，避免死锁
+    pub(crate) fn lock_keys<'a, I>(&self, keys: I) -> Vec<MutexGuard<'_, ()>>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let mut indexes: Vec<usize> = keys.into_iter().map(|k| self.key_lock_index(k)).collect();
+        indexes.sort();
+        indexes.dedup();
+        indexes.iter().map(|i| self.key_locks[*i].lock()).collect()
+    }
+
+    // 使用 key_normalizer 转换 key，没有配置时直接借用原来的 key
+    pub(crate) fn normalize_key<'a>(&self, key: &'a [u8]) -> Cow<'a, [u8]> {
+        match self.options.key_normalizer.as_ref() {
+            Some(key_normalizer) => Cow::Owned(key_normalizer(key)),
+            None => Cow::Borrowed(key),
+        }
+    }
+
+    // key 所在分段的下标，配置了 shard_fn 时由其决定，否则使用 key 的 hash，
+    // 开启 hash_keys_in_index 时 hash 相同的 key 使用同一个分段
+    pub(crate) fn key_lock_index(&self, key: &[u8]) -> usize {
+        if let Some(shard_fn) = self.options.shard_fn.as_ref() {
+            return shard_fn(key) % KEY_LOCK_STRIPES;
+        }
+        u128::from_be_bytes((self.key_hasher)(key)) as usize % KEY_LOCK_STRIPES
+    }
+
     // 从内存索引中获取数据位置信息
-    fn get_log_record_pos(&self, key: &Bytes) -> Result<LogRecordPos, Errors> {
-        let pos = self.index.get(key.to_vec());
-        if pos.is_none() {
-            return Err(Errors::KeyIsNotFound);
+    pub(crate) fn get_log_record_pos(&self, key: &[u8]) -> Result<LogRecordPos, Errors> {
+        let (_, pos) = self.lookup_index(key, &|pos| self.read_key_at(pos))?;
+        pos.ok_or(Errors::KeyIsNotFound)
+    }
+
+    // 在索引中查找 key，返回索引中使用的 key 和当前的位置信息
+    // 开启 hash_keys_in_index 时，索引 key 为 key 的 16 字节 hash，
+    // hash 对应的位置被其他 key 占用（hash 冲突）时，使用 hash + 完整 key 作为索引 key；
+    // 通过 read_key 读取候选位置上数据的完整 key 进行比较，从而区分冲突的 key
+    // key 不存在时返回写入时应该使用的索引 key
+    pub(crate) fn lookup_index(
+        &self,
+        key: &[u8],
+        read_key: &dyn Fn(&LogRecordPos) -> Result<Vec<u8>, Errors>,
+    ) -> Result<(Vec<u8>, Option<LogRecordPos>), Errors> {
+        self.check_open()?;
+        if !self.options.hash_keys_in_index {
+            return Ok((key.to_vec(), self.index.get(key.to_vec())));
         }
-        Ok(pos.unwrap())
+
+        let hash_key = (self.key_hasher)(key).to_vec();
+        let mut collision_key = hash_key.clone();
+        collision_key.extend_from_slice(key);
+
+        if let Some(pos) = self.index.get(hash_key.clone()) {
+            if read_key(&pos)? == key {
+                return Ok((hash_key, Some(pos)));
+            }
+            // hash 冲突，该 key 只能保存在 hash + 完整 key 的位置
+            let pos = self.index.get(collision_key.clone());
+            return Ok((collision_key, pos));
+        }
+
+        // hash 位置空闲，但该 key 之前可能因为冲突保存在 hash + 完整 key 的位置
+        match self.index.get(collision_key.clone()) {
+            Some(pos) => Ok((collision_key, Some(pos))),
+            None => Ok((hash_key, None)),
+        }
+    }
+
+    // 获取写入或删除 key 时索引中使用的 key，以及该 key 是否可能存在于索引中
+    // 未开启 hash_keys_in_index 时直接使用 key，不需要查找索引
+    pub(crate) fn resolve_index_key(
+        &self,
+        key: Vec<u8>,
+        read_key: &dyn Fn(&LogRecordPos) -> Result<Vec<u8>, Errors>,
+    ) -> Result<(Vec<u8>, bool), Errors> {
+        if !self.options.hash_keys_in_index {
+            return Ok((key, true));
+        }
+        let (index_key, pos) = self.lookup_index(&key, read_key)?;
+        Ok((index_key, pos.is_some()))
+    }
+
+    // 读取位置信息对应的数据的完整 key
+    pub(crate) fn read_key_at(&self, pos: &LogRecordPos) -> Result<Vec<u8>, Errors> {
+        Ok(self.read_record_at(pos)?.key)
+    }
+
+    // 读取位置信息对应的数据，返回的 LogRecord 中 key 为实际的 key
+    pub(crate) fn read_record_at(&self, pos: &LogRecordPos) -> Result<LogRecord, Errors> {
+        Ok(self.read_record_with_seq_at(pos)?.0)
+    }
+
+    // 读取位置信息对应的数据和写入时的事务序列号，返回的 LogRecord 中 key 为实际的 key
+    pub(crate) fn read_record_with_seq_at(
+        &self,
+        pos: &LogRecordPos,
+    ) -> Result<(LogRecord, usize), Errors> {
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
+        read_record(&active_file, &older_files, pos)
+    }
+
+    // 读取位置信息对应的数据的完整 key 和 value，value 保存在 blob 文件中时从 blob 文件中读取
+    pub(crate) fn read_key_value_at(
+        &self,
+        pos: &LogRecordPos,
+    ) -> Result<(Vec<u8>, Vec<u8>), Errors> {
+        let _blob_guard = self.blobs.read_guard();
+        let record = self.read_record_at(pos)?;
+        let source = ValueSource::of(record.rec_type, &record.value)?;
+        if let ValueSource::Inline = source {
+            return Ok((record.key, record.value));
+        }
+        let value = self.resolve_value_with(source, &record.value, |value| value.to_vec())?;
+        Ok((record.key, value))
+    }
+
+    // 读取位置信息对应的数据的完整 key、value 和写入时的事务序列号
+    pub(crate) fn read_key_value_seq_at(
+        &self,
+        pos: &LogRecordPos,
+    ) -> Result<(Vec<u8>, Bytes, usize), Errors> {
+        let _blob_guard = self.blobs.read_guard();
+        let (record, seq_no) = self.read_record_with_seq_at(pos)?;
+        let value = self.resolve_value(&record)?;
+        Ok((record.key, value, seq_no))
+    }
+
+    // 打开数据目录中指定 id 的数据文件，使用配置项中的文件后缀
+    pub(crate) fn open_data_file(&self, file_id: u32, io_type: IOType) -> Result<DataFile, Errors> {
+        open_data_file(&self.options, file_id, io_type)
+    }
+
+    // 持久化当前活跃文件并加入旧文件列表，打开下一个 id 的文件作为新的活跃文件，返回封存的文件 id 和新的文件 id
+    // 写入和 merge 都在持有活跃文件写锁时切换，同一个 id 只会被封存一次；
+    // 封存的文件记录实际写入的大小，新文件的 id 已经在旧文件列表中时返回错误，不会覆盖已有的文件
+    pub(crate) fn rotate_active_file(
+        &self,
+        active_file: &mut DataFile,
+    ) -> Result<(u32, u32), Errors> {
+        let current_fid = active_file.get_file_id();
+        let new_fid = current_fid + 1;
+        {
+            let older_files = self.older_files.read();
+            if older_files.contains(current_fid) || older_files.contains(new_fid) {
+                return Err(Errors::DataFileIdInUse);
+            }
+        }
+
+        // 旧的活跃文件之后不会再 sync，其中的数据引用的 blob 需要一起持久化
+        self.blobs.sync()?;
+        active_file.sync()?;
+
+        let mut older_files = self.older_files.write();
+        let mut old_file = self.open_data_file(current_fid, IOType::FileIO)?;
+        old_file.set_write_off(active_file.get_write_off());
+        let new_file = self.open_data_file(new_fid, IOType::FileIO)?;
+        older_files.insert(old_file);
+        *active_file = new_file;
+        drop(older_files);
+
+        // 持久化数据目录，保证崩溃后新建的活跃文件仍然存在
+        if self.options.sync_dir_enabled() {
+            sync_dir(&self.options.dir_path)?;
+        }
+        Ok((current_fid, new_fid))
     }
 
     // 追加写入数据到当前活跃文件中
     pub(crate) fn append_log_record(&self, log_record: LogRecord) -> Result<LogRecordPos, Errors> {
-        let dir_path = self.options.dir_path.clone();
+        self.check_open()?;
+
+        // value 超过阈值时先写入 blob 文件，数据文件中只保存 blob 位置
+        let log_record = self.separate_value(log_record)?;
 
         // 编码写入数据
         let enc_record = log_record.encode();
         let record_len = enc_record.len() as u64;
 
+        // 超过单条记录的大小上限时，在写入和切换活跃文件之前返回错误
+        if let Some(max_record_size) = self.options.max_record_size {
+            if record_len > max_record_size {
+                return Err(Errors::RecordTooLarge);
+            }
+        }
+
         // 获取当前活跃文件
         let mut active_file = self.active_file.write();
 
         // 判断当前活跃文件是否达到阈值，是则持久化当前活跃文件
         // 并将其存储到旧文件列表，最后打开一个新的活跃文件
+        let mut rotated = None;
         if active_file.get_write_off() + record_len > self.options.data_file_size {
-            active_file.sync()?;
-
-            let mut older_files = self.older_files.write();
-            let current_fid = active_file.get_file_id();
-            let old_file = DataFile::new(dir_path.clone(), current_fid, IOType::FileIO)?;
-            older_files.insert(current_fid, old_file);
-
-            let new_file = DataFile::new(dir_path, current_fid + 1, IOType::FileIO)?;
-            *active_file = new_file;
+            rotated = Some(self.rotate_active_file(&mut active_file)?);
         }
 
-        // 追加写入数据
+        // 追加写入数据，写偏移的分配和写入都在活跃文件的写锁内完成，保证索引记录的位置和文件内容一致
         let write_off = active_file.get_write_off();
         active_file.write(&enc_record)?;
+        let file_id = active_file.get_file_id();
+        // 写入完成后释放写锁，持久化时不阻塞其他线程的编码和写入
+        drop(active_file);
+
+        // 在锁外执行文件切换的回调，回调中可以访问存储引擎
+        if let (Some((sealed_id, new_id)), Some(on_file_rotate)) =
+            (rotated, self.options.on_file_rotate.as_ref())
+        {
+            on_file_rotate(sealed_id, new_id);
+        }
 
         let previous = self
             .bytes_write
             .fetch_add(enc_record.len(), Ordering::SeqCst);
+        self.total_bytes_written
+            .fetch_add(enc_record.len() as u64, Ordering::SeqCst);
+        self.total_records_written.fetch_add(1, Ordering::SeqCst);
+        self.disk_size.fetch_add(record_len, Ordering::SeqCst);
+        self.data_files_size.fetch_add(record_len, Ordering::SeqCst);
         // 根据配置项决定是否持久化
         let mut need_sync = self.options.sync_writes;
         if !need_sync
@@ -367,19 +1430,87 @@ impl Engine {
         {
             need_sync = true;
         }
+        // 未持久化的数据量达到上限，阻塞当前写入并强制持久化
+        if let Some(max_unsynced_bytes) = self.options.max_unsynced_bytes {
+            if previous + enc_record.len() >= max_unsynced_bytes {
+                need_sync = true;
+            }
+        }
 
         if need_sync {
-            active_file.sync()?;
-            self.bytes_write.store(0, Ordering::SeqCst);
+            self.sync_to(file_id, write_off + record_len)?;
         }
 
         Ok(LogRecordPos {
-            file_id: active_file.get_file_id(),
+            file_id,
             offset: write_off,
             size: enc_record.len() as u32,
         })
     }
 
+    // 开启 separate_values_over 且 value 超过阈值时，将 value 写入 blob 文件，返回保存 blob 位置的 LogRecord
+    // 只分离正常写入的数据，已经是 blob 位置的数据（merge、GC 重写）保持不变
+    fn separate_value(&self, log_record: LogRecord) -> Result<LogRecord, Errors> {
+        let threshold = match self.options.separate_values_over {
+            Some(threshold) => threshold,
+            None => return Ok(log_record),
+        };
+        if log_record.rec_type != LogRecordType::NOAMAL || log_record.value.len() <= threshold {
+            return Ok(log_record);
+        }
+
+        let blob_pos = self.write_blob(&log_record.key, &log_record.value)?;
+        Ok(LogRecord {
+            key: log_record.key,
+            value: blob_pos.encode(),
+            rec_type: LogRecordType::BLOBPOINTER,
+        })
+    }
+
+    // 写入 value 到 blob 文件，返回 blob 位置
+    fn write_blob(&self, key: &[u8], value: &[u8]) -> Result<LogRecordPos, Errors> {
+        let blob_pos = self.blobs.write(key, value)?;
+        self.disk_size
+            .fetch_add(blob_pos.size as u64, Ordering::SeqCst);
+        Ok(blob_pos)
+    }
+
+    // 保证数据已经持久化到指定的位置
+    // 并发写入时，一次 sync 可以覆盖多个线程写入的数据，已经被覆盖的写入不需要再次 sync
+    fn sync_to(&self, file_id: u32, offset: u64) -> Result<(), Errors> {
+        let mut synced_pos = self.synced_pos.lock();
+        if *synced_pos >= (file_id, offset) {
+            return Ok(());
+        }
+
+        let active_file = self.active_file.read();
+        // 活跃文件已经切换，旧的活跃文件在切换时已经持久化
+        if active_file.get_file_id() != file_id {
+            return Ok(());
+        }
+        // 写偏移之前的数据引用的 blob 都已经写入，先持久化 blob 再持久化活跃文件
+        let write_off = active_file.get_write_off();
+        self.blobs.sync()?;
+        active_file.sync()?;
+        self.bytes_write.store(0, Ordering::SeqCst);
+        self.sync_count.fetch_add(1, Ordering::SeqCst);
+        *synced_pos = (file_id, write_off);
+
+        Ok(())
+    }
+
+    // 组提交，保证数据已经持久化到 pos 对应的数据末尾
+    // 先等待 window 时间，让同一时间窗口内提交的其他事务也写入活跃文件，之后一次 sync 覆盖所有写入，
+    // 等待期间已经被其他事务的 sync 覆盖时直接返回
+    pub(crate) fn group_sync(&self, pos: &LogRecordPos, window: Duration) -> Result<(), Errors> {
+        let end = (pos.file_id, pos.offset + pos.size as u64);
+        if *self.synced_pos.lock() >= end {
+            return Ok(());
+        }
+        std::thread::sleep(window);
+        self.sync_to(end.0, end.1)
+    }
+
     // 从数据文件中加载内存索引
     fn load_index(&mut self) -> Result<(), Errors> {
         if self.file_ids.is_empty() {
@@ -390,10 +1521,15 @@ impl Engine {
         let mut non_merge_fid = 0;
         let merge_fin_file = self.options.dir_path.join(MERGE_FINISHED_FILE_NAME);
         if merge_fin_file.is_file() {
-            let merge_fin_file = DataFile::new_merge_finished_file(self.options.dir_path.clone())?;
-            let read_res = merge_fin_file.read(0)?;
-            let v = String::from_utf8(read_res.record.value).unwrap();
-            non_merge_fid = v.parse::<u32>().unwrap();
+            // 标识文件损坏时无法确定哪些文件已经 merge，从所有数据文件中加载索引
+            // merge 后的文件只包含有效数据，并且 id 比其他文件小，重新加载一遍结果仍然正确
+            match get_non_merge_file_id(self.options.dir_path.clone(), self.options.read_only) {
+                Ok(fid) => non_merge_fid = fid,
+                Err(Errors::DataDirCorrupted) => {
+                    warn!("merge finished file is corrupted, load index from all data files")
+                }
+                Err(e) => return Err(e),
+            }
         }
 
         let mut active_file = self.active_file.write();
@@ -401,6 +1537,8 @@ impl Engine {
 
         // 暂存事务序列号和事务内所有数据的信息
         let mut txn_batch: HashMap<usize, Vec<TransactionLogRecord>> = HashMap::new();
+        // 按 Truncate 策略需要截断的旧数据文件和截断的位置
+        let mut truncated_files = Vec::new();
 
         // 读取所有数据文件并构建内存索引
         for (i, file_id) in self.file_ids.iter().enumerate() {
@@ -409,25 +1547,71 @@ impl Engine {
                 continue;
             }
 
+            // 旧数据文件在读取期间保持打开
+            let older_file = match *file_id == active_file.get_file_id() {
+                true => None,
+                false => match older_files.get(*file_id)? {
+                    Some(data_file) => Some(data_file),
+                    None => return Err(Errors::DataFileIsNotFound),
+                },
+            };
+
             let mut offset = 0;
             loop {
-                let log_record_res = match *file_id == active_file.get_file_id() {
-                    true => active_file.read(offset),
-                    false => {
-                        let data_file = older_files.get(file_id).unwrap();
-                        data_file.read(offset)
-                    }
+                let data_file = match older_file.as_ref() {
+                    None => &*active_file,
+                    Some(data_file) => data_file,
                 };
-
-                // 读到文件末尾则继续读下个文件
-                let (mut log_record, size) = match log_record_res {
-                    Ok(r) => (r.record, r.size),
-                    Err(e) => {
-                        if e == Errors::ReadDataFileEOF {
+                // 读取数据并解析 key，拿到实际 key 和事务序列号，key 无法解析时和其他损坏的数据一样按 on_corruption 处理
+                let log_record_res = data_file.read(offset).and_then(|mut r| {
+                    let (real_key, seq_no) =
+                        parse_log_record_key(std::mem::take(&mut r.record.key))?;
+                    Ok((r.record, r.size, real_key, seq_no))
+                });
+
+                let (mut log_record, size, real_key, seq_no) = match log_record_res {
+                    Ok(r) => r,
+                    // 读到文件末尾则继续读下个文件
+                    Err(Errors::ReadDataFileEOF) if offset >= data_file.file_size() => break,
+                    // 没有读到文件末尾时，说明数据损坏或者没有写完整
+                    Err(e) => match self.options.on_corruption {
+                        CorruptionPolicy::Fail => {
+                            if e == Errors::ReadDataFileEOF {
+                                break;
+                            }
+                            return Err(e);
+                        }
+                        CorruptionPolicy::SkipRecord => {
+                            let next = data_file.next_valid_offset(offset);
+                            let end = next.unwrap_or(data_file.file_size());
+                            warn!(
+                                "skip corrupted data in data file {} from offset {} to {}: {}",
+                                file_id, offset, end, e
+                            );
+                            // 跳过的数据在 merge 时被清理，活跃文件末尾的数据在打开时直接截断
+                            if next.is_some() || older_file.is_some() {
+                                self.add_file_reclaim(*file_id, (end - offset) as usize);
+                            }
+                            match next {
+                                Some(next) => {
+                                    offset = next;
+                                    continue;
+                                }
+                                None => break,
+                            }
+                        }
+                        CorruptionPolicy::Truncate => {
+                            warn!(
+                                "ignore corrupted data in data file {} after offset {}: {}",
+                                file_id, offset, e
+                            );
+                            // 活跃文件按写偏移截断
+                            if older_file.is_some() {
+                                truncated_files.push((*file_id, offset));
+                            }
                             break;
                         }
-                        return Err(e);
-                    }
+                    },
                 };
 
                 // 构建内存索引
@@ -437,26 +1621,22 @@ impl Engine {
                     size: size as u32,
                 };
 
-                // 解析 key ,拿到实际 key 和事务序列号
-                let (real_key, seq_no) = parse_log_record_key(log_record.key);
-
                 // 更新事务序列号
                 self.seq_no.fetch_max(seq_no + 1, Ordering::SeqCst);
 
                 // 非事务数据直接更新索引，事务数据先暂存，读到 TXN_FIN_KEY 统一更新索引
+                let read_key = |pos: &LogRecordPos| -> Result<Vec<u8>, Errors> {
+                    Ok(read_record(&active_file, &older_files, pos)?.0.key)
+                };
                 if seq_no == NON_TXN_SEQ_NO {
-                    self.update_index(real_key, log_record.rec_type, log_record_pos);
+                    self.update_index(real_key, log_record.rec_type, log_record_pos, &read_key)?;
                 } else {
                     if log_record.rec_type == LogRecordType::TXNFINISHED {
-                        let records = txn_batch.get(&seq_no).unwrap();
-                        for txn_record in records.iter() {
-                            self.update_index(
-                                txn_record.record.key.clone(),
-                                txn_record.record.rec_type,
-                                txn_record.pos,
-                            );
+                        // 事务的数据可能在已经 merge 的文件中，merge 时已经改写为非事务数据，这里没有暂存的数据
+                        let records = txn_batch.remove(&seq_no).unwrap_or_default();
+                        for txn_record in records {
+                            self.replay_txn_record(txn_record, &read_key)?;
                         }
-                        txn_batch.remove(&seq_no);
                     } else {
                         log_record.key = real_key;
                         txn_batch
@@ -477,73 +1657,232 @@ impl Engine {
                 active_file.set_write_off(offset)
             }
         }
+        drop(older_files);
+        drop(active_file);
+
+        // 截断旧数据文件中损坏的数据，已经打开的文件在重置 IO 类型时重新打开
+        if !self.options.read_only {
+            for (file_id, offset) in truncated_files {
+                let mut data_file = self.open_data_file(file_id, IOType::FileIO)?;
+                warn!(
+                    "truncate {} bytes of corrupted data at the end of data file {}",
+                    data_file.file_size() - offset,
+                    file_id
+                );
+                data_file.truncate(offset)?;
+            }
+        }
+
+        // 扫描完所有文件后仍然没有完成标识的事务没有提交成功，忽略这些数据
+        if !txn_batch.is_empty() {
+            let count: usize = txn_batch.values().map(|records| records.len()).sum();
+            warn!(
+                "ignore {} records of {} uncommitted transactions",
+                count,
+                txn_batch.len()
+            );
+        }
 
         Ok(())
     }
 
+    // 读到事务完成标识时更新事务中一条数据的索引
+    // 事务的数据写入之后、完成标识写入之前，同一个 key 可能已经被非事务数据覆盖，
+    // 因此索引中已经有位置更靠后的数据时，只记录可以回收的数据量，不覆盖索引
+    fn replay_txn_record(
+        &self,
+        txn_record: TransactionLogRecord,
+        read_key: &dyn Fn(&LogRecordPos) -> Result<Vec<u8>, Errors>,
+    ) -> Result<(), Errors> {
+        let TransactionLogRecord { record, pos } = txn_record;
+        let (_, current) = self.lookup_index(&record.key, read_key)?;
+        if let Some(current) = current {
+            if (current.file_id, current.offset) > (pos.file_id, pos.offset) {
+                self.add_reclaim(&pos);
+                return Ok(());
+            }
+        }
+        self.update_index(record.key, record.rec_type, pos, read_key)
+    }
+
+    // 记录 pos 位置的数据变为无效数据，merge 时可以回收
+    pub(crate) fn add_reclaim(&self, pos: &LogRecordPos) {
+        self.add_file_reclaim(pos.file_id, pos.size as usize);
+    }
+
+    fn add_file_reclaim(&self, file_id: u32, size: usize) {
+        self.reclaim_size.fetch_add(size, Ordering::SeqCst);
+        *self.reclaim_by_file.lock().entry(file_id).or_default() += size;
+    }
+
     // 启动时更新内存索引
-    pub(crate) fn update_index(&self, key: Vec<u8>, rec_type: LogRecordType, pos: LogRecordPos) {
-        if rec_type == LogRecordType::NOAMAL {
-            if let Some(old_pos) = self.index.put(key.clone(), pos) {
-                self.reclaim_size
-                    .fetch_add(old_pos.size as usize, Ordering::SeqCst);
+    pub(crate) fn update_index(
+        &self,
+        key: Vec<u8>,
+        rec_type: LogRecordType,
+        pos: LogRecordPos,
+        read_key: &dyn Fn(&LogRecordPos) -> Result<Vec<u8>, Errors>,
+    ) -> Result<(), Errors> {
+        let (index_key, exists) = self.resolve_index_key(key, read_key)?;
+        if rec_type == LogRecordType::NOAMAL || rec_type == LogRecordType::BLOBPOINTER {
+            if let Some(old_pos) = self.index.put(index_key.clone(), pos) {
+                self.add_reclaim(&old_pos);
             }
         }
         if rec_type == LogRecordType::DELETED {
-            let mut size = pos.size;
-            if let Some(old_pos) = self.index.delete(key) {
-                size += old_pos.size;
+            self.add_reclaim(&pos);
+            if exists {
+                if let Some(old_pos) = self.index.delete(index_key) {
+                    self.add_reclaim(&old_pos);
+                }
             }
-            self.reclaim_size.fetch_add(size as usize, Ordering::SeqCst);
         }
+        Ok(())
     }
 
-    // 加载事务序列号
-    fn load_seq_no(&self) -> (bool, usize) {
-        let file_path = self.options.dir_path.join(SEQ_NO_FILE_NAME);
-        if !file_path.is_file() {
-            return (false, 0);
-        }
-
-        let seq_no_file = DataFile::new_seq_no_file(self.options.dir_path.clone()).unwrap();
-        let record = match seq_no_file.read(0) {
-            Ok(res) => res.record,
-            Err(e) => panic!("failed to read seq no: {}", e),
-        };
-        let v = String::from_utf8(record.value).unwrap();
-        let seq_no = v.parse::<usize>().unwrap();
+    // 从 id 不小于 from_fid 的数据文件中恢复事务序列号，返回下一个可用的事务序列号
+    fn recover_seq_no(&self, seq_no: usize, from_fid: u32) -> Result<usize, Errors> {
+        let active_file = self.active_file.read();
+        let older_files = self.older_files.read();
 
-        // 加载后删除文件，避免追加写入
-        remove_file(file_path).unwrap();
+        let mut seq_no = seq_no;
+        for file_id in self.file_ids.iter().filter(|fid| **fid >= from_fid) {
+            let older_file = match *file_id == active_file.get_file_id() {
+                true => None,
+                false => match older_files.get(*file_id)? {
+                    Some(data_file) => Some(data_file),
+                    None => return Err(Errors::DataFileIsNotFound),
+                },
+            };
+            let data_file = older_file.as_deref().unwrap_or(&*active_file);
+            for item in data_file.iter_records() {
+                let (log_record, _) = item?;
+                let (_, record_seq_no) = parse_log_record_key(log_record.key)?;
+                seq_no = std::cmp::max(seq_no, record_seq_no + 1);
+            }
+        }
 
-        (true, seq_no)
+        Ok(seq_no)
     }
 
-    fn reset_io_type(&self) {
+    fn reset_io_type(&self) -> Result<(), Errors> {
+        // 压缩归档的数据文件只能通过 archive_reader 读取
+        if self.options.archive_reader.is_some() {
+            return Ok(());
+        }
         let mut active_file = self.active_file.write();
-        active_file.set_io_manager(self.options.dir_path.clone(), IOType::FileIO);
+        active_file.set_io_manager(IOType::FileIO, self.options.read_only)?;
+        // 已经打开的旧数据文件会被关闭，之后读取时使用标准文件 IO 重新打开
         let mut older_files = self.older_files.write();
-        for (_, file) in older_files.iter_mut() {
-            file.set_io_manager(self.options.dir_path.clone(), IOType::FileIO);
-        }
+        older_files.set_io_type(IOType::FileIO);
+        Ok(())
     }
 }
 
+#[cfg(test)]
+thread_local! {
+    // 当前线程中最近一次 Drop 时关闭存储引擎的错误，用于测试
+    pub(crate) static LAST_DROP_ERROR: std::cell::RefCell<Option<Errors>> =
+        const { std::cell::RefCell::new(None) };
+}
+
 impl Drop for Engine {
     fn drop(&mut self) {
+        // 不需要持久化时只释放文件锁，MANIFEST 保持为打开时的检查点，下次打开时可以恢复事务序列号
+        if !self.options.sync_on_drop {
+            // 不持久化，但写缓冲区中的数据仍然写入文件，和没有开启写缓冲时一致
+            if let Err(e) = self.active_file.read().flush() {
+                log::error!("failed to flush active file: {}", e);
+            }
+            if let Err(e) = self.lock_file.unlock() {
+                log::error!("failed to unlock database directory: {}", e);
+            }
+            return;
+        }
+
         if let Err(e) = self.close() {
             log::error!("error while closing engine: {}", e);
+            #[cfg(test)]
+            LAST_DROP_ERROR.with(|last| *last.borrow_mut() = Some(e));
         }
     }
 }
 
-fn check_options(options: &Options) -> Option<Errors> {
+/// 可以在多个线程之间共享的存储引擎句柄，内部持有 Arc<Engine>，clone 时共享同一个存储引擎
+///
+/// 所有句柄都释放后才会关闭存储引擎，close 只在最后一个句柄上真正关闭
+#[derive(Clone)]
+pub struct Db {
+    engine: Arc<Engine>,
+}
+
+impl Db {
+    /// 打开存储引擎并返回句柄
+    pub fn open(options: Options) -> Result<Self, Errors> {
+        Ok(Self {
+            engine: Arc::new(Engine::open(options)?),
+        })
+    }
+
+    /// 获取句柄共享的存储引擎，用于调用其他方法
+    pub fn engine(&self) -> &Engine {
+        &self.engine
+    }
+
+    pub fn put(&self, key: Bytes, value: Bytes) -> Result<(), Errors> {
+        self.engine.put(key, value)
+    }
+
+    pub fn get(&self, key: Bytes) -> Result<Bytes, Errors> {
+        self.engine.get(key)
+    }
+
+    pub fn delete(&self, key: Bytes) -> Result<(), Errors> {
+        self.engine.delete(key)
+    }
+
+    pub fn iter(&self, options: IteratorOptions) -> crate::iterator::Iterator<'_> {
+        self.engine.iter(options)
+    }
+
+    pub fn merge(&self) -> Result<(), Errors> {
+        self.engine.merge()
+    }
+
+    /// 释放当前句柄，返回是否关闭了存储引擎
+    /// 还有其他句柄时只释放当前句柄，返回 false；最后一个句柄关闭存储引擎并返回 true，
+    /// 多个线程同时调用时只有一个线程会关闭存储引擎
+    pub fn close(self) -> Result<bool, Errors> {
+        match Arc::into_inner(self.engine) {
+            Some(engine) => engine.close().map(|_| true),
+            None => Ok(false),
+        }
+    }
+}
+
+impl From<Engine> for Db {
+    fn from(engine: Engine) -> Self {
+        Self {
+            engine: Arc::new(engine),
+        }
+    }
+}
+
+pub(crate) fn check_options(options: &Options) -> Option<Errors> {
     let dir_path = options.dir_path.to_str();
     if dir_path.is_none() || dir_path.unwrap().len() == 0 {
         return Some(Errors::DirPathIsEmpty);
     }
 
-    if options.data_file_size <= 0 {
+    if options.data_file_size == 0 || options.merge_file_size == Some(0) {
+        return Some(Errors::DataFileSizeInvalid);
+    }
+
+    // merge 后的文件更小时会占用更多的文件 id，可能和 merge 之后写入的数据文件冲突
+    if options
+        .merge_file_size
+        .is_some_and(|size| size < options.data_file_size)
+    {
         return Some(Errors::DataFileSizeInvalid);
     }
 
@@ -551,26 +1890,217 @@ fn check_options(options: &Options) -> Option<Errors> {
         return Some(Errors::InvalidMergeRatio);
     }
 
+    if options.merge_throughput_limit == Some(0) {
+        return Some(Errors::InvalidMergeThroughputLimit);
+    }
+
+    // 数据文件后缀和锁文件名不能和数据目录中的其他文件混淆
+    if !valid_file_names(&options.file_suffix, &options.lock_file_name) {
+        return Some(Errors::InvalidFileName);
+    }
+
+    // B+ 树索引持久化在磁盘上，不支持只保存 key 的 hash
+    if options.hash_keys_in_index && options.index_type == IndexType::BPlusTree {
+        return Some(Errors::HashKeysInIndexUnsupported);
+    }
+
+    // hash 冲突的 key 共用索引位置，必须和 hash 一样落在同一个分段上
+    if options.hash_keys_in_index && options.shard_fn.is_some() {
+        return Some(Errors::ShardFnWithHashKeysUnsupported);
+    }
+
+    // 压缩归档的数据文件无法追加写入
+    if options.archive_reader.is_some() && !options.read_only {
+        return Some(Errors::ArchiveRequiresReadOnly);
+    }
+
+    // B+ 树索引文件总是以读写方式打开并加锁，无法保证只读模式下不修改数据目录
+    if options.read_only && options.index_type == IndexType::BPlusTree {
+        return Some(Errors::ReadOnlyBPlusTreeUnsupported);
+    }
+
     None
 }
 
-fn load_data_files(dir_path: PathBuf, use_mmap: bool) -> Result<Vec<DataFile>, Errors> {
+// 记录中的 value 实际保存的位置，由记录的类型决定
+// 读取 value 的路径都通过 ValueSource::of 分派，新的存储方式只需要增加对应的变体
+enum ValueSource {
+    Inline,             // value 直接保存在数据文件的记录中
+    Blob(LogRecordPos), // value 保存在 blob 文件中，记录中只保存 blob 位置
+}
+
+impl ValueSource {
+    // 被删除的数据没有 value，返回 KeyIsNotFound
+    fn of(rec_type: LogRecordType, value: &[u8]) -> Result<Self, Errors> {
+        match rec_type {
+            LogRecordType::NOAMAL | LogRecordType::TXNFINISHED => Ok(ValueSource::Inline),
+            LogRecordType::DELETED => Err(Errors::KeyIsNotFound),
+            LogRecordType::BLOBPOINTER => Ok(ValueSource::Blob(codec::decode_pos_v1(value))),
+        }
+    }
+}
+
+// 打开数据目录中的锁文件，不存在时新建
+// 只读模式下不会新建锁文件，已经存在时只读打开，否则改为锁住数据目录本身
+fn open_lock_file(dir_path: &PathBuf, options: &Options) -> Result<File, Errors> {
+    let lock_path = dir_path.join(&options.lock_file_name);
+    if options.read_only {
+        if lock_path.is_file() {
+            return Ok(File::open(lock_path)?);
+        }
+        return Ok(File::open(dir_path)?);
+    }
+    let lock_file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_path)?;
+    Ok(lock_file)
+}
+
+// 校验数据文件后缀和锁文件名
+fn valid_file_names(file_suffix: &str, lock_file_name: &str) -> bool {
+    if file_suffix.is_empty() || lock_file_name.is_empty() {
+        return false;
+    }
+    if file_suffix.contains(std::path::is_separator)
+        || lock_file_name.contains(std::path::is_separator)
+    {
+        return false;
+    }
+    // 按后缀识别数据文件，其他文件的名称不能以该后缀结尾
+    let reserved = [
+        lock_file_name,
+        HINT_FILE_NAME,
+        MERGE_FINISHED_FILE_NAME,
+        SEQ_NO_FILE_NAME,
+        INDEX_TYPE_FILE_NAME,
+        MANIFEST_FILE_NAME,
+        MANIFEST_TMP_FILE_NAME,
+        BPTREE_INDEX_FILE_NAME,
+        BLOB_FILE_NAME_SUFFIX,
+    ];
+    !reserved.iter().any(|name| name.ends_with(file_suffix))
+}
+
+// 打开数据目录中指定 id 的数据文件，配置了 archive_reader 时按压缩归档的文件读取
+fn open_data_file(options: &Options, file_id: u32, io_type: IOType) -> Result<DataFile, Errors> {
+    let mut data_file = match options.archive_reader.as_ref() {
+        Some(reader) => DataFile::new_archived(
+            options.dir_path.clone(),
+            file_id,
+            &options.file_suffix,
+            reader.clone(),
+        ),
+        None => DataFile::new_with_suffix(
+            options.dir_path.clone(),
+            file_id,
+            io_type,
+            &options.file_suffix,
+            options.read_only,
+        )?,
+    };
+    data_file.set_reuse_read_buffers(options.reuse_read_buffers);
+    data_file.set_io_retry(options.io_retry);
+    data_file.set_write_buffer_size(options.write_buffer_size);
+    Ok(data_file)
+}
+
+// 从活跃文件或旧数据文件中读取位置信息对应的数据，并解析出实际的 key 和事务序列号
+fn read_record(
+    active_file: &DataFile,
+    older_files: &FileCache,
+    pos: &LogRecordPos,
+) -> Result<(LogRecord, usize), Errors> {
+    let read_res = match pos.file_id == active_file.get_file_id() {
+        true => active_file.read(pos.offset)?,
+        false => match older_files.get(pos.file_id)? {
+            Some(data_file) => data_file.read(pos.offset)?,
+            None => return Err(Errors::DataFileIsNotFound),
+        },
+    };
+    let mut record = read_res.record;
+    let (real_key, seq_no) = parse_log_record_key(record.key)?;
+    record.key = real_key;
+    Ok((record, seq_no))
+}
+
+// 索引类型在 MANIFEST 中的名称
+fn index_type_name(index_type: &IndexType) -> &'static str {
+    match index_type {
+        IndexType::BTree => "btree",
+        IndexType::SkipList => "skiplist",
+        IndexType::BPlusTree => "bptree",
+    }
+}
+
+// 校验并记录数据目录的元数据：使用的索引类型和数据文件大小，返回记录后的元数据
+// b+树索引持久化在磁盘上，和其他索引类型相互切换会导致索引与数据不一致，因此直接返回错误；
+// 内存索引启动时会从数据文件重建，可以自由切换
+// 数据文件大小调小之后，之前写入的旧数据文件会超过新的大小，根据配置项给出警告或者返回错误
+fn check_metadata(dir_path: PathBuf, options: &Options) -> Result<Manifest, Errors> {
+    let bptree_name = index_type_name(&IndexType::BPlusTree);
+    let current = index_type_name(&options.index_type);
+
+    // 拿到之前记录的元数据，没有记录索引类型时根据 b+树索引文件是否存在判断
+    let mut manifest = Manifest::read(dir_path.clone(), options.read_only)?;
+    let stored = match manifest.index_type.clone() {
+        None if dir_path.join(BPTREE_INDEX_FILE_NAME).is_file() => Some(bptree_name.to_string()),
+        stored => stored,
+    };
+    let stored_data_file_size = manifest.data_file_size;
+
+    if let Some(stored) = stored.as_ref() {
+        if stored != current && (stored == bptree_name || current == bptree_name) {
+            return Err(Errors::IndexTypeMismatch);
+        }
+    }
+
+    // 记录使用过的最大的数据文件大小，之后每次用更小的值打开都会检查出来
+    let mut data_file_size = options.data_file_size;
+    if let Some(stored_size) = stored_data_file_size {
+        if options.data_file_size < stored_size {
+            if options.error_on_data_file_size_shrink {
+                return Err(Errors::DataFileSizeShrunk);
+            }
+            warn!(
+                "data file size {} is smaller than {} used before, older data files may exceed the limit",
+                options.data_file_size, stored_size
+            );
+            data_file_size = stored_size;
+        }
+    }
+
+    // 记录元数据，没有变化和只读模式下不记录
+    if options.read_only
+        || (stored.as_deref() == Some(current) && stored_data_file_size == Some(data_file_size))
+    {
+        return Ok(manifest);
+    }
+    manifest.index_type = Some(current.to_string());
+    manifest.data_file_size = Some(data_file_size);
+    manifest.write(dir_path, options.sync_dir_enabled())?;
+
+    Ok(manifest)
+}
+
+// 加载数据目录中所有数据文件的 id，按从小到大的顺序排列
+fn load_data_files(dir_path: PathBuf, file_suffix: &str) -> Result<Vec<u32>, Errors> {
     let dir = read_dir(dir_path.clone());
     if dir.is_err() {
         return Err(Errors::FailedOpenDatabaseDir);
     }
 
     let mut file_ids = Vec::new();
-    let mut data_files = Vec::new();
     for file in dir.unwrap() {
         if let Ok(entry) = file {
             let os_string = entry.file_name();
             let file_name = os_string.to_str().unwrap();
 
-            // 数据文件名用 .data 作为后缀
-            if file_name.ends_with(DATA_FILE_NAME_SUFFIX) {
-                let split_names: Vec<&str> = file_name.split(".").collect();
-                let file_id = match split_names[0].parse::<u32>() {
+            // 数据文件名为文件 id 加上配置的后缀，默认为 .data
+            if let Some(file_id_str) = file_name.strip_suffix(file_suffix) {
+                let file_id = match file_id_str.parse::<u32>() {
                     Ok(fid) => fid,
                     Err(_) => {
                         return Err(Errors::DataDirCorrupted);
@@ -581,19 +2111,6 @@ fn load_data_files(dir_path: PathBuf, use_mmap: bool) -> Result<Vec<DataFile>, E
         }
     }
 
-    if file_ids.is_empty() {
-        return Ok(data_files);
-    }
-
-    let mut io_type = IOType::FileIO;
-    if use_mmap {
-        io_type = IOType::MMapIO;
-    }
     file_ids.sort();
-    for file_id in file_ids.iter() {
-        let data_file = DataFile::new(dir_path.clone(), *file_id, io_type)?;
-        data_files.push(data_file);
-    }
-
-    Ok(data_files)
+    Ok(file_ids)
 }
@@ -1,11 +1,36 @@
 use bytes::Bytes;
-use std::path::PathBuf;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use std::{
+    collections::HashSet,
+    io::{Read, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 
 use crate::{
-    db::Engine,
-    errors::Errors,
-    options::Options,
-    util::rand_kv::{get_test_key, get_test_value},
+    batch::parse_log_record_key,
+    data::{
+        blob_store::BLOB_FILE_NAME_SUFFIX,
+        codec,
+        data_file::{get_data_file_path, DataFile, DATA_FILE_NAME_SUFFIX, MANIFEST_FILE_NAME},
+        log_record::{LogRecord, LogRecordPos, LogRecordType},
+        manifest::{Manifest, MANIFEST_TMP_FILE_NAME},
+    },
+    db::{Db, Engine, FILE_LOCK_NAME, LAST_DROP_ERROR},
+    errors::{Errors, IoError},
+    fio::{file_io::FileIO, IOManager},
+    index::bptree::BPTREE_INDEX_FILE_NAME,
+    options::{
+        CorruptionPolicy, IOType, IndexType, IteratorOptions, Options, RetryPolicy,
+        WriteBatchOptions,
+    },
+    util::{
+        file::{dir_disk_size, dir_disk_size_with_suffix},
+        rand_kv::{get_test_key, get_test_value},
+    },
 };
 
 #[test]
@@ -118,6 +143,91 @@ fn test_engine_get() {
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
 }
 
+#[test]
+fn test_engine_get_opt() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-get-opt");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.value 为空和不为空的 key
+    let res1 = engine.put(Bytes::from("empty"), Bytes::new());
+    assert!(res1.is_ok());
+    let res2 = engine.put(get_test_key(1), get_test_value(1));
+    assert!(res2.is_ok());
+    assert_eq!(Ok(Some(Bytes::new())), engine.get_opt(Bytes::from("empty")));
+    assert_eq!(Ok(Some(get_test_value(1))), engine.get_opt(get_test_key(1)));
+
+    // 2.不存在的 key 和被删除的 key
+    assert_eq!(Ok(None), engine.get_opt(Bytes::from("not existed key")));
+    let res3 = engine.delete(get_test_key(1));
+    assert!(res3.is_ok());
+    assert_eq!(Ok(None), engine.get_opt(get_test_key(1)));
+
+    // 3.空的 key 仍然返回错误
+    assert_eq!(
+        Errors::KeyIsEmpty,
+        engine.get_opt(Bytes::new()).err().unwrap()
+    );
+
+    // 4.重启后 value 为空的 key 仍然存在
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(
+        Ok(Some(Bytes::new())),
+        engine2.get_opt(Bytes::from("empty"))
+    );
+    assert_eq!(Ok(None), engine2.get_opt(get_test_key(1)));
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_locate() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-locate");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 不存在的 key 和空的 key
+    assert_eq!(None, engine.locate(b"not existed key"));
+    assert_eq!(None, engine.locate(b""));
+
+    for i in 0..10 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    assert!(engine.sync().is_ok());
+
+    // 通过数据文件直接读取返回的位置上的记录
+    let (file_id, offset, size) = engine.locate(&get_test_key(5)).unwrap();
+    let data_file = DataFile::new(opts.dir_path.clone(), file_id, IOType::FileIO)
+        .expect("failed to open data file");
+    let read_res = data_file.read(offset).expect("failed to read record");
+    assert_eq!(size as u64, read_res.size);
+    let (real_key, _) = parse_log_record_key(read_res.record.key().to_vec()).unwrap();
+    assert_eq!(get_test_key(5), Bytes::from(real_key));
+    assert_eq!(get_test_value(5).as_ref(), read_res.record.value());
+    assert_eq!(LogRecordType::NOAMAL, read_res.record.rec_type());
+
+    // 覆盖写入后位置改变，删除后返回 None
+    let res1 = engine.put(get_test_key(5), get_test_value(15));
+    assert!(res1.is_ok());
+    assert_ne!(
+        Some((file_id, offset, size)),
+        engine.locate(&get_test_key(5))
+    );
+    let res2 = engine.delete(get_test_key(5));
+    assert!(res2.is_ok());
+    assert_eq!(None, engine.locate(&get_test_key(5)));
+    std::mem::drop(engine);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
 #[test]
 fn test_engine_delete() {
     let mut opts = Options::default();
@@ -164,6 +274,77 @@ fn test_engine_delete() {
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
 }
 
+#[test]
+fn test_engine_delete_existing() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-delete-existing");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 存在的 key 写入删除标记
+    let put_res = engine.put(get_test_key(111), get_test_value(111));
+    assert!(put_res.is_ok());
+    assert_eq!(Ok(true), engine.delete_existing(get_test_key(111)));
+    let get_res = engine.get(get_test_key(111));
+    assert_eq!(Errors::KeyIsNotFound, get_res.err().unwrap());
+
+    // 已经删除的 key 和不存在的 key 不写入删除标记
+    let offset = engine.active_file_offset();
+    assert_eq!(Ok(false), engine.delete_existing(get_test_key(111)));
+    assert_eq!(
+        Ok(false),
+        engine.delete_existing(Bytes::from("not-existed-key"))
+    );
+    assert_eq!(offset, engine.active_file_offset());
+
+    // 空的 key
+    let del_res = engine.delete_existing(Bytes::new());
+    assert_eq!(Errors::KeyIsEmpty, del_res.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_delete_writes_one_tombstone() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-delete-one-tombstone");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let count_tombstones = |engine: &Engine, key: &Bytes| {
+        engine
+            .scan_raw()
+            .filter(|r| r.rec_type == LogRecordType::DELETED && r.key == key)
+            .count()
+    };
+
+    // 连续删除两次
+    let put_res = engine.put(get_test_key(111), get_test_value(111));
+    assert!(put_res.is_ok());
+    assert!(engine.delete(get_test_key(111)).is_ok());
+    assert!(engine.delete(get_test_key(111)).is_ok());
+    assert_eq!(1, count_tombstones(&engine, &get_test_key(111)));
+
+    // 多个线程同时删除同一个 key
+    let put_res = engine.put(get_test_key(222), get_test_value(222));
+    assert!(put_res.is_ok());
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            let engine = &engine;
+            s.spawn(move || {
+                for _ in 0..10 {
+                    assert!(engine.delete(get_test_key(222)).is_ok());
+                }
+            });
+        }
+    });
+    assert_eq!(1, count_tombstones(&engine, &get_test_key(222)));
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
 #[test]
 fn test_engine_close() {
     let mut opts = Options::default();
@@ -181,6 +362,93 @@ fn test_engine_close() {
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
 }
 
+#[test]
+fn test_engine_closed() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-closed");
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let res1 = engine.put(get_test_key(1), get_test_value(1));
+    assert!(res1.is_ok());
+
+    let close_res = engine.close();
+    assert!(close_res.is_ok());
+
+    // 关闭之后的读写操作都返回 EngineClosed，重复关闭直接返回成功
+    let res2 = engine.put(get_test_key(2), get_test_value(2));
+    assert_eq!(Errors::EngineClosed, res2.err().unwrap());
+    let res3 = engine.get(get_test_key(1));
+    assert_eq!(Errors::EngineClosed, res3.err().unwrap());
+    let res4 = engine.delete(get_test_key(1));
+    assert_eq!(Errors::EngineClosed, res4.err().unwrap());
+    let res5 = engine.write_batch(vec![(get_test_key(3), Some(get_test_value(3)))]);
+    assert_eq!(Errors::EngineClosed, res5.err().unwrap());
+    assert_eq!(Errors::EngineClosed, engine.sync().err().unwrap());
+    assert_eq!(Errors::EngineClosed, engine.merge().err().unwrap());
+    assert_eq!(Errors::EngineClosed, engine.stat().err().unwrap());
+    let res6 = engine.value_len(&get_test_key(1));
+    assert_eq!(Errors::EngineClosed, res6.err().unwrap());
+    assert!(engine.close().is_ok());
+
+    // 遍历接口不返回任何数据
+    assert!(engine.list_keys().is_empty());
+    assert!(!engine.iter(IteratorOptions::default()).has_next());
+    assert!(engine.to_map().is_empty());
+    assert_eq!(0, engine.scan_raw().count());
+    assert_eq!(0, engine.estimate_range_count(&[], &[0xff]));
+    let folded = std::cell::Cell::new(0);
+    engine.fold(|_, _| {
+        folded.set(folded.get() + 1);
+        true
+    });
+    assert_eq!(0, folded.get());
+
+    // 关闭之后数据目录可以被其他实例打开，之前的实例不会再写入
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    std::mem::drop(engine);
+    assert_eq!(1, engine2.list_keys().len());
+    assert_eq!(get_test_value(1), engine2.get(get_test_key(1)).unwrap());
+    std::mem::drop(engine2);
+
+    // 只读模式下关闭之后同样返回 EngineClosed，而不是 ReadOnlyMode
+    let mut read_only_opts = opts.clone();
+    read_only_opts.read_only = true;
+    let engine3 = Engine::open(read_only_opts).expect("failed to open engine");
+    assert!(engine3.close().is_ok());
+    let res7 = engine3.put_slice(&get_test_key(2), &get_test_value(2));
+    assert_eq!(Errors::EngineClosed, res7.err().unwrap());
+    let res8 = engine3.delete(get_test_key(1));
+    assert_eq!(Errors::EngineClosed, res8.err().unwrap());
+    let res9 = engine3.new_write_batch(WriteBatchOptions::default());
+    assert_eq!(Errors::EngineClosed, res9.err().unwrap());
+    std::mem::drop(engine3);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_closed_bptree() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-closed-bptree");
+    opts.index_type = IndexType::BPlusTree;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let res1 = engine.put(get_test_key(1), get_test_value(1));
+    assert!(res1.is_ok());
+
+    let close_res = engine.close();
+    assert!(close_res.is_ok());
+
+    // 关闭之后释放了 B+ 树索引文件，旧的实例还没有释放时也可以重新打开
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(1, engine2.list_keys().len());
+    assert_eq!(get_test_value(1), engine2.get(get_test_key(1)).unwrap());
+    std::mem::drop(engine);
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
 #[test]
 fn test_engine_sync() {
     let mut opts = Options::default();
@@ -238,6 +506,109 @@ fn test_engine_stat() {
 
     let stat = engine.stat().unwrap();
     assert!(stat.reclaim_size > 0);
+    assert_eq!(None, stat.active_file_hits);
+    assert_eq!(None, stat.older_file_hits);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_live_size() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-live-size");
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..1000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    let stat1 = engine.stat().unwrap();
+    assert_eq!(0, stat1.reclaim_size);
+    assert_eq!(stat1.disk_size, stat1.live_size);
+
+    // 覆盖写入之后旧数据可以回收，live_size 比 disk_size 小可以回收的数据量
+    for i in 0..500 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    let stat2 = engine.stat().unwrap();
+    assert!(stat2.reclaim_size > 0);
+    assert!(stat2.live_size < stat2.disk_size);
+    assert_eq!(stat2.disk_size - stat2.reclaim_size as u64, stat2.live_size);
+    assert_eq!(stat2.live_size, engine.live_size());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_stat_disk_size() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-stat-disk-size");
+    opts.data_file_size = 64 * 1024;
+    opts.data_file_merge_ratio = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(
+        dir_disk_size(opts.dir_path.clone()),
+        engine.stat().unwrap().disk_size
+    );
+
+    // 写入、覆盖写入、删除，并切换多个活跃文件
+    for i in 0..1000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    for i in 0..500 {
+        let res = engine.put(get_test_key(i), get_test_value(i + 1000));
+        assert!(res.is_ok());
+    }
+    for i in 500..700 {
+        let res = engine.delete(get_test_key(i));
+        assert!(res.is_ok());
+    }
+    assert!(engine.data_file_ids().len() > 2);
+    let disk_size1 = engine.stat().unwrap().disk_size;
+    assert_eq!(dir_disk_size(opts.dir_path.clone()), disk_size1);
+    assert_eq!(
+        dir_disk_size_with_suffix(opts.dir_path.clone(), &opts.file_suffix),
+        engine.stat().unwrap().data_files_size
+    );
+
+    // merge 结果在重新打开之后生效，打开时重新统计
+    let merge_res = engine.merge();
+    assert!(merge_res.is_ok());
+    assert_eq!(disk_size1, engine.stat().unwrap().disk_size);
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let disk_size2 = engine2.stat().unwrap().disk_size;
+    assert!(disk_size2 < disk_size1);
+    assert_eq!(dir_disk_size(opts.dir_path.clone()), disk_size2);
+    for i in 0..100 {
+        let res = engine2.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    assert_eq!(
+        dir_disk_size(opts.dir_path.clone()),
+        engine2.stat().unwrap().disk_size
+    );
+    assert_eq!(
+        dir_disk_size_with_suffix(opts.dir_path.clone(), &opts.file_suffix),
+        engine2.stat().unwrap().data_files_size
+    );
+
+    // 辅助文件的变化在重新统计之后体现
+    assert!(engine2.checkpoint().is_ok());
+    assert_eq!(
+        dir_disk_size(opts.dir_path.clone()),
+        engine2.stat().unwrap().disk_size
+    );
+    assert_eq!(
+        engine2.reconcile_disk_size(),
+        engine2.stat().unwrap().disk_size
+    );
+    std::mem::drop(engine2);
 
     // 删除测试的文件夹
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
@@ -267,3 +638,2763 @@ fn test_engine_backup() {
     std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     std::fs::remove_dir_all(backup_dir).expect("failed to remove path");
 }
+
+#[test]
+fn test_engine_warmup() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-warmup");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 没有数据时也可以预热
+    let res1 = engine.warmup();
+    assert!(res1.is_ok());
+
+    for i in 0..1000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    let res2 = engine.warmup();
+    assert!(res2.is_ok());
+    assert_eq!(get_test_value(10), engine.get(get_test_key(10)).unwrap());
+    std::mem::drop(engine);
+
+    // 只读模式和 b+树索引
+    opts.read_only = true;
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let res3 = engine2.warmup();
+    assert!(res3.is_ok());
+    std::mem::drop(engine2);
+
+    opts.read_only = false;
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-warmup-bptree");
+    opts.index_type = IndexType::BPlusTree;
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..100 {
+        let res = engine3.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    let res4 = engine3.warmup();
+    assert!(res4.is_ok());
+
+    // 关闭之后返回 EngineClosed
+    let res5 = engine3.close();
+    assert!(res5.is_ok());
+    assert_eq!(Errors::EngineClosed, engine3.warmup().err().unwrap());
+    std::mem::drop(engine3);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all("/tmp/bitcask-rs-warmup").expect("failed to remove path");
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_repair_index() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-repair-index");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..=10000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    let keys1 = engine.list_keys();
+    assert_eq!(keys1.len(), 10001);
+
+    // 1.数据文件都存在时不会移除任何 key
+    let res1 = engine.repair_index();
+    assert_eq!(res1.unwrap(), 0);
+
+    // 2.删除一个旧的数据文件后修复索引
+    std::fs::remove_file(opts.dir_path.join("000000000.data")).expect("failed to remove file");
+    let res2 = engine.repair_index();
+    let removed = res2.unwrap();
+    assert!(removed > 0);
+    let keys2 = engine.list_keys();
+    assert_eq!(keys2.len(), 10001 - removed);
+    let res3 = engine.get(get_test_key(0));
+    assert_eq!(Errors::KeyIsNotFound, res3.err().unwrap());
+    let res4 = engine.get(get_test_key(10000));
+    assert_eq!(get_test_value(10000), res4.unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_skip_missing_data_file() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-skip-missing");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..=10000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+
+    // 1.旧数据文件缺失时默认返回错误
+    engine.older_files.write().remove(0);
+    let res1 = engine.get(get_test_key(0));
+    assert_eq!(Errors::DataFileIsNotFound, res1.err().unwrap());
+    std::mem::drop(engine);
+
+    // 2.开启配置项后跳过缺失的数据文件
+    opts.skip_missing_data_file = true;
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    engine2.older_files.write().remove(0);
+    let res2 = engine2.get(get_test_key(0));
+    assert_eq!(Errors::KeyIsNotFound, res2.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_clear() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-clear");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.空数据库清空
+    let res1 = engine.clear();
+    assert!(res1.is_ok());
+
+    // 2.有数据的情况下清空
+    for i in 0..=10000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    let res2 = engine.clear();
+    assert!(res2.is_ok());
+    assert_eq!(engine.list_keys().len(), 0);
+    assert_eq!(engine.stat().unwrap().reclaim_size, 0);
+    let res3 = engine.get(get_test_key(100));
+    assert_eq!(Errors::KeyIsNotFound, res3.err().unwrap());
+
+    let data_files: Vec<_> = std::fs::read_dir(opts.dir_path.clone())
+        .unwrap()
+        .flatten()
+        .filter(|entry| entry.file_name().to_str().unwrap().ends_with(".data"))
+        .collect();
+    assert_eq!(data_files.len(), 1);
+    assert_eq!(
+        data_files[0].file_name().to_str().unwrap(),
+        "000000000.data"
+    );
+    assert_eq!(data_files[0].metadata().unwrap().len(), 0);
+
+    // 3.清空后重新写入，重启后数据正确
+    let res4 = engine.put(get_test_key(22), get_test_value(22));
+    assert!(res4.is_ok());
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(engine2.list_keys().len(), 1);
+    let res5 = engine2.get(get_test_key(22));
+    assert_eq!(get_test_value(22), res5.unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_index_type_mismatch() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-index-type");
+    opts.index_type = IndexType::BPlusTree;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let res1 = engine.put(get_test_key(11), get_test_value(11));
+    assert!(res1.is_ok());
+    std::mem::drop(engine);
+
+    // 1.b+树索引的数据目录使用其他索引类型打开
+    opts.index_type = IndexType::SkipList;
+    let res2 = Engine::open(opts.clone());
+    assert_eq!(Errors::IndexTypeMismatch, res2.err().unwrap());
+
+    // 2.使用相同的索引类型重新打开
+    opts.index_type = IndexType::BPlusTree;
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let res3 = engine2.get(get_test_key(11));
+    assert_eq!(get_test_value(11), res3.unwrap());
+    std::mem::drop(engine2);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+
+    // 3.内存索引之间可以相互切换，但不能切换为 b+树索引
+    opts.index_type = IndexType::BTree;
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    let res4 = engine3.put(get_test_key(22), get_test_value(22));
+    assert!(res4.is_ok());
+    std::mem::drop(engine3);
+
+    opts.index_type = IndexType::SkipList;
+    let engine4 = Engine::open(opts.clone()).expect("failed to open engine");
+    let res5 = engine4.get(get_test_key(22));
+    assert_eq!(get_test_value(22), res5.unwrap());
+    std::mem::drop(engine4);
+
+    opts.index_type = IndexType::BPlusTree;
+    let res6 = Engine::open(opts.clone());
+    assert_eq!(Errors::IndexTypeMismatch, res6.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_data_file_size_shrink() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-data-file-size-shrink");
+    opts.data_file_size = 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(128));
+        assert!(put_res.is_ok());
+    }
+    std::mem::drop(engine);
+
+    // 1.配置了返回错误时，使用更小的数据文件大小打开返回错误
+    opts.data_file_size = 64 * 1024;
+    opts.error_on_data_file_size_shrink = true;
+    let res1 = Engine::open(opts.clone());
+    assert_eq!(Errors::DataFileSizeShrunk, res1.err().unwrap());
+
+    // 2.默认只给出警告，可以正常打开
+    opts.error_on_data_file_size_shrink = false;
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(1000, engine2.list_keys().len());
+    std::mem::drop(engine2);
+
+    // 3.记录的是使用过的最大的数据文件大小，之后仍然可以检查出来
+    opts.error_on_data_file_size_shrink = true;
+    let res3 = Engine::open(opts.clone());
+    assert_eq!(Errors::DataFileSizeShrunk, res3.err().unwrap());
+
+    // 4.使用相同或者更大的数据文件大小打开
+    opts.data_file_size = 1024 * 1024;
+    let engine4 = Engine::open(opts.clone()).expect("failed to open engine");
+    std::mem::drop(engine4);
+    opts.data_file_size = 4 * 1024 * 1024;
+    let engine5 = Engine::open(opts.clone()).expect("failed to open engine");
+    std::mem::drop(engine5);
+    opts.data_file_size = 1024 * 1024;
+    let res6 = Engine::open(opts.clone());
+    assert_eq!(Errors::DataFileSizeShrunk, res6.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_db_handle_shared() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-db-handle");
+    let db = Db::open(opts.clone()).expect("failed to open engine");
+
+    // 1.多个线程使用 clone 的句柄并发写入，线程结束时释放句柄
+    let mut handles = Vec::new();
+    for t in 0..4 {
+        let db = db.clone();
+        handles.push(std::thread::spawn(move || {
+            for i in t * 100..(t + 1) * 100 {
+                let put_res = db.put(get_test_key(i), get_test_value(i));
+                assert!(put_res.is_ok());
+            }
+        }));
+    }
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    assert_eq!(400, db.engine().list_keys().len());
+
+    // 2.还有其他句柄时 close 只释放当前句柄，存储引擎仍然可用
+    let db2 = db.clone();
+    assert!(!db2.close().unwrap());
+    let res1 = Engine::open(opts.clone());
+    assert_eq!(Errors::DatabaseIsUsing, res1.err().unwrap());
+    assert_eq!(get_test_value(1), db.get(get_test_key(1)).unwrap());
+
+    // 3.最后一个句柄关闭存储引擎
+    assert!(db.close().unwrap());
+    let db3 = Db::open(opts.clone()).expect("failed to open engine");
+    let iter = db3.iter(IteratorOptions::default());
+    let mut count = 0;
+    while iter.next_key_with(|_| ()).is_some() {
+        count += 1;
+    }
+    assert_eq!(400, count);
+
+    // 4.最后一个句柄 Drop 时也会关闭存储引擎
+    let db4 = db3.clone();
+    std::mem::drop(db3);
+    let delete_res = db4.delete(get_test_key(1));
+    assert!(delete_res.is_ok());
+    std::mem::drop(db4);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(399, engine.list_keys().len());
+    std::mem::drop(engine);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_total_write_counters() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-total-write-counters");
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let stat1 = engine.stat().unwrap();
+    assert_eq!(0, stat1.total_bytes_written);
+    assert_eq!(0, stat1.total_records_written);
+
+    // 1.第一次打开时写入的数据
+    for i in 0..10 {
+        let put_res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(put_res.is_ok());
+    }
+    let stat2 = engine.stat().unwrap();
+    assert!(stat2.total_bytes_written > 0);
+    assert_eq!(10, stat2.total_records_written);
+    engine.close().expect("failed to close");
+    std::mem::drop(engine);
+
+    // 2.重新打开后从之前记录的值继续累计
+    opts.sync_on_drop = false;
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let stat3 = engine2.stat().unwrap();
+    assert_eq!(stat2.total_bytes_written, stat3.total_bytes_written);
+    assert_eq!(10, stat3.total_records_written);
+    for i in 0..10 {
+        let put_res = engine2.put(get_test_key(i), get_test_value(i));
+        assert!(put_res.is_ok());
+    }
+    let del_res = engine2.delete(get_test_key(0));
+    assert!(del_res.is_ok());
+    let stat4 = engine2.stat().unwrap();
+    assert!(stat4.total_bytes_written > stat3.total_bytes_written * 2);
+    assert_eq!(21, stat4.total_records_written);
+
+    // 3.检查点时也会记录，没有正常关闭时从检查点记录的值继续累计
+    engine2.checkpoint().expect("failed to checkpoint");
+    let put_res = engine2.put(get_test_key(100), get_test_value(100));
+    assert!(put_res.is_ok());
+    std::mem::drop(engine2);
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    let stat5 = engine3.stat().unwrap();
+    assert_eq!(stat4.total_bytes_written, stat5.total_bytes_written);
+    assert_eq!(21, stat5.total_records_written);
+    std::mem::drop(engine3);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+// 写入 1000 条数据后关闭存储引擎，返回每个 key 的位置信息，用于之后注入损坏的数据
+fn write_corruption_test_data(opts: &Options) -> Vec<LogRecordPos> {
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(put_res.is_ok());
+    }
+    let positions = (0..1000)
+        .map(|i| engine.get_log_record_pos(&get_test_key(i)).unwrap())
+        .collect();
+    engine.close().expect("failed to close");
+    positions
+}
+
+// 修改数据文件中指定偏移处的一个字节
+fn corrupt_data_file(opts: &Options, file_id: u32, offset: u64, f: impl Fn(u8) -> u8) {
+    let file_path = get_data_file_path(opts.dir_path.clone(), file_id, &opts.file_suffix);
+    let mut data = std::fs::read(&file_path).unwrap();
+    data[offset as usize] = f(data[offset as usize]);
+    std::fs::write(file_path, data).unwrap();
+}
+
+// 修改数据的 value 的最后一个字节，使 CRC 校验失败
+fn corrupt_record_value(opts: &Options, pos: &LogRecordPos) {
+    corrupt_data_file(opts, pos.file_id, pos.offset + pos.size as u64 - 5, |b| !b);
+}
+
+fn data_file_len(opts: &Options, file_id: u32) -> u64 {
+    let file_path = get_data_file_path(opts.dir_path.clone(), file_id, &opts.file_suffix);
+    std::fs::metadata(file_path).unwrap().len()
+}
+
+#[test]
+fn test_engine_corruption_fail() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-corruption-fail");
+    opts.data_file_size = 64 * 1024;
+    let positions = write_corruption_test_data(&opts);
+    corrupt_record_value(&opts, &positions[100]);
+
+    // 默认遇到损坏的数据时无法打开
+    assert_eq!(CorruptionPolicy::Fail, opts.on_corruption);
+    let res = Engine::open(opts.clone());
+    assert_eq!(Errors::InvalidLogRecordCrc, res.err().unwrap());
+
+    // 打开失败时释放文件锁，数据文件保持不变
+    let res2 = Engine::open(opts.clone());
+    assert_eq!(Errors::InvalidLogRecordCrc, res2.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_corruption_skip_record() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-corruption-skip-record");
+    opts.data_file_size = 64 * 1024;
+    let positions = write_corruption_test_data(&opts);
+    let active_fid = positions[999].file_id;
+    assert!(positions[100].file_id < active_fid);
+    assert_eq!(active_fid, positions[900].file_id);
+
+    // 旧数据文件中 value 损坏的数据，以及 key 长度损坏、无法按 header 跳过的数据，活跃文件中 value 损坏的数据
+    corrupt_record_value(&opts, &positions[100]);
+    corrupt_data_file(
+        &opts,
+        positions[200].file_id,
+        positions[200].offset + 1,
+        |_| 0x7f,
+    );
+    corrupt_record_value(&opts, &positions[900]);
+    let file_lens: Vec<u64> = (0..=active_fid)
+        .map(|fid| data_file_len(&opts, fid))
+        .collect();
+
+    // 跳过损坏的数据，其他数据都能正常读取，数据文件保持不变
+    opts.on_corruption = CorruptionPolicy::SkipRecord;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+        let get_res = engine.get(get_test_key(i));
+        match i {
+            100 | 200 | 900 => assert_eq!(Errors::KeyIsNotFound, get_res.err().unwrap()),
+            _ => assert_eq!(get_test_value(i), get_res.unwrap()),
+        }
+    }
+    for fid in 0..=active_fid {
+        assert_eq!(file_lens[fid as usize], data_file_len(&opts, fid));
+    }
+    assert!(engine.stat().unwrap().reclaim_size >= positions[100].size as usize * 3);
+
+    // 之后写入的数据追加在活跃文件末尾
+    let put_res = engine.put(get_test_key(900), get_test_value(900));
+    assert!(put_res.is_ok());
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(998, engine2.list_keys().len());
+    assert_eq!(get_test_value(900), engine2.get(get_test_key(900)).unwrap());
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_corruption_truncate() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-corruption-truncate");
+    opts.data_file_size = 64 * 1024;
+    let positions = write_corruption_test_data(&opts);
+    let (older_fid, active_fid) = (positions[100].file_id, positions[900].file_id);
+    assert!(older_fid < active_fid);
+    corrupt_record_value(&opts, &positions[100]);
+    corrupt_record_value(&opts, &positions[900]);
+
+    // 只读模式下忽略损坏的数据及其之后的数据，不截断数据文件
+    opts.on_corruption = CorruptionPolicy::Truncate;
+    opts.read_only = true;
+    let file_len = data_file_len(&opts, older_fid);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(file_len, data_file_len(&opts, older_fid));
+    std::mem::drop(engine);
+
+    // 同一个文件中损坏位置之后的数据都被忽略，数据文件截断到损坏的位置
+    opts.read_only = false;
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let mut key_num = 0;
+    for (i, pos) in positions.iter().enumerate() {
+        let get_res = engine2.get(get_test_key(i));
+        if (pos.file_id == older_fid && i < 100) || (pos.file_id == active_fid && i < 900) {
+            assert_eq!(get_test_value(i), get_res.unwrap());
+            key_num += 1;
+        } else if pos.file_id == older_fid || pos.file_id == active_fid {
+            assert_eq!(Errors::KeyIsNotFound, get_res.err().unwrap());
+        }
+    }
+    assert_eq!(positions[100].offset, data_file_len(&opts, older_fid));
+    assert_eq!(positions[900].offset, data_file_len(&opts, active_fid));
+    let put_res = engine2.put(get_test_key(900), get_test_value(900));
+    assert!(put_res.is_ok());
+    std::mem::drop(engine2);
+
+    // 损坏的数据已经被截断，默认配置也可以打开
+    opts.on_corruption = CorruptionPolicy::Fail;
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine3.list_keys().len() > key_num);
+    assert_eq!(get_test_value(900), engine3.get(get_test_key(900)).unwrap());
+    std::mem::drop(engine3);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_corruption_invalid_key() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-corruption-invalid-key");
+    opts.data_file_size = 64 * 1024;
+    let positions = write_corruption_test_data(&opts);
+    let active_fid = positions[999].file_id;
+    let file_len = data_file_len(&opts, active_fid);
+
+    // 在活跃文件末尾追加一条 CRC 正确、但 key 开头不是合法变长编码的数据
+    let mut key = vec![0xff; 11];
+    key.extend_from_slice(b"malformed");
+    let record = LogRecord {
+        key,
+        value: get_test_value(1).to_vec(),
+        rec_type: LogRecordType::NOAMAL,
+    };
+    let mut data_file = DataFile::new_with_suffix(
+        opts.dir_path.clone(),
+        active_fid,
+        IOType::FileIO,
+        &opts.file_suffix,
+        false,
+    )
+    .unwrap();
+    assert!(data_file.write(&record.encode()).is_ok());
+    std::mem::drop(data_file);
+
+    // 默认返回错误，不会 panic
+    let res = Engine::open(opts.clone());
+    assert_eq!(Errors::InvalidLogRecordKey, res.err().unwrap());
+
+    // 跳过这条数据，活跃文件末尾的数据被截断
+    opts.on_corruption = CorruptionPolicy::SkipRecord;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(1000, engine.list_keys().len());
+    assert_eq!(file_len, data_file_len(&opts, active_fid));
+    std::mem::drop(engine);
+
+    opts.on_corruption = CorruptionPolicy::Fail;
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(get_test_value(999), engine2.get(get_test_key(999)).unwrap());
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_max_record_size() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-max-record-size");
+    opts.data_file_size = 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let res1 = engine.put(Bytes::from("key-1"), Bytes::from(vec![b'a'; 100]));
+    assert!(res1.is_ok());
+    let record_size = engine.stat().unwrap().total_bytes_written;
+    std::mem::drop(engine);
+
+    // 1.编码后的大小等于上限时可以写入
+    opts.max_record_size = Some(record_size);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let res2 = engine2.put(Bytes::from("key-2"), Bytes::from(vec![b'b'; 100]));
+    assert!(res2.is_ok());
+
+    // 2.超过上限时返回错误，不写入数据也不切换活跃文件
+    let file_ids = engine2.data_file_ids();
+    let stat1 = engine2.stat().unwrap();
+    let res3 = engine2.put(Bytes::from("key-3"), Bytes::from(vec![b'c'; 101]));
+    assert_eq!(Errors::RecordTooLarge, res3.err().unwrap());
+    let res4 = engine2.put(Bytes::from("key-4"), Bytes::from(vec![b'd'; 4096]));
+    assert_eq!(Errors::RecordTooLarge, res4.err().unwrap());
+    let stat2 = engine2.stat().unwrap();
+    assert_eq!(stat1.total_bytes_written, stat2.total_bytes_written);
+    assert_eq!(stat1.total_records_written, stat2.total_records_written);
+    assert_eq!(file_ids, engine2.data_file_ids());
+    assert_eq!(
+        Errors::KeyIsNotFound,
+        engine2.get(Bytes::from("key-3")).err().unwrap()
+    );
+    assert_eq!(2, engine2.list_keys().len());
+
+    // 3.删除数据时写入的记录更小，不受影响
+    let res5 = engine2.delete(Bytes::from("key-1"));
+    assert!(res5.is_ok());
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_max_unsynced_bytes() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-max-unsynced");
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.不设置上限时不会自动持久化
+    let res1 = engine.put(get_test_key(1), get_test_value(1));
+    assert!(res1.is_ok());
+    let record_size = engine.bytes_write.load(Ordering::SeqCst);
+    assert!(record_size > 0);
+    for i in 2..=10 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    assert_eq!(engine.bytes_write.load(Ordering::SeqCst), record_size * 10);
+    std::mem::drop(engine);
+
+    // 2.每写入 3 条数据持久化一次
+    opts.max_unsynced_bytes = Some(record_size * 3);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 1..=10 {
+        let res = engine2.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+        assert_eq!(
+            engine2.bytes_write.load(Ordering::SeqCst),
+            (i % 3) * record_size
+        );
+    }
+
+    // 3.手动持久化后重新计数
+    let res2 = engine2.sync();
+    assert!(res2.is_ok());
+    assert_eq!(engine2.bytes_write.load(Ordering::SeqCst), 0);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_get_with() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-get-with");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.正常读取一条数据
+    let res1 = engine.put(get_test_key(111), get_test_value(111));
+    assert!(res1.is_ok());
+    let res2 = engine.get_with(get_test_key(111), |value| value.to_vec());
+    assert_eq!(get_test_value(111).to_vec(), res2.unwrap());
+
+    // 2.读取不存在的 key 和空的 key
+    let res3 = engine.get_with(Bytes::from("not existed key"), |value| value.len());
+    assert_eq!(Errors::KeyIsNotFound, res3.err().unwrap());
+    let res4 = engine.get_with(Bytes::new(), |value| value.len());
+    assert_eq!(Errors::KeyIsEmpty, res4.err().unwrap());
+
+    // 3.值被删除后读取，回调函数不会被调用
+    let res5 = engine.delete(get_test_key(111));
+    assert!(res5.is_ok());
+    let res6 = engine.get_with(get_test_key(111), |_| panic!("should not be called"));
+    assert_eq!(Errors::KeyIsNotFound, res6.err().unwrap());
+
+    // 4.从旧的数据文件中读取
+    for i in 0..=1000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    let res7 = engine.get_with(get_test_key(5), |value| value == get_test_value(5));
+    assert!(res7.unwrap());
+
+    // 5.value 为空
+    let res8 = engine.put(get_test_key(2000), Bytes::new());
+    assert!(res8.is_ok());
+    let res9 = engine.get_with(get_test_key(2000), |value| value.len());
+    assert_eq!(0, res9.unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_resolve_value() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-resolve-value");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.直接保存在记录中的 value，各个读取路径得到的结果一致
+    let res1 = engine.put(get_test_key(1), get_test_value(1));
+    assert!(res1.is_ok());
+    let pos = engine.get_log_record_pos(&get_test_key(1)).unwrap();
+    let record = engine.read_record_at(&pos).unwrap();
+    assert_eq!(LogRecordType::NOAMAL, record.rec_type);
+    assert_eq!(get_test_value(1), engine.resolve_value(&record).unwrap());
+    assert_eq!(
+        get_test_value(1),
+        engine.get_value_by_position(pos).unwrap()
+    );
+    let (key, value) = engine.read_key_value_at(&pos).unwrap();
+    assert_eq!(get_test_value(1).to_vec(), value);
+    assert_eq!(get_test_key(1).to_vec(), key);
+    let mut buf = Vec::new();
+    let res2 = engine.get_into(&get_test_key(1), &mut buf);
+    assert!(res2.is_ok());
+    assert_eq!(get_test_value(1).to_vec(), buf);
+
+    // 2.覆盖写入之后读取新的 value，旧的记录仍然可以解析出旧的 value
+    let res3 = engine.put(get_test_key(1), get_test_value(2));
+    assert!(res3.is_ok());
+    let new_pos = engine.get_log_record_pos(&get_test_key(1)).unwrap();
+    assert_eq!(
+        get_test_value(2),
+        engine.get_value_by_position(new_pos).unwrap()
+    );
+    assert_eq!(
+        get_test_value(1),
+        engine.get_value_by_position(pos).unwrap()
+    );
+
+    // 3.删除的记录解析时返回 KeyIsNotFound
+    let res4 = engine.delete(get_test_key(1));
+    assert!(res4.is_ok());
+    let deleted = LogRecord {
+        key: get_test_key(1).to_vec(),
+        value: Default::default(),
+        rec_type: LogRecordType::DELETED,
+    };
+    assert_eq!(
+        Errors::KeyIsNotFound,
+        engine.resolve_value(&deleted).err().unwrap()
+    );
+
+    // 4.value 为空
+    let res5 = engine.put(get_test_key(3), Bytes::new());
+    assert!(res5.is_ok());
+    let pos = engine.get_log_record_pos(&get_test_key(3)).unwrap();
+    let record = engine.read_record_at(&pos).unwrap();
+    assert_eq!(Bytes::new(), engine.resolve_value(&record).unwrap());
+
+    // 5.从旧的数据文件中读取，重启之后结果不变
+    for i in 10..=1000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let pos = engine2.get_log_record_pos(&get_test_key(10)).unwrap();
+    assert_ne!(pos.file_id, engine2.active_file.read().get_file_id());
+    let record = engine2.read_record_at(&pos).unwrap();
+    assert_eq!(get_test_value(10), engine2.resolve_value(&record).unwrap());
+    assert_eq!(get_test_value(10), engine2.get(get_test_key(10)).unwrap());
+    assert_eq!(
+        Errors::KeyIsNotFound,
+        engine2.get(get_test_key(1)).err().unwrap()
+    );
+
+    std::mem::drop(engine2);
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_get_stale_index_entry() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-get-stale-index-entry");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..3 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    let res1 = engine.sync();
+    assert!(res1.is_ok());
+
+    // 从外部截断数据文件，key-1 只保留一半，key-2 整条数据超出文件末尾
+    let (file_id, offset, size) = engine.locate(&get_test_key(1)).unwrap();
+    let file_path = get_data_file_path(opts.dir_path.clone(), file_id, DATA_FILE_NAME_SUFFIX);
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(file_path)
+        .unwrap();
+    assert!(file.set_len(offset + size as u64 / 2).is_ok());
+
+    assert_eq!(get_test_value(0), engine.get(get_test_key(0)).unwrap());
+    assert_eq!(
+        Errors::StaleIndexEntry,
+        engine.get(get_test_key(1)).err().unwrap()
+    );
+    assert_eq!(
+        Errors::StaleIndexEntry,
+        engine.get(get_test_key(2)).err().unwrap()
+    );
+    let res2 = engine.get_with(get_test_key(2), |value| value.len());
+    assert_eq!(Errors::StaleIndexEntry, res2.err().unwrap());
+
+    std::mem::drop(engine);
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_close_release_lock_on_error() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-close-release-lock");
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let res1 = engine.put(get_test_key(11), get_test_value(11));
+    assert!(res1.is_ok());
+
+    // 1.用同名目录占据 MANIFEST 临时文件的位置，模拟写入事务序列号失败
+    let seq_no_path = opts.dir_path.join(MANIFEST_TMP_FILE_NAME);
+    std::fs::create_dir(seq_no_path.clone()).expect("failed to create dir");
+    let res2 = engine.close();
+    assert!(res2.is_err());
+
+    // close 失败后文件锁已释放，可以重新打开
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let res3 = engine2.get(get_test_key(11));
+    assert_eq!(get_test_value(11), res3.unwrap());
+
+    // 2.Drop 时写入事务序列号失败，也能释放文件锁
+    std::mem::drop(engine2);
+    let res4 = Engine::open(opts.clone());
+    assert!(res4.is_ok());
+    std::mem::drop(res4);
+
+    std::fs::remove_dir(seq_no_path).expect("failed to remove dir");
+    std::mem::drop(engine);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_concurrent_put() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-concurrent-put");
+    opts.data_file_size = 64 * 1024;
+    opts.sync_writes = true;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 多个线程同时写入相同的 key 和不同的 key
+    std::thread::scope(|s| {
+        for t in 0..8 {
+            let engine = &engine;
+            s.spawn(move || {
+                for i in 0..200 {
+                    let res1 = engine.put(get_test_key(0), get_test_value(t * 1000 + i));
+                    assert!(res1.is_ok());
+                    let res2 = engine.put(get_test_key(t * 1000 + i + 1), get_test_value(i));
+                    assert!(res2.is_ok());
+                }
+            });
+        }
+    });
+
+    let keys = engine.list_keys();
+    assert_eq!(8 * 200 + 1, keys.len());
+
+    // 重启后索引指向的数据和重启前一致
+    let val1 = engine.get(get_test_key(0)).unwrap();
+    engine.close().expect("failed to close");
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let val2 = engine2.get(get_test_key(0)).unwrap();
+    assert_eq!(val1, val2);
+    assert_eq!(8 * 200 + 1, engine2.list_keys().len());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_open_io_error() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-open-io-error");
+    std::fs::create_dir_all(opts.dir_path.join(FILE_LOCK_NAME)).expect("failed to create dir");
+
+    // 文件锁的位置被同名目录占据，打开时返回 io 错误
+    let res1 = Engine::open(opts.clone());
+    assert!(res1.is_err());
+    let err = res1.err().unwrap();
+    assert!(matches!(err, Errors::Io(_)));
+    assert!(err.io_error().is_some());
+
+    // 可以通过 source 拿到原始的 io 错误
+    let source = std::error::Error::source(&err).unwrap();
+    let io_err = source.downcast_ref::<IoError>().unwrap();
+    assert_eq!(io_err.kind(), err.io_error().unwrap().kind());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_open_dir_path_is_file() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-dir-path-is-file");
+    std::fs::write(&opts.dir_path, b"not a directory").expect("failed to create file");
+
+    // 数据目录的路径上是一个普通文件
+    let res = Engine::open(opts.clone());
+    assert_eq!(Errors::DirPathIsNotADirectory, res.err().unwrap());
+    assert!(opts.dir_path.is_file());
+
+    // 删除测试的文件
+    std::fs::remove_file(opts.clone().dir_path).expect("failed to remove file");
+}
+
+#[test]
+fn test_engine_rebuild_keys_from_data_files() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-rebuild-keys");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..1000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    for i in 0..100 {
+        let res = engine.delete(get_test_key(i * 3));
+        assert!(res.is_ok());
+    }
+
+    let file_ids = engine.data_file_ids();
+    assert!(file_ids.len() > 1);
+    assert!(file_ids.windows(2).all(|w| w[0] < w[1]));
+
+    // 通过公开的接口遍历每个数据文件，重建 key 集合
+    let mut keys = HashSet::new();
+    for file_id in file_ids {
+        let data_file = DataFile::new(opts.dir_path.clone(), file_id, IOType::FileIO)
+            .expect("failed to open data file");
+        let mut last_offset = None;
+        for item in data_file.iter_records() {
+            let (record, offset) = item.expect("failed to read record");
+            if let Some(last_offset) = last_offset {
+                assert!(offset > last_offset);
+            } else {
+                assert_eq!(offset, 0);
+            }
+            last_offset = Some(offset);
+
+            let (real_key, _) = parse_log_record_key(record.key().to_vec()).unwrap();
+            match record.rec_type() {
+                LogRecordType::NOAMAL | LogRecordType::BLOBPOINTER => {
+                    keys.insert(Bytes::from(real_key));
+                }
+                LogRecordType::DELETED => {
+                    keys.remove(&Bytes::from(real_key));
+                }
+                LogRecordType::TXNFINISHED => {}
+            }
+        }
+    }
+
+    let index_keys: HashSet<Bytes> = engine.list_keys().into_iter().collect();
+    assert_eq!(900, keys.len());
+    assert_eq!(index_keys, keys);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_hash_keys_in_index() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-hash-keys-in-index");
+    opts.data_file_size = 64 * 1024;
+    opts.hash_keys_in_index = true;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 长 key 在索引中只保存 16 字节的 hash
+    let long_key = |i: usize| Bytes::from(std::format!("https://example.com/{:0>200}", i));
+    for i in 0..500 {
+        let res = engine.put(long_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    assert!(engine.index.list_keys().iter().all(|k| k.len() == 16));
+    for i in 0..500 {
+        assert_eq!(get_test_value(i), engine.get(long_key(i)).unwrap());
+    }
+
+    // 覆盖写入和删除
+    let res1 = engine.put(long_key(1), get_test_value(1001));
+    assert!(res1.is_ok());
+    let res2 = engine.delete(long_key(2));
+    assert!(res2.is_ok());
+    assert_eq!(get_test_value(1001), engine.get(long_key(1)).unwrap());
+    assert_eq!(
+        Errors::KeyIsNotFound,
+        engine.get(long_key(2)).err().unwrap()
+    );
+
+    // list_keys 和迭代器返回完整的 key
+    let keys = engine.list_keys();
+    assert_eq!(499, keys.len());
+    assert!(keys.contains(&long_key(1)));
+    let count = std::cell::Cell::new(0);
+    engine.fold(|key, value| {
+        assert!(key.starts_with(b"https://example.com/"));
+        assert!(!value.is_empty());
+        count.set(count.get() + 1);
+        true
+    });
+    assert_eq!(499, count.get());
+
+    // 重启之后校验
+    engine.close().expect("failed to close");
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(499, engine2.list_keys().len());
+    assert_eq!(get_test_value(1001), engine2.get(long_key(1)).unwrap());
+    assert_eq!(
+        Errors::KeyIsNotFound,
+        engine2.get(long_key(2)).err().unwrap()
+    );
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_hash_keys_in_index_collision() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-hash-keys-collision");
+    opts.hash_keys_in_index = true;
+    let mut engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 所有 key 的 hash 都相同，强制产生冲突
+    engine.key_hasher = |_| [0u8; 16];
+
+    for i in 0..3 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    assert_eq!(3, engine.index.list_keys().len());
+    for i in 0..3 {
+        assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+    }
+
+    // 覆盖写入冲突的 key
+    let res1 = engine.put(get_test_key(1), get_test_value(11));
+    assert!(res1.is_ok());
+    assert_eq!(get_test_value(11), engine.get(get_test_key(1)).unwrap());
+    assert_eq!(3, engine.index.list_keys().len());
+
+    // 删除占据 hash 位置的 key，冲突的 key 仍然可以读取
+    let res2 = engine.delete(get_test_key(0));
+    assert!(res2.is_ok());
+    assert_eq!(
+        Errors::KeyIsNotFound,
+        engine.get(get_test_key(0)).err().unwrap()
+    );
+    assert_eq!(get_test_value(11), engine.get(get_test_key(1)).unwrap());
+    assert_eq!(get_test_value(2), engine.get(get_test_key(2)).unwrap());
+
+    // 删除不存在的 key 不影响冲突的 key
+    let res3 = engine.delete(get_test_key(0));
+    assert!(res3.is_ok());
+    assert_eq!(2, engine.index.list_keys().len());
+
+    // 冲突的 key 再次写入，不会产生重复的索引
+    let res4 = engine.put(get_test_key(2), get_test_value(22));
+    assert!(res4.is_ok());
+    let res5 = engine.put(get_test_key(3), get_test_value(3));
+    assert!(res5.is_ok());
+    assert_eq!(3, engine.index.list_keys().len());
+    assert_eq!(get_test_value(22), engine.get(get_test_key(2)).unwrap());
+
+    // 事务中写入冲突的 key
+    let res6 = engine.write_batch(vec![
+        (get_test_key(4), Some(get_test_value(4))),
+        (get_test_key(5), Some(get_test_value(5))),
+        (get_test_key(3), None),
+    ]);
+    assert!(res6.is_ok());
+    assert_eq!(get_test_value(4), engine.get(get_test_key(4)).unwrap());
+    assert_eq!(get_test_value(5), engine.get(get_test_key(5)).unwrap());
+    assert_eq!(
+        Errors::KeyIsNotFound,
+        engine.get(get_test_key(3)).err().unwrap()
+    );
+
+    let mut keys = engine.list_keys();
+    keys.sort();
+    assert_eq!(
+        vec![
+            get_test_key(1),
+            get_test_key(2),
+            get_test_key(4),
+            get_test_key(5)
+        ],
+        keys
+    );
+
+    // 重启之后使用正常的 hash 重建索引
+    engine.close().expect("failed to close");
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(4, engine2.list_keys().len());
+    assert_eq!(get_test_value(11), engine2.get(get_test_key(1)).unwrap());
+    assert_eq!(get_test_value(22), engine2.get(get_test_key(2)).unwrap());
+    assert_eq!(
+        Errors::KeyIsNotFound,
+        engine2.get(get_test_key(3)).err().unwrap()
+    );
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_hash_keys_in_index_with_bptree() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-hash-keys-bptree");
+    opts.hash_keys_in_index = true;
+    opts.index_type = IndexType::BPlusTree;
+    let res = Engine::open(opts.clone());
+    assert_eq!(Errors::HashKeysInIndexUnsupported, res.err().unwrap());
+}
+
+#[test]
+fn test_engine_read_only_with_bptree() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-read-only-bptree");
+    opts.index_type = IndexType::BPlusTree;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let res1 = engine.put(get_test_key(1), get_test_value(1));
+    assert!(res1.is_ok());
+    std::mem::drop(engine);
+
+    // b+树索引无法只读打开，直接返回错误
+    opts.read_only = true;
+    let res2 = Engine::open(opts.clone());
+    assert_eq!(Errors::ReadOnlyBPlusTreeUnsupported, res2.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_key_normalizer() {
+    // 不区分大小写查找，保存转换为小写之后的 key
+    let opts = Options::builder()
+        .dir_path("/tmp/bitcask-rs-key-normalizer")
+        .key_normalizer(|key: &[u8]| key.to_ascii_lowercase())
+        .build()
+        .expect("invalid options");
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let res1 = engine.put(Bytes::from("key"), get_test_value(1));
+    assert!(res1.is_ok());
+    assert_eq!(get_test_value(1), engine.get(Bytes::from("KEY")).unwrap());
+    assert_eq!(get_test_value(1), engine.get(Bytes::from("Key")).unwrap());
+
+    // 大小写不同的 key 覆盖写入同一个 key，迭代时返回转换后的 key
+    let res2 = engine.put(Bytes::from("KEY"), get_test_value(2));
+    assert!(res2.is_ok());
+    assert_eq!(get_test_value(2), engine.get(Bytes::from("key")).unwrap());
+    assert_eq!(vec![Bytes::from("key")], engine.list_keys());
+
+    // 事务中的写入同样转换 key
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .expect("failed to create write batch");
+    assert!(wb.put(Bytes::from("Other"), get_test_value(3)).is_ok());
+    assert!(wb.commit().is_ok());
+    assert_eq!(get_test_value(3), engine.get(Bytes::from("OTHER")).unwrap());
+
+    // 定位、版本、长度和流式读取同样转换 key
+    assert_eq!(engine.locate(b"key"), engine.locate(b"KEY"));
+    assert!(engine.locate(b"KEY").is_some());
+    assert_eq!(engine.key_version(b"key"), engine.key_version(b"Key"));
+    assert!(engine.key_version(b"Key").is_some());
+    assert_eq!(get_test_value(2).len(), engine.value_len(b"KEY").unwrap());
+    let mut buf = Vec::new();
+    let mut reader = engine.get_reader(b"KeY").unwrap();
+    assert!(std::io::Read::read_to_end(&mut reader, &mut buf).is_ok());
+    assert_eq!(get_test_value(2).to_vec(), buf);
+    let res3 = engine.compact_key(b"OTHER");
+    assert!(res3.is_ok());
+    assert_eq!(get_test_value(3), engine.get(Bytes::from("other")).unwrap());
+
+    // increment 和 get 读写同一个 key
+    assert_eq!(5, engine.increment(Bytes::from("Counter"), 5).unwrap());
+    assert_eq!(7, engine.increment(Bytes::from("COUNTER"), 2).unwrap());
+    assert_eq!(
+        Bytes::copy_from_slice(&7i64.to_le_bytes()),
+        engine.get(Bytes::from("counter")).unwrap()
+    );
+    let res4 = engine.delete(Bytes::from("counter"));
+    assert!(res4.is_ok());
+
+    let res5 = engine.delete(Bytes::from("kEy"));
+    assert!(res5.is_ok());
+    assert_eq!(
+        Errors::KeyIsNotFound,
+        engine.get(Bytes::from("key")).err().unwrap()
+    );
+
+    // 重新打开后仍然可以不区分大小写查找
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(
+        get_test_value(3),
+        engine2.get(Bytes::from("other")).unwrap()
+    );
+    assert_eq!(vec![Bytes::from("other")], engine2.list_keys());
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_shard_fn() {
+    // 按 ':' 之前的前缀分段，相同前缀的 key 落在同一个分段上
+    let opts = Options::builder()
+        .dir_path("/tmp/bitcask-rs-shard-fn")
+        .shard_fn(|key: &[u8]| {
+            let prefix = key.split(|b| *b == b':').next().unwrap();
+            prefix.iter().map(|b| *b as usize).sum()
+        })
+        .build()
+        .expect("invalid options");
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    assert_eq!(
+        engine.key_lock_index(b"user1:name"),
+        engine.key_lock_index(b"user1:email")
+    );
+    assert_ne!(
+        engine.key_lock_index(b"user1:name"),
+        engine.key_lock_index(b"user2:name")
+    );
+
+    // 读写不受分段方式影响
+    let wb = engine
+        .new_write_batch(WriteBatchOptions::default())
+        .expect("failed to create write batch");
+    let keys = ["user1:name", "user1:email", "user2:name"];
+    assert!(wb.put(Bytes::from(keys[0]), get_test_value(0)).is_ok());
+    assert!(wb.put(Bytes::from(keys[1]), get_test_value(1)).is_ok());
+    assert!(wb.commit().is_ok());
+    assert!(engine.put(Bytes::from(keys[2]), get_test_value(2)).is_ok());
+    for (i, key) in keys.iter().enumerate() {
+        assert_eq!(get_test_value(i), engine.get(Bytes::from(*key)).unwrap());
+    }
+    std::mem::drop(engine);
+
+    // 默认按 key 的 hash 分段，结果稳定
+    let mut opts2 = Options::default();
+    opts2.dir_path = PathBuf::from("/tmp/bitcask-rs-shard-fn-default");
+    let engine2 = Engine::open(opts2.clone()).expect("failed to open engine");
+    assert_eq!(
+        engine2.key_lock_index(b"user1:name"),
+        engine2.key_lock_index(b"user1:name")
+    );
+    std::mem::drop(engine2);
+
+    // 不能和 hash_keys_in_index 一起使用
+    let mut opts3 = opts.clone();
+    opts3.hash_keys_in_index = true;
+    let res = Engine::open(opts3);
+    assert_eq!(Errors::ShardFnWithHashKeysUnsupported, res.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    std::fs::remove_dir_all(opts2.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_key_version() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-key-version");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 不存在的 key 和空的 key
+    assert_eq!(None, engine.key_version(&get_test_key(1)));
+    assert_eq!(None, engine.key_version(&[]));
+
+    let put_res1 = engine.put(get_test_key(1), get_test_value(1));
+    assert!(put_res1.is_ok());
+    let version1 = engine.key_version(&get_test_key(1));
+    assert!(version1.is_some());
+    // 没有写入时版本不变
+    assert_eq!(version1, engine.key_version(&get_test_key(1)));
+
+    // 覆盖写入后版本改变，即使 value 相同
+    let put_res2 = engine.put(get_test_key(1), get_test_value(1));
+    assert!(put_res2.is_ok());
+    let version2 = engine.key_version(&get_test_key(1));
+    assert!(version2.is_some());
+    assert_ne!(version1, version2);
+
+    // 删除后没有版本，重新写入后得到新的版本
+    let del_res = engine.delete(get_test_key(1));
+    assert!(del_res.is_ok());
+    assert_eq!(None, engine.key_version(&get_test_key(1)));
+    let put_res3 = engine.put(get_test_key(1), get_test_value(1));
+    assert!(put_res3.is_ok());
+    let version3 = engine.key_version(&get_test_key(1));
+    assert!(version3.is_some());
+    assert_ne!(version1, version3);
+    assert_ne!(version2, version3);
+
+    // 写入大量数据触发文件切换后，版本仍然各不相同
+    let mut versions = HashSet::new();
+    for i in 0..2000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(128));
+        assert!(put_res.is_ok());
+        assert!(versions.insert(engine.key_version(&get_test_key(i)).unwrap()));
+    }
+    assert!(engine.data_file_ids().len() > 1);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_on_file_rotate() {
+    let rotations = Arc::new(Mutex::new(Vec::new()));
+    let rotations_clone = rotations.clone();
+
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-on-file-rotate");
+    opts.data_file_size = 64 * 1024;
+    opts.data_file_merge_ratio = 0 as f32;
+    opts.on_file_rotate = Some(Arc::new(move |sealed_id, new_id| {
+        rotations_clone.lock().unwrap().push((sealed_id, new_id));
+    }));
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..2000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(128));
+        assert!(put_res.is_ok());
+    }
+
+    // 每次切换都会调用一次回调，文件 id 依次递增
+    let file_ids = engine.data_file_ids();
+    assert!(file_ids.len() > 1);
+    {
+        let rotations = rotations.lock().unwrap();
+        assert_eq!(file_ids.len() - 1, rotations.len());
+        for (i, (sealed_id, new_id)) in rotations.iter().enumerate() {
+            assert_eq!(i as u32, *sealed_id);
+            assert_eq!(sealed_id + 1, *new_id);
+        }
+    }
+
+    // merge 时活跃文件切换也会调用回调
+    let last_id = *file_ids.last().unwrap();
+    let merge_res = engine.merge();
+    assert!(merge_res.is_ok());
+    assert_eq!(
+        Some(&(last_id, last_id + 1)),
+        rotations.lock().unwrap().last()
+    );
+
+    // 重启加载 merge 的结果，清理 merge 目录
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_collect_read_stats() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-read-stats");
+    opts.data_file_size = 64 * 1024;
+    opts.collect_read_stats = true;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..1000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(128));
+        assert!(put_res.is_ok());
+    }
+    assert!(engine.data_file_ids().len() > 1);
+
+    let stat1 = engine.stat().unwrap();
+    assert_eq!(Some(0), stat1.active_file_hits);
+    assert_eq!(Some(0), stat1.older_file_hits);
+
+    // 第一个 key 在旧数据文件中，最后一个 key 在活跃文件中
+    for _ in 0..3 {
+        let get_res = engine.get(get_test_key(0));
+        assert!(get_res.is_ok());
+    }
+    for _ in 0..2 {
+        let get_res = engine.get_with(get_test_key(999), |value| value.len());
+        assert!(get_res.is_ok());
+    }
+    // 不存在的 key 不读取数据文件
+    let get_res = engine.get(Bytes::from("not existed key"));
+    assert_eq!(Errors::KeyIsNotFound, get_res.err().unwrap());
+
+    let stat2 = engine.stat().unwrap();
+    assert_eq!(Some(2), stat2.active_file_hits);
+    assert_eq!(Some(3), stat2.older_file_hits);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_custom_file_names() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-custom-file-names");
+    opts.data_file_size = 64 * 1024;
+    opts.data_file_merge_ratio = 0 as f32;
+    opts.file_suffix = ".users.bc".to_string();
+    opts.lock_file_name = "users.lock".to_string();
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..1000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(128));
+        assert!(put_res.is_ok());
+    }
+    for i in 0..500 {
+        let del_res = engine.delete(get_test_key(i));
+        assert!(del_res.is_ok());
+    }
+    assert!(engine.data_file_ids().len() > 1);
+
+    // 数据文件使用自定义的后缀，锁文件使用自定义的文件名
+    let file_names = |dir_path: &PathBuf| -> Vec<String> {
+        std::fs::read_dir(dir_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect()
+    };
+    let names = file_names(&opts.dir_path);
+    assert!(names.contains(&"users.lock".to_string()));
+    assert!(!names.contains(&FILE_LOCK_NAME.to_string()));
+    assert!(names.iter().any(|name| name.ends_with(".users.bc")));
+    assert!(!names.iter().any(|name| name.ends_with(".data")));
+
+    // merge 后重启，数据文件后缀保持一致
+    let merge_res = engine.merge();
+    assert!(merge_res.is_ok());
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(500, engine2.list_keys().len());
+    for i in 500..1000 {
+        let get_res = engine2.get(get_test_key(i));
+        assert_eq!(get_test_value(128), get_res.unwrap());
+    }
+    let names = file_names(&opts.dir_path);
+    assert!(!names.iter().any(|name| name.ends_with(".data")));
+    std::mem::drop(engine2);
+
+    // 不合法的后缀和锁文件名
+    let mut invalid_opts = opts.clone();
+    invalid_opts.file_suffix = String::new();
+    assert_eq!(
+        Errors::InvalidFileName,
+        Engine::open(invalid_opts).err().unwrap()
+    );
+    let mut invalid_opts = opts.clone();
+    invalid_opts.file_suffix = "index".to_string();
+    assert_eq!(
+        Errors::InvalidFileName,
+        Engine::open(invalid_opts).err().unwrap()
+    );
+    let mut invalid_opts = opts.clone();
+    invalid_opts.lock_file_name = "lock.users.bc".to_string();
+    assert_eq!(
+        Errors::InvalidFileName,
+        Engine::open(invalid_opts).err().unwrap()
+    );
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_max_open_files() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-max-open-files");
+    opts.data_file_size = 16 * 1024;
+    opts.data_file_merge_ratio = 0 as f32;
+    opts.max_open_files = 2;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..2000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(128));
+        assert!(put_res.is_ok());
+    }
+    assert!(engine.data_file_ids().len() > 10);
+
+    // 跨多个旧数据文件读取，打开的文件数量不超过上限
+    for i in 0..2000 {
+        let get_res = engine.get(get_test_key(i));
+        assert!(get_res.is_ok());
+        assert!(engine.older_files.read().open_files() <= 2);
+    }
+    std::mem::drop(engine);
+
+    // 重新打开后加载索引、读取和 merge 都只会按需打开文件
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert!(engine2.older_files.read().open_files() <= 2);
+    for i in (0..2000).rev() {
+        let get_res = engine2.get(get_test_key(i));
+        assert!(get_res.is_ok());
+    }
+    assert!(engine2.older_files.read().open_files() <= 2);
+
+    for i in 0..1000 {
+        let del_res = engine2.delete(get_test_key(i));
+        assert!(del_res.is_ok());
+    }
+    let merge_res = engine2.merge();
+    assert!(merge_res.is_ok());
+    std::mem::drop(engine2);
+
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(1000, engine3.list_keys().len());
+    for i in 1000..2000 {
+        let get_res = engine3.get(get_test_key(i));
+        assert!(get_res.is_ok());
+    }
+    assert!(engine3.older_files.read().open_files() <= 2);
+    std::mem::drop(engine3);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_checkpoint() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-checkpoint");
+    opts.data_file_size = 64 * 1024;
+    opts.index_type = IndexType::BPlusTree;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let commit = |engine: &Engine, i: usize| {
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        assert!(wb.put(get_test_key(i), get_test_value(128)).is_ok());
+        wb.commit().unwrap()
+    };
+    for i in 0..10 {
+        commit(&engine, i);
+    }
+
+    // 检查点写入的序列号和当前的序列号一致
+    let checkpoint_res = engine.checkpoint();
+    assert!(checkpoint_res.is_ok());
+    let manifest = Manifest::read(opts.dir_path.clone(), false).unwrap();
+    assert_eq!(Some(engine.current_seq_no()), manifest.seq_no);
+    let checkpoint = std::fs::read(opts.dir_path.join(MANIFEST_FILE_NAME)).unwrap();
+
+    // 检查点之后继续提交事务，写满多个数据文件
+    let mut last_seq_no = 0;
+    for i in 10..1000 {
+        last_seq_no = commit(&engine, i);
+    }
+    assert!(engine.data_file_ids().len() > 1);
+    std::mem::drop(engine);
+
+    // 恢复检查点写入的 MANIFEST，模拟检查点之后没有正常关闭
+    std::fs::write(opts.dir_path.join(MANIFEST_FILE_NAME), checkpoint).unwrap();
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(last_seq_no + 1, engine2.current_seq_no());
+    assert_eq!(last_seq_no + 1, commit(&engine2, 1000));
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_keep_checkpoint_after_open() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-keep-checkpoint");
+    opts.data_file_size = 64 * 1024;
+    opts.index_type = IndexType::BPlusTree;
+
+    let commit = |engine: &Engine, i: usize| {
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        assert!(wb.put(get_test_key(i), get_test_value(128)).is_ok());
+        wb.commit().unwrap()
+    };
+
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..10 {
+        commit(&engine, i);
+    }
+    let closed_seq_no = engine.current_seq_no();
+    std::mem::drop(engine);
+
+    // 打开之后 MANIFEST 中记录的是以当前活跃文件为起点的检查点
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let manifest_path = opts.dir_path.join(MANIFEST_FILE_NAME);
+    let manifest = Manifest::read(opts.dir_path.clone(), false).unwrap();
+    assert_eq!(Some(closed_seq_no), manifest.seq_no);
+    let active_fid = *engine2.data_file_ids().last().unwrap();
+    assert_eq!(Some(active_fid), manifest.checkpoint_fid);
+    let manifest_after_open = std::fs::read(&manifest_path).unwrap();
+
+    let mut last_seq_no = 0;
+    for i in 10..1000 {
+        last_seq_no = commit(&engine2, i);
+    }
+    std::mem::drop(engine2);
+
+    // 恢复打开时的 MANIFEST，模拟没有正常关闭，事务序列号从检查点恢复
+    std::fs::write(&manifest_path, manifest_after_open).unwrap();
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(last_seq_no + 1, engine3.current_seq_no());
+    assert_eq!(last_seq_no + 1, commit(&engine3, 1000));
+    assert_eq!(1001, engine3.list_keys().len());
+    std::mem::drop(engine3);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_manifest() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-manifest");
+    opts.data_file_size = 64 * 1024;
+    opts.index_type = IndexType::BPlusTree;
+
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        assert!(wb.put(get_test_key(i), get_test_value(128)).is_ok());
+        assert!(wb.commit().is_ok());
+    }
+    let seq_no = engine.current_seq_no();
+    let stat = engine.stat().unwrap();
+    let res = engine.close();
+    assert!(res.is_ok());
+
+    // 关闭时所有元数据写入同一个 MANIFEST，不再有单独的 seq-no 和 index-type 文件
+    let manifest = Manifest::read(opts.dir_path.clone(), false).unwrap();
+    assert_eq!(Some(1), manifest.format_version);
+    assert_eq!(Some("bptree".to_string()), manifest.index_type);
+    assert_eq!(Some(opts.data_file_size), manifest.data_file_size);
+    assert_eq!(stat.total_bytes_written, manifest.total_bytes_written);
+    assert_eq!(stat.total_records_written, manifest.total_records_written);
+    assert_eq!(Some(seq_no), manifest.seq_no);
+    assert_eq!(None, manifest.checkpoint_fid);
+    assert!(!opts.dir_path.join("seq-no").exists());
+    assert!(!opts.dir_path.join("index-type").exists());
+    assert!(!opts.dir_path.join(MANIFEST_TMP_FILE_NAME).exists());
+
+    std::mem::drop(engine);
+
+    // 没有 MANIFEST 时扫描所有数据文件恢复事务序列号
+    std::fs::remove_file(opts.dir_path.join(MANIFEST_FILE_NAME)).unwrap();
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(seq_no, engine2.current_seq_no());
+    assert_eq!(1000, engine2.list_keys().len());
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_put_slice_get_into() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-put-slice");
+    opts.data_file_size = 64 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.空的 key
+    let res1 = engine.put_slice(b"", b"value");
+    assert_eq!(Errors::KeyIsEmpty, res1.err().unwrap());
+    let mut buf = Vec::new();
+    let res2 = engine.get_into(b"", &mut buf);
+    assert_eq!(Errors::KeyIsEmpty, res2.err().unwrap());
+
+    // 2.和 Bytes 接口写入的数据互通
+    let put_res1 = engine.put_slice(b"name", b"bitcask-rs");
+    assert!(put_res1.is_ok());
+    assert_eq!(
+        Bytes::from("bitcask-rs"),
+        engine.get(Bytes::from("name")).unwrap()
+    );
+    let put_res2 = engine.put(Bytes::from("lang"), Bytes::from("rust"));
+    assert!(put_res2.is_ok());
+    let get_res1 = engine.get_into(b"lang", &mut buf);
+    assert!(get_res1.is_ok());
+    assert_eq!(b"rust".to_vec(), buf);
+
+    // 3.复用 buf，原有的内容会被清空
+    let get_res2 = engine.get_into(b"name", &mut buf);
+    assert!(get_res2.is_ok());
+    assert_eq!(b"bitcask-rs".to_vec(), buf);
+
+    // 4.不存在或已删除的 key
+    let get_res3 = engine.get_into(b"not existed key", &mut buf);
+    assert_eq!(Errors::KeyIsNotFound, get_res3.err().unwrap());
+    let del_res = engine.delete(Bytes::from("name"));
+    assert!(del_res.is_ok());
+    let get_res4 = engine.get_into(b"name", &mut buf);
+    assert_eq!(Errors::KeyIsNotFound, get_res4.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_rebuild_corrupted_bptree_index() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-rebuild-bptree");
+    opts.data_file_size = 64 * 1024;
+    opts.index_type = IndexType::BPlusTree;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..1000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(put_res.is_ok());
+    }
+    for i in 0..100 {
+        let del_res = engine.delete(get_test_key(i));
+        assert!(del_res.is_ok());
+    }
+    assert!(engine.data_file_ids().len() > 1);
+    std::mem::drop(engine);
+
+    // 破坏 b+树索引文件
+    let index_path = opts.dir_path.join(BPTREE_INDEX_FILE_NAME);
+    std::fs::write(&index_path, vec![0xff; 16 * 1024]).unwrap();
+
+    // 1.默认返回错误，不会 panic
+    let res1 = Engine::open(opts.clone());
+    assert_eq!(Errors::BPlusTreeIndexCorrupted, res1.err().unwrap());
+
+    // 2.开启配置项后从数据文件中重建索引
+    opts.rebuild_index_on_corruption = true;
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(900, engine2.list_keys().len());
+    for i in 0..100 {
+        let get_res = engine2.get(get_test_key(i));
+        assert_eq!(Errors::KeyIsNotFound, get_res.err().unwrap());
+    }
+    for i in 100..1000 {
+        let get_res = engine2.get(get_test_key(i));
+        assert_eq!(get_test_value(i), get_res.unwrap());
+    }
+    let put_res = engine2.put(get_test_key(1000), get_test_value(1000));
+    assert!(put_res.is_ok());
+    std::mem::drop(engine2);
+
+    // 3.重建后的索引正常持久化
+    opts.rebuild_index_on_corruption = false;
+    let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(901, engine3.list_keys().len());
+    std::mem::drop(engine3);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_active_file_remaining() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-active-file-remaining");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    assert_eq!(0, engine.active_file_offset());
+    assert_eq!(64 * 1024, engine.active_file_remaining());
+
+    // 每条数据编码后的长度相同
+    let record_size = codec::encoded_size_v1(
+        &[&[0u8][..], &get_test_key(0)].concat(),
+        &get_test_value(128),
+    ) as u64;
+    for i in 0..10 {
+        let put_res = engine.put(get_test_key(i), get_test_value(128));
+        assert!(put_res.is_ok());
+        let offset = (i as u64 + 1) * record_size;
+        assert_eq!(offset, engine.active_file_offset());
+        assert_eq!(64 * 1024 - offset, engine.active_file_remaining());
+    }
+
+    // 剩余空间不足时切换活跃文件
+    while engine.active_file_remaining() >= record_size {
+        let put_res = engine.put(get_test_key(0), get_test_value(128));
+        assert!(put_res.is_ok());
+    }
+    let put_res = engine.put(get_test_key(0), get_test_value(128));
+    assert!(put_res.is_ok());
+    assert_eq!(2, engine.data_file_ids().len());
+    assert_eq!(record_size, engine.active_file_offset());
+    std::mem::drop(engine);
+
+    // 重新打开后保持一致
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(record_size, engine2.active_file_offset());
+    assert_eq!(64 * 1024 - record_size, engine2.active_file_remaining());
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_truncate_trailing_garbage() {
+    for (index_type, name) in [
+        (IndexType::BTree, "btree"),
+        (IndexType::BPlusTree, "bptree"),
+    ] {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from(format!("/tmp/bitcask-rs-trailing-garbage-{}", name));
+        opts.data_file_size = 64 * 1024 * 1024;
+        opts.index_type = index_type;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..100 {
+            let put_res = engine.put(get_test_key(i), get_test_value(24));
+            assert!(put_res.is_ok());
+        }
+        let valid_size = engine.active_file_offset();
+        std::mem::drop(engine);
+
+        // 模拟没有正常关闭，活跃文件末尾留下非零的垃圾数据，解析出的长度超出文件末尾
+        let _ = std::fs::remove_file(opts.dir_path.join(MANIFEST_FILE_NAME));
+        let data_file_path = opts.dir_path.join(format!("{:09}.data", 0));
+        let mut garbage = vec![1u8, 0xff, 0xff, 0xff, 0x0f, 0x05];
+        garbage.extend_from_slice(&[0xab; 32]);
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&data_file_path)
+            .unwrap();
+        std::io::Write::write_all(&mut file, &garbage).unwrap();
+        std::mem::drop(file);
+
+        // 重新打开后停在最后一条完整数据的位置，垃圾数据被截断
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(valid_size, engine2.active_file_offset());
+        assert_eq!(
+            valid_size,
+            std::fs::metadata(&data_file_path).unwrap().len()
+        );
+
+        // 之后追加写入的数据落在正确的位置
+        let put_res = engine2.put(get_test_key(100), get_test_value(24));
+        assert!(put_res.is_ok());
+        assert_eq!(
+            engine2.active_file_offset(),
+            std::fs::metadata(&data_file_path).unwrap().len()
+        );
+        std::mem::drop(engine2);
+
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..=100 {
+            assert!(engine3.get(get_test_key(i)).is_ok());
+        }
+        assert_eq!(101, engine3.list_keys().len());
+        std::mem::drop(engine3);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+}
+
+#[test]
+fn test_engine_compact_key() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-compact-key");
+    opts.data_file_size = 64 * 1024;
+    opts.data_file_merge_ratio = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 热点 key 被多次覆盖写入，之后写入其他数据使其落在旧数据文件中
+    let hot_key = get_test_key(0);
+    let record_size =
+        codec::encoded_size_v1(&[&[0u8][..], &hot_key].concat(), &get_test_value(128));
+    for _ in 0..50 {
+        let put_res = engine.put(hot_key.clone(), get_test_value(128));
+        assert!(put_res.is_ok());
+    }
+    for i in 1..1000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(128));
+        assert!(put_res.is_ok());
+    }
+    assert!(engine.data_file_ids().len() > 1);
+    assert_eq!(49 * record_size, engine.stat().unwrap().reclaim_size);
+
+    // 重写之后 key 的所有旧版本都可以回收
+    let compact_res = engine.compact_key(&hot_key);
+    assert!(compact_res.is_ok());
+    assert_eq!(50 * record_size, engine.stat().unwrap().reclaim_size);
+    let value = engine.get(hot_key.clone()).unwrap();
+    assert_eq!(get_test_value(128).len(), value.len());
+
+    // 已经在活跃文件中的 key 不再重写
+    let compact_res = engine.compact_key(&hot_key);
+    assert!(compact_res.is_ok());
+    assert_eq!(50 * record_size, engine.stat().unwrap().reclaim_size);
+
+    // 不存在的 key
+    let compact_res = engine.compact_key(b"not existed key");
+    assert_eq!(Errors::KeyIsNotFound, compact_res.err().unwrap());
+
+    // merge 之后重新打开，数据保持一致
+    let merge_res = engine.merge();
+    assert!(merge_res.is_ok());
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(value, engine2.get(hot_key.clone()).unwrap());
+    assert_eq!(1000, engine2.list_keys().len());
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_compact_prefix() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-compact-prefix");
+    opts.data_file_size = 64 * 1024;
+    opts.data_file_merge_ratio = 0 as f32;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..1000 {
+        let put_res = engine.put(Bytes::from(format!("ns1:{:09}", i)), get_test_value(128));
+        assert!(put_res.is_ok());
+        let put_res = engine.put(Bytes::from(format!("ns2:{:09}", i)), get_test_value(128));
+        assert!(put_res.is_ok());
+    }
+    assert!(engine.data_file_ids().len() > 2);
+
+    // 1.删除 ns1 前缀下的所有数据，之后没有需要重新写入的 key
+    let ns1_keys: Vec<Bytes> = engine.prefix_iter("ns1:").map(|(key, _)| key).collect();
+    assert_eq!(Ok(1000), engine.delete_batch(&ns1_keys));
+    assert_eq!(Ok(0), engine.compact_prefix(b"ns1:"));
+
+    // 2.重新写入 ns2 前缀下不在活跃文件中的 key，旧数据文件中只剩下无效数据
+    let reclaim_size1 = engine.stat().unwrap().reclaim_size;
+    let rewritten = engine.compact_prefix(b"ns2:").unwrap();
+    assert!(rewritten > 0 && rewritten <= 1000);
+    assert!(engine.stat().unwrap().reclaim_size > reclaim_size1);
+    assert_eq!(Ok(0), engine.compact_prefix(b"ns3:"));
+
+    // 3.merge 之后释放这些空间
+    let disk_size1 = engine.stat().unwrap().disk_size;
+    let merge_res = engine.merge();
+    assert!(merge_res.is_ok());
+    std::mem::drop(engine);
+
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    let stat = engine2.stat().unwrap();
+    assert!(stat.disk_size < disk_size1 / 2);
+    assert_eq!(0, engine2.prefix_iter("ns1:").count());
+    assert_eq!(1000, engine2.prefix_iter("ns2:").count());
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_increment() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-increment");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 不存在的 key 从 0 开始
+    assert_eq!(Ok(5), engine.increment(Bytes::from("counter"), 5));
+    assert_eq!(Ok(2), engine.increment(Bytes::from("counter"), -3));
+
+    // 多个线程同时增加，结果不会丢失
+    std::thread::scope(|s| {
+        for _ in 0..8 {
+            let engine = &engine;
+            s.spawn(move || {
+                for _ in 0..200 {
+                    let res = engine.increment(Bytes::from("counter"), 1);
+                    assert!(res.is_ok());
+                }
+            });
+        }
+    });
+    let value = engine.get(Bytes::from("counter")).unwrap();
+    assert_eq!(
+        8 * 200 + 2,
+        i64::from_le_bytes(value[..].try_into().unwrap())
+    );
+
+    // value 不是数字
+    let put_res = engine.put(Bytes::from("name"), Bytes::from("bitcask-rs"));
+    assert!(put_res.is_ok());
+    let res = engine.increment(Bytes::from("name"), 1);
+    assert_eq!(Errors::NotANumber, res.err().unwrap());
+    assert_eq!(
+        Bytes::from("bitcask-rs"),
+        engine.get(Bytes::from("name")).unwrap()
+    );
+
+    // 重启后保持一致
+    std::mem::drop(engine);
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(
+        Ok(8 * 200 + 3),
+        engine2.increment(Bytes::from("counter"), 1)
+    );
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_value_len() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-value-len");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    let values = [
+        Bytes::new(),
+        Bytes::from("a"),
+        get_test_value(1),
+        Bytes::from(vec![b'v'; 10 * 1024]),
+    ];
+    for (i, value) in values.iter().enumerate() {
+        let put_res = engine.put(get_test_key(i), value.clone());
+        assert!(put_res.is_ok());
+    }
+    for i in 10..1000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(128));
+        assert!(put_res.is_ok());
+    }
+    assert!(engine.data_file_ids().len() > 1);
+
+    // 和读取完整 value 的长度一致，包括旧数据文件和活跃文件中的数据
+    for i in (0..values.len()).chain(10..1000) {
+        let value = engine.get(get_test_key(i)).unwrap();
+        assert_eq!(Ok(value.len()), engine.value_len(&get_test_key(i)));
+    }
+
+    // 被删除的 key 和不存在的 key
+    assert!(engine.delete(get_test_key(1)).is_ok());
+    let len_res = engine.value_len(&get_test_key(1));
+    assert_eq!(Errors::KeyIsNotFound, len_res.err().unwrap());
+    let len_res = engine.value_len(b"not existed key");
+    assert_eq!(Errors::KeyIsNotFound, len_res.err().unwrap());
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_get_reader() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-get-reader");
+    opts.data_file_size = 8 * 1024 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 两个 5MB 的 value，第一个在旧数据文件中，第二个在活跃文件中
+    let values: Vec<Bytes> = (0..2)
+        .map(|i: usize| {
+            (0..5 * 1024 * 1024)
+                .map(|j: usize| (j * 7 + i) as u8)
+                .collect()
+        })
+        .collect();
+    for (i, value) in values.iter().enumerate() {
+        let put_res = engine.put(get_test_key(i), value.clone());
+        assert!(put_res.is_ok());
+    }
+    assert_eq!(2, engine.data_file_ids().len());
+
+    for (i, value) in values.iter().enumerate() {
+        let mut reader = engine.get_reader(&get_test_key(i)).unwrap();
+        let mut buf = Vec::new();
+        let copy_res = std::io::copy(&mut reader, &mut buf);
+        assert_eq!(value.len() as u64, copy_res.unwrap());
+        assert_eq!(value.as_ref(), buf.as_slice());
+    }
+
+    // 空的 value、被删除的 key 和不存在的 key
+    let put_res = engine.put(get_test_key(2), Bytes::new());
+    assert!(put_res.is_ok());
+    let mut buf = Vec::new();
+    let mut reader = engine.get_reader(&get_test_key(2)).unwrap();
+    assert_eq!(0, reader.read_to_end(&mut buf).unwrap());
+    assert!(engine.delete(get_test_key(2)).is_ok());
+    let reader_res = engine.get_reader(&get_test_key(2));
+    assert_eq!(Errors::KeyIsNotFound, reader_res.err().unwrap());
+    let reader_res = engine.get_reader(b"not existed key");
+    assert_eq!(Errors::KeyIsNotFound, reader_res.err().unwrap());
+
+    // value 中的数据损坏时，读到末尾校验 CRC 失败
+    let (file_id, offset, size) = engine.locate(&get_test_key(1)).unwrap();
+    let file_path = get_data_file_path(opts.dir_path.clone(), file_id, DATA_FILE_NAME_SUFFIX);
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(file_path)
+        .unwrap();
+    let corrupt_off = offset + size as u64 / 2;
+    assert!(std::os::unix::fs::FileExt::write_at(&file, &[0xff, 0xff], corrupt_off).is_ok());
+    let mut reader = engine.get_reader(&get_test_key(1)).unwrap();
+    let copy_res = std::io::copy(&mut reader, &mut std::io::sink());
+    assert_eq!(
+        std::io::ErrorKind::InvalidData,
+        copy_res.err().unwrap().kind()
+    );
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_sync_dir_on_new_file() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-sync-dir-on-new-file");
+    opts.data_file_size = 64 * 1024;
+    opts.data_file_merge_ratio = 0 as f32;
+    opts.sync_writes = true;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 切换活跃文件和 merge 时都会持久化目录
+    for i in 0..1000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(128));
+        assert!(put_res.is_ok());
+    }
+    assert!(engine.data_file_ids().len() > 1);
+    let merge_res = engine.merge();
+    assert!(merge_res.is_ok());
+    std::mem::drop(engine);
+
+    // 重新打开时加载 merge 文件
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(1000, engine2.list_keys().len());
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+// 统计 sync 次数的 IO 管理接口，可以模拟 sync 失败
+struct CountingIO {
+    inner: FileIO,
+    syncs: Arc<AtomicUsize>,
+    fail_sync: bool,
+}
+
+impl IOManager for CountingIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize, Errors> {
+        self.inner.read(buf, offset)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Errors> {
+        self.inner.write(buf)
+    }
+
+    fn sync(&self) -> Result<(), Errors> {
+        self.syncs.fetch_add(1, Ordering::SeqCst);
+        if self.fail_sync {
+            return Err(Errors::FailedSyncDataFile);
+        }
+        self.inner.sync()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+}
+
+#[test]
+fn test_engine_sync_on_drop() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-sync-on-drop");
+    opts.data_file_size = 64 * 1024 * 1024;
+
+    // 将活跃文件的 IO 管理接口替换为统计 sync 次数的实现
+    let open_with_counting_io = |opts: &Options, fail_sync: bool| {
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        let put_res = engine.put(get_test_key(1), get_test_value(10));
+        assert!(put_res.is_ok());
+
+        let syncs = Arc::new(AtomicUsize::new(0));
+        let file_path = get_data_file_path(opts.dir_path.clone(), 0, DATA_FILE_NAME_SUFFIX);
+        let io = CountingIO {
+            inner: FileIO::new(file_path, false).unwrap(),
+            syncs: syncs.clone(),
+            fail_sync,
+        };
+        engine.active_file.write().replace_io_manager(Box::new(io));
+        (engine, syncs)
+    };
+    LAST_DROP_ERROR.with(|last| *last.borrow_mut() = None);
+
+    // 1.默认 Drop 时持久化活跃文件
+    let (engine, syncs) = open_with_counting_io(&opts, false);
+    std::mem::drop(engine);
+    assert_eq!(1, syncs.load(Ordering::SeqCst));
+    assert!(LAST_DROP_ERROR.with(|last| last.borrow().is_none()));
+
+    // 2.关闭之后 Drop 时不持久化，但会释放文件锁
+    opts.sync_on_drop = false;
+    let (engine, syncs) = open_with_counting_io(&opts, false);
+    std::mem::drop(engine);
+    assert_eq!(0, syncs.load(Ordering::SeqCst));
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(get_test_value(10), engine2.get(get_test_key(1)).unwrap());
+    std::mem::drop(engine2);
+
+    // 3.Drop 时持久化失败，记录错误
+    opts.sync_on_drop = true;
+    let (engine, syncs) = open_with_counting_io(&opts, true);
+    std::mem::drop(engine);
+    assert_eq!(1, syncs.load(Ordering::SeqCst));
+    let drop_err = LAST_DROP_ERROR.with(|last| last.borrow_mut().take());
+    assert_eq!(Some(Errors::FailedSyncDataFile), drop_err);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_open_archived() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-open-archived");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(put_res.is_ok());
+    }
+    for i in 0..100 {
+        let del_res = engine.delete(get_test_key(i));
+        assert!(del_res.is_ok());
+    }
+    let file_ids = engine.data_file_ids();
+    assert!(file_ids.len() > 1);
+    engine.close().expect("failed to close");
+    std::mem::drop(engine);
+
+    // 将所有数据文件压缩为 gzip 文件，并删除原来的数据文件
+    for fid in file_ids.iter() {
+        let path = get_data_file_path(opts.dir_path.clone(), *fid, DATA_FILE_NAME_SUFFIX);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&std::fs::read(&path).unwrap()).unwrap();
+        let gz_path = get_data_file_path(opts.dir_path.clone(), *fid, ".data.gz");
+        std::fs::write(gz_path, encoder.finish().unwrap()).unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn read_gz(path: &std::path::Path) -> std::io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        GzDecoder::new(std::fs::File::open(path)?).read_to_end(&mut data)?;
+        Ok(data)
+    }
+    let builder = Options::builder()
+        .dir_path(opts.dir_path.clone())
+        .data_file_size(opts.data_file_size)
+        .file_suffix(".data.gz")
+        .archive_reader(read_gz);
+
+    // 1.只能以只读模式打开
+    let res1 = builder.clone().build();
+    assert_eq!(Errors::ArchiveRequiresReadOnly, res1.err().unwrap());
+
+    // 2.只读模式下直接读取压缩后的数据文件
+    let archive_opts = builder.read_only(true).build().unwrap();
+    let engine2 = Engine::open(archive_opts).expect("failed to open engine");
+    assert_eq!(900, engine2.list_keys().len());
+    for i in 0..1000 {
+        let get_res = engine2.get(get_test_key(i));
+        match i < 100 {
+            true => assert_eq!(Errors::KeyIsNotFound, get_res.err().unwrap()),
+            false => assert_eq!(get_test_value(i), get_res.unwrap()),
+        }
+    }
+
+    // 3.修改数据的操作都会返回错误，不会在数据目录中写入数据文件
+    let put_res = engine2.put(get_test_key(0), get_test_value(0));
+    assert_eq!(Errors::ReadOnlyMode, put_res.err().unwrap());
+    assert_eq!(Errors::ReadOnlyMode, engine2.merge().err().unwrap());
+    std::mem::drop(engine2);
+    for fid in file_ids.iter() {
+        let path = get_data_file_path(opts.dir_path.clone(), *fid, DATA_FILE_NAME_SUFFIX);
+        assert!(!path.exists());
+    }
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+// 数据目录中 blob 文件的数量
+fn blob_file_num(dir_path: PathBuf) -> usize {
+    std::fs::read_dir(dir_path)
+        .unwrap()
+        .flatten()
+        .filter(|entry| {
+            let file_name = entry.file_name();
+            file_name.to_str().unwrap().ends_with(BLOB_FILE_NAME_SUFFIX)
+        })
+        .count()
+}
+
+// 偶数 key 的 value 很小，奇数 key 的 value 超过 separate_values_over 的阈值
+fn separate_test_value(i: usize, version: usize) -> Bytes {
+    let len = if i.is_multiple_of(2) { 100 } else { 4096 };
+    (0..len).map(|j: usize| (i + j + version) as u8).collect()
+}
+
+#[test]
+fn test_engine_separate_values() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-separate-values");
+    opts.data_file_size = 64 * 1024;
+    opts.data_file_merge_ratio = 0.0;
+    opts.separate_values_over = Some(1024);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    for i in 0..1000 {
+        let put_res = engine.put(get_test_key(i), separate_test_value(i, 0));
+        assert!(put_res.is_ok());
+    }
+    assert!(engine.data_file_ids().len() > 1);
+    assert!(blob_file_num(opts.dir_path.clone()) > 1);
+
+    // 小的 value 保存在数据文件中，大的 value 在数据文件中只保存 blob 位置
+    let (_, _, inline_size) = engine.locate(&get_test_key(0)).unwrap();
+    assert!(inline_size > 100);
+    let (_, _, separated_size) = engine.locate(&get_test_key(1)).unwrap();
+    assert!(separated_size < 100);
+
+    for i in 0..1000 {
+        let value = separate_test_value(i, 0);
+        assert_eq!(value, engine.get(get_test_key(i)).unwrap());
+        assert_eq!(value.len(), engine.value_len(&get_test_key(i)).unwrap());
+    }
+    let mut buf = Vec::new();
+    let mut reader = engine.get_reader(&get_test_key(1)).unwrap();
+    assert!(reader.read_to_end(&mut buf).is_ok());
+    assert_eq!(separate_test_value(1, 0).as_ref(), buf.as_slice());
+    let iter = engine.iter(IteratorOptions::default());
+    let mut count = 0;
+    while let Some((key, value)) = iter.next_with(|key, value| (key.to_vec(), value)) {
+        assert_eq!(engine.get(Bytes::from(key)).unwrap(), value);
+        count += 1;
+    }
+    assert_eq!(1000, count);
+    drop(iter);
+
+    // 删除一半的 key 之后 merge，重新打开后剩下的 value 仍然可以读取
+    for i in 0..500 {
+        let del_res = engine.delete(get_test_key(i));
+        assert!(del_res.is_ok());
+    }
+    let merge_res = engine.merge();
+    assert!(merge_res.is_ok());
+    std::mem::drop(engine);
+
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..500 {
+        let get_res = engine.get(get_test_key(i));
+        assert_eq!(Errors::KeyIsNotFound, get_res.err().unwrap());
+    }
+    for i in 500..1000 {
+        assert_eq!(
+            separate_test_value(i, 0),
+            engine.get(get_test_key(i)).unwrap()
+        );
+    }
+
+    // 关闭分离之后，之前分离的 value 仍然可以读取，新写入的大 value 保存在数据文件中
+    std::mem::drop(engine);
+    opts.separate_values_over = None;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(
+        separate_test_value(501, 0),
+        engine.get(get_test_key(501)).unwrap()
+    );
+    let put_res = engine.put(get_test_key(1), separate_test_value(1, 0));
+    assert!(put_res.is_ok());
+    let (_, _, size) = engine.locate(&get_test_key(1)).unwrap();
+    assert!(size > 4096);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_gc_blobs() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-gc-blobs");
+    opts.data_file_size = 64 * 1024;
+    opts.separate_values_over = Some(1024);
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 没有写满的 blob 文件时不需要 GC
+    assert_eq!(0, engine.gc_blobs().unwrap());
+
+    // 覆盖写入一部分 key，删除一部分 key，旧的 value 仍然占据 blob 文件
+    for i in 0..200 {
+        let put_res = engine.put(get_test_key(i), separate_test_value(i, 0));
+        assert!(put_res.is_ok());
+    }
+    for i in 0..100 {
+        let put_res = engine.put(get_test_key(i), separate_test_value(i, 1));
+        assert!(put_res.is_ok());
+    }
+    for i in 100..150 {
+        let del_res = engine.delete(get_test_key(i));
+        assert!(del_res.is_ok());
+    }
+    let blob_num = blob_file_num(opts.dir_path.clone());
+    let disk_size = engine.stat().unwrap().disk_size;
+
+    // 写满的 blob 文件都被删除，仍然被引用的 value 重新写入新的 blob 文件
+    let gc_res = engine.gc_blobs();
+    assert_eq!(blob_num - 1, gc_res.unwrap());
+    assert!(blob_file_num(opts.dir_path.clone()) < blob_num);
+    assert!(engine.stat().unwrap().disk_size < disk_size);
+
+    let check = |engine: &Engine| {
+        for i in 0..200 {
+            let get_res = engine.get(get_test_key(i));
+            match i {
+                0..=99 => assert_eq!(separate_test_value(i, 1), get_res.unwrap()),
+                100..=149 => assert_eq!(Errors::KeyIsNotFound, get_res.err().unwrap()),
+                _ => assert_eq!(separate_test_value(i, 0), get_res.unwrap()),
+            }
+        }
+    };
+    check(&engine);
+    std::mem::drop(engine);
+
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    check(&engine);
+
+    // 只读模式下不能 GC
+    std::mem::drop(engine);
+    opts.read_only = true;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(Errors::ReadOnlyMode, engine.gc_blobs().err().unwrap());
+    std::mem::drop(engine);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_open_read_only_dir() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-open-read-only-dir");
+    opts.data_file_size = 64 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    for i in 0..1000 {
+        let res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(res.is_ok());
+    }
+    let res1 = engine.delete(get_test_key(0));
+    assert!(res1.is_ok());
+    std::mem::drop(engine);
+
+    // 模拟只读的数据目录：去掉锁文件，数据目录和其中的文件都设置为只读
+    let list_dir = |opts: &Options| {
+        let mut names: Vec<String> = std::fs::read_dir(opts.dir_path.clone())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        names.sort();
+        names
+    };
+    std::fs::remove_file(opts.dir_path.join(FILE_LOCK_NAME)).expect("failed to remove lock file");
+    let set_mode = |opts: &Options, file_mode: u32, dir_mode: u32| {
+        for name in list_dir(opts) {
+            let perm = std::fs::Permissions::from_mode(file_mode);
+            std::fs::set_permissions(opts.dir_path.join(name), perm).unwrap();
+        }
+        let perm = std::fs::Permissions::from_mode(dir_mode);
+        std::fs::set_permissions(opts.dir_path.clone(), perm).unwrap();
+    };
+    set_mode(&opts, 0o444, 0o555);
+    let files = list_dir(&opts);
+
+    // 只读模式下可以正常读取，写入返回 ReadOnlyMode，数据目录中不会新建任何文件
+    opts.read_only = true;
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(get_test_value(1), engine2.get(get_test_key(1)).unwrap());
+    assert_eq!(get_test_value(999), engine2.get(get_test_key(999)).unwrap());
+    assert_eq!(
+        Errors::KeyIsNotFound,
+        engine2.get(get_test_key(0)).err().unwrap()
+    );
+    assert_eq!(999, engine2.list_keys().len());
+    let res2 = engine2.put(get_test_key(1000), get_test_value(1000));
+    assert_eq!(Errors::ReadOnlyMode, res2.err().unwrap());
+    let res3 = engine2.delete(get_test_key(1));
+    assert_eq!(Errors::ReadOnlyMode, res3.err().unwrap());
+    assert_eq!(Errors::ReadOnlyMode, engine2.merge().err().unwrap());
+    std::mem::drop(engine2);
+    assert_eq!(files, list_dir(&opts));
+
+    // 只读模式下不会新建数据目录
+    let mut missing_opts = opts.clone();
+    missing_opts.dir_path = PathBuf::from("/tmp/bitcask-rs-open-read-only-dir-missing");
+    let res4 = Engine::open(missing_opts.clone());
+    assert_eq!(Errors::DatabaseNotFound, res4.err().unwrap());
+    assert!(!missing_opts.dir_path.exists());
+
+    // 删除测试的文件夹
+    set_mode(&opts, 0o644, 0o755);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_create() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-create");
+    opts.data_file_size = 64 * 1024 * 1024;
+
+    // 数据目录不存在时新建
+    let engine = Engine::create(opts.clone()).expect("failed to create engine");
+    let put_res = engine.put(get_test_key(1), get_test_value(1));
+    assert!(put_res.is_ok());
+    std::mem::drop(engine);
+
+    // 已经有数据时返回错误，open 仍然可以打开
+    let create_res = Engine::create(opts.clone());
+    assert_eq!(Errors::DatabaseAlreadyExists, create_res.err().unwrap());
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+
+    // 空的数据目录可以新建
+    std::fs::create_dir_all(opts.dir_path.clone()).unwrap();
+    let engine = Engine::create(opts.clone()).expect("failed to create engine");
+    std::mem::drop(engine);
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+
+    // 关闭 create_if_missing 时不会新建数据目录
+    opts.create_if_missing = false;
+    let open_res = Engine::open(opts.clone());
+    assert_eq!(Errors::DatabaseNotFound, open_res.err().unwrap());
+    assert!(!opts.dir_path.exists());
+}
+
+#[test]
+fn test_engine_initial_file_id() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-initial-file-id");
+    opts.data_file_size = 64 * 1024;
+    opts.data_file_merge_ratio = 0.0;
+    opts.initial_file_id = 100;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(vec![100], engine.data_file_ids());
+
+    // 之后的文件 id 从初始 id 开始递增
+    for i in 0..1000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(put_res.is_ok());
+    }
+    let file_ids = engine.data_file_ids();
+    assert!(file_ids.len() > 1);
+    for (i, file_id) in file_ids.iter().enumerate() {
+        assert_eq!(100 + i as u32, *file_id);
+        let file_path = get_data_file_path(opts.dir_path.clone(), *file_id, DATA_FILE_NAME_SUFFIX);
+        assert!(file_path.is_file());
+    }
+
+    // merge 后的文件同样从初始 id 开始
+    for i in 0..500 {
+        let del_res = engine.delete(get_test_key(i));
+        assert!(del_res.is_ok());
+    }
+    let merge_res = engine.merge();
+    assert!(merge_res.is_ok());
+    std::mem::drop(engine);
+
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let file_ids = engine.data_file_ids();
+    assert_eq!(100, file_ids[0]);
+    for i in 500..1000 {
+        assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+    }
+
+    // 清空之后活跃文件重置为初始 id
+    let clear_res = engine.clear();
+    assert!(clear_res.is_ok());
+    assert_eq!(vec![100], engine.data_file_ids());
+    std::mem::drop(engine);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+// 写入失败指定次数之后恢复正常的 IO 管理接口
+struct FlakyIO {
+    inner: FileIO,
+    failures: AtomicUsize,
+    writes: Arc<AtomicUsize>,
+    no_space: bool,
+}
+
+impl IOManager for FlakyIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize, Errors> {
+        self.inner.read(buf, offset)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, Errors> {
+        self.writes.fetch_add(1, Ordering::SeqCst);
+        if self.failures.load(Ordering::SeqCst) > 0 {
+            self.failures.fetch_sub(1, Ordering::SeqCst);
+            return match self.no_space {
+                true => Err(Errors::NoSpaceLeft),
+                false => Err(Errors::FailedWriteToDataFile),
+            };
+        }
+        self.inner.write(buf)
+    }
+
+    fn sync(&self) -> Result<(), Errors> {
+        self.inner.sync()
+    }
+
+    fn size(&self) -> u64 {
+        self.inner.size()
+    }
+}
+
+#[test]
+fn test_engine_io_retry() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-io-retry");
+    opts.data_file_size = 64 * 1024 * 1024;
+    opts.io_retry = Some(RetryPolicy {
+        attempts: 3,
+        backoff: std::time::Duration::from_millis(1),
+    });
+
+    // 将活跃文件的 IO 管理接口替换为写入失败指定次数的实现，返回写入的次数
+    let dir_path = opts.dir_path.clone();
+    let set_flaky_io = move |engine: &Engine, failures: usize, no_space: bool| {
+        let writes = Arc::new(AtomicUsize::new(0));
+        let file_path = get_data_file_path(dir_path.clone(), 0, DATA_FILE_NAME_SUFFIX);
+        let io = FlakyIO {
+            inner: FileIO::new(file_path, false).unwrap(),
+            failures: AtomicUsize::new(failures),
+            writes: writes.clone(),
+            no_space,
+        };
+        engine.active_file.write().replace_io_manager(Box::new(io));
+        writes
+    };
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 1.失败一次之后重试成功
+    let writes = set_flaky_io(&engine, 1, false);
+    let put_res = engine.put(get_test_key(1), get_test_value(1));
+    assert!(put_res.is_ok());
+    assert_eq!(2, writes.load(Ordering::SeqCst));
+    assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+
+    // 2.超过重试次数之后返回错误
+    let writes = set_flaky_io(&engine, 5, false);
+    let put_res = engine.put(get_test_key(2), get_test_value(2));
+    assert_eq!(Errors::FailedWriteToDataFile, put_res.err().unwrap());
+    assert_eq!(3, writes.load(Ordering::SeqCst));
+
+    // 3.磁盘空间不足时不重试
+    let writes = set_flaky_io(&engine, 1, true);
+    let put_res = engine.put(get_test_key(2), get_test_value(2));
+    assert_eq!(Errors::NoSpaceLeft, put_res.err().unwrap());
+    assert_eq!(1, writes.load(Ordering::SeqCst));
+    std::mem::drop(engine);
+
+    // 4.默认不重试
+    opts.io_retry = None;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+    let writes = set_flaky_io(&engine, 1, false);
+    let put_res = engine.put(get_test_key(2), get_test_value(2));
+    assert_eq!(Errors::FailedWriteToDataFile, put_res.err().unwrap());
+    assert_eq!(1, writes.load(Ordering::SeqCst));
+    assert_eq!(get_test_value(1), engine.get(get_test_key(1)).unwrap());
+    std::mem::drop(engine);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
+
+#[test]
+fn test_engine_write_buffer() {
+    let mut opts = Options::default();
+    opts.dir_path = PathBuf::from("/tmp/bitcask-rs-write-buffer");
+    opts.data_file_size = 64 * 1024 * 1024;
+    opts.write_buffer_size = 4 * 1024;
+    let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+    // 将活跃文件的 IO 管理接口替换为统计写入次数的实现
+    let writes = Arc::new(AtomicUsize::new(0));
+    let file_path = get_data_file_path(opts.dir_path.clone(), 0, DATA_FILE_NAME_SUFFIX);
+    let io = FlakyIO {
+        inner: FileIO::new(file_path.clone(), false).unwrap(),
+        failures: AtomicUsize::new(0),
+        writes: writes.clone(),
+        no_space: false,
+    };
+    engine.active_file.write().replace_io_manager(Box::new(io));
+
+    // 写入之后立即可以读到还在缓冲区中的数据
+    for i in 0..1000 {
+        let put_res = engine.put(get_test_key(i), get_test_value(i));
+        assert!(put_res.is_ok());
+        assert_eq!(get_test_value(i), engine.get(get_test_key(i)).unwrap());
+    }
+
+    // 每次写入文件至少写入一个缓冲区大小的数据
+    let write_off = engine.active_file_offset();
+    let write_count = writes.load(Ordering::SeqCst);
+    assert!(write_count > 0);
+    assert!(write_count as u64 <= write_off / opts.write_buffer_size as u64);
+
+    // sync 时写入缓冲区中剩余的数据
+    let sync_res = engine.sync();
+    assert!(sync_res.is_ok());
+    assert_eq!(write_off, std::fs::metadata(&file_path).unwrap().len());
+
+    // 没有写满缓冲区的数据不写入文件，关闭时写入
+    let put_res = engine.put(get_test_key(1000), get_test_value(1000));
+    assert!(put_res.is_ok());
+    assert_eq!(write_off, std::fs::metadata(&file_path).unwrap().len());
+    assert!(engine.active_file_offset() > write_off);
+    std::mem::drop(engine);
+
+    // 重新打开后读取所有数据
+    let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+    assert_eq!(1001, engine2.list_keys().len());
+    for i in 0..=1000 {
+        assert_eq!(get_test_value(i), engine2.get(get_test_key(i)).unwrap());
+    }
+    std::mem::drop(engine2);
+
+    // 删除测试的文件夹
+    std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+}
@@ -1,5 +1,7 @@
 // use std::result;
 
+use std::{io, sync::Arc};
+
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -31,6 +33,9 @@ pub enum Errors {
     #[error("database directory path can not be empty")]
     DirPathIsEmpty,
 
+    #[error("database directory path exists but is not a directory")]
+    DirPathIsNotADirectory,
+
     #[error("data file size must be greater than 0")]
     DataFileSizeInvalid,
 
@@ -55,6 +60,9 @@ pub enum Errors {
     #[error("merge is in processing")]
     MergeInProcess,
 
+    #[error("merge is cancelled")]
+    MergeCancelled,
+
     #[error("unable to use write batch, seq file not exists")]
     UnableToUseWriteBatch,
 
@@ -72,6 +80,117 @@ pub enum Errors {
 
     #[error("failed to copy data directory")]
     FailedToCopyDir,
+
+    #[error("failed to remove file in database directory")]
+    FailedToRemoveFile,
+
+    #[error("index type mismatch, the database was created with another index type")]
+    IndexTypeMismatch,
+
+    #[error("database is opened in read only mode")]
+    ReadOnlyMode,
+
+    #[error("hash keys in index is not supported by B+ tree index")]
+    HashKeysInIndexUnsupported,
+
+    #[error("invalid data file suffix or lock file name")]
+    InvalidFileName,
+
+    #[error("the B+ tree index file is corrupted")]
+    BPlusTreeIndexCorrupted,
+
+    #[error("data file size is smaller than the size used to write existing data files")]
+    DataFileSizeShrunk,
+
+    #[error("the value is not a number")]
+    NotANumber,
+
+    #[error("archived data files can only be opened in read only mode")]
+    ArchiveRequiresReadOnly,
+
+    #[error("B+ tree index can not be opened in read only mode")]
+    ReadOnlyBPlusTreeUnsupported,
+
+    #[error("the encoded log record exceeds the max record size")]
+    RecordTooLarge,
+
+    #[error("custom shard function is not supported with hash keys in index")]
+    ShardFnWithHashKeysUnsupported,
+
+    #[error("invalid log record key, the sequence number can not be decoded")]
+    InvalidLogRecordKey,
+
+    #[error("the database already exists in the directory")]
+    DatabaseAlreadyExists,
+
+    #[error("the database does not exist in the directory")]
+    DatabaseNotFound,
+
+    #[error("no space left on device")]
+    NoSpaceLeft,
+
+    #[error("data file id is already in use")]
+    DataFileIdInUse,
+
+    #[error("engine is closed")]
+    EngineClosed,
+
+    #[error("the database was written with an unsupported format version")]
+    UnsupportedFormatVersion,
+
+    #[error("merge throughput limit must be greater than 0")]
+    InvalidMergeThroughputLimit,
+
+    #[error("index entry points beyond the end of data file")]
+    StaleIndexEntry,
+
+    #[error("io error: {0}")]
+    Io(#[from] IoError),
+}
+
+impl Errors {
+    /// 如果是 io 错误，返回原始的 io::Error
+    pub fn io_error(&self) -> Option<&io::Error> {
+        match self {
+            Errors::Io(e) => Some(e.inner()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Errors {
+    fn from(e: io::Error) -> Self {
+        Errors::Io(IoError::from(e))
+    }
+}
+
+/// 包装 io::Error，按 ErrorKind 比较，使 Errors 可以继续实现 PartialEq
+#[derive(Error, Debug, Clone)]
+#[error(transparent)]
+pub struct IoError(Arc<io::Error>);
+
+impl IoError {
+    /// io 错误的类型
+    pub fn kind(&self) -> io::ErrorKind {
+        self.0.kind()
+    }
+
+    /// 原始的 io::Error
+    pub fn inner(&self) -> &io::Error {
+        &self.0
+    }
+}
+
+impl PartialEq for IoError {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind() == other.kind()
+    }
+}
+
+impl From<io::Error> for IoError {
+    fn from(e: io::Error) -> Self {
+        IoError(Arc::new(e))
+    }
 }
 
 // pub type Result<T> = result::Result<T, Errors>;
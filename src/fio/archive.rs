@@ -0,0 +1,114 @@
+use std::{path::PathBuf, sync::OnceLock};
+
+use log::error;
+
+use crate::{errors::Errors, options::ArchiveReader};
+
+use super::IOManager;
+
+// ArchiveIO 读取压缩归档的数据文件，第一次访问时将整个文件解压到内存中，只支持读取
+pub struct ArchiveIO {
+    file_path: PathBuf,
+    reader: ArchiveReader,
+    data: OnceLock<Vec<u8>>, // 解压后的文件内容
+}
+
+impl ArchiveIO {
+    pub fn new(file_path: PathBuf, reader: ArchiveReader) -> Self {
+        Self {
+            file_path,
+            reader,
+            data: OnceLock::new(),
+        }
+    }
+
+    // 获取解压后的文件内容，第一次访问时调用 reader 解压
+    fn data(&self) -> Result<&[u8], Errors> {
+        if let Some(data) = self.data.get() {
+            return Ok(data);
+        }
+        let data = match (self.reader)(&self.file_path) {
+            Ok(data) => data,
+            Err(e) => {
+                error!(
+                    "failed to read archived data file {}: {}",
+                    self.file_path.display(),
+                    e
+                );
+                return Err(Errors::FailedReadFromDataFile);
+            }
+        };
+        // 多个线程同时第一次访问时只保留其中一份解压结果
+        Ok(self.data.get_or_init(|| data))
+    }
+}
+
+impl IOManager for ArchiveIO {
+    fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize, Errors> {
+        let data = self.data()?;
+        let end = offset + buf.len() as u64;
+        if end > data.len() as u64 {
+            return Err(Errors::ReadDataFileEOF);
+        }
+        buf.copy_from_slice(&data[offset as usize..end as usize]);
+        Ok(buf.len())
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, Errors> {
+        Err(Errors::ReadOnlyMode)
+    }
+
+    fn sync(&self) -> Result<(), Errors> {
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        match self.data() {
+            Ok(data) => data.len() as u64,
+            Err(_) => 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, sync::Arc};
+
+    use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+
+    use super::*;
+
+    #[test]
+    fn test_archive_io_read() {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        std::io::Write::write_all(&mut encoder, b"aabbcc").unwrap();
+        let gz = encoder.finish().unwrap();
+
+        // 在内存中解压，不需要访问磁盘上的文件
+        let reader: ArchiveReader = Arc::new(move |_| {
+            let mut data = Vec::new();
+            GzDecoder::new(gz.as_slice()).read_to_end(&mut data)?;
+            Ok(data)
+        });
+        let archive_io = ArchiveIO::new(PathBuf::from("/tmp/archive-test.data.gz"), reader);
+        assert_eq!(6, archive_io.size());
+
+        let mut buf = [0u8; 2];
+        assert_eq!(2, archive_io.read(&mut buf, 2).unwrap());
+        assert_eq!(b"bb", &buf);
+        assert_eq!(
+            Errors::ReadDataFileEOF,
+            archive_io.read(&mut buf, 5).err().unwrap()
+        );
+        assert_eq!(Errors::ReadOnlyMode, archive_io.write(b"dd").err().unwrap());
+
+        // 解压失败时返回错误
+        let reader: ArchiveReader = Arc::new(|_| Err(std::io::ErrorKind::InvalidData.into()));
+        let archive_io = ArchiveIO::new(PathBuf::from("/tmp/archive-test.data.gz"), reader);
+        assert_eq!(0, archive_io.size());
+        assert_eq!(
+            Errors::FailedReadFromDataFile,
+            archive_io.read(&mut buf, 0).err().unwrap()
+        );
+    }
+}
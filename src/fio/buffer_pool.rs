@@ -0,0 +1,115 @@
+use std::{
+    cell::RefCell,
+    ops::{Deref, DerefMut},
+};
+
+use bytes::BytesMut;
+
+// 每个线程最多缓存的缓冲区数量，一次读取同时使用 header 和 key/value 两个缓冲区
+const MAX_POOLED_BUFFERS: usize = 4;
+// 超过该容量的缓冲区用完后直接释放，避免读取过大的 value 之后一直占用内存
+const MAX_POOLED_CAPACITY: usize = 64 * 1024;
+
+thread_local! {
+    // 每个线程独立的缓冲池，并发读取时不需要加锁
+    static POOL: RefCell<Vec<BytesMut>> = const { RefCell::new(Vec::new()) };
+}
+
+/// 读取数据文件时使用的缓冲区
+/// 开启复用时从当前线程的缓冲池中取出，Drop 时放回缓冲池，之后的读取不需要重新分配内存
+pub struct ReadBuffer {
+    buf: BytesMut,
+    pooled: bool,
+}
+
+impl ReadBuffer {
+    /// 获取长度为 len、内容全部为 0 的缓冲区，pooled 为 true 时复用当前线程缓存的缓冲区
+    pub fn zeroed(len: usize, pooled: bool) -> Self {
+        if !pooled {
+            return Self {
+                buf: BytesMut::zeroed(len),
+                pooled,
+            };
+        }
+
+        let mut buf = POOL
+            .with(|pool| pool.borrow_mut().pop())
+            .unwrap_or_default();
+        buf.clear();
+        buf.resize(len, 0);
+        Self { buf, pooled }
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        self.buf.truncate(len);
+    }
+}
+
+impl Deref for ReadBuffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buf
+    }
+}
+
+impl DerefMut for ReadBuffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        &mut self.buf
+    }
+}
+
+impl Drop for ReadBuffer {
+    fn drop(&mut self) {
+        if !self.pooled || self.buf.capacity() > MAX_POOLED_CAPACITY {
+            return;
+        }
+        let buf = std::mem::take(&mut self.buf);
+        // 线程退出时缓冲池可能已经销毁，此时直接释放
+        let _ = POOL.try_with(|pool| {
+            let mut pool = pool.borrow_mut();
+            if pool.len() < MAX_POOLED_BUFFERS {
+                pool.push(buf);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_buffer_reuse() {
+        // 放回缓冲池之后再次获取，复用同一块内存，内容重新清零
+        let mut buf1 = ReadBuffer::zeroed(16, true);
+        buf1.copy_from_slice(&[1u8; 16]);
+        let ptr1 = buf1.as_ptr();
+        drop(buf1);
+        let buf2 = ReadBuffer::zeroed(8, true);
+        assert_eq!(ptr1, buf2.as_ptr());
+        assert_eq!(&[0u8; 8], &buf2[..]);
+
+        // 同时使用的缓冲区不会相互复用
+        let buf3 = ReadBuffer::zeroed(8, true);
+        assert_ne!(buf2.as_ptr(), buf3.as_ptr());
+        drop(buf2);
+        drop(buf3);
+
+        // 过大的缓冲区不放回缓冲池
+        let large = ReadBuffer::zeroed(MAX_POOLED_CAPACITY + 1, true);
+        drop(large);
+        POOL.with(|pool| {
+            let pool = pool.borrow();
+            assert_eq!(1, pool.len());
+            assert!(pool.iter().all(|b| b.capacity() <= MAX_POOLED_CAPACITY));
+        });
+
+        // 其他线程使用独立的缓冲池
+        std::thread::spawn(|| {
+            assert!(POOL.with(|pool| pool.borrow().is_empty()));
+        })
+        .join()
+        .unwrap();
+    }
+}
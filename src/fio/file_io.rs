@@ -1,17 +1,17 @@
 use std::{
     fs::{File, OpenOptions},
-    io::Write,
+    io::{self, Write},
     os::unix::prelude::FileExt,
     path::PathBuf,
     sync::Arc,
 };
 
-use log::error;
+use log::{error, warn};
 use parking_lot::RwLock;
 
-use crate::errors::Errors;
+use crate::{errors::Errors, options::RetryPolicy};
 
-use super::IOManager;
+use super::{open_file, IOManager};
 
 // FileIO 标准系统文件 IO
 pub struct FileIO {
@@ -19,20 +19,16 @@ pub struct FileIO {
 }
 
 impl FileIO {
-    pub fn new(file_path: PathBuf) -> Result<Self, Errors> {
-        match OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .append(true)
-            .open(file_path)
-        {
+    pub fn new(file_path: PathBuf, read_only: bool) -> Result<Self, Errors> {
+        let mut options = OpenOptions::new();
+        options.create(true).read(true).write(true).append(true);
+        match open_file(&options, &file_path, read_only) {
             Ok(file) => Ok(Self {
                 fd: Arc::new(RwLock::new(file)),
             }),
             Err(err) => {
                 error!("open data file error: {}", err);
-                Err(Errors::FailedOpenDataFile)
+                Err(map_io_error(&err, Errors::FailedOpenDataFile))
             }
         }
     }
@@ -42,6 +38,8 @@ impl IOManager for FileIO {
     fn read(&self, buf: &mut [u8], offset: u64) -> Result<usize, Errors> {
         let read_guard = self.fd.read();
         match read_guard.read_at(buf, offset) {
+            // 偏移超出文件末尾时和 mmap 一样返回 EOF，不足 buf 长度的部分读取仍然返回实际读取的字节数
+            Ok(0) if !buf.is_empty() => Err(Errors::ReadDataFileEOF),
             Ok(n) => Ok(n),
             Err(err) => {
                 error!("read from data file error: {}", err);
@@ -56,7 +54,7 @@ impl IOManager for FileIO {
             Ok(n) => Ok(n),
             Err(err) => {
                 error!("write to data file error: {}", err);
-                Err(Errors::FailedWriteToDataFile)
+                Err(map_io_error(&err, Errors::FailedWriteToDataFile))
             }
         }
     }
@@ -67,7 +65,7 @@ impl IOManager for FileIO {
             Ok(_) => Ok(()),
             Err(err) => {
                 error!("failed to sync data file: {}", err);
-                Err(Errors::FailedSyncDataFile)
+                Err(map_io_error(&err, Errors::FailedSyncDataFile))
             }
         }
     }
@@ -79,6 +77,47 @@ impl IOManager for FileIO {
     }
 }
 
+// 磁盘空间不足时重试没有意义，单独返回 NoSpaceLeft，其他错误返回 default
+pub(crate) fn map_io_error(err: &io::Error, default: Errors) -> Errors {
+    match err.kind() {
+        io::ErrorKind::StorageFull => Errors::NoSpaceLeft,
+        _ => default,
+    }
+}
+
+/// 按重试策略执行写入或持久化操作，返回可能是暂时性的错误时等待 backoff 之后重试，最多执行 attempts 次
+/// 磁盘空间不足等明确无法通过重试恢复的错误直接返回；没有设置重试策略时只执行一次
+pub(crate) fn with_retry<T, F>(policy: Option<&RetryPolicy>, mut op: F) -> Result<T, Errors>
+where
+    F: FnMut() -> Result<T, Errors>,
+{
+    let attempts = policy.map_or(1, |policy| policy.attempts);
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(e) if attempt < attempts && is_retryable(&e) => {
+                warn!(
+                    "io operation failed, retry {}/{}: {}",
+                    attempt,
+                    attempts - 1,
+                    e
+                );
+                std::thread::sleep(policy.unwrap().backoff);
+                attempt += 1;
+            }
+            res => return res,
+        }
+    }
+}
+
+// 写入和持久化失败的通用错误可能是暂时性的，可以重试
+fn is_retryable(e: &Errors) -> bool {
+    matches!(
+        e,
+        Errors::FailedWriteToDataFile | Errors::FailedSyncDataFile
+    )
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -89,7 +128,7 @@ mod tests {
     #[test]
     fn test_file_io_write() {
         let path = PathBuf::from("/tmp/a.data");
-        let fio_res = FileIO::new(path.clone());
+        let fio_res = FileIO::new(path.clone(), false);
         assert!(fio_res.is_ok());
         let fio = fio_res.unwrap();
 
@@ -108,7 +147,7 @@ mod tests {
     #[test]
     fn test_file_io_read() {
         let path = PathBuf::from("/tmp/b.data");
-        let fio_res = FileIO::new(path.clone());
+        let fio_res = FileIO::new(path.clone(), false);
         assert!(fio_res.is_ok());
         let fio = fio_res.unwrap();
 
@@ -129,6 +168,12 @@ mod tests {
         assert!(read_res2.is_ok());
         assert_eq!(read_res2.unwrap(), 3);
 
+        // 偏移等于或超过文件大小
+        let read_res3 = fio.read(&mut buf, 8);
+        assert_eq!(read_res3.err().unwrap(), Errors::ReadDataFileEOF);
+        let read_res4 = fio.read(&mut buf, 100);
+        assert_eq!(read_res4.err().unwrap(), Errors::ReadDataFileEOF);
+
         let remove_res = remove_file(path);
         assert!(remove_res.is_ok());
     }
@@ -136,7 +181,7 @@ mod tests {
     #[test]
     fn test_file_io_sync() {
         let path = PathBuf::from("/tmp/c.data");
-        let fio_res = FileIO::new(path.clone());
+        let fio_res = FileIO::new(path.clone(), false);
         assert!(fio_res.is_ok());
         let fio = fio_res.unwrap();
 
@@ -154,4 +199,35 @@ mod tests {
         let remove_res = remove_file(path);
         assert!(remove_res.is_ok());
     }
+
+    #[test]
+    fn test_file_io_open_read_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = PathBuf::from("/tmp/d.data");
+        let fio_res = FileIO::new(path.clone(), false);
+        assert!(fio_res.is_ok());
+        let res1 = fio_res.unwrap().write("key-a".as_bytes());
+        assert!(res1.is_ok());
+
+        let perm = std::fs::Permissions::from_mode(0o444);
+        std::fs::set_permissions(path.clone(), perm).unwrap();
+
+        // 只读模式下无法写入的文件改为只读打开
+        let fio_res2 = FileIO::new(path.clone(), true);
+        assert!(fio_res2.is_ok());
+        let mut buf = [0u8; 5];
+        let read_res = fio_res2.unwrap().read(&mut buf, 0);
+        assert!(read_res.is_ok());
+        assert_eq!("key-a".as_bytes(), buf);
+
+        // 读写模式下返回打开文件的错误，root 用户不受文件权限限制时跳过
+        if OpenOptions::new().write(true).open(path.clone()).is_err() {
+            let fio_res3 = FileIO::new(path.clone(), false);
+            assert_eq!(Errors::FailedOpenDataFile, fio_res3.err().unwrap());
+        }
+
+        let remove_res = remove_file(path);
+        assert!(remove_res.is_ok());
+    }
 }
@@ -6,20 +6,17 @@ use parking_lot::Mutex;
 
 use crate::errors::Errors;
 
-use super::IOManager;
+use super::{file_io::map_io_error, open_file, IOManager};
 
 pub struct MMapIO {
     map: Arc<Mutex<Mmap>>,
 }
 
 impl MMapIO {
-    pub fn new(file_path: PathBuf) -> Result<Self, Errors> {
-        match OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(file_path)
-        {
+    pub fn new(file_path: PathBuf, read_only: bool) -> Result<Self, Errors> {
+        let mut options = OpenOptions::new();
+        options.create(true).read(true).write(true);
+        match open_file(&options, &file_path, read_only) {
             Ok(file) => {
                 let map = unsafe { Mmap::map(&file).expect("failed to map the file") };
                 return Ok(MMapIO {
@@ -28,7 +25,7 @@ impl MMapIO {
             }
             Err(e) => {
                 error!("failed to open data file: {}", e);
-                return Err(Errors::FailedOpenDataFile);
+                return Err(map_io_error(&e, Errors::FailedOpenDataFile));
             }
         }
     }
@@ -74,14 +71,14 @@ mod tests {
         let path = PathBuf::from("/tmp/mmap-test.data");
 
         // 文件为空
-        let mmap_res1 = MMapIO::new(path.clone());
+        let mmap_res1 = MMapIO::new(path.clone(), false);
         assert!(mmap_res1.is_ok());
         let mmap_io1 = mmap_res1.ok().unwrap();
         let mut buf1 = [0u8; 10];
         let read_res1 = mmap_io1.read(&mut buf1, 0);
         assert_eq!(read_res1.err().unwrap(), Errors::ReadDataFileEOF);
 
-        let fio_res = FileIO::new(path.clone());
+        let fio_res = FileIO::new(path.clone(), false);
         assert!(fio_res.is_ok());
         let fio = fio_res.ok().unwrap();
         fio.write(b"aa").unwrap();
@@ -89,7 +86,7 @@ mod tests {
         fio.write(b"cc").unwrap();
 
         // 有数据的情况
-        let mmap_res2 = MMapIO::new(path.clone());
+        let mmap_res2 = MMapIO::new(path.clone(), false);
         assert!(mmap_res2.is_ok());
         let mmap_io2 = mmap_res2.ok().unwrap();
 
@@ -1,11 +1,20 @@
+pub mod archive;
+pub mod buffer_pool;
 pub mod file_io;
 pub mod mmap;
 
-use std::path::PathBuf;
+use std::{
+    fs::{File, OpenOptions},
+    io,
+    path::{Path, PathBuf},
+};
 
-use crate::{errors::Errors, options::IOType};
+use crate::{
+    errors::Errors,
+    options::{ArchiveReader, IOType},
+};
 
-use self::{file_io::FileIO, mmap::MMapIO};
+use self::{archive::ArchiveIO, file_io::FileIO, mmap::MMapIO};
 
 /// 抽象 IO 管理接口
 pub trait IOManager: Sync + Send {
@@ -22,10 +31,32 @@ pub trait IOManager: Sync + Send {
     fn size(&self) -> u64;
 }
 
-/// 根据数据文件路径初始化 IOManager
-pub fn new_io_manager(file_path: PathBuf, io_type: IOType) -> Box<dyn IOManager> {
+/// 根据数据文件路径初始化 IOManager，read_only 为 true 时可以打开只读的文件
+pub fn new_io_manager(
+    file_path: PathBuf,
+    io_type: IOType,
+    read_only: bool,
+) -> Result<Box<dyn IOManager>, Errors> {
     match io_type {
-        IOType::FileIO => Box::new(FileIO::new(file_path).unwrap()),
-        IOType::MMapIO => Box::new(MMapIO::new(file_path).unwrap()),
+        IOType::FileIO => Ok(Box::new(FileIO::new(file_path, read_only)?)),
+        IOType::MMapIO => Ok(Box::new(MMapIO::new(file_path, read_only)?)),
     }
 }
+
+/// 按 options 打开文件，只读模式下数据目录可能在只读的文件系统上，无法以写入方式打开时已经存在的文件改为只读打开；
+/// 读写模式下以及文件不存在时返回原来的错误
+pub(crate) fn open_file(
+    options: &OpenOptions,
+    file_path: &Path,
+    read_only: bool,
+) -> io::Result<File> {
+    match options.open(file_path) {
+        Err(e) if read_only && file_path.is_file() => File::open(file_path).map_err(|_| e),
+        res => res,
+    }
+}
+
+/// 根据数据文件路径初始化读取压缩归档文件的 IOManager，只能读取
+pub fn new_archive_io_manager(file_path: PathBuf, reader: ArchiveReader) -> Box<dyn IOManager> {
+    Box::new(ArchiveIO::new(file_path, reader))
+}
@@ -1,41 +1,75 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{
+    panic::{self, AssertUnwindSafe},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use parking_lot::RwLock;
 
 use bytes::Bytes;
 use jammdb::DB;
+use log::error;
 
 use crate::{
     data::log_record::{decode_log_record_pos, LogRecordPos},
+    errors::Errors,
     options::IteratorOptions,
 };
 
 use super::{IndexIterator, Indexer};
 
-const BPTREE_INDEX_FILE_NAME: &str = "bptree-index";
+pub(crate) const BPTREE_INDEX_FILE_NAME: &str = "bptree-index";
 const BPTREE_BUCKET_NAME: &str = "bitcask-index";
+// 正序遍历时每次从 B+ 树中加载的数据条数
+const BPTREE_ITER_BATCH_SIZE: usize = 256;
 
 // B+树索引
 pub struct BPlusTree {
-    tree: Arc<DB>,
+    // 关闭之后置为 None，释放索引文件及其文件锁
+    tree: RwLock<Option<Arc<DB>>>,
 }
 
 impl BPlusTree {
-    pub fn new(dir_path: PathBuf) -> Self {
-        // 打开 B+ 树实例，并创建对应的 bucket
-        let bptree =
-            DB::open(dir_path.join(BPTREE_INDEX_FILE_NAME)).expect("failed to open bptree");
-        let tree = Arc::new(bptree);
-        let tx = tree.tx(true).expect("failed to begin tx");
-        tx.get_or_create_bucket(BPTREE_BUCKET_NAME).unwrap();
-        tx.commit().unwrap();
+    /// 打开 B+ 树实例，并创建对应的 bucket，索引文件损坏无法打开时返回错误
+    pub fn open(dir_path: PathBuf) -> Result<Self, Errors> {
+        let corrupted = |e: jammdb::Error| {
+            error!("failed to open bptree index: {}", e);
+            Errors::BPlusTreeIndexCorrupted
+        };
+        // jammdb 读取到损坏的 meta 页时直接 panic，不会返回错误，同样视为索引文件损坏
+        let open = || -> Result<Arc<DB>, Errors> {
+            let tree =
+                Arc::new(DB::open(dir_path.join(BPTREE_INDEX_FILE_NAME)).map_err(corrupted)?);
+            let tx = tree.tx(true).map_err(corrupted)?;
+            tx.get_or_create_bucket(BPTREE_BUCKET_NAME)
+                .map_err(corrupted)?;
+            tx.commit().map_err(corrupted)?;
+            Ok(tree)
+        };
+        let tree = match panic::catch_unwind(AssertUnwindSafe(open)) {
+            Ok(res) => res?,
+            Err(_) => {
+                error!("failed to open bptree index: index file is corrupted");
+                return Err(Errors::BPlusTreeIndexCorrupted);
+            }
+        };
+
+        Ok(Self {
+            tree: RwLock::new(Some(tree)),
+        })
+    }
 
-        Self { tree: tree.clone() }
+    // 索引已经关闭时返回 None
+    fn tree(&self) -> Option<Arc<DB>> {
+        self.tree.read().clone()
     }
 }
 
 impl Indexer for BPlusTree {
     fn put(&self, key: Vec<u8>, pos: LogRecordPos) -> Option<LogRecordPos> {
         let mut result = None;
-        let tx = self.tree.tx(true).expect("failed to begin tx");
+        let tree = self.tree()?;
+        let tx = tree.tx(true).expect("failed to begin tx");
         let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
 
         // 先获取到旧的值
@@ -54,7 +88,8 @@ impl Indexer for BPlusTree {
     }
 
     fn get(&self, key: Vec<u8>) -> Option<crate::data::log_record::LogRecordPos> {
-        let tx = self.tree.tx(false).expect("failed to begin tx");
+        let tree = self.tree()?;
+        let tx = tree.tx(false).expect("failed to begin tx");
         let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
         if let Some(kv) = bucket.get_kv(key) {
             return Some(decode_log_record_pos(kv.value().to_vec()));
@@ -64,7 +99,8 @@ impl Indexer for BPlusTree {
 
     fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos> {
         let mut result = None;
-        let tx = self.tree.tx(true).expect("failed to begin tx");
+        let tree = self.tree()?;
+        let tx = tree.tx(true).expect("failed to begin tx");
         let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
         if let Ok(kv) = bucket.delete(key) {
             let pos = decode_log_record_pos(kv.value().to_vec());
@@ -76,28 +112,28 @@ impl Indexer for BPlusTree {
     }
 
     fn iterator(&self, options: crate::options::IteratorOptions) -> Box<dyn super::IndexIterator> {
-        let mut items = Vec::new();
-        let tx = self.tree.tx(false).expect("failed to begin tx");
-        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
-
-        for data in bucket.cursor() {
-            let key = data.key().to_vec();
-            let pos = decode_log_record_pos(data.kv().value().to_vec());
-            items.push((key, pos));
-        }
-        if options.reverse {
-            items.reverse();
-        }
-
-        Box::new(BPTreeIterator {
-            items,
+        let mut iter = BPTreeIterator {
+            tree: self.tree(),
+            start_key: Vec::new(),
+            skip_start: false,
+            finished: false,
+            items: Arc::new(Vec::new()),
+            loaded: false,
             curr_index: 0,
+            end_index: 0,
             options,
-        })
+        };
+        iter.rewind();
+
+        Box::new(iter)
     }
 
     fn list_keys(&self) -> Vec<Bytes> {
-        let tx = self.tree.tx(false).expect("failed to begin tx");
+        let tree = match self.tree() {
+            Some(tree) => tree,
+            None => return Vec::new(),
+        };
+        let tx = tree.tx(false).expect("failed to begin tx");
         let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
         let mut keys = Vec::new();
 
@@ -107,46 +143,211 @@ impl Indexer for BPlusTree {
 
         keys
     }
+
+    fn clear(&self) {
+        let tree = match self.tree() {
+            Some(tree) => tree,
+            None => return,
+        };
+        let tx = tree.tx(true).expect("failed to begin tx");
+        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
+        let keys: Vec<Vec<u8>> = bucket.cursor().map(|data| data.key().to_vec()).collect();
+        for key in keys {
+            bucket
+                .delete(key)
+                .expect("failed to delete value in bptree");
+        }
+        tx.commit().unwrap();
+    }
+
+    // 数据保存在磁盘上的索引文件中，内存中只有数据库实例本身
+    fn memory_usage(&self) -> usize {
+        std::mem::size_of::<Self>()
+    }
+
+    // jammdb 没有记录子树的数据量，通过游标定位到 lower 之后逐条计数
+    fn range_count(&self, lower: &[u8], upper: &[u8]) -> usize {
+        let tree = match self.tree() {
+            Some(tree) if lower < upper => tree,
+            _ => return 0,
+        };
+        let tx = tree.tx(false).expect("failed to begin tx");
+        let bucket = tx.get_bucket(BPTREE_BUCKET_NAME).unwrap();
+        let mut cursor = bucket.cursor();
+        cursor.seek(lower);
+        // 游标可能停在 lower 之前的一条数据上
+        cursor
+            .skip_while(|data| data.key() < lower)
+            .take_while(|data| data.key() < upper)
+            .count()
+    }
+
+    // 释放数据库实例，已经创建的迭代器持有实例的引用，在迭代器释放之后才会关闭索引文件
+    fn close(&self) {
+        self.tree.write().take();
+    }
 }
 
 /// B+ 树索引迭代器
+/// 正序遍历时每次从 B+ 树中加载一批数据，一批数据只开启一次读事务，不会将所有数据加载到内存中；
+/// jammdb 的游标只支持正向遍历，因此逆序遍历时仍然需要加载数据，有前缀时只加载该前缀下的数据；
+/// 正序遍历时第一次从末尾遍历也会加载剩余的数据，之后两端都从加载的数据中遍历
+#[derive(Clone)]
 pub struct BPTreeIterator {
-    items: Vec<(Vec<u8>, LogRecordPos)>, // 存储 key+索引
-    curr_index: usize,                   // 当前遍历的位置下标
-    options: IteratorOptions,            // 配置项
+    tree: Option<Arc<DB>>,                    // B+ 树实例，索引关闭之后为 None
+    start_key: Vec<u8>,                       // 正序遍历时下一批数据的起始 key
+    skip_start: bool,                         // 是否跳过等于起始 key 的数据（上一批的最后一条）
+    finished: bool,                           // 正序遍历时 B+ 树中是否已经没有更多的数据
+    items: Arc<Vec<(Vec<u8>, LogRecordPos)>>, // 加载的 key+索引，复制的迭代器之间共享
+    loaded: bool,                             // 正序遍历时是否已经加载了剩余的数据
+    curr_index: usize,                        // 加载的数据中当前位置下标
+    end_index: usize,                         // 加载的数据中从末尾遍历的位置下标
+    options: IteratorOptions,                 // 配置项
+}
+
+impl BPTreeIterator {
+    // 从 B+ 树中加载大于等于 key 并且满足前缀的数据，最多加载 limit 条，skip_equal 为 true 时跳过等于 key 的数据
+    // 读取 B+ 树失败或者索引已经关闭时返回空的结果，迭代器随之结束
+    fn load_from(
+        &self,
+        key: &[u8],
+        skip_equal: bool,
+        limit: usize,
+    ) -> Vec<(Vec<u8>, LogRecordPos)> {
+        let tx = match self.tree.as_ref().map(|tree| tree.tx(false)) {
+            Some(Ok(tx)) => tx,
+            Some(Err(e)) => {
+                error!("failed to read bptree index: {}", e);
+                return Vec::new();
+            }
+            None => return Vec::new(),
+        };
+        let bucket = match tx.get_bucket(BPTREE_BUCKET_NAME) {
+            Ok(bucket) => bucket,
+            Err(e) => {
+                error!("failed to read bptree index: {}", e);
+                return Vec::new();
+            }
+        };
+        let mut cursor = bucket.cursor();
+        cursor.seek(key);
+
+        let mut items = Vec::new();
+        for data in cursor {
+            // 游标可能停在 key 之前的一条数据上
+            if data.key() < key || (skip_equal && data.key() == key) {
+                continue;
+            }
+            if items.len() >= limit {
+                break;
+            }
+            // 数据有序，超出前缀的范围之后不会再有满足条件的数据
+            let prefix = &self.options.prefix;
+            if !prefix.is_empty() && !data.key().starts_with(prefix) {
+                break;
+            }
+            let pos = decode_log_record_pos(data.kv().value().to_vec());
+            items.push((data.key().to_vec(), pos));
+        }
+        items
+    }
+
+    // 加载逆序遍历的数据
+    fn load_reverse_items(&mut self) {
+        let mut items = self.load_from(&self.options.prefix, false, usize::MAX);
+        items.reverse();
+        self.set_items(items);
+    }
+
+    // 正序遍历时从上一批数据之后加载下一批数据
+    fn load_next_batch(&mut self) {
+        let items = self.load_from(&self.start_key, self.skip_start, BPTREE_ITER_BATCH_SIZE);
+        if items.len() < BPTREE_ITER_BATCH_SIZE {
+            self.finished = true;
+        }
+        if let Some((key, _)) = items.last() {
+            self.start_key = key.clone();
+            self.skip_start = true;
+        }
+        self.set_items(items);
+    }
+
+    // 正序遍历时加载还没有遍历的数据，包括当前批次中剩余的数据
+    fn load_remaining_items(&mut self) {
+        let mut items = self.items[self.curr_index..self.end_index].to_vec();
+        if !self.finished {
+            items.extend(self.load_from(&self.start_key, self.skip_start, usize::MAX));
+        }
+        self.set_items(items);
+        self.finished = true;
+        self.loaded = true;
+    }
+
+    // 正序遍历回到 key 的位置，之后从 key 开始重新加载数据
+    fn reset_forward(&mut self, key: Vec<u8>) {
+        self.start_key = key;
+        self.skip_start = false;
+        self.finished = false;
+        self.loaded = false;
+        self.set_items(Vec::new());
+    }
+
+    fn set_items(&mut self, items: Vec<(Vec<u8>, LogRecordPos)>) {
+        self.curr_index = 0;
+        self.end_index = items.len();
+        self.items = Arc::new(items);
+    }
 }
 
 impl IndexIterator for BPTreeIterator {
     fn rewind(&mut self) {
-        self.curr_index = 0;
+        if self.options.reverse {
+            self.load_reverse_items();
+            return;
+        }
+        self.reset_forward(self.options.prefix.clone());
     }
 
     fn seek(&mut self, key: Vec<u8>) {
-        self.curr_index = match self.items.binary_search_by(|(x, _)| {
-            if self.options.reverse {
-                x.cmp(&key).reverse()
-            } else {
-                x.cmp(&key)
-            }
-        }) {
-            Ok(equal_val) => equal_val,
-            Err(insert_val) => insert_val,
-        };
+        if self.options.reverse {
+            self.curr_index = self.items.partition_point(|(x, _)| {
+                // 逆序时以 key 为前缀的数据也视为满足条件
+                !(x.starts_with(&key) || *x < key)
+            });
+            self.end_index = self.items.len();
+            return;
+        }
+        // 起始位置不能在前缀之前
+        self.reset_forward(std::cmp::max(key, self.options.prefix.clone()));
     }
 
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-        if self.curr_index >= self.items.len() {
+        // 正序遍历完当前批次的数据之后，从上一批的最后一条数据之后继续加载
+        if !self.options.reverse && self.curr_index >= self.end_index && !self.finished {
+            self.load_next_batch();
+        }
+        if self.curr_index >= self.end_index {
             return None;
         }
+        let item = self.items.get(self.curr_index)?;
+        self.curr_index += 1;
+        Some((&item.0, &item.1))
+    }
 
-        while let Some(item) = self.items.get(self.curr_index) {
-            self.curr_index += 1;
-            let prefix = &self.options.prefix;
-            if prefix.is_empty() || item.0.starts_with(&prefix) {
-                return Some((&item.0, &item.1));
-            }
+    fn next_back(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
+        if !self.options.reverse && !self.loaded {
+            self.load_remaining_items();
         }
-        None
+        if self.end_index <= self.curr_index {
+            return None;
+        }
+        self.end_index -= 1;
+        let item = self.items.get(self.end_index)?;
+        Some((&item.0, &item.1))
+    }
+
+    fn clone_box(&self) -> Box<dyn IndexIterator> {
+        Box::new(self.clone())
     }
 }
 
@@ -160,7 +361,7 @@ mod tests {
     fn test_bptree_put() {
         let path = PathBuf::from("/tmp/bptree-put");
         fs::create_dir_all(path.clone()).unwrap();
-        let bpt = BPlusTree::new(path.clone());
+        let bpt = BPlusTree::open(path.clone()).unwrap();
 
         let res1 = bpt.put(
             b"ccbde".to_vec(),
@@ -219,7 +420,7 @@ mod tests {
     fn test_bptree_get() {
         let path = PathBuf::from("/tmp/bptree-get");
         fs::create_dir_all(path.clone()).unwrap();
-        let bpt = BPlusTree::new(path.clone());
+        let bpt = BPlusTree::open(path.clone()).unwrap();
 
         let v1 = bpt.get(b"not exist".to_vec());
         assert!(v1.is_none());
@@ -253,7 +454,7 @@ mod tests {
     fn test_bptree_delete() {
         let path = PathBuf::from("/tmp/bptree-delete");
         fs::create_dir_all(path.clone()).unwrap();
-        let bpt = BPlusTree::new(path.clone());
+        let bpt = BPlusTree::open(path.clone()).unwrap();
 
         let r1 = bpt.delete(b"not exist".to_vec());
         assert!(r1.is_none());
@@ -282,7 +483,7 @@ mod tests {
     fn test_bptree_list_keys() {
         let path = PathBuf::from("/tmp/bptree-list-keys");
         fs::create_dir_all(path.clone()).unwrap();
-        let bpt = BPlusTree::new(path.clone());
+        let bpt = BPlusTree::open(path.clone()).unwrap();
 
         let keys1 = bpt.list_keys();
         assert_eq!(keys1.len(), 0);
@@ -330,7 +531,7 @@ mod tests {
     fn test_bptree_itreator() {
         let path = PathBuf::from("/tmp/bptree-iterator");
         fs::create_dir_all(path.clone()).unwrap();
-        let bpt = BPlusTree::new(path.clone());
+        let bpt = BPlusTree::open(path.clone()).unwrap();
 
         bpt.put(
             b"ccbde".to_vec(),
@@ -374,4 +575,138 @@ mod tests {
 
         fs::remove_dir_all(path.clone()).unwrap();
     }
+
+    #[test]
+    fn test_bptree_iterator_reverse_prefix_seek() {
+        let path = PathBuf::from("/tmp/bptree-iterator-reverse-prefix-seek");
+        fs::create_dir_all(path.clone()).unwrap();
+        let bpt = BPlusTree::open(path.clone()).unwrap();
+        for key in ["aade", "bbcc", "dd", "ddaa", "ddce", "eecc"] {
+            bpt.put(
+                key.as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 123,
+                    offset: 883,
+                    size: 11,
+                },
+            );
+        }
+
+        // 逆序 + 前缀，seek 到前缀时从前缀下最大的 key 开始
+        let mut opts1 = IteratorOptions::default();
+        opts1.reverse = true;
+        opts1.prefix = "dd".as_bytes().to_vec();
+        let mut iter1 = bpt.iterator(opts1);
+        iter1.seek("dd".as_bytes().to_vec());
+        assert_eq!(iter1.next().unwrap().0, &b"ddce".to_vec());
+        assert_eq!(iter1.next().unwrap().0, &b"ddaa".to_vec());
+        assert_eq!(iter1.next().unwrap().0, &b"dd".to_vec());
+        assert!(iter1.next().is_none());
+
+        // 逆序 + 前缀，seek 到前缀中间的 key
+        let mut opts2 = IteratorOptions::default();
+        opts2.reverse = true;
+        opts2.prefix = "dd".as_bytes().to_vec();
+        let mut iter2 = bpt.iterator(opts2);
+        iter2.seek("ddb".as_bytes().to_vec());
+        assert_eq!(iter2.next().unwrap().0, &b"ddaa".to_vec());
+        assert_eq!(iter2.next().unwrap().0, &b"dd".to_vec());
+        assert!(iter2.next().is_none());
+
+        // 正序 + 前缀，seek 到前缀时从前缀下最小的 key 开始
+        let mut opts3 = IteratorOptions::default();
+        opts3.prefix = "dd".as_bytes().to_vec();
+        let mut iter3 = bpt.iterator(opts3);
+        iter3.seek("dd".as_bytes().to_vec());
+        assert_eq!(iter3.next().unwrap().0, &b"dd".to_vec());
+        assert_eq!(iter3.next().unwrap().0, &b"ddaa".to_vec());
+        assert_eq!(iter3.next().unwrap().0, &b"ddce".to_vec());
+        assert!(iter3.next().is_none());
+
+        fs::remove_dir_all(path.clone()).unwrap();
+    }
+
+    #[test]
+    fn test_bptree_iterator_large() {
+        let path = PathBuf::from("/tmp/bptree-iterator-large");
+        fs::create_dir_all(path.clone()).unwrap();
+        let bpt = BPlusTree::open(path.clone()).unwrap();
+        for i in 0..10000 {
+            bpt.put(
+                std::format!("bitcask-key-{:09}", i).into_bytes(),
+                LogRecordPos {
+                    file_id: 1,
+                    offset: i,
+                    size: 11,
+                },
+            );
+        }
+
+        // 正序遍历，按顺序返回所有数据
+        let mut iter1 = bpt.iterator(IteratorOptions::default());
+        let mut count = 0;
+        while let Some((key, pos)) = iter1.next() {
+            assert_eq!(key, &std::format!("bitcask-key-{:09}", count).into_bytes());
+            assert_eq!(pos.offset, count);
+            count += 1;
+        }
+        assert_eq!(count, 10000);
+        assert!(iter1.next().is_none());
+
+        // 正序 seek 之后继续遍历
+        iter1.seek(b"bitcask-key-000009990".to_vec());
+        let mut count = 0;
+        while iter1.next().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 10);
+
+        // 正序 + 前缀
+        let mut opts2 = IteratorOptions::default();
+        opts2.prefix = b"bitcask-key-00000999".to_vec();
+        let mut iter2 = bpt.iterator(opts2);
+        let mut count = 0;
+        while let Some((key, _)) = iter2.next() {
+            assert!(key.starts_with(b"bitcask-key-00000999"));
+            count += 1;
+        }
+        assert_eq!(count, 10);
+        iter2.rewind();
+        assert_eq!(iter2.next().unwrap().0, &b"bitcask-key-000009990".to_vec());
+
+        // 逆序遍历
+        let mut opts3 = IteratorOptions::default();
+        opts3.reverse = true;
+        let mut iter3 = bpt.iterator(opts3);
+        assert_eq!(iter3.next().unwrap().0, &b"bitcask-key-000009999".to_vec());
+        iter3.seek(b"bitcask-key-000000009".to_vec());
+        let mut count = 0;
+        while iter3.next().is_some() {
+            count += 1;
+        }
+        assert_eq!(count, 10);
+
+        // 正序 + 前缀，两端交替遍历，跨越多个加载批次
+        let mut opts4 = IteratorOptions::default();
+        opts4.prefix = b"bitcask-key-000001".to_vec();
+        let mut iter4 = bpt.iterator(opts4);
+        let mut front = Vec::new();
+        for _ in 0..(BPTREE_ITER_BATCH_SIZE + 10) {
+            front.push(iter4.next().unwrap().0.clone());
+        }
+        let mut back = Vec::new();
+        while let Some((key, _)) = iter4.next_back() {
+            back.push(key.clone());
+        }
+        assert!(iter4.next().is_none());
+        assert_eq!(front.len() + back.len(), 1000);
+        assert_eq!(front[0], b"bitcask-key-000001000".to_vec());
+        assert_eq!(back[0], b"bitcask-key-000001999".to_vec());
+        assert_eq!(
+            back.last().unwrap(),
+            &std::format!("bitcask-key-{:09}", 1000 + front.len()).into_bytes()
+        );
+
+        fs::remove_dir_all(path.clone()).unwrap();
+    }
 }
@@ -1,4 +1,4 @@
-use std::{collections::BTreeMap, sync::Arc};
+use std::{collections::BTreeMap, ops::Bound, sync::Arc};
 
 use bytes::Bytes;
 use parking_lot::RwLock;
@@ -7,6 +7,10 @@ use crate::{data::log_record::LogRecordPos, options::IteratorOptions};
 
 use super::{IndexIterator, Indexer};
 
+// 每条数据除 key 本身之外的内存开销估计：key 的 Vec 结构、位置信息和 BTreeMap 节点平摊的开销
+const ENTRY_OVERHEAD: usize =
+    std::mem::size_of::<Vec<u8>>() + std::mem::size_of::<LogRecordPos>() + 8;
+
 // Btree 索引，主要封装了标准库的 BTreeMap 结构
 pub struct BTree {
     tree: Arc<RwLock<BTreeMap<Vec<u8>, LogRecordPos>>>,
@@ -47,7 +51,8 @@ impl Indexer for BTree {
         }
 
         Box::new(BTreeIterator {
-            items,
+            end_index: items.len(),
+            items: Arc::new(items),
             curr_index: 0,
             options,
         })
@@ -62,40 +67,75 @@ impl Indexer for BTree {
 
         keys
     }
+
+    fn clear(&self) {
+        let mut write_guard = self.tree.write();
+        write_guard.clear();
+    }
+
+    fn memory_usage(&self) -> usize {
+        let read_guard = self.tree.read();
+        read_guard
+            .keys()
+            .map(|key| key.len() + ENTRY_OVERHEAD)
+            .sum()
+    }
+
+    fn range_count(&self, lower: &[u8], upper: &[u8]) -> usize {
+        if lower >= upper {
+            return 0;
+        }
+        let read_guard = self.tree.read();
+        read_guard
+            .range::<[u8], _>((Bound::Included(lower), Bound::Excluded(upper)))
+            .count()
+    }
 }
 
 /// BTree 索引迭代器
+#[derive(Clone)]
 pub struct BTreeIterator {
-    items: Vec<(Vec<u8>, LogRecordPos)>, // 存储 key+索引
-    curr_index: usize,                   // 当前位置下标
-    options: IteratorOptions,            // 配置项
+    items: Arc<Vec<(Vec<u8>, LogRecordPos)>>, // 存储 key+索引，复制的迭代器之间共享
+    curr_index: usize,                        // 当前位置下标
+    end_index: usize,                         // 从末尾遍历的位置下标，之后的数据已经遍历过
+    options: IteratorOptions,                 // 配置项
 }
 
 impl IndexIterator for BTreeIterator {
     fn rewind(&mut self) {
         self.curr_index = 0;
+        self.end_index = self.items.len();
     }
 
     fn seek(&mut self, key: Vec<u8>) {
-        self.curr_index = match self.items.binary_search_by(|(x, _)| {
+        self.curr_index = self.items.partition_point(|(x, _)| {
             if self.options.reverse {
-                x.cmp(&key).reverse()
+                // 逆序时以 key 为前缀的数据也视为满足条件
+                !(x.starts_with(&key) || *x < key)
             } else {
-                x.cmp(&key)
+                *x < key
             }
-        }) {
-            Ok(n) => n,
-            Err(n) => n,
-        };
+        });
+        self.end_index = self.items.len();
     }
 
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-        if self.curr_index >= self.items.len() {
-            return None;
+        while self.curr_index < self.end_index {
+            let item = &self.items[self.curr_index];
+            self.curr_index += 1;
+            let prefix = &self.options.prefix;
+            if prefix.is_empty() || item.0.starts_with(prefix) {
+                return Some((&item.0, &item.1));
+            }
         }
 
-        while let Some(item) = self.items.get(self.curr_index) {
-            self.curr_index += 1;
+        None
+    }
+
+    fn next_back(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
+        while self.end_index > self.curr_index {
+            self.end_index -= 1;
+            let item = &self.items[self.end_index];
             let prefix = &self.options.prefix;
             if prefix.is_empty() || item.0.starts_with(&prefix) {
                 return Some((&item.0, &item.1));
@@ -104,6 +144,10 @@ impl IndexIterator for BTreeIterator {
 
         None
     }
+
+    fn clone_box(&self) -> Box<dyn IndexIterator> {
+        Box::new(self.clone())
+    }
 }
 
 #[cfg(test)]
@@ -8,6 +8,7 @@ use bytes::Bytes;
 
 use crate::{
     data::log_record::LogRecordPos,
+    errors::Errors,
     options::{IndexType, IteratorOptions},
 };
 
@@ -24,19 +25,32 @@ pub trait Indexer: Sync + Send {
     /// 根据 key 删除对应的数据位置信息
     fn delete(&self, key: Vec<u8>) -> Option<LogRecordPos>;
 
-    /// 返回索引迭代器
+    /// 返回索引迭代器，所有实现都按 key 的字节序遍历，逆序时按相反的顺序
     fn iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator>;
 
     /// 返回索引中所有的 key
     fn list_keys(&self) -> Vec<Bytes>;
+
+    /// 清空索引中所有数据
+    fn clear(&self);
+
+    /// 索引占用内存的估计值，为所有 key 的长度加上每条数据的固定开销
+    fn memory_usage(&self) -> usize;
+
+    /// 大于等于 lower 并且小于 upper 的 key 的数量，lower 不小于 upper 时返回 0
+    /// 直接在索引结构上按范围计数，不需要像迭代器一样先复制所有数据
+    fn range_count(&self, lower: &[u8], upper: &[u8]) -> usize;
+
+    /// 关闭索引，释放索引持有的文件等资源，内存索引不需要处理
+    fn close(&self) {}
 }
 
-/// 根据类型打开内存索引
-pub fn new_indexer(index_type: IndexType, dir_path: PathBuf) -> Box<dyn Indexer> {
+/// 根据类型打开内存索引，B+ 树索引文件损坏时返回错误
+pub fn new_indexer(index_type: IndexType, dir_path: PathBuf) -> Result<Box<dyn Indexer>, Errors> {
     match index_type {
-        IndexType::BTree => Box::new(BTree::new()),
-        IndexType::SkipList => Box::new(SkipList::new()),
-        IndexType::BPlusTree => Box::new(BPlusTree::new(dir_path))
+        IndexType::BTree => Ok(Box::new(BTree::new())),
+        IndexType::SkipList => Ok(Box::new(SkipList::new())),
+        IndexType::BPlusTree => Ok(Box::new(BPlusTree::open(dir_path)?))
     }
 }
 
@@ -46,8 +60,187 @@ pub trait IndexIterator: Sync + Send {
     fn rewind(&mut self);
 
     // 根据 key 寻找第一个大于（或小于）等于的目标 key，从它开始遍历
+    // 正序时定位到第一个大于等于 key 的位置；
+    // 逆序时定位到第一个小于等于 key 或以 key 为前缀的位置，
+    // 因此逆序 seek 到前缀时能从该前缀下最大的 key 开始遍历
     fn seek(&mut self, key: Vec<u8>);
 
     // 跳转到下一个 key，返回 None 说明迭代完毕
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)>;
+
+    // 从末尾取出一个 key，和 next 从两端遍历同一份数据，两端相遇之后都返回 None
+    // rewind 和 seek 会重置末尾的位置
+    fn next_back(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)>;
+
+    // 复制迭代器，新的迭代器和原迭代器共享同一份数据快照，从当前位置开始各自独立遍历
+    fn clone_box(&self) -> Box<dyn IndexIterator>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use super::*;
+
+    // 测试用的 key，包含互为前缀的 key 和非 ASCII 字节
+    fn test_keys() -> Vec<Vec<u8>> {
+        vec![
+            b"b".to_vec(),
+            b"ab".to_vec(),
+            b"a".to_vec(),
+            b"abc".to_vec(),
+            b"aa".to_vec(),
+            b"ba".to_vec(),
+            b"abd".to_vec(),
+            vec![0x00],
+            vec![0xff],
+            vec![b'a', 0xff],
+            b"c".to_vec(),
+        ]
+    }
+
+    // 按迭代器配置和 seek 的 key 遍历索引，返回遍历到的 key
+    fn collect_keys(
+        indexer: &dyn Indexer,
+        options: IteratorOptions,
+        seek: Option<&[u8]>,
+    ) -> Vec<Vec<u8>> {
+        let mut iter = indexer.iterator(options);
+        if let Some(key) = seek {
+            iter.seek(key.to_vec());
+        }
+        let mut keys = Vec::new();
+        while let Some((key, _)) = iter.next() {
+            keys.push(key.clone());
+        }
+        keys
+    }
+
+    // 按照文档中约定的顺序计算期望的遍历结果
+    fn expected_keys(options: &IteratorOptions, seek: Option<&[u8]>) -> Vec<Vec<u8>> {
+        let sorted: BTreeSet<Vec<u8>> = test_keys().into_iter().collect();
+        let mut keys: Vec<Vec<u8>> = sorted
+            .into_iter()
+            .filter(|k| k.starts_with(&options.prefix))
+            .collect();
+        if options.reverse {
+            keys.reverse();
+        }
+        match seek {
+            Some(seek) if options.reverse => {
+                keys.retain(|k| k.as_slice() <= seek || k.starts_with(seek))
+            }
+            Some(seek) => keys.retain(|k| k.as_slice() >= seek),
+            None => {}
+        }
+        keys
+    }
+
+    #[test]
+    fn test_indexer_iteration_order() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-index-iteration-order");
+        std::fs::create_dir_all(dir_path.clone()).unwrap();
+
+        let cases: Vec<(IteratorOptions, Option<&[u8]>)> = vec![
+            (IteratorOptions::new(), None),
+            (IteratorOptions::new().reverse(true), None),
+            (IteratorOptions::new(), Some(b"ab")),
+            (IteratorOptions::new().reverse(true), Some(b"ab")),
+            (IteratorOptions::new(), Some(b"abz")),
+            (IteratorOptions::new().reverse(true), Some(b"abz")),
+            (IteratorOptions::new(), Some(&[0xff, 0xff])),
+            (IteratorOptions::new().reverse(true), Some(&[0x00])),
+            (IteratorOptions::new().prefix("a"), None),
+            (IteratorOptions::new().prefix("a").reverse(true), None),
+            (IteratorOptions::new().prefix("a"), Some(b"abc")),
+            (
+                IteratorOptions::new().prefix("a").reverse(true),
+                Some(b"abc"),
+            ),
+            (IteratorOptions::new().prefix("a"), Some(b"0")),
+            (IteratorOptions::new().prefix("a").reverse(true), Some(b"z")),
+            (IteratorOptions::new().prefix("not-existed"), None),
+        ];
+
+        for (index_type, name) in [
+            (IndexType::BTree, "btree"),
+            (IndexType::SkipList, "skiplist"),
+            (IndexType::BPlusTree, "bptree"),
+        ] {
+            let indexer = new_indexer(index_type, dir_path.clone()).unwrap();
+            for (i, key) in test_keys().into_iter().enumerate() {
+                let pos = LogRecordPos {
+                    file_id: 1,
+                    offset: i as u64,
+                    size: 10,
+                };
+                indexer.put(key, pos);
+            }
+
+            // 所有索引类型的遍历顺序一致，都按 key 的字节序排列
+            for (options, seek) in cases.iter() {
+                assert_eq!(
+                    expected_keys(options, *seek),
+                    collect_keys(indexer.as_ref(), options.clone(), *seek),
+                    "index type: {}, options: {:?}, seek: {:?}",
+                    name,
+                    options,
+                    seek
+                );
+            }
+        }
+
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
+
+    #[test]
+    fn test_indexer_range_count() {
+        let dir_path = PathBuf::from("/tmp/bitcask-rs-index-range-count");
+        std::fs::create_dir_all(dir_path.clone()).unwrap();
+
+        let cases: Vec<(&[u8], &[u8])> = vec![
+            (b"", &[0xff, 0xff]),
+            (b"a", b"b"),
+            (b"ab", b"abd"),
+            (b"ab", b"abz"),
+            (b"aa", b"aa"),
+            (b"b", b"a"),
+            (&[0x00], &[0x01]),
+            (b"d", b"z"),
+        ];
+
+        for (index_type, name) in [
+            (IndexType::BTree, "btree"),
+            (IndexType::SkipList, "skiplist"),
+            (IndexType::BPlusTree, "bptree"),
+        ] {
+            let indexer = new_indexer(index_type, dir_path.clone()).unwrap();
+            for (i, key) in test_keys().into_iter().enumerate() {
+                let pos = LogRecordPos {
+                    file_id: 1,
+                    offset: i as u64,
+                    size: 10,
+                };
+                indexer.put(key, pos);
+            }
+
+            // 计数结果和逐个比较 key 的结果一致
+            for (lower, upper) in cases.iter() {
+                let expected = test_keys()
+                    .iter()
+                    .filter(|k| k.as_slice() >= *lower && k.as_slice() < *upper)
+                    .count();
+                assert_eq!(
+                    expected,
+                    indexer.range_count(lower, upper),
+                    "index type: {}, range: {:?}..{:?}",
+                    name,
+                    lower,
+                    upper
+                );
+            }
+        }
+
+        std::fs::remove_dir_all(dir_path).unwrap();
+    }
 }
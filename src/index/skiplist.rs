@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{ops::Bound, sync::Arc};
 
 use bytes::Bytes;
 use crossbeam_skiplist::SkipMap;
@@ -7,6 +7,10 @@ use crate::{data::log_record::LogRecordPos, options::IteratorOptions};
 
 use super::{IndexIterator, Indexer};
 
+// 每条数据除 key 本身之外的内存开销估计：key 的 Vec 结构、位置信息、引用计数和平均高度的索引层指针
+const ENTRY_OVERHEAD: usize =
+    std::mem::size_of::<Vec<u8>>() + std::mem::size_of::<LogRecordPos>() + 32;
+
 // 跳表索引
 pub struct SkipList {
     skl: Arc<SkipMap<Vec<u8>, LogRecordPos>>,
@@ -54,7 +58,8 @@ impl Indexer for SkipList {
         }
 
         Box::new(SkipListIterator {
-            items,
+            end_index: items.len(),
+            items: Arc::new(items),
             curr_index: 0,
             options,
         })
@@ -67,40 +72,72 @@ impl Indexer for SkipList {
         }
         keys
     }
+
+    fn clear(&self) {
+        self.skl.clear();
+    }
+
+    fn memory_usage(&self) -> usize {
+        self.skl
+            .iter()
+            .map(|entry| entry.key().len() + ENTRY_OVERHEAD)
+            .sum()
+    }
+
+    fn range_count(&self, lower: &[u8], upper: &[u8]) -> usize {
+        if lower >= upper {
+            return 0;
+        }
+        self.skl
+            .range::<[u8], _>((Bound::Included(lower), Bound::Excluded(upper)))
+            .count()
+    }
 }
 
 // 跳表索引迭代器
+#[derive(Clone)]
 pub struct SkipListIterator {
-    items: Vec<(Vec<u8>, LogRecordPos)>, // 存储 key+索引
-    curr_index: usize,                   // 当前位置下标
-    options: IteratorOptions,            // 配置项
+    items: Arc<Vec<(Vec<u8>, LogRecordPos)>>, // 存储 key+索引，复制的迭代器之间共享
+    curr_index: usize,                        // 当前位置下标
+    end_index: usize,                         // 从末尾遍历的位置下标，之后的数据已经遍历过
+    options: IteratorOptions,                 // 配置项
 }
 
 impl IndexIterator for SkipListIterator {
     fn rewind(&mut self) {
         self.curr_index = 0;
+        self.end_index = self.items.len();
     }
 
     fn seek(&mut self, key: Vec<u8>) {
-        self.curr_index = match self.items.binary_search_by(|(x, _)| {
+        self.curr_index = self.items.partition_point(|(x, _)| {
             if self.options.reverse {
-                x.cmp(&key).reverse()
+                // 逆序时以 key 为前缀的数据也视为满足条件
+                !(x.starts_with(&key) || *x < key)
             } else {
-                x.cmp(&key)
+                *x < key
             }
-        }) {
-            Ok(n) => n,
-            Err(n) => n,
-        };
+        });
+        self.end_index = self.items.len();
     }
 
     fn next(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
-        if self.curr_index >= self.items.len() {
-            return None;
+        while self.curr_index < self.end_index {
+            let item = &self.items[self.curr_index];
+            self.curr_index += 1;
+            let prefix = &self.options.prefix;
+            if prefix.is_empty() || item.0.starts_with(prefix) {
+                return Some((&item.0, &item.1));
+            }
         }
 
-        while let Some(item) = self.items.get(self.curr_index) {
-            self.curr_index += 1;
+        None
+    }
+
+    fn next_back(&mut self) -> Option<(&Vec<u8>, &LogRecordPos)> {
+        while self.end_index > self.curr_index {
+            self.end_index -= 1;
+            let item = &self.items[self.end_index];
             let prefix = &self.options.prefix;
             if prefix.is_empty() || item.0.starts_with(&prefix) {
                 return Some((&item.0, &item.1));
@@ -109,6 +146,10 @@ impl IndexIterator for SkipListIterator {
 
         None
     }
+
+    fn clone_box(&self) -> Box<dyn IndexIterator> {
+        Box::new(self.clone())
+    }
 }
 
 #[cfg(test)]
@@ -325,4 +366,91 @@ mod tests {
             assert!(!key.is_empty());
         }
     }
+
+    #[test]
+    fn test_skl_iterator_reverse_prefix_seek() {
+        let skl = SkipList::new();
+        for key in ["aade", "bbcc", "dd", "ddaa", "ddce", "eecc"] {
+            skl.put(
+                key.as_bytes().to_vec(),
+                LogRecordPos {
+                    file_id: 1123,
+                    offset: 1232,
+                    size: 11,
+                },
+            );
+        }
+
+        // 逆序 + 前缀，seek 到前缀时从前缀下最大的 key 开始
+        let mut opts1 = IteratorOptions::default();
+        opts1.reverse = true;
+        opts1.prefix = "dd".as_bytes().to_vec();
+        let mut iter1 = skl.iterator(opts1);
+        iter1.seek("dd".as_bytes().to_vec());
+        assert_eq!(iter1.next().unwrap().0, &b"ddce".to_vec());
+        assert_eq!(iter1.next().unwrap().0, &b"ddaa".to_vec());
+        assert_eq!(iter1.next().unwrap().0, &b"dd".to_vec());
+        assert!(iter1.next().is_none());
+
+        // 逆序 + 前缀，seek 到前缀中间的 key
+        let mut opts2 = IteratorOptions::default();
+        opts2.reverse = true;
+        opts2.prefix = "dd".as_bytes().to_vec();
+        let mut iter2 = skl.iterator(opts2);
+        iter2.seek("ddb".as_bytes().to_vec());
+        assert_eq!(iter2.next().unwrap().0, &b"ddaa".to_vec());
+        assert_eq!(iter2.next().unwrap().0, &b"dd".to_vec());
+        assert!(iter2.next().is_none());
+
+        // 逆序，seek 到不存在的 key 时定位到第一个小于它的 key
+        let mut opts3 = IteratorOptions::default();
+        opts3.reverse = true;
+        let mut iter3 = skl.iterator(opts3);
+        iter3.seek("cc".as_bytes().to_vec());
+        assert_eq!(iter3.next().unwrap().0, &b"bbcc".to_vec());
+        iter3.seek("zz".as_bytes().to_vec());
+        assert_eq!(iter3.next().unwrap().0, &b"eecc".to_vec());
+        iter3.seek("a".as_bytes().to_vec());
+        assert_eq!(iter3.next().unwrap().0, &b"aade".to_vec());
+        iter3.seek("0".as_bytes().to_vec());
+        assert!(iter3.next().is_none());
+    }
+
+    #[test]
+    fn test_skl_memory_usage() {
+        let skl = SkipList::new();
+        assert_eq!(skl.memory_usage(), 0);
+
+        for i in 0..1000 {
+            let key = format!("bitcask-rs-key-{:017}", i).into_bytes();
+            assert_eq!(key.len(), 32);
+            skl.put(
+                key,
+                LogRecordPos {
+                    file_id: 1,
+                    offset: i,
+                    size: 11,
+                },
+            );
+        }
+
+        // 每条数据至少占用 key 的长度，额外开销在合理的范围内
+        let usage = skl.memory_usage();
+        assert!(usage > 1000 * 32);
+        assert!(usage < 1000 * (32 + 128));
+
+        // 覆盖写入不增加，删除后减少
+        let key = format!("bitcask-rs-key-{:017}", 0).into_bytes();
+        skl.put(
+            key.clone(),
+            LogRecordPos {
+                file_id: 2,
+                offset: 0,
+                size: 11,
+            },
+        );
+        assert_eq!(skl.memory_usage(), usage);
+        skl.delete(key);
+        assert!(skl.memory_usage() < usage);
+    }
 }
@@ -1,28 +1,272 @@
-use std::sync::Arc;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+    sync::Arc,
+};
 
 use bytes::Bytes;
-use parking_lot::RwLock;
+use log::error;
+use parking_lot::{Mutex, RwLock};
 
-use crate::{db::Engine, index::IndexIterator, options::IteratorOptions};
+use crate::{
+    batch::parse_log_record_key,
+    data::log_record::{LogRecordPos, LogRecordType},
+    db::Engine,
+    errors::Errors,
+    index::{btree::BTree, IndexIterator, Indexer},
+    options::IteratorOptions,
+};
+
+// scan 时数据量达到该值才分段并行读取 value
+const SCAN_PARALLEL_MIN_ITEMS: usize = 1024;
 
 /// 迭代器接口
 pub struct Iterator<'a> {
     index_iter: Arc<RwLock<Box<dyn IndexIterator>>>,
     engine: &'a Engine,
+    prefix: Vec<u8>, // 索引中保存 key 的 hash 时，需要读取完整的 key 再按前缀过滤
+    peeked: Mutex<Option<Option<(Bytes, Bytes)>>>, // peek 读取后缓存的下一条数据
+    last_key: Mutex<Vec<u8>>, // 最近一次返回的 key，用于保存游标，复用同一块缓冲区
+}
+
+/// 数据文件中的原始记录，包括被删除数据的墓碑值和事务完成标识
+#[derive(Debug)]
+pub struct RawRecord {
+    /// 实际的 key
+    pub key: Bytes,
+    /// value，被删除的数据为空
+    pub value: Bytes,
+    /// 记录类型
+    pub rec_type: LogRecordType,
+    /// 事务序列号，非事务数据为 0
+    pub seq_no: usize,
+    /// 所在数据文件 id
+    pub file_id: u32,
+    /// 在数据文件中的偏移
+    pub offset: u64,
+}
+
+/// 原始记录迭代器，按文件 id 和偏移的顺序遍历所有数据文件
+pub struct RawRecordIterator<'a> {
+    engine: &'a Engine,
+    file_ids: Vec<u32>, // 创建迭代器时的数据文件 id 列表
+    curr_file: usize,   // 当前遍历的文件下标
+    offset: u64,        // 当前文件中的偏移
 }
 
 impl Engine {
-    /// 获取迭代器
+    /// 获取迭代器，按 key 的字节序遍历（逆序时按相反的顺序），和索引类型无关
+    /// 开启 hash_keys_in_index 时，遍历顺序为 key 的 hash 顺序，每条数据都需要从数据文件中读取完整的 key
     pub fn iter(&self, options: IteratorOptions) -> Iterator {
+        let mut prefix = Vec::new();
+        let mut options = options;
+        if self.options.hash_keys_in_index {
+            prefix = std::mem::take(&mut options.prefix);
+        }
         Iterator {
-            index_iter: Arc::new(RwLock::new(self.index.iterator(options))),
+            index_iter: Arc::new(RwLock::new(self.index_iterator(options))),
+            engine: self,
+            prefix,
+            peeked: Mutex::new(None),
+            last_key: Mutex::new(Vec::new()),
+        }
+    }
+
+    // 返回索引迭代器，存储引擎已经关闭时返回空的迭代器，不会再读取数据文件
+    fn index_iterator(&self, options: IteratorOptions) -> Box<dyn IndexIterator> {
+        if self.check_open().is_err() {
+            return BTree::new().iterator(options);
+        }
+        self.index.iterator(options)
+    }
+
+    /// 从 Iterator::save_cursor 保存的游标处继续遍历，不包括游标对应的 key，游标为空时从头开始遍历
+    /// 游标只记录 key，创建迭代器时的 options 需要和保存游标时一致；
+    /// 游标对应的 key 在之后被删除不影响恢复，仍然从它之后的位置开始遍历
+    pub fn iter_from(&self, cursor: &[u8], options: IteratorOptions) -> Iterator<'_> {
+        let reverse = options.reverse;
+        let iter = self.iter(options);
+        if cursor.is_empty() {
+            return iter;
+        }
+
+        // 开启 hash_keys_in_index 时索引按 key 的 hash 排序，需要定位到 key 在索引中使用的 key
+        let index_key = match self.options.hash_keys_in_index {
+            true => match self.lookup_index(cursor, &|pos| self.read_key_at(pos)) {
+                Ok((index_key, _)) => index_key,
+                Err(_) => (self.key_hasher)(cursor).to_vec(),
+            },
+            false => cursor.to_vec(),
+        };
+
+        {
+            // seek 定位到第一个大于等于（逆序时小于等于或以其为前缀）的 key，
+            // 再跳过游标对应的 key 以及逆序时以它为前缀、已经遍历过的 key
+            let mut index_iter = iter.index_iter.write();
+            index_iter.seek(index_key.clone());
+            loop {
+                let mut probe = index_iter.clone_box();
+                let visited = match probe.next() {
+                    Some((key, _)) if reverse => *key >= index_key,
+                    Some((key, _)) => *key <= index_key,
+                    None => false,
+                };
+                if !visited {
+                    break;
+                }
+                index_iter.next();
+            }
+        }
+        *iter.last_key.lock() = cursor.to_vec();
+        iter
+    }
+
+    /// 遍历以 prefix 开头的所有数据，其他选项使用默认值，按 key 的字节序返回
+    /// 开启 hash_keys_in_index 时和 iter 一样按 key 的 hash 顺序返回
+    pub fn prefix_iter(
+        &self,
+        prefix: impl Into<Vec<u8>>,
+    ) -> impl std::iter::Iterator<Item = (Bytes, Bytes)> + '_ {
+        let iter = self.iter(IteratorOptions::new().prefix(prefix));
+        std::iter::from_fn(move || iter.next())
+    }
+
+    /// 遍历 options 范围内的数据，同时返回每条数据当前版本写入时的事务序列号，非事务写入的数据为 0
+    /// 同一个 WriteBatch 提交的数据序列号相同，可以用于检查事务是否原子地提交；每条数据都需要读取完整的记录
+    pub fn iter_with_seq(
+        &self,
+        options: IteratorOptions,
+    ) -> impl std::iter::Iterator<Item = (Bytes, Bytes, usize)> + '_ {
+        let mut options = options;
+        let mut prefix = Vec::new();
+        if self.options.hash_keys_in_index {
+            prefix = std::mem::take(&mut options.prefix);
+        }
+        let mut index_iter = self.index_iterator(options);
+        std::iter::from_fn(move || {
+            while let Some((_, pos)) = index_iter.next() {
+                let (key, value, seq_no) = self.read_key_value_seq_at(pos).unwrap();
+                if !key.starts_with(&prefix) {
+                    continue;
+                }
+                return Some((Bytes::from(key), value, seq_no));
+            }
+            None
+        })
+    }
+
+    /// 遍历 options 范围内的数据，返回 key 和 value 满足 pred 的数据，按迭代器的顺序排列
+    /// 范围内所有的 value 仍然都需要读取；数据较多时按 CPU 数量分段，在多个线程中并行读取和过滤
+    pub fn scan<F>(&self, options: IteratorOptions, pred: F) -> Vec<(Bytes, Bytes)>
+    where
+        F: Fn(&[u8], &[u8]) -> bool + Sync,
+    {
+        // 先从索引快照中取出所有位置，开启 hash_keys_in_index 时读取完整的 key 之后再按前缀过滤
+        let mut options = options;
+        let mut prefix = Vec::new();
+        if self.options.hash_keys_in_index {
+            prefix = std::mem::take(&mut options.prefix);
+        }
+        let mut positions = Vec::new();
+        let mut index_iter = self.index_iterator(options);
+        while let Some((key, pos)) = index_iter.next() {
+            positions.push((key.clone(), *pos));
+        }
+
+        let scan_chunk = |chunk: &[(Vec<u8>, LogRecordPos)]| {
+            let mut matches = Vec::new();
+            for (key, pos) in chunk {
+                let (key, value) = match self.options.hash_keys_in_index {
+                    true => {
+                        let (key, value) = self.read_key_value_at(pos).unwrap();
+                        if !key.starts_with(&prefix) {
+                            continue;
+                        }
+                        (Bytes::from(key), Bytes::from(value))
+                    }
+                    false => (
+                        Bytes::copy_from_slice(key),
+                        self.get_value_by_position(*pos).unwrap(),
+                    ),
+                };
+                if pred(&key, &value) {
+                    matches.push((key, value));
+                }
+            }
+            matches
+        };
+
+        let threads = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(1);
+        if threads <= 1 || positions.len() < SCAN_PARALLEL_MIN_ITEMS {
+            return scan_chunk(&positions);
+        }
+
+        // 每个线程处理连续的一段数据，按分段的顺序合并结果
+        let scan_chunk = &scan_chunk;
+        let chunk_size = positions.len().div_ceil(threads);
+        std::thread::scope(|s| {
+            let handles: Vec<_> = positions
+                .chunks(chunk_size)
+                .map(|chunk| s.spawn(move || scan_chunk(chunk)))
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        })
+    }
+
+    /// 按写入顺序遍历数据文件中的原始记录，包括覆盖写入、删除和事务完成标识，可用于 CDC
+    /// 存储引擎已经关闭时不返回任何记录
+    pub fn scan_raw(&self) -> impl std::iter::Iterator<Item = RawRecord> + '_ {
+        let file_ids = match self.check_open() {
+            Ok(()) => self.data_file_ids(),
+            Err(_) => Vec::new(),
+        };
+        RawRecordIterator {
             engine: self,
+            file_ids,
+            curr_file: 0,
+            offset: 0,
         }
     }
 
+    /// 返回数据文件中有删除标记、当前已经不存在的 key，按 key 的字节序排列
+    /// 这些 key 的旧数据和删除标记仍然占用磁盘空间，merge 之后不再返回；
+    /// 删除之后又重新写入的 key 不会返回
+    pub fn deleted_keys(&self) -> Vec<Bytes> {
+        let mut keys = BTreeSet::new();
+        for record in self.scan_raw() {
+            if record.rec_type != LogRecordType::DELETED || keys.contains(&record.key) {
+                continue;
+            }
+            if let Err(Errors::KeyIsNotFound) = self.get_log_record_pos(&record.key) {
+                keys.insert(record.key);
+            }
+        }
+        keys.into_iter().collect()
+    }
+
     /// 返回存储引擎中所有的 key
+    /// 开启 hash_keys_in_index 时索引中只有 key 的 hash，需要从数据文件中读取完整的 key
     pub fn list_keys(&self) -> Vec<Bytes> {
-        self.index.list_keys()
+        if self.check_open().is_err() {
+            return Vec::new();
+        }
+        if !self.options.hash_keys_in_index {
+            return self.index.list_keys();
+        }
+
+        let mut keys = Vec::new();
+        let mut index_iter = self.index.iterator(IteratorOptions::default());
+        while let Some((_, pos)) = index_iter.next() {
+            keys.push(Bytes::from(self.read_key_at(pos).unwrap()));
+        }
+        keys
     }
 
     /// 对所有数据执行自定义函数，函数返回 false 提前终止
@@ -38,6 +282,70 @@ impl Engine {
             }
         }
     }
+
+    /// 返回所有数据组成的 HashMap
+    /// 所有 key 和 value 都会加载到内存中，只适合数据量较小的数据库
+    pub fn to_map(&self) -> HashMap<Bytes, Bytes> {
+        let mut map = HashMap::new();
+        let iter = self.iter(IteratorOptions::default());
+        while let Some((key, value)) = iter.next() {
+            map.insert(key, value);
+        }
+        map
+    }
+
+    /// 将所有数据导出到文件，文件已经存在时会被覆盖
+    /// 每条数据依次写入 key 的长度、key、value 的长度、value，长度为 4 字节大端编码的 u32；
+    /// 估计大于等于 lower 并且小于 upper 的 key 的数量，可以用于查询规划
+    /// 内存索引和 B+ 树索引都直接在索引结构上计数，结果是准确的；
+    /// 开启 hash_keys_in_index 时索引不按 key 排序，需要从数据文件中读取所有 key 再比较
+    pub fn estimate_range_count(&self, lower: &[u8], upper: &[u8]) -> usize {
+        if self.check_open().is_err() {
+            return 0;
+        }
+        if !self.options.hash_keys_in_index {
+            return self.index.range_count(lower, upper);
+        }
+        if lower >= upper {
+            return 0;
+        }
+        self.list_keys()
+            .iter()
+            .filter(|key| key.as_ref() >= lower && key.as_ref() < upper)
+            .count()
+    }
+
+    /// 导出时逐条写入，不会将所有数据加载到内存中
+    pub fn export_to_file(&self, path: impl AsRef<Path>) -> Result<(), Errors> {
+        self.check_open()?;
+        let mut writer = BufWriter::new(File::create(path)?);
+        let iter = self.iter(IteratorOptions::default());
+        while let Some((key, value)) = iter.next() {
+            writer.write_all(&(key.len() as u32).to_be_bytes())?;
+            writer.write_all(&key)?;
+            writer.write_all(&(value.len() as u32).to_be_bytes())?;
+            writer.write_all(&value)?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// 复制迭代器，新的迭代器和原迭代器共享同一份索引快照，不会重新拷贝索引数据；
+/// 新的迭代器从原迭代器当前的位置开始，之后各自独立遍历，可以交给不同的线程并行遍历
+impl Clone for Iterator<'_> {
+    fn clone(&self) -> Self {
+        // 和 peek 保持相同的加锁顺序
+        let peeked = self.peeked.lock();
+        let index_iter = self.index_iter.read().clone_box();
+        Iterator {
+            index_iter: Arc::new(RwLock::new(index_iter)),
+            engine: self.engine,
+            prefix: self.prefix.clone(),
+            peeked: Mutex::new(peeked.clone()),
+            last_key: Mutex::new(self.last_key.lock().clone()),
+        }
+    }
 }
 
 impl Iterator<'_> {
@@ -46,6 +354,8 @@ impl Iterator<'_> {
     fn rewind(&self) {
         let mut index_iter = self.index_iter.write();
         index_iter.rewind();
+        self.peeked.lock().take();
+        self.last_key.lock().clear();
     }
 
     #[allow(dead_code)]
@@ -53,14 +363,231 @@ impl Iterator<'_> {
     fn seek(&self, key: Vec<u8>) {
         let mut index_iter = self.index_iter.write();
         index_iter.seek(key);
+        self.peeked.lock().take();
+    }
+
+    /// 查看下一条数据但不移动迭代器，返回 None 说明迭代完毕
+    /// 需要从数据文件中读取下一条数据的 value，读取结果会被缓存，之后的 next 直接返回缓存的数据，不会重复读取；
+    /// 因此 peek 之后对该 key 的修改不会体现在 next 的返回值中
+    pub fn peek(&self) -> Option<(Bytes, Bytes)> {
+        let mut peeked = self.peeked.lock();
+        if peeked.is_none() {
+            *peeked = Some(self.read_next());
+        }
+        peeked.clone().unwrap()
+    }
+
+    /// 是否还有下一条数据，和 peek 一样需要读取下一条数据的 value
+    pub fn has_next(&self) -> bool {
+        self.peek().is_some()
+    }
+
+    /// 保存遍历进度，返回最近一次 next 返回的 key 组成的游标，还没有返回过数据时为空
+    /// 游标可以持久化，重启之后通过 Engine::iter_from 从该 key 之后继续遍历；peek 不会改变游标
+    pub fn save_cursor(&self) -> Vec<u8> {
+        self.last_key.lock().clone()
+    }
+
+    // 记录最近一次返回的 key
+    fn set_last_key(&self, key: &[u8]) {
+        let mut last_key = self.last_key.lock();
+        last_key.clear();
+        last_key.extend_from_slice(key);
     }
 
     // 跳转到下一个 key 并返回 value，返回 None 说明迭代完毕
     fn next(&self) -> Option<(Bytes, Bytes)> {
+        let peeked = self.peeked.lock().take();
+        let item = match peeked {
+            Some(item) => item,
+            None => self.read_next(),
+        };
+        if let Some((key, _)) = &item {
+            self.set_last_key(key);
+        }
+        item
+    }
+
+    /// 跳转到下一个 key，将 key 的切片和 value 交给回调函数处理，返回 None 说明迭代完毕
+    /// key 直接借用索引快照中的数据，不会为每个 key 分配新的 Bytes；key 的切片只在回调函数执行期间有效
+    pub fn next_with<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8], Bytes) -> R,
+    {
+        // 先释放 peek 缓存的锁再调用回调函数
+        let peeked = self.peeked.lock().take();
+        if let Some(item) = peeked {
+            return item.map(|(key, value)| {
+                self.set_last_key(&key);
+                f(&key, value)
+            });
+        }
+        self.read_next_with(|key, value| {
+            self.set_last_key(key);
+            f(key, value)
+        })
+    }
+
+    /// 跳转到下一个 key，只将 key 的切片交给回调函数处理，不读取 value，返回 None 说明迭代完毕
+    /// 适合只需要遍历 key 的场景；开启 hash_keys_in_index 时仍然需要从数据文件中读取完整的 key
+    pub fn next_key_with<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8]) -> R,
+    {
+        let peeked = self.peeked.lock().take();
+        if let Some(item) = peeked {
+            return item.map(|(key, _)| {
+                self.set_last_key(&key);
+                f(&key)
+            });
+        }
+
         let mut index_iter = self.index_iter.write();
-        if let Some(item) = index_iter.next() {
-            let value = self.engine.get_value_by_position(*item.1).unwrap();
-            return Some((Bytes::from(item.0.to_vec()), value));
+        while let Some((key, pos)) = index_iter.next() {
+            if !self.engine.options.hash_keys_in_index {
+                self.set_last_key(key);
+                return Some(f(key));
+            }
+
+            let key = self.engine.read_key_at(pos).unwrap();
+            if !self.prefix.is_empty() && !key.starts_with(&self.prefix) {
+                continue;
+            }
+            self.set_last_key(&key);
+            return Some(f(&key));
+        }
+
+        None
+    }
+
+    // 从索引迭代器中取出下一个 key，并从数据文件中读取 value
+    fn read_next(&self) -> Option<(Bytes, Bytes)> {
+        self.read_next_with(|key, value| (Bytes::copy_from_slice(key), value))
+    }
+
+    // 从索引迭代器中取出下一个 key，从数据文件中读取 value 后交给回调函数处理
+    fn read_next_with<R, F>(&self, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8], Bytes) -> R,
+    {
+        self.read_with(false, f)
+    }
+
+    // 从索引迭代器的末尾取出一个 key，不会改变游标；已经 peek 过的数据不会从末尾返回
+    fn next_back(&self) -> Option<(Bytes, Bytes)> {
+        self.read_with(true, |key, value| (Bytes::copy_from_slice(key), value))
+    }
+
+    // 从索引迭代器的一端取出 key，back 为 true 时从末尾取出，读取 value 后交给回调函数处理
+    fn read_with<R, F>(&self, back: bool, f: F) -> Option<R>
+    where
+        F: FnOnce(&[u8], Bytes) -> R,
+    {
+        let mut index_iter = self.index_iter.write();
+        loop {
+            let item = match back {
+                true => index_iter.next_back()?,
+                false => index_iter.next()?,
+            };
+            if !self.engine.options.hash_keys_in_index {
+                let value = self.engine.get_value_by_position(*item.1).unwrap();
+                return Some(f(item.0, value));
+            }
+
+            // 索引中保存的是 key 的 hash，从数据文件中读取完整的 key 和 value
+            let (key, value) = self.engine.read_key_value_at(item.1).unwrap();
+            if !self.prefix.is_empty() && !key.starts_with(&self.prefix) {
+                continue;
+            }
+            return Some(f(&key, Bytes::from(value)));
+        }
+    }
+}
+
+/// 转换为标准库的迭代器，可以使用 for 循环和迭代器适配器
+impl<'a> IntoIterator for Iterator<'a> {
+    type Item = (Bytes, Bytes);
+    type IntoIter = StdIterator<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        StdIterator { iter: self }
+    }
+}
+
+/// 标准库迭代器适配器，支持通过 next_back 从末尾遍历同一份索引快照
+/// 两端交替遍历时相遇之后都返回 None，同一条数据只会返回一次；不需要在创建迭代器时设置 reverse
+pub struct StdIterator<'a> {
+    iter: Iterator<'a>,
+}
+
+impl std::iter::Iterator for StdIterator<'_> {
+    type Item = (Bytes, Bytes);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter.next()
+    }
+}
+
+impl DoubleEndedIterator for StdIterator<'_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.iter.next_back()
+    }
+}
+
+impl std::iter::Iterator for RawRecordIterator<'_> {
+    type Item = RawRecord;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(file_id) = self.file_ids.get(self.curr_file) {
+            let file_id = *file_id;
+            let read_res = {
+                let active_file = self.engine.active_file.read();
+                let older_files = self.engine.older_files.read();
+                match file_id == active_file.get_file_id() {
+                    true => active_file.read(self.offset),
+                    false => match older_files.get(file_id) {
+                        Ok(Some(data_file)) => data_file.read(self.offset),
+                        Ok(None) => Err(Errors::DataFileIsNotFound),
+                        Err(e) => Err(e),
+                    },
+                }
+            };
+
+            // 读到文件末尾则继续读下个文件
+            let (log_record, size) = match read_res {
+                Ok(r) => (r.record, r.size),
+                Err(e) => {
+                    if e == Errors::ReadDataFileEOF {
+                        self.curr_file += 1;
+                        self.offset = 0;
+                        continue;
+                    }
+                    error!("failed to scan raw record in data file {}: {}", file_id, e);
+                    self.curr_file = self.file_ids.len();
+                    return None;
+                }
+            };
+
+            // 解析 key，拿到实际 key 和事务序列号
+            let (real_key, seq_no) = match parse_log_record_key(log_record.key) {
+                Ok(parsed) => parsed,
+                Err(e) => {
+                    error!("failed to scan raw record in data file {}: {}", file_id, e);
+                    self.curr_file = self.file_ids.len();
+                    return None;
+                }
+            };
+            let record = RawRecord {
+                key: Bytes::from(real_key),
+                value: Bytes::from(log_record.value),
+                rec_type: log_record.rec_type,
+                seq_no,
+                file_id,
+                offset: self.offset,
+            };
+            self.offset += size;
+
+            return Some(record);
         }
 
         None
@@ -71,7 +598,10 @@ impl Iterator<'_> {
 mod tests {
     use std::path::PathBuf;
 
-    use crate::{options::Options, util};
+    use crate::{
+        options::{IndexType, Options, WriteBatchOptions},
+        util,
+    };
 
     use super::*;
 
@@ -194,6 +724,202 @@ mod tests {
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
 
+    #[test]
+    fn test_iterator_clone() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-iter-clone");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..1000 {
+            let put_res = engine.put(
+                util::rand_kv::get_test_key(i),
+                util::rand_kv::get_test_value(i),
+            );
+            assert!(put_res.is_ok());
+        }
+
+        let iter = engine.iter(IteratorOptions::default());
+        // 创建迭代器之后写入的数据不在快照中
+        let put_res = engine.put(Bytes::from("new key"), Bytes::from("new value"));
+        assert!(put_res.is_ok());
+
+        // 多个线程各自遍历复制的迭代器
+        let engine = &engine;
+        std::thread::scope(|s| {
+            let handles: Vec<_> = (0..4)
+                .map(|_| {
+                    let iter = iter.clone();
+                    s.spawn(move || {
+                        let mut keys = Vec::new();
+                        while let Some((key, value)) = iter.next() {
+                            assert_eq!(engine.get(key.clone()).unwrap(), value);
+                            keys.push(key);
+                        }
+                        keys
+                    })
+                })
+                .collect();
+            for handle in handles {
+                let keys = handle.join().unwrap();
+                assert_eq!(1000, keys.len());
+                assert!(!keys.contains(&Bytes::from("new key")));
+            }
+        });
+
+        // 复制的迭代器从原迭代器当前的位置开始，之后互不影响
+        let first = iter.next().unwrap().0;
+        let second = iter.peek().unwrap().0;
+        let iter2 = iter.clone();
+        assert_eq!(second, iter2.next().unwrap().0);
+        assert_eq!(second, iter.next().unwrap().0);
+        iter2.rewind();
+        assert_eq!(first, iter2.next().unwrap().0);
+        assert_ne!(first, iter.next().unwrap().0);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_engine_to_map() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-to-map");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 没有数据的情况
+        assert!(engine.to_map().is_empty());
+
+        for i in 0..100 {
+            let put_res = engine.put(
+                util::rand_kv::get_test_key(i),
+                util::rand_kv::get_test_value(i),
+            );
+            assert!(put_res.is_ok());
+        }
+        for i in 0..10 {
+            let del_res = engine.delete(util::rand_kv::get_test_key(i));
+            assert!(del_res.is_ok());
+        }
+        let map = engine.to_map();
+        assert_eq!(90, map.len());
+        assert_eq!(
+            Some(&util::rand_kv::get_test_value(50)),
+            map.get(&util::rand_kv::get_test_key(50))
+        );
+
+        // 导入到另一个存储引擎
+        let mut opts2 = Options::default();
+        opts2.dir_path = PathBuf::from("/tmp/bitcask-rs-to-map-import");
+        let engine2 = Engine::open(opts2.clone()).expect("failed to open engine");
+        let import_res = engine2.import_from_map(&map);
+        assert!(import_res.is_ok());
+        assert_eq!(map, engine2.to_map());
+
+        // 导出到文件后按长度前缀解析
+        let export_path = PathBuf::from("/tmp/bitcask-rs-to-map-export");
+        let export_res = engine.export_to_file(&export_path);
+        assert!(export_res.is_ok());
+        let buf = std::fs::read(&export_path).unwrap();
+        let mut exported = HashMap::new();
+        let mut offset = 0;
+        let read_chunk = |offset: &mut usize| {
+            let len = u32::from_be_bytes(buf[*offset..*offset + 4].try_into().unwrap()) as usize;
+            let chunk = Bytes::copy_from_slice(&buf[*offset + 4..*offset + 4 + len]);
+            *offset += 4 + len;
+            chunk
+        };
+        while offset < buf.len() {
+            let key = read_chunk(&mut offset);
+            let value = read_chunk(&mut offset);
+            exported.insert(key, value);
+        }
+        assert_eq!(map, exported);
+
+        // 删除测试的文件夹
+        std::fs::remove_file(export_path).expect("failed to remove file");
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        std::fs::remove_dir_all(opts2.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_iterator_peek() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-iter-peek");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 没有数据的情况
+        let iter1 = engine.iter(IteratorOptions::default());
+        assert!(!iter1.has_next());
+        assert!(iter1.peek().is_none());
+        assert!(iter1.next().is_none());
+
+        for key in ["aacc", "bbac", "ccde"] {
+            let put_res = engine.put(Bytes::from(key), Bytes::from(key.repeat(2)));
+            assert!(put_res.is_ok());
+        }
+
+        // peek 不会移动迭代器
+        let iter2 = engine.iter(IteratorOptions::default());
+        for key in ["aacc", "bbac", "ccde"] {
+            assert!(iter2.has_next());
+            assert_eq!(Bytes::from(key), iter2.peek().unwrap().0);
+            assert_eq!(Bytes::from(key.repeat(2)), iter2.peek().unwrap().1);
+            assert_eq!(Bytes::from(key), iter2.next().unwrap().0);
+        }
+        assert!(!iter2.has_next());
+        assert!(iter2.peek().is_none());
+        assert!(iter2.next().is_none());
+
+        // rewind 和 seek 之后重新 peek
+        iter2.rewind();
+        assert_eq!(Bytes::from("aacc"), iter2.peek().unwrap().0);
+        iter2.seek("bb".as_bytes().to_vec());
+        assert_eq!(Bytes::from("bbac"), iter2.peek().unwrap().0);
+        assert_eq!(Bytes::from("bbac"), iter2.next().unwrap().0);
+        assert_eq!(Bytes::from("ccde"), iter2.next().unwrap().0);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_iterator_next_with() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-iter-next-with");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for key in ["aacc", "bbac", "ccde"] {
+            let put_res = engine.put(Bytes::from(key), Bytes::from(key.repeat(2)));
+            assert!(put_res.is_ok());
+        }
+
+        // 借用 key 读取 value
+        let iter1 = engine.iter(IteratorOptions::default());
+        for key in ["aacc", "bbac", "ccde"] {
+            let res = iter1.next_with(|k, v| {
+                assert_eq!(key.as_bytes(), k);
+                assert_eq!(Bytes::from(key.repeat(2)), v);
+            });
+            assert!(res.is_some());
+        }
+        assert!(iter1.next_with(|_, _| ()).is_none());
+
+        // 只遍历 key，peek 过的数据不会被跳过
+        let iter2 = engine.iter(IteratorOptions::default());
+        assert_eq!(Bytes::from("aacc"), iter2.peek().unwrap().0);
+        let mut keys = Vec::new();
+        while let Some(key) = iter2.next_key_with(|k| k.to_vec()) {
+            keys.push(key);
+        }
+        assert_eq!(
+            vec![b"aacc".to_vec(), b"bbac".to_vec(), b"ccde".to_vec()],
+            keys
+        );
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
     #[test]
     fn test_iterator_prefix() {
         let mut opts = Options::default();
@@ -221,4 +947,485 @@ mod tests {
         // 删除测试的文件夹
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
+
+    #[test]
+    fn test_prefix_iter() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-prefix-iter");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for key in ["user:2", "order:1", "user:1", "order:3", "users", "order:2"] {
+            let put_res = engine.put(Bytes::from(key), Bytes::from(key.to_uppercase()));
+            assert!(put_res.is_ok());
+        }
+
+        // 两个前缀下的数据各自按 key 的顺序返回，互不包含
+        let users: Vec<(Bytes, Bytes)> = engine.prefix_iter("user:").collect();
+        assert_eq!(
+            vec![
+                (Bytes::from("user:1"), Bytes::from("USER:1")),
+                (Bytes::from("user:2"), Bytes::from("USER:2")),
+            ],
+            users
+        );
+        let orders: Vec<Bytes> = engine.prefix_iter("order:").map(|(key, _)| key).collect();
+        assert_eq!(
+            vec![
+                Bytes::from("order:1"),
+                Bytes::from("order:2"),
+                Bytes::from("order:3"),
+            ],
+            orders
+        );
+
+        // 没有匹配的前缀
+        assert_eq!(0, engine.prefix_iter("none:").count());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_iterator_double_ended() {
+        for index_type in [IndexType::BTree, IndexType::SkipList, IndexType::BPlusTree] {
+            let mut opts = Options::default();
+            opts.dir_path = PathBuf::from("/tmp/bitcask-rs-iter-double-ended");
+            opts.index_type = index_type;
+            let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+            for i in 0..101 {
+                let put_res = engine.put(
+                    util::rand_kv::get_test_key(i),
+                    util::rand_kv::get_test_value(i),
+                );
+                assert!(put_res.is_ok());
+            }
+
+            // 两端交替遍历，正向按 key 的顺序、反向按相反的顺序返回，相遇之后都返回 None
+            let mut iter = engine.iter(IteratorOptions::default()).into_iter();
+            let mut front = Vec::new();
+            let mut back = Vec::new();
+            while let Some((key, _)) = iter.next() {
+                front.push(key);
+                match iter.next_back() {
+                    Some((key, value)) => {
+                        assert_eq!(engine.get(key.clone()).unwrap(), value);
+                        back.push(key);
+                    }
+                    None => break,
+                }
+            }
+            assert!(iter.next().is_none());
+            assert!(iter.next_back().is_none());
+            assert_eq!(51, front.len());
+            assert_eq!(50, back.len());
+
+            back.reverse();
+            front.extend(back);
+            let expected: Vec<Bytes> = (0..101).map(util::rand_kv::get_test_key).collect();
+            assert_eq!(expected, front);
+
+            // 和前缀、peek 一起使用，peek 过的数据不会从末尾返回
+            let put_res = engine.put(Bytes::from("user:1"), Bytes::from("USER:1"));
+            assert!(put_res.is_ok());
+            let put_res = engine.put(Bytes::from("user:2"), Bytes::from("USER:2"));
+            assert!(put_res.is_ok());
+            let iter = engine.iter(IteratorOptions::new().prefix("user:"));
+            assert_eq!(Bytes::from("user:1"), iter.peek().unwrap().0);
+            let mut iter = iter.into_iter();
+            assert_eq!(Bytes::from("user:2"), iter.next_back().unwrap().0);
+            assert!(iter.next_back().is_none());
+            assert_eq!(Bytes::from("user:1"), iter.next().unwrap().0);
+            assert!(iter.next().is_none());
+
+            // 删除测试的文件夹
+            std::mem::drop(engine);
+            std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        }
+    }
+
+    #[test]
+    fn test_engine_scan() {
+        for hash_keys_in_index in [false, true] {
+            let mut opts = Options::default();
+            opts.dir_path = PathBuf::from("/tmp/bitcask-rs-scan");
+            opts.hash_keys_in_index = hash_keys_in_index;
+            let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+            // 数据量超过并行读取的阈值，每 7 个 user 中有一个 vip
+            for i in 0..3000 {
+                let level = if i % 7 == 0 { "vip" } else { "normal" };
+                let put_res = engine.put(
+                    Bytes::from(format!("user:{:04}", i)),
+                    Bytes::from(format!("{}-{}", level, i)),
+                );
+                assert!(put_res.is_ok());
+                let put_res = engine.put(
+                    Bytes::from(format!("order:{:04}", i)),
+                    Bytes::from(format!("vip-order-{}", i)),
+                );
+                assert!(put_res.is_ok());
+            }
+
+            let is_vip = |_: &[u8], value: &[u8]| value.starts_with(b"vip");
+            let mut matches = engine.scan(IteratorOptions::new().prefix("user:"), is_vip);
+            // 索引中保存 key 的 hash 时按 hash 的顺序返回
+            if hash_keys_in_index {
+                matches.sort();
+            }
+            let expected: Vec<(Bytes, Bytes)> = (0..3000)
+                .filter(|i| i % 7 == 0)
+                .map(|i| {
+                    (
+                        Bytes::from(format!("user:{:04}", i)),
+                        Bytes::from(format!("vip-{}", i)),
+                    )
+                })
+                .collect();
+            assert_eq!(expected, matches);
+
+            // 逆序遍历时按相反的顺序返回，key 也可以参与过滤
+            if !hash_keys_in_index {
+                let matches = engine.scan(
+                    IteratorOptions::new().prefix("user:").reverse(true),
+                    |key, value| key.ends_with(b"0") && value.starts_with(b"vip"),
+                );
+                let keys: Vec<Bytes> = matches.into_iter().map(|(key, _)| key).collect();
+                let expected: Vec<Bytes> = (0..3000)
+                    .rev()
+                    .filter(|i| i % 70 == 0)
+                    .map(|i| Bytes::from(format!("user:{:04}", i)))
+                    .collect();
+                assert_eq!(expected, keys);
+            }
+
+            // 没有匹配的数据
+            assert!(engine
+                .scan(IteratorOptions::new().prefix("none:"), is_vip)
+                .is_empty());
+            assert!(engine
+                .scan(IteratorOptions::default(), |_, _| false)
+                .is_empty());
+
+            // 删除测试的文件夹
+            std::mem::drop(engine);
+            std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        }
+    }
+
+    #[test]
+    fn test_estimate_range_count() {
+        for hash_keys_in_index in [false, true] {
+            let mut opts = Options::default();
+            opts.dir_path = PathBuf::from("/tmp/bitcask-rs-estimate-range-count");
+            opts.hash_keys_in_index = hash_keys_in_index;
+            let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+            for i in 0..100 {
+                let put_res = engine.put(
+                    Bytes::from(format!("key-{:03}", i)),
+                    util::rand_kv::get_test_value(i),
+                );
+                assert!(put_res.is_ok());
+            }
+            let del_res = engine.delete(Bytes::from("key-015"));
+            assert!(del_res.is_ok());
+
+            // [key-010, key-020) 中除去被删除的 key-015
+            assert_eq!(9, engine.estimate_range_count(b"key-010", b"key-020"));
+            assert_eq!(99, engine.estimate_range_count(b"", b"z"));
+            assert_eq!(0, engine.estimate_range_count(b"key-020", b"key-010"));
+            assert_eq!(0, engine.estimate_range_count(b"z", b"zz"));
+
+            // 删除测试的文件夹
+            std::mem::drop(engine);
+            std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+        }
+    }
+
+    #[test]
+    fn test_scan_raw() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-scan-raw");
+        opts.data_file_size = 64 * 1024;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 没有数据的情况
+        assert_eq!(engine.scan_raw().count(), 0);
+
+        // 写入、覆盖写入、删除
+        let put_res1 = engine.put(Bytes::from("aacc"), Bytes::from("v1"));
+        assert!(put_res1.is_ok());
+        let put_res2 = engine.put(Bytes::from("aacc"), Bytes::from("v2"));
+        assert!(put_res2.is_ok());
+        let del_res = engine.delete(Bytes::from("aacc"));
+        assert!(del_res.is_ok());
+
+        // 事务写入
+        let wb = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        let put_res3 = wb.put(Bytes::from("bbdd"), Bytes::from("v3"));
+        assert!(put_res3.is_ok());
+        let commit_res = wb.commit();
+        assert!(commit_res.is_ok());
+
+        let records: Vec<RawRecord> = engine.scan_raw().collect();
+        assert_eq!(records.len(), 5);
+
+        assert_eq!(records[0].key, Bytes::from("aacc"));
+        assert_eq!(records[0].value, Bytes::from("v1"));
+        assert_eq!(records[0].rec_type, LogRecordType::NOAMAL);
+        assert_eq!(records[0].seq_no, 0);
+        assert_eq!(records[0].offset, 0);
+
+        assert_eq!(records[1].key, Bytes::from("aacc"));
+        assert_eq!(records[1].value, Bytes::from("v2"));
+        assert_eq!(records[1].rec_type, LogRecordType::NOAMAL);
+        assert!(records[1].offset > records[0].offset);
+
+        assert_eq!(records[2].key, Bytes::from("aacc"));
+        assert!(records[2].value.is_empty());
+        assert_eq!(records[2].rec_type, LogRecordType::DELETED);
+
+        assert_eq!(records[3].key, Bytes::from("bbdd"));
+        assert_eq!(records[3].rec_type, LogRecordType::NOAMAL);
+        assert!(records[3].seq_no > 0);
+        assert_eq!(records[4].rec_type, LogRecordType::TXNFINISHED);
+        assert_eq!(records[4].seq_no, records[3].seq_no);
+
+        // 跨多个数据文件
+        for i in 0..1000 {
+            let put_res = engine.put(
+                util::rand_kv::get_test_key(i),
+                util::rand_kv::get_test_value(i),
+            );
+            assert!(put_res.is_ok());
+        }
+        let records: Vec<RawRecord> = engine.scan_raw().collect();
+        assert_eq!(records.len(), 1005);
+        assert!(records.last().unwrap().file_id > 0);
+        assert_eq!(
+            records.last().unwrap().key,
+            util::rand_kv::get_test_key(999)
+        );
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_iter_with_seq() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-iter-with-seq");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 没有数据的情况
+        assert_eq!(engine.iter_with_seq(IteratorOptions::default()).count(), 0);
+
+        // 同一个事务中写入的 key 序列号相同
+        let wb1 = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        for key in ["aa", "bb", "cc"] {
+            let put_res = wb1.put(Bytes::from(key), Bytes::from(format!("{}-v1", key)));
+            assert!(put_res.is_ok());
+        }
+        let commit_res1 = wb1.commit();
+        assert!(commit_res1.is_ok());
+
+        // 之后的事务覆盖写入其中一个 key，非事务写入的序列号为 0
+        let wb2 = engine
+            .new_write_batch(WriteBatchOptions::default())
+            .unwrap();
+        let put_res1 = wb2.put(Bytes::from("bb"), Bytes::from("bb-v2"));
+        assert!(put_res1.is_ok());
+        let commit_res2 = wb2.commit();
+        assert!(commit_res2.is_ok());
+        let put_res2 = engine.put(Bytes::from("dd"), Bytes::from("dd-v1"));
+        assert!(put_res2.is_ok());
+
+        let items: Vec<(Bytes, Bytes, usize)> =
+            engine.iter_with_seq(IteratorOptions::default()).collect();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].0, Bytes::from("aa"));
+        assert_eq!(items[0].1, Bytes::from("aa-v1"));
+        assert!(items[0].2 > 0);
+        assert_eq!(items[2].0, Bytes::from("cc"));
+        assert_eq!(items[2].2, items[0].2);
+        assert_eq!(items[1].0, Bytes::from("bb"));
+        assert_eq!(items[1].1, Bytes::from("bb-v2"));
+        assert!(items[1].2 > items[0].2);
+        assert_eq!(items[3].0, Bytes::from("dd"));
+        assert_eq!(items[3].2, 0);
+
+        // 按前缀和逆序遍历，重启之后序列号不变
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let items2: Vec<(Bytes, Bytes, usize)> = engine2
+            .iter_with_seq(IteratorOptions::new().prefix("c").reverse(true))
+            .collect();
+        assert_eq!(items2, vec![items[2].clone()]);
+        let items3: Vec<(Bytes, Bytes, usize)> = engine2
+            .iter_with_seq(IteratorOptions::new().reverse(true))
+            .collect();
+        assert_eq!(items3.first(), items.last());
+
+        // 删除测试的文件夹
+        std::mem::drop(engine2);
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_deleted_keys() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-deleted-keys");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine.deleted_keys().is_empty());
+
+        for i in 0..1000 {
+            let put_res = engine.put(
+                util::rand_kv::get_test_key(i),
+                util::rand_kv::get_test_value(i),
+            );
+            assert!(put_res.is_ok());
+        }
+
+        // 单独删除、重复删除、事务删除
+        for i in 0..10 {
+            assert!(engine.delete(util::rand_kv::get_test_key(i)).is_ok());
+        }
+        assert!(engine.delete(util::rand_kv::get_test_key(5)).is_ok());
+        let keys = [
+            util::rand_kv::get_test_key(30),
+            util::rand_kv::get_test_key(31),
+        ];
+        assert_eq!(Ok(2), engine.delete_batch(&keys));
+        // 删除之后重新写入的 key 和不存在的 key 不会返回
+        assert!(engine.delete(util::rand_kv::get_test_key(20)).is_ok());
+        let put_res = engine.put(util::rand_kv::get_test_key(20), Bytes::from("new value"));
+        assert!(put_res.is_ok());
+        assert!(engine.delete(Bytes::from("not existed key")).is_ok());
+
+        let mut expected: Vec<Bytes> = (0..10).map(util::rand_kv::get_test_key).collect();
+        expected.extend(keys.iter().cloned());
+        assert_eq!(expected, engine.deleted_keys());
+        assert_eq!(1000 - 12, engine.list_keys().len());
+
+        // merge 之后删除标记被清理
+        let merge_res = engine.merge();
+        assert!(merge_res.is_ok());
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(engine2.deleted_keys().is_empty());
+        assert_eq!(1000 - 12, engine2.list_keys().len());
+        std::mem::drop(engine2);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_iterator_hash_keys_in_index() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-iter-hash-keys");
+        opts.hash_keys_in_index = true;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for key in ["aacc", "aade", "bbcc", "bbed", "ccde"] {
+            let put_res = engine.put(Bytes::from(key), util::rand_kv::get_test_value(10));
+            assert!(put_res.is_ok());
+        }
+
+        // 按完整 key 的前缀过滤
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.prefix = "bb".as_bytes().to_vec();
+        let iter = engine.iter(iter_opts);
+        let mut keys = Vec::new();
+        while let Some((key, value)) = iter.next() {
+            assert_eq!(util::rand_kv::get_test_value(10), value);
+            keys.push(key);
+        }
+        keys.sort();
+        assert_eq!(vec![Bytes::from("bbcc"), Bytes::from("bbed")], keys);
+
+        // 只遍历 key 时同样按完整 key 的前缀过滤
+        let mut iter_opts = IteratorOptions::default();
+        iter_opts.prefix = "aa".as_bytes().to_vec();
+        let iter = engine.iter(iter_opts);
+        let mut keys = Vec::new();
+        while let Some(key) = iter.next_key_with(|k| k.to_vec()) {
+            keys.push(key);
+        }
+        keys.sort();
+        assert_eq!(vec![b"aacc".to_vec(), b"aade".to_vec()], keys);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_iter_from_cursor() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-iter-from-cursor");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..100 {
+            let put_res = engine.put(
+                util::rand_kv::get_test_key(i),
+                util::rand_kv::get_test_value(i),
+            );
+            assert!(put_res.is_ok());
+        }
+
+        // 还没有返回数据时游标为空，从头开始遍历
+        let iter1 = engine.iter(IteratorOptions::default());
+        assert!(iter1.save_cursor().is_empty());
+
+        // 遍历一半数据后保存游标，peek 不会改变游标
+        let mut visited = Vec::new();
+        for _ in 0..50 {
+            visited.push(iter1.next().unwrap().0);
+        }
+        assert!(iter1.peek().is_some());
+        let cursor = iter1.save_cursor();
+        assert_eq!(visited[49].to_vec(), cursor);
+        std::mem::drop(iter1);
+        std::mem::drop(engine);
+
+        // 重启后从游标处恢复，剩余的 key 每个只返回一次
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let iter2 = engine2.iter_from(&cursor, IteratorOptions::default());
+        while let Some(key) = iter2.next_key_with(Bytes::copy_from_slice) {
+            visited.push(key);
+        }
+        let expected: Vec<Bytes> = (0..100).map(util::rand_kv::get_test_key).collect();
+        assert_eq!(expected, visited);
+
+        // 逆序遍历时跳过游标 key 以及以它为前缀的 key
+        for key in ["ab", "abc", "abd", "b"] {
+            let put_res = engine2.put(Bytes::from(key), util::rand_kv::get_test_value(10));
+            assert!(put_res.is_ok());
+        }
+        let iter_opts = IteratorOptions::new().prefix("a").reverse(true);
+        let iter3 = engine2.iter(iter_opts.clone());
+        assert_eq!(Bytes::from("abd"), iter3.next().unwrap().0);
+        assert_eq!(Bytes::from("abc"), iter3.next().unwrap().0);
+        assert_eq!(Bytes::from("ab"), iter3.next().unwrap().0);
+        let iter4 = engine2.iter_from(&iter3.save_cursor(), iter_opts);
+        assert!(iter4.next().is_none());
+
+        // 游标对应的 key 被删除后仍然从它之后继续遍历
+        assert!(engine2.delete(Bytes::from("abc")).is_ok());
+        let iter5 = engine2.iter_from(b"abc", IteratorOptions::new().prefix("a"));
+        assert_eq!(Bytes::from("abd"), iter5.next().unwrap().0);
+        assert!(iter5.next().is_none());
+        std::mem::drop(engine2);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
 }
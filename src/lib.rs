@@ -1,5 +1,5 @@
 mod index;
-mod data;
+pub mod data;
 mod fio;
 pub mod errors;
 mod util;
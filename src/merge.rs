@@ -1,34 +1,118 @@
-use log::error;
+use log::{error, warn};
 use std::{
-    fs::{create_dir_all, read_dir, remove_dir_all, remove_file, rename},
+    collections::HashMap,
+    fs::{create_dir_all, metadata, read_dir, remove_dir_all, remove_file, rename},
     path::PathBuf,
-    sync::atomic::Ordering,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
 use crate::{
     batch::{log_record_key_with_seq, parse_log_record_key, NON_TXN_SEQ_NO},
     data::{
+        codec,
         data_file::{
-            get_data_file_path, DataFile, DATA_FILE_NAME_SUFFIX, HINT_FILE_NAME,
+            get_data_file_path, DataFile, HINT_FILE_NAME, INDEX_TYPE_FILE_NAME, MANIFEST_FILE_NAME,
             MERGE_FINISHED_FILE_NAME, SEQ_NO_FILE_NAME,
         },
-        log_record::{decode_log_record_pos, LogRecord, LogRecordType},
+        log_record::{LogRecord, LogRecordType},
     },
-    db::{Engine, FILE_LOCK_NAME},
+    db::{Engine, Stat},
     errors::Errors,
-    options::{IOType, Options},
-    util::file::{available_disk_size, dir_disk_size},
+    options::{CorruptionPolicy, IOType, IndexType, Options},
+    util::file::{available_disk_size, sync_dir},
 };
 
 const MERGE_DIR_NAME: &str = "merge";
 const MERGE_FIN_KEY: &[u8] = "merge.finished".as_bytes();
+const MERGE_PROGRESS_INTERVAL: u64 = 1024 * 1024;
+
+/// merge 的触发策略，merge 和 merge_if_needed 根据统计信息判断是否需要 merge 以及 merge 的范围
+/// 通过 Options::merge_policy 设置，没有设置时使用 RatioMergePolicy
+pub trait MergePolicy: Send + Sync {
+    /// 返回 None 表示不需要 merge，reclaim_by_file 为每个数据文件中可以回收的数据量
+    fn should_merge(
+        &self,
+        stats: &Stat,
+        reclaim_by_file: &HashMap<u32, usize>,
+    ) -> Option<MergePlan>;
+}
+
+/// merge 的范围，参与 merge 的始终是 id 最小的一段旧数据文件
+/// 开始 merge 时当前的活跃文件也会加入旧数据文件，默认值表示 merge 所有旧数据文件
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MergePlan {
+    /// 只 merge id 不大于该值的数据文件，None 表示不限制
+    pub max_file_id: Option<u32>,
+    /// 跳过最近的若干个旧数据文件
+    pub keep_recent_files: usize,
+}
+
+/// 默认的 merge 策略，可以回收的数据量占数据文件总大小的比例达到 ratio 时 merge，
+/// 跳过最近的 keep_recent_files 个旧数据文件；没有设置 merge_policy 时使用
+/// data_file_merge_ratio 和 merge_keep_recent_files 构造
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RatioMergePolicy {
+    pub ratio: f32,
+    pub keep_recent_files: usize,
+}
+
+impl MergePolicy for RatioMergePolicy {
+    fn should_merge(&self, stats: &Stat, _: &HashMap<u32, usize>) -> Option<MergePlan> {
+        let ratio = if stats.data_files_size == 0 {
+            0.0
+        } else {
+            stats.reclaim_size as f32 / stats.data_files_size as f32
+        };
+        if ratio < self.ratio {
+            return None;
+        }
+        Some(MergePlan {
+            max_file_id: None,
+            keep_recent_files: self.keep_recent_files,
+        })
+    }
+}
 
 impl Engine {
     // merge 数据目录，处理无效数据，并生成 hint 索引文件
+    // merge 期间只读取旧数据文件并写入 merge 目录，不会修改或删除数据目录中的文件，读写可以正常进行；
+    // merge 后的文件在下次打开存储引擎时由 load_merge_files 替换旧数据文件，此时还没有任何读取，
+    // 因此读取不会遇到被 merge 删除的数据文件
     pub fn merge(&self) -> Result<(), Errors> {
+        self.merge_with_cancel(Arc::new(AtomicBool::new(false)))
+    }
+
+    /// merge 数据目录，cancel 被设置为 true 时取消 merge
+    /// 每处理完一个数据文件检查一次是否取消，取消时删除未完成的 merge 目录并返回 MergeCancelled，
+    /// 数据目录中的数据不受影响
+    pub fn merge_with_cancel(&self, cancel: Arc<AtomicBool>) -> Result<(), Errors> {
+        self.run_merge(cancel).map(|_| ())
+    }
+
+    /// 达到 merge 的阈值时执行 merge，返回是否执行了 merge，阈值由 merge_policy 判断
+    /// 未达到阈值、数据库为空或者没有需要 merge 的文件时返回 false，适合在定时任务中调用
+    pub fn merge_if_needed(&self) -> Result<bool, Errors> {
+        match self.run_merge(Arc::new(AtomicBool::new(false))) {
+            Err(Errors::MergeRatioUnreached) => Ok(false),
+            res => res,
+        }
+    }
+
+    // 执行 merge，返回是否生成了 merge 后的数据文件
+    fn run_merge(&self, cancel: Arc<AtomicBool>) -> Result<bool, Errors> {
+        self.check_open()?;
+        if self.options.read_only {
+            return Err(Errors::ReadOnlyMode);
+        }
+
         // 如果是空的数据库则直接返回
         if self.is_empty_engine() {
-            return Ok(());
+            return Ok(false);
         }
 
         let lock = self.merging_lock.try_lock();
@@ -36,41 +120,70 @@ impl Engine {
             return Err(Errors::MergeInProcess);
         }
 
-        // 判断是否达到了 merge 的阈值
-        let reclaim_size = self.reclaim_size.load(Ordering::SeqCst);
-        let total_size = dir_disk_size(self.options.dir_path.clone());
-        if (reclaim_size as f32 / total_size as f32) < self.options.data_file_merge_ratio {
-            return Err(Errors::MergeRatioUnreached);
-        }
+        // 根据 merge 策略判断是否需要 merge
+        let stat = self.stat()?;
+        let plan = match self.merge_plan(&stat) {
+            Some(plan) => plan,
+            None => return Err(Errors::MergeRatioUnreached),
+        };
 
         // 判断磁盘剩余空间是否足够
-        if total_size - reclaim_size as u64 >= available_disk_size() {
+        let remain_size = stat
+            .data_files_size
+            .saturating_sub(stat.reclaim_size as u64);
+        if remain_size >= available_disk_size() {
             return Err(Errors::MergeNoEnoughSpace);
         }
 
         // 如果 merge 目录已经存在，删除并新建 merge 目录
         let merge_path = get_merge_path(self.options.dir_path.clone());
         if merge_path.is_dir() {
-            remove_dir_all(merge_path.clone()).unwrap();
+            remove_dir_all(merge_path.clone())?;
         }
         if let Err(e) = create_dir_all(merge_path.clone()) {
             error!("failed to create merge path {}", e);
             return Err(Errors::FailedCreateDatabaseDir);
         }
 
-        let merge_files = self.get_merge_files()?;
+        let merge_file_ids = self.get_merge_files(&plan)?;
+        // 旧文件都被保留时没有需要 merge 的文件
+        if merge_file_ids.is_empty() {
+            remove_dir_all(merge_path)?;
+            return Ok(false);
+        }
 
         // 打开用于 merge 的存储引擎实例
-        let mut merge_opts = Options::default();
-        merge_opts.dir_path = merge_path.clone();
-        merge_opts.data_file_size = self.options.data_file_size;
-        let merge_engine = Engine::open(merge_opts)?;
+        let merge_engine = Engine::open(self.merge_engine_options(merge_path.clone()))?;
 
         // 打开 hint 索引文件
-        let mut hint_file = DataFile::new_hint_file(merge_path.clone())?;
+        let mut hint_file = DataFile::new_hint_file(merge_path.clone(), false)?;
+
+        // 按旧数据文件的字节数统计进度，参与 merge 的文件都已经写满，大小不会再变化
+        let total = merge_file_ids
+            .iter()
+            .map(|file_id| {
+                let file_path = get_data_file_path(
+                    self.options.dir_path.clone(),
+                    *file_id,
+                    &self.options.file_suffix,
+                );
+                metadata(file_path).map(|meta| meta.len()).unwrap_or(0)
+            })
+            .sum();
+        let mut progress = MergeProgress::new(&self.options, total);
 
         // 处理所有 merge 文件，重写有效的数据
-        for data_file in merge_files.iter() {
+        for file_id in merge_file_ids.iter() {
+            if cancel.load(Ordering::SeqCst) {
+                // 先关闭 merge 存储引擎和 hint 文件，再删除 merge 目录
+                drop(merge_engine);
+                drop(hint_file);
+                remove_dir_all(merge_path)?;
+                return Err(Errors::MergeCancelled);
+            }
+
+            // 依次打开 merge 文件，处理完成后关闭，不会同时打开所有旧数据文件
+            let data_file = self.open_data_file(*file_id, IOType::FileIO)?;
             let mut offset = 0;
             loop {
                 let (mut log_record, size) = match data_file.read(offset) {
@@ -84,8 +197,9 @@ impl Engine {
                 };
 
                 // 解码拿到实际的 key
-                let (real_key, _) = parse_log_record_key(log_record.key);
-                if let Some(index_pos) = self.index.get(real_key.clone()) {
+                let (real_key, _) = parse_log_record_key(log_record.key)?;
+                let (_, index_pos) = self.lookup_index(&real_key, &|pos| self.read_key_at(pos))?;
+                if let Some(index_pos) = index_pos {
                     // 索引中数据位置信息与当前数据位置信息一致，说明当前数据有效
                     if index_pos.file_id == data_file.get_file_id() && index_pos.offset == offset {
                         // 取出 key 的事务标识
@@ -96,16 +210,18 @@ impl Engine {
                 }
 
                 offset += size;
+                progress.advance(size);
             }
         }
+        progress.finish();
 
         // merge 文件和 hint 文件持久化
         merge_engine.sync()?;
         hint_file.sync()?;
 
         // 拿到最近未参与 merge 的文件 id，将其写入到文件中标识 merge 成功
-        let non_merge_file_id = merge_files.last().unwrap().get_file_id() + 1;
-        let mut merge_fin_file = DataFile::new_merge_finished_file(merge_path.clone())?;
+        let non_merge_file_id = merge_file_ids.last().unwrap() + 1;
+        let mut merge_fin_file = DataFile::new_merge_finished_file(merge_path.clone(), false)?;
         let merge_fin_record = LogRecord {
             key: MERGE_FIN_KEY.to_vec(),
             value: non_merge_file_id.to_string().into_bytes(),
@@ -115,49 +231,126 @@ impl Engine {
         merge_fin_file.write(&&enc_record)?;
         merge_fin_file.sync()?;
 
-        Ok(())
+        // 持久化 merge 目录，保证崩溃后 merge 目录中新建的文件仍然存在
+        if self.options.sync_dir_enabled() {
+            sync_dir(&merge_path)?;
+        }
+
+        Ok(true)
+    }
+
+    // merge 存储引擎的配置项
+    // merge 引擎只负责顺序重写有效数据，真正的索引信息记录在 hint 文件中，
+    // 因此使用开销最小的内存 BTree 索引，不使用 mmap，也不在每次写入后持久化，
+    // 而是在 merge 结束时统一 sync；数据文件大小使用 merge_file_size，没有设置时与当前引擎保持一致
+    fn merge_engine_options(&self, merge_path: PathBuf) -> Options {
+        Options {
+            dir_path: merge_path,
+            data_file_size: self
+                .options
+                .merge_file_size
+                .unwrap_or(self.options.data_file_size),
+            sync_writes: false,
+            bytes_per_sync: 0,
+            index_type: IndexType::BTree,
+            mmap_at_startup: false,
+            data_file_merge_ratio: self.options.data_file_merge_ratio,
+            skip_missing_data_file: false,
+            max_unsynced_bytes: None,
+            read_only: false,
+            hash_keys_in_index: false,
+            merge_keep_recent_files: 0,
+            on_file_rotate: None,
+            collect_read_stats: false,
+            file_suffix: self.options.file_suffix.clone(),
+            lock_file_name: self.options.lock_file_name.clone(),
+            max_open_files: self.options.max_open_files,
+            rebuild_index_on_corruption: false,
+            error_on_data_file_size_shrink: false,
+            sync_on_drop: true,
+            archive_reader: None,
+            reuse_read_buffers: self.options.reuse_read_buffers,
+            merge_file_size: None,
+            // merge 只重写已经写入的数据，不再检查记录大小
+            max_record_size: None,
+            group_commit_window: None,
+            on_corruption: CorruptionPolicy::Fail,
+            // merge 引擎单线程顺序写入，不需要自定义分段
+            shard_fn: None,
+            // blob 位置按原样复制到 merge 后的数据文件，不重新分离 value
+            separate_values_over: None,
+            create_if_missing: true,
+            error_if_exists: false,
+            // merge 后的文件替换 id 最小的一段文件，和数据目录使用相同的起始 id
+            initial_file_id: self.options.initial_file_id,
+            io_retry: self.options.io_retry,
+            merge_policy: None,
+            // 数据文件中保存的已经是转换后的 key，merge 时不再重复转换
+            key_normalizer: None,
+            // merge 引擎只负责写入，限速和进度由当前引擎按读取的字节数统计
+            merge_throughput_limit: None,
+            on_merge_progress: None,
+            // merge 引擎顺序写入大量数据，使用和当前引擎相同的写缓冲区
+            write_buffer_size: self.options.write_buffer_size,
+        }
+    }
+
+    // 使用配置的 merge 策略计算 merge 的范围，没有配置时按 data_file_merge_ratio 判断
+    fn merge_plan(&self, stat: &Stat) -> Option<MergePlan> {
+        let reclaim_by_file = self.reclaim_by_file();
+        match self.options.merge_policy.as_ref() {
+            Some(policy) => policy.should_merge(stat, &reclaim_by_file),
+            None => RatioMergePolicy {
+                ratio: self.options.data_file_merge_ratio,
+                keep_recent_files: self.options.merge_keep_recent_files,
+            }
+            .should_merge(stat, &reclaim_by_file),
+        }
+    }
+
+    /// 可以回收的数据量占数据文件总大小的比例，没有设置 merge_policy 时达到 data_file_merge_ratio 才会 merge
+    /// 只统计数据文件，hint 文件、MANIFEST、文件锁等辅助文件不计入总大小
+    pub fn reclaim_ratio(&self) -> f32 {
+        let total_size = self.data_files_size.load(Ordering::SeqCst);
+        if total_size == 0 {
+            return 0.0;
+        }
+        self.reclaim_size.load(Ordering::SeqCst) as f32 / total_size as f32
     }
 
     fn is_empty_engine(&self) -> bool {
         let active_file = self.active_file.read();
         let older_files = self.older_files.read();
-        active_file.get_write_off() == 0 && older_files.len() == 0
+        active_file.get_write_off() == 0 && older_files.is_empty()
     }
 
-    // 获取所有需要 merge 的数据文件
-    fn get_merge_files(&self) -> Result<Vec<DataFile>, Errors> {
+    // 获取所有需要 merge 的数据文件 id
+    fn get_merge_files(&self, plan: &MergePlan) -> Result<Vec<u32>, Errors> {
         let mut active_file = self.active_file.write();
-        let mut older_files = self.older_files.write();
-
-        // 持久化当前活跃文件并加入到旧文件列表，设置新的活跃文件
-        active_file.sync()?;
-        let current_fid = active_file.get_file_id();
-        let old_file = DataFile::new(self.options.dir_path.clone(), current_fid, IOType::FileIO)?;
-        older_files.insert(current_fid, old_file);
-        let new_file = DataFile::new(
-            self.options.dir_path.clone(),
-            current_fid + 1,
-            IOType::FileIO,
-        )?;
-        *active_file = new_file;
+
+        // 持久化当前活跃文件并加入到旧文件列表，设置新的活跃文件，持有活跃文件的写锁期间不会再切换
+        let (current_fid, new_fid) = self.rotate_active_file(&mut active_file)?;
+        let older_files = self.older_files.read();
 
         // merge 文件从小到大依次 merge
-        let mut merge_file_ids = Vec::new();
-        for fid in older_files.keys() {
-            merge_file_ids.push(*fid);
+        let mut merge_file_ids = older_files.file_ids();
+
+        // 最近的文件中的数据很可能还会被修改，不参与 merge
+        // 参与 merge 的始终是 id 最小的一段文件，保留的文件 id 都不小于 merge 完成文件中记录的 id
+        let keep = std::cmp::min(plan.keep_recent_files, merge_file_ids.len());
+        merge_file_ids.truncate(merge_file_ids.len() - keep);
+        if let Some(max_file_id) = plan.max_file_id {
+            merge_file_ids.retain(|file_id| *file_id <= max_file_id);
         }
-        merge_file_ids.sort();
 
-        let mut merge_files = Vec::new();
-        for fid in merge_file_ids.iter() {
-            merge_files.push(DataFile::new(
-                self.options.dir_path.clone(),
-                *fid,
-                IOType::FileIO,
-            )?);
+        // 释放锁之后再执行文件切换的回调
+        drop(older_files);
+        drop(active_file);
+        if let Some(on_file_rotate) = self.options.on_file_rotate.as_ref() {
+            on_file_rotate(current_fid, new_fid);
         }
 
-        Ok(merge_files)
+        Ok(merge_file_ids)
     }
 
     // 从 hint 文件中加载索引
@@ -167,7 +360,8 @@ impl Engine {
             return Ok(());
         }
 
-        let hint_file = DataFile::new_hint_file(self.options.dir_path.clone())?;
+        let hint_file =
+            DataFile::new_hint_file(self.options.dir_path.clone(), self.options.read_only)?;
         let mut offset = 0;
         loop {
             let (log_record, size) = match hint_file.read(offset) {
@@ -180,14 +374,109 @@ impl Engine {
                 }
             };
             // 解析 value 得到 key 位置信息，添加到内存索引
-            let pos = decode_log_record_pos(log_record.value);
-            self.index.put(log_record.key, pos);
+            let (key, pos) = codec::decode_hint_v1(log_record);
+            let (index_key, _) = self.resolve_index_key(key, &|pos| self.read_key_at(pos))?;
+            self.index.put(index_key, pos);
 
             offset += size;
         }
 
         Ok(())
     }
+
+    // merge 完成后从 hint 文件中更新 B+ 树索引
+    // 只更新仍指向已参与 merge 的数据文件的 key，merge 期间新写入或删除的 key 保持不变
+    pub(crate) fn load_bptree_index_from_hint_file(&self) -> Result<(), Errors> {
+        let hint_file_name = self.options.dir_path.join(HINT_FILE_NAME);
+        if !hint_file_name.is_file() {
+            return Ok(());
+        }
+
+        let non_merge_fid =
+            get_non_merge_file_id(self.options.dir_path.clone(), self.options.read_only)?;
+        let hint_file =
+            DataFile::new_hint_file(self.options.dir_path.clone(), self.options.read_only)?;
+        let mut offset = 0;
+        loop {
+            let (log_record, size) = match hint_file.read(offset) {
+                Ok(read_res) => (read_res.record, read_res.size),
+                Err(e) => {
+                    if e == Errors::ReadDataFileEOF {
+                        break;
+                    }
+                    return Err(e);
+                }
+            };
+
+            if let Some(old_pos) = self.index.get(log_record.key.clone()) {
+                if old_pos.file_id < non_merge_fid {
+                    let (key, pos) = codec::decode_hint_v1(log_record);
+                    self.index.put(key, pos);
+                }
+            }
+
+            offset += size;
+        }
+
+        Ok(())
+    }
+}
+
+// merge 的进度和限速，按读取的旧数据文件字节数统计
+struct MergeProgress {
+    throughput_limit: Option<u64>, // 每秒最多处理的字节数
+    on_progress: Option<Arc<dyn Fn(u64, u64) + Send + Sync>>, // 进度回调
+    started: Instant,              // 开始处理旧数据文件的时间
+    processed: u64,                // 已经处理的字节数
+    reported: u64,                 // 上次回调时已经处理的字节数
+    total: u64,                    // 总共需要处理的字节数
+}
+
+impl MergeProgress {
+    fn new(options: &Options, total: u64) -> Self {
+        MergeProgress {
+            throughput_limit: options.merge_throughput_limit,
+            on_progress: options.on_merge_progress.clone(),
+            started: Instant::now(),
+            processed: 0,
+            reported: 0,
+            total,
+        }
+    }
+
+    // 处理完 size 字节之后调用，处理速度超过限制时休眠到限制的速度，
+    // 每处理 MERGE_PROGRESS_INTERVAL 字节回调一次进度
+    fn advance(&mut self, size: u64) {
+        self.processed += size;
+        if let Some(delay) = self.throttle_delay(self.started.elapsed()) {
+            thread::sleep(delay);
+        }
+        if self.processed - self.reported >= MERGE_PROGRESS_INTERVAL {
+            self.report();
+        }
+    }
+
+    // 开始处理之后经过了 elapsed，按限速处理已经处理的数据需要更长的时间时，返回还需要等待的时间
+    fn throttle_delay(&self, elapsed: Duration) -> Option<Duration> {
+        let limit = self.throughput_limit?;
+        let expected = Duration::from_secs_f64(self.processed as f64 / limit as f64);
+        expected
+            .checked_sub(elapsed)
+            .filter(|delay| !delay.is_zero())
+    }
+
+    // 所有旧数据文件处理完成，回调最终的进度
+    fn finish(&mut self) {
+        self.report();
+    }
+
+    // 回调当前的进度，已经处理的字节数不会超过总字节数
+    fn report(&mut self) {
+        self.reported = self.processed;
+        if let Some(on_progress) = self.on_progress.as_ref() {
+            on_progress(self.processed, self.total.max(self.processed));
+        }
+    }
 }
 
 // 获取临时用于 merge 的数据目录
@@ -198,12 +487,39 @@ fn get_merge_path(dir_path: PathBuf) -> PathBuf {
     parent.to_path_buf().join(merge_name)
 }
 
-// 加载 merge 数据目录
-pub(crate) fn load_merge_files(dir_path: PathBuf) -> Result<(), Errors> {
+// 从标识 merge 完成的文件中拿到最近未参与 merge 的文件 id
+// 文件被截断或者内容损坏时返回 DataDirCorrupted
+pub(crate) fn get_non_merge_file_id(dir_path: PathBuf, read_only: bool) -> Result<u32, Errors> {
+    let merge_fin_file = DataFile::new_merge_finished_file(dir_path, read_only)?;
+    let read_res = match merge_fin_file.read(0) {
+        Ok(res) => res,
+        Err(Errors::ReadDataFileEOF) | Err(Errors::InvalidLogRecordCrc) => {
+            return Err(Errors::DataDirCorrupted)
+        }
+        Err(e) => return Err(e),
+    };
+    String::from_utf8(read_res.record.value)
+        .ok()
+        .and_then(|v| v.parse::<u32>().ok())
+        .ok_or(Errors::DataDirCorrupted)
+}
+
+// 加载 merge 数据目录，返回是否有 merge 文件被移动到数据目录
+// 按照以下顺序处理，中途退出后再次打开时可以继续完成：
+// 1. hint 文件还在 merge 目录中时，说明还没有开始移动文件，删除参与 merge 的旧数据文件
+// 2. 先移动 hint 文件，再移动 merge 后的数据文件，已经移动过的文件不在 merge 目录中，自然跳过
+// 3. 最后移动 merge 完成标识文件，然后删除 merge 目录
+// sync_dir_after_move 为 true 时，移动完成后持久化数据目录，保证崩溃后重命名的结果仍然有效
+pub(crate) fn load_merge_files(
+    dir_path: PathBuf,
+    file_suffix: &str,
+    lock_file_name: &str,
+    sync_dir_after_move: bool,
+) -> Result<bool, Errors> {
     let merge_path = get_merge_path(dir_path.clone());
     // 没有发生过 merge 则直接返回
     if !merge_path.is_dir() {
-        return Ok(());
+        return Ok(false);
     }
 
     let dir = match read_dir(merge_path.clone()) {
@@ -217,65 +533,121 @@ pub(crate) fn load_merge_files(dir_path: PathBuf) -> Result<(), Errors> {
     // 读到标识 merge 完成的文件才能继续
     let mut merge_file_names = Vec::new();
     let mut merge_finished = false;
+    let mut hint_moved = true;
     for file in dir {
         if let Ok(entry) = file {
             let file_os_str = entry.file_name();
             let file_name = file_os_str.to_str().unwrap();
 
+            // 标识文件在所有文件移动完成后单独移动
             if file_name.ends_with(MERGE_FINISHED_FILE_NAME) {
                 merge_finished = true;
+                continue;
+            }
+            if file_name == HINT_FILE_NAME {
+                hint_moved = false;
             }
             if file_name.ends_with(SEQ_NO_FILE_NAME) {
                 continue;
             }
-            if file_name.ends_with(FILE_LOCK_NAME) {
+            if file_name.ends_with(INDEX_TYPE_FILE_NAME) {
+                continue;
+            }
+            if file_name.starts_with(MANIFEST_FILE_NAME) {
+                continue;
+            }
+            if file_name.ends_with(lock_file_name) {
                 continue;
             }
 
             // 数据文件容量为空则跳过
-            let meta = entry.metadata().unwrap();
-            if file_name.ends_with(DATA_FILE_NAME_SUFFIX) && meta.len() == 0 {
+            let meta = entry.metadata()?;
+            if file_name.ends_with(file_suffix) && meta.len() == 0 {
                 continue;
             }
             merge_file_names.push(entry.file_name());
         }
     }
 
-    // merge 没有完成，删除 merge 目录并返回
-    if !merge_finished {
-        remove_dir_all(merge_path.clone()).unwrap();
-        return Ok(());
+    // 拿到最近未参与 merge 的文件 id，标识文件损坏时当作 merge 没有完成
+    let mut non_merge_id = None;
+    if merge_finished {
+        match get_non_merge_file_id(merge_path.clone(), false) {
+            Ok(fid) => non_merge_id = Some(fid),
+            Err(Errors::DataDirCorrupted) => {
+                warn!("merge finished file is corrupted, discard the merge dir")
+            }
+            Err(e) => return Err(e),
+        }
     }
 
-    // 拿到最近未参与 merge 的文件 id
-    let merge_fin_file = DataFile::new_merge_finished_file(merge_path.clone())?;
-    let read_res = merge_fin_file.read(0)?;
-    let v = String::from_utf8(read_res.record.value).unwrap();
-    let non_merge_id = v.parse::<u32>().unwrap();
+    // merge 没有完成，删除 merge 目录并返回
+    // 没有标识文件并且 merge 目录中也没有需要移动的文件时，说明上次已经全部移动完成，只是没有删除 merge 目录
+    let non_merge_id = match non_merge_id {
+        Some(fid) => fid,
+        None => {
+            let moved = !merge_finished && hint_moved && merge_file_names.is_empty();
+            remove_dir_all(merge_path.clone())?;
+            return Ok(moved);
+        }
+    };
 
-    // 删除旧的数据文件
-    for fid in 0..non_merge_id {
-        let file_path = get_data_file_path(dir_path.clone(), fid);
-        remove_file(file_path).unwrap();
+    // merge 后的数据文件不能覆盖没有参与 merge 的数据文件，否则会丢失 merge 之后写入的数据，
+    // 还没有开始移动文件时放弃这次 merge 的结果，保留原有的数据文件
+    let overwrite_live = merge_file_names.iter().any(|file_name| {
+        file_name
+            .to_str()
+            .and_then(|name| name.strip_suffix(file_suffix))
+            .and_then(|fid| fid.parse::<u32>().ok())
+            .is_some_and(|fid| {
+                fid >= non_merge_id
+                    && get_data_file_path(dir_path.clone(), fid, file_suffix).is_file()
+            })
+    });
+    if overwrite_live {
+        if hint_moved {
+            return Err(Errors::DataDirCorrupted);
+        }
+        error!("merged data files would overwrite live data files, discard the merge dir");
+        remove_dir_all(merge_path)?;
+        return Ok(false);
     }
 
-    // 将 merge 文件移动到数据目录
+    // 还没有开始移动文件时删除旧的数据文件，之前的 merge 可能使文件 id 不连续，跳过不存在的文件
+    if !hint_moved {
+        for fid in 0..non_merge_id {
+            let file_path = get_data_file_path(dir_path.clone(), fid, file_suffix);
+            if file_path.is_file() {
+                remove_file(file_path)?;
+            }
+        }
+    }
+
+    // 将 merge 文件移动到数据目录，hint 文件最先移动，标识文件最后移动
+    merge_file_names.sort_by_key(|file_name| file_name.as_os_str() != HINT_FILE_NAME);
+    merge_file_names.push(MERGE_FINISHED_FILE_NAME.into());
     for file_name in merge_file_names {
         let src_path = merge_path.join(file_name.clone());
         let dest_path = dir_path.join(file_name.clone());
-        rename(src_path, dest_path).unwrap();
+        rename(src_path, dest_path)?;
+    }
+    if sync_dir_after_move {
+        sync_dir(&dir_path)?;
     }
 
     // 删除 merge 目录
-    remove_dir_all(merge_path).unwrap();
+    remove_dir_all(merge_path)?;
 
-    Ok(())
+    Ok(true)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::util::rand_kv::{get_test_key, get_test_value};
+    use crate::{
+        data::data_file::DATA_FILE_NAME_SUFFIX,
+        util::rand_kv::{get_test_key, get_test_value},
+    };
     use bytes::Bytes;
     use std::{sync::Arc, thread};
 
@@ -403,6 +775,121 @@ mod tests {
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
 
+    #[test]
+    fn test_merge_bptree() {
+        // B+ 树索引的存储引擎进行 merge
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-bptree");
+        opts.data_file_size = 32 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        opts.index_type = IndexType::BPlusTree;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..1000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        for i in 0..200 {
+            let put_res = engine.put(get_test_key(i), Bytes::from("new value in merge"));
+            assert!(put_res.is_ok());
+        }
+        for i in 800..1000 {
+            let del_res = engine.delete(get_test_key(i));
+            assert!(del_res.is_ok());
+        }
+
+        let res1 = engine.merge();
+        assert!(res1.is_ok());
+
+        // merge 之后有新的写入
+        let put_res = engine.put(get_test_key(300), Bytes::from("new value after merge"));
+        assert!(put_res.is_ok());
+
+        // 重启校验
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = engine2.list_keys();
+        assert_eq!(keys.len(), 800);
+
+        for i in 0..200 {
+            let get_res = engine2.get(get_test_key(i));
+            assert_eq!(Bytes::from("new value in merge"), get_res.ok().unwrap());
+        }
+        for i in 200..800 {
+            let get_res = engine2.get(get_test_key(i));
+            if i == 300 {
+                assert_eq!(Bytes::from("new value after merge"), get_res.ok().unwrap());
+            } else {
+                assert_eq!(get_test_value(i), get_res.ok().unwrap());
+            }
+        }
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_read_only() {
+        // 只读模式打开有未加载 merge 目录的数据库
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-read-only");
+        opts.data_file_size = 32 * 1024 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..10000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        for i in 0..5000 {
+            let del_res = engine.delete(get_test_key(i));
+            assert!(del_res.is_ok());
+        }
+        let res1 = engine.merge();
+        assert!(res1.is_ok());
+        std::mem::drop(engine);
+
+        let merge_path = get_merge_path(opts.dir_path.clone());
+        let merge_file_num = read_dir(merge_path.clone()).unwrap().count();
+        let data_file_num = read_dir(opts.dir_path.clone()).unwrap().count();
+
+        let mut ro_opts = opts.clone();
+        ro_opts.read_only = true;
+        let engine2 = Engine::open(ro_opts).expect("failed to open engine");
+        let keys = engine2.list_keys();
+        assert_eq!(keys.len(), 5000);
+        let get_res = engine2.get(get_test_key(6000));
+        assert_eq!(get_test_value(6000), get_res.unwrap());
+
+        // 只读模式下不能写入和 merge
+        let put_res = engine2.put(get_test_key(1), get_test_value(1));
+        assert_eq!(Errors::ReadOnlyMode, put_res.err().unwrap());
+        let merge_res = engine2.merge();
+        assert_eq!(Errors::ReadOnlyMode, merge_res.err().unwrap());
+        std::mem::drop(engine2);
+
+        // merge 目录和数据目录都没有被修改
+        assert!(merge_path.is_dir());
+        assert_eq!(
+            read_dir(merge_path.clone()).unwrap().count(),
+            merge_file_num
+        );
+        assert_eq!(
+            read_dir(opts.dir_path.clone()).unwrap().count(),
+            data_file_num
+        );
+
+        // 正常打开时加载 merge 目录
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(!merge_path.is_dir());
+        let keys = engine3.list_keys();
+        assert_eq!(keys.len(), 5000);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
     #[test]
     fn test_merge_5() {
         // Merge 的过程中有新的写入和删除
@@ -457,4 +944,786 @@ mod tests {
         // 删除测试的文件夹
         std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
     }
+
+    #[test]
+    fn test_merge_concurrent_reads() {
+        // Merge 的过程中并发读取，读取不会因为数据文件被移动或关闭而失败
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-concurrent-reads");
+        opts.data_file_size = 32 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        opts.max_open_files = 2;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..4000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        for i in 2000..4000 {
+            let del_res = engine.delete(get_test_key(i));
+            assert!(del_res.is_ok());
+        }
+
+        let eng = Arc::new(engine);
+        let merging = Arc::new(std::sync::atomic::AtomicBool::new(true));
+
+        // 读取的 key 在 merge 期间不会被修改，每次都必须读到同样的值
+        let mut handles = vec![];
+        for t in 0..4 {
+            let eng = eng.clone();
+            let merging = merging.clone();
+            handles.push(thread::spawn(move || {
+                let mut reads = 0;
+                while merging.load(Ordering::SeqCst) || reads < 2000 {
+                    let i = (reads * 7 + t) % 2000;
+                    let get_res = eng.get(get_test_key(i));
+                    assert_eq!(get_test_value(i), get_res.unwrap());
+                    reads += 1;
+                }
+            }));
+        }
+
+        // 并发写入，让活跃文件不断切换
+        let eng1 = eng.clone();
+        handles.push(thread::spawn(move || {
+            for i in 5000..8000 {
+                let put_res = eng1.put(get_test_key(i), get_test_value(i));
+                assert!(put_res.is_ok());
+            }
+        }));
+
+        for _ in 0..3 {
+            let merge_res = eng.merge();
+            assert!(merge_res.is_ok());
+        }
+        merging.store(false, Ordering::SeqCst);
+
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        // 重启后替换 merge 文件，数据保持一致
+        std::mem::drop(eng);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(engine2.list_keys().len(), 5000);
+        for i in 0..2000 {
+            let get_res = engine2.get(get_test_key(i));
+            assert_eq!(get_test_value(i), get_res.unwrap());
+        }
+        std::mem::drop(engine2);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_cancel() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-cancel");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..50000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        for i in 0..10000 {
+            let del_res = engine.delete(get_test_key(i));
+            assert!(del_res.is_ok());
+        }
+        let merge_path = get_merge_path(opts.dir_path.clone());
+
+        // 1.开始之前已经取消
+        let cancel = Arc::new(AtomicBool::new(true));
+        let res1 = engine.merge_with_cancel(cancel);
+        assert_eq!(Errors::MergeCancelled, res1.err().unwrap());
+        assert!(!merge_path.exists());
+
+        // 2.merge 的过程中取消，merge 目录中写入了数据之后再设置取消标识
+        let cancel = Arc::new(AtomicBool::new(false));
+        let res2 = thread::scope(|s| {
+            let cancel_flag = cancel.clone();
+            let merge_file = merge_path.join(format!("{:09}{}", 0, DATA_FILE_NAME_SUFFIX));
+            s.spawn(move || {
+                while !merge_file.is_file() || merge_file.metadata().unwrap().len() == 0 {
+                    thread::sleep(std::time::Duration::from_millis(1));
+                }
+                cancel_flag.store(true, Ordering::SeqCst);
+            });
+            engine.merge_with_cancel(cancel)
+        });
+        assert_eq!(Errors::MergeCancelled, res2.err().unwrap());
+        assert!(!merge_path.exists());
+        assert!(!opts.dir_path.join(HINT_FILE_NAME).exists());
+
+        // 重启后数据保持不变
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(40000, engine2.list_keys().len());
+        for i in 0..10000 {
+            let get_res = engine2.get(get_test_key(i));
+            assert_eq!(Errors::KeyIsNotFound, get_res.err().unwrap());
+        }
+        for i in 10000..50000 {
+            let get_res = engine2.get(get_test_key(i));
+            assert_eq!(get_test_value(i), get_res.unwrap());
+        }
+
+        // 取消之后可以正常 merge
+        let merge_res = engine2.merge();
+        assert!(merge_res.is_ok());
+        std::mem::drop(engine2);
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(40000, engine3.list_keys().len());
+        std::mem::drop(engine3);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_while_rotating() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-rotating");
+        opts.data_file_size = 4 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 写入线程频繁切换活跃文件，同时 merge 也会切换活跃文件
+        thread::scope(|s| {
+            for t in 0..4 {
+                let engine = &engine;
+                s.spawn(move || {
+                    for i in t * 500..(t + 1) * 500 {
+                        let put_res = engine.put(get_test_key(i), get_test_value(i));
+                        assert!(put_res.is_ok());
+                    }
+                });
+            }
+            s.spawn(|| {
+                for _ in 0..20 {
+                    let merge_res = engine.merge();
+                    assert!(merge_res.is_ok());
+                }
+            });
+        });
+
+        // 旧文件的 id 连续且不重复，不包含活跃文件，写偏移都是文件的实际大小
+        let active_fid = engine.active_file.read().get_file_id();
+        {
+            let older_files = engine.older_files.read();
+            let file_ids = older_files.file_ids();
+            assert_eq!((0..active_fid).collect::<Vec<_>>(), file_ids);
+            for file_id in file_ids {
+                let data_file = older_files.get(file_id).unwrap().unwrap();
+                assert_eq!(data_file.file_size(), data_file.get_write_off());
+            }
+        }
+
+        // 重启后所有数据都能正常读取
+        std::mem::drop(engine);
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(2000, engine2.list_keys().len());
+        for i in 0..2000 {
+            let get_res = engine2.get(get_test_key(i));
+            assert_eq!(get_test_value(i), get_res.unwrap());
+        }
+        std::mem::drop(engine2);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_keep_recent_files() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-keep-recent");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        opts.merge_keep_recent_files = 2;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..2000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        for i in 0..2000 {
+            let put_res = engine.put(get_test_key(i), Bytes::from("new value"));
+            assert!(put_res.is_ok());
+        }
+
+        // merge 时当前活跃文件会转为旧文件，最近的两个文件不参与 merge
+        let file_ids = engine.data_file_ids();
+        assert!(file_ids.len() > 3);
+        let kept_ids = file_ids[file_ids.len() - 2..].to_vec();
+        let kept_contents: Vec<Vec<u8>> = kept_ids
+            .iter()
+            .map(|fid| {
+                std::fs::read(get_data_file_path(
+                    opts.dir_path.clone(),
+                    *fid,
+                    &opts.file_suffix,
+                ))
+                .unwrap()
+            })
+            .collect();
+
+        let res1 = engine.merge();
+        assert!(res1.is_ok());
+        std::mem::drop(engine);
+
+        // 重启后保留的文件内容不变，并且数据都能正常读取
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        for (fid, content) in kept_ids.iter().zip(kept_contents.iter()) {
+            let file_path = get_data_file_path(opts.dir_path.clone(), *fid, &opts.file_suffix);
+            assert_eq!(&std::fs::read(file_path).unwrap(), content);
+        }
+        assert!(engine2.data_file_ids().len() < file_ids.len());
+
+        let keys = engine2.list_keys();
+        assert_eq!(keys.len(), 2000);
+        for i in 0..2000 {
+            let get_res = engine2.get(get_test_key(i));
+            assert_eq!(Bytes::from("new value"), get_res.unwrap());
+        }
+
+        // 再次 merge，数据文件 id 不连续时也能正常加载
+        let res2 = engine2.merge();
+        assert!(res2.is_ok());
+        std::mem::drop(engine2);
+
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        let keys = engine3.list_keys();
+        assert_eq!(keys.len(), 2000);
+        for i in 0..2000 {
+            let get_res = engine3.get(get_test_key(i));
+            assert_eq!(Bytes::from("new value"), get_res.unwrap());
+        }
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    // 单个数据文件中可以回收的数据量超过阈值时，只 merge 到该文件为止
+    struct FileReclaimPolicy {
+        threshold: usize,
+    }
+
+    impl MergePolicy for FileReclaimPolicy {
+        fn should_merge(
+            &self,
+            _: &Stat,
+            reclaim_by_file: &HashMap<u32, usize>,
+        ) -> Option<MergePlan> {
+            reclaim_by_file
+                .iter()
+                .filter(|(_, size)| **size > self.threshold)
+                .map(|(file_id, _)| *file_id)
+                .max()
+                .map(|file_id| MergePlan {
+                    max_file_id: Some(file_id),
+                    keep_recent_files: 0,
+                })
+        }
+    }
+
+    #[test]
+    fn test_merge_throughput_limit() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-throughput-limit");
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for i in 0..10000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(128));
+            assert!(put_res.is_ok());
+        }
+        let data_size = engine.stat().unwrap().data_files_size;
+        std::mem::drop(engine);
+
+        // 限速远大于数据大小，不会明显拖慢 merge，等待时间由 test_merge_progress_throttle 检查
+        let progress = Arc::new(parking_lot::Mutex::new(Vec::new()));
+        let progress_clone = progress.clone();
+        let mut opts2 = Options::builder()
+            .merge_throughput_limit(Some(data_size * 20))
+            .on_merge_progress(move |processed, total| {
+                progress_clone.lock().push((processed, total));
+            })
+            .build()
+            .expect("invalid options");
+        opts2.dir_path = opts.dir_path.clone();
+        opts2.data_file_merge_ratio = 0 as f32;
+        let engine2 = Engine::open(opts2.clone()).expect("failed to open engine");
+        let res1 = engine2.merge();
+        assert!(res1.is_ok());
+
+        // 进度单调递增，最后一次回调时处理完所有数据
+        let progress = progress.lock();
+        assert!(progress.len() > 1);
+        assert!(progress.windows(2).all(|w| w[0].0 <= w[1].0));
+        assert_eq!(Some(&(data_size, data_size)), progress.last());
+        std::mem::drop(engine2);
+
+        // 重新打开后加载 merge 的结果
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(10000, engine3.list_keys().len());
+        std::mem::drop(engine3);
+
+        // 限速必须大于 0
+        let res2 = Options::builder().merge_throughput_limit(Some(0)).build();
+        assert_eq!(Errors::InvalidMergeThroughputLimit, res2.err().unwrap());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_progress_throttle() {
+        let mut opts = Options::default();
+        opts.merge_throughput_limit = Some(1000);
+        let mut progress = MergeProgress::new(&opts, 2000);
+
+        // 按每秒 1000 字节处理 500 字节需要 500 毫秒，减去已经经过的时间
+        progress.processed = 500;
+        assert_eq!(
+            Some(Duration::from_millis(500)),
+            progress.throttle_delay(Duration::ZERO)
+        );
+        assert_eq!(
+            Some(Duration::from_millis(300)),
+            progress.throttle_delay(Duration::from_millis(200))
+        );
+        assert_eq!(None, progress.throttle_delay(Duration::from_millis(500)));
+        assert_eq!(None, progress.throttle_delay(Duration::from_millis(800)));
+
+        // 没有限速时不需要等待
+        opts.merge_throughput_limit = None;
+        let mut progress2 = MergeProgress::new(&opts, 2000);
+        progress2.processed = 500;
+        assert_eq!(None, progress2.throttle_delay(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_merge_policy() {
+        let opts = Options::builder()
+            .dir_path(PathBuf::from("/tmp/bitcask-rs-merge-policy"))
+            .data_file_size(64 * 1024)
+            .merge_policy(FileReclaimPolicy {
+                threshold: 8 * 1024,
+            })
+            .build()
+            .expect("failed to build options");
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..2000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        assert_eq!(Ok(false), engine.merge_if_needed());
+
+        // 覆盖写入第一个数据文件中的 key，无效数据只在第一个文件中，总的比例远低于默认的阈值
+        for i in 0..200 {
+            let put_res = engine.put(get_test_key(i), Bytes::from("new value"));
+            assert!(put_res.is_ok());
+        }
+        let reclaim_by_file = engine.reclaim_by_file();
+        assert_eq!(reclaim_by_file.keys().collect::<Vec<_>>(), vec![&0]);
+        assert!(reclaim_by_file[&0] > 8 * 1024);
+        assert!(engine.reclaim_ratio() < opts.data_file_merge_ratio);
+
+        // 第一个文件之后的文件不参与 merge，内容保持不变
+        let file_ids = engine.data_file_ids();
+        assert!(file_ids.len() > 3);
+        let kept_path = get_data_file_path(opts.dir_path.clone(), file_ids[1], &opts.file_suffix);
+        let kept_content = std::fs::read(kept_path.clone()).unwrap();
+
+        assert_eq!(Ok(true), engine.merge_if_needed());
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(std::fs::read(kept_path).unwrap(), kept_content);
+        assert_eq!(engine2.list_keys().len(), 2000);
+        for i in 0..2000 {
+            let get_res = engine2.get(get_test_key(i));
+            if i < 200 {
+                assert_eq!(Bytes::from("new value"), get_res.unwrap());
+            } else {
+                assert_eq!(get_test_value(i), get_res.unwrap());
+            }
+        }
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_corrupted_finished_file() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-corrupted-fin");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..2000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        for i in 0..1000 {
+            let del_res = engine.delete(get_test_key(i));
+            assert!(del_res.is_ok());
+        }
+
+        // merge 目录中的标识文件损坏，merge 结果被丢弃
+        let res1 = engine.merge();
+        assert!(res1.is_ok());
+        std::mem::drop(engine);
+        let merge_path = get_merge_path(opts.dir_path.clone());
+        std::fs::write(merge_path.join(MERGE_FINISHED_FILE_NAME), "garbage").unwrap();
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(!merge_path.exists());
+        assert!(!opts.dir_path.join(MERGE_FINISHED_FILE_NAME).exists());
+        assert_eq!(1000, engine2.list_keys().len());
+
+        // 数据目录中的标识文件损坏，从所有数据文件中加载索引
+        let res2 = engine2.merge();
+        assert!(res2.is_ok());
+        std::mem::drop(engine2);
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        std::mem::drop(engine3);
+        let fin_path = opts.dir_path.join(MERGE_FINISHED_FILE_NAME);
+        assert!(fin_path.is_file());
+        let invalid_record = LogRecord {
+            key: MERGE_FIN_KEY.to_vec(),
+            value: "not a file id".as_bytes().to_vec(),
+            rec_type: LogRecordType::NOAMAL,
+        };
+        std::fs::write(fin_path, invalid_record.encode()).unwrap();
+
+        let engine4 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(1000, engine4.list_keys().len());
+        for i in 0..2000 {
+            let get_res = engine4.get(get_test_key(i));
+            if i < 1000 {
+                assert_eq!(Errors::KeyIsNotFound, get_res.err().unwrap());
+            } else {
+                assert_eq!(get_test_value(i), get_res.unwrap());
+            }
+        }
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_resume_interrupted_load() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-resume");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+
+        let merge_path = get_merge_path(opts.dir_path.clone());
+        let data_file_names = |dir_path: &PathBuf| -> Vec<String> {
+            let mut names: Vec<String> = read_dir(dir_path)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .filter(|name| name.ends_with(DATA_FILE_NAME_SUFFIX))
+                .collect();
+            names.sort();
+            names
+        };
+        let check_data = |engine: &Engine, round: usize| {
+            assert_eq!(1000, engine.list_keys().len());
+            for i in 0..1000 {
+                let get_res = engine.get(get_test_key(i));
+                assert_eq!(get_test_value(round * 1000 + i), get_res.unwrap());
+            }
+        };
+
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        for round in 0..2 {
+            for i in 0..1000 {
+                let put_res = engine.put(get_test_key(i), get_test_value(round * 1000 + i));
+                assert!(put_res.is_ok());
+            }
+        }
+
+        // 1.移动了 hint 文件和部分数据文件之后退出
+        let res1 = engine.merge();
+        assert!(res1.is_ok());
+        std::mem::drop(engine);
+
+        let non_merge_id = get_non_merge_file_id(merge_path.clone(), false).unwrap();
+        let merged_names = data_file_names(&merge_path);
+        assert!(merged_names.len() > 1);
+        for fid in 0..non_merge_id {
+            let file_path = get_data_file_path(opts.dir_path.clone(), fid, DATA_FILE_NAME_SUFFIX);
+            remove_file(file_path).unwrap();
+        }
+        rename(
+            merge_path.join(HINT_FILE_NAME),
+            opts.dir_path.join(HINT_FILE_NAME),
+        )
+        .unwrap();
+        rename(
+            merge_path.join(&merged_names[0]),
+            opts.dir_path.join(&merged_names[0]),
+        )
+        .unwrap();
+
+        // 再次打开时继续完成移动
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(!merge_path.exists());
+        let names = data_file_names(&opts.dir_path);
+        for name in merged_names.iter() {
+            assert!(names.contains(name));
+        }
+        check_data(&engine2, 1);
+
+        // 2.移动了标识文件之后，删除 merge 目录之前退出
+        for i in 0..1000 {
+            let put_res = engine2.put(get_test_key(i), get_test_value(2000 + i));
+            assert!(put_res.is_ok());
+        }
+        let res2 = engine2.merge();
+        assert!(res2.is_ok());
+        std::mem::drop(engine2);
+
+        let non_merge_id = get_non_merge_file_id(merge_path.clone(), false).unwrap();
+        for fid in 0..non_merge_id {
+            let file_path = get_data_file_path(opts.dir_path.clone(), fid, DATA_FILE_NAME_SUFFIX);
+            if file_path.is_file() {
+                remove_file(file_path).unwrap();
+            }
+        }
+        let mut moved_names = vec![HINT_FILE_NAME.to_string()];
+        moved_names.extend(data_file_names(&merge_path));
+        moved_names.push(MERGE_FINISHED_FILE_NAME.to_string());
+        for name in moved_names {
+            rename(merge_path.join(&name), opts.dir_path.join(&name)).unwrap();
+        }
+        assert!(merge_path.is_dir());
+
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(!merge_path.exists());
+        check_data(&engine3, 2);
+        std::mem::drop(engine3);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_reclaim_ratio() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-reclaim-ratio");
+        opts.data_file_size = 32 * 1024 * 1024;
+        opts.data_file_merge_ratio = 0.3;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(0.0, engine.reclaim_ratio());
+
+        // 300 条数据，其中 100 条被覆盖，共写入 400 条相同大小的数据，可以回收的比例是 1/4
+        for i in 0..300 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        for i in 0..100 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        let record_size = engine.stat().unwrap().total_bytes_written / 400;
+        assert_eq!(
+            100 * record_size,
+            engine.reclaim_size.load(Ordering::SeqCst) as u64
+        );
+
+        // 检查点写入的 MANIFEST 和文件锁不计入总大小
+        engine.checkpoint().expect("failed to checkpoint");
+        assert_eq!(0.25, engine.reclaim_ratio());
+
+        // 未达到阈值时不会 merge
+        let res1 = engine.merge();
+        assert_eq!(Errors::MergeRatioUnreached, res1.err().unwrap());
+        std::mem::drop(engine);
+
+        // 阈值和实际比例相同时可以 merge
+        opts.data_file_merge_ratio = 0.25;
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(0.25, engine2.reclaim_ratio());
+        let res2 = engine2.merge();
+        assert!(res2.is_ok());
+        std::mem::drop(engine2);
+
+        // 重新打开后加载 merge 的结果，不再有可以回收的数据
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(0.0, engine3.reclaim_ratio());
+        assert_eq!(300, engine3.list_keys().len());
+        std::mem::drop(engine3);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_file_size() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-file-size");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        opts.merge_file_size = Some(16 * 1024 * 1024);
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..5000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        let file_count = engine.data_file_ids().len();
+        assert!(file_count > 3);
+
+        let res1 = engine.merge();
+        assert!(res1.is_ok());
+        std::mem::drop(engine);
+
+        // merge 后的旧数据写入同一个更大的文件中，超过 data_file_size
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        let file_ids = engine2.data_file_ids();
+        assert!(file_ids.len() < file_count);
+        let merged_size = std::fs::metadata(get_data_file_path(
+            opts.dir_path.clone(),
+            file_ids[0],
+            &opts.file_suffix,
+        ))
+        .unwrap()
+        .len();
+        assert!(merged_size > opts.data_file_size);
+
+        // 之后写入的数据文件仍然使用 data_file_size
+        for i in 0..5000 {
+            let put_res = engine2.put(get_test_key(i), Bytes::from("new value"));
+            assert!(put_res.is_ok());
+        }
+        for fid in engine2.data_file_ids().iter().skip(file_ids.len()) {
+            let file_path = get_data_file_path(opts.dir_path.clone(), *fid, &opts.file_suffix);
+            assert!(std::fs::metadata(file_path).unwrap().len() <= opts.data_file_size);
+        }
+        assert_eq!(5000, engine2.list_keys().len());
+        std::mem::drop(engine2);
+
+        // merge_file_size 不能为 0，也不能小于 data_file_size
+        opts.merge_file_size = Some(0);
+        let res2 = Engine::open(opts.clone());
+        assert_eq!(Errors::DataFileSizeInvalid, res2.err().unwrap());
+        opts.merge_file_size = Some(4 * 1024);
+        let res3 = Engine::open(opts.clone());
+        assert_eq!(Errors::DataFileSizeInvalid, res3.err().unwrap());
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_write_after_merge() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-write-after-merge");
+        opts.data_file_size = 64 * 1024;
+        opts.data_file_merge_ratio = 0 as f32;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        for i in 0..2000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        let res1 = engine.merge();
+        assert!(res1.is_ok());
+
+        // merge 之后、重新打开之前写入的数据不会被 merge 后的文件覆盖
+        for i in 2000..2600 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(2600, engine2.list_keys().len());
+        for i in 0..2600 {
+            assert_eq!(get_test_value(i), engine2.get(get_test_key(i)).unwrap());
+        }
+        let res2 = engine2.merge();
+        assert!(res2.is_ok());
+        for i in 2600..3200 {
+            let put_res = engine2.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        let live_fid = *engine2.data_file_ids().last().unwrap();
+        std::mem::drop(engine2);
+
+        // 模拟 merge 后的数据文件占用了 merge 之后写入的文件 id，放弃 merge 的结果
+        let merge_path = get_merge_path(opts.dir_path.clone());
+        std::fs::copy(
+            get_data_file_path(merge_path.clone(), 0, &opts.file_suffix),
+            get_data_file_path(merge_path.clone(), live_fid, &opts.file_suffix),
+        )
+        .unwrap();
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert!(!merge_path.exists());
+        assert_eq!(3200, engine3.list_keys().len());
+        for i in 0..3200 {
+            assert_eq!(get_test_value(i), engine3.get(get_test_key(i)).unwrap());
+        }
+        std::mem::drop(engine3);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
+
+    #[test]
+    fn test_merge_if_needed() {
+        let mut opts = Options::default();
+        opts.dir_path = PathBuf::from("/tmp/bitcask-rs-merge-if-needed");
+        opts.data_file_size = 32 * 1024 * 1024;
+        opts.data_file_merge_ratio = 0.3;
+        let engine = Engine::open(opts.clone()).expect("failed to open engine");
+
+        // 空的数据库不需要 merge
+        assert_eq!(Ok(false), engine.merge_if_needed());
+
+        // 未达到阈值时不 merge
+        for i in 0..1000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        for i in 0..100 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        assert_eq!(Ok(false), engine.merge_if_needed());
+        let merge_path = get_merge_path(opts.dir_path.clone());
+        assert!(!merge_path.exists());
+
+        // 覆盖写入足够多的数据之后执行 merge
+        for i in 0..1000 {
+            let put_res = engine.put(get_test_key(i), get_test_value(i));
+            assert!(put_res.is_ok());
+        }
+        assert_eq!(Ok(true), engine.merge_if_needed());
+        assert!(merge_path.is_dir());
+        std::mem::drop(engine);
+
+        let engine2 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(1000, engine2.list_keys().len());
+        assert_eq!(0.0, engine2.reclaim_ratio());
+        std::mem::drop(engine2);
+
+        // 只读模式下返回错误
+        opts.read_only = true;
+        let engine3 = Engine::open(opts.clone()).expect("failed to open engine");
+        assert_eq!(
+            Errors::ReadOnlyMode,
+            engine3.merge_if_needed().err().unwrap()
+        );
+        std::mem::drop(engine3);
+
+        // 删除测试的文件夹
+        std::fs::remove_dir_all(opts.clone().dir_path).expect("failed to remove path");
+    }
 }
@@ -1,4 +1,15 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use crate::{
+    data::data_file::DATA_FILE_NAME_SUFFIX,
+    db::{check_options, FILE_LOCK_NAME},
+    errors::Errors,
+    merge::MergePolicy,
+};
 
 #[derive(Clone)]
 pub struct Options {
@@ -9,6 +20,339 @@ pub struct Options {
     pub index_type: IndexType, // 索引类型
     pub mmap_at_startup: bool, // 是否使用 mmap 读取数据文件
     pub data_file_merge_ratio: f32, // 达到阈值时 merge 
+    pub skip_missing_data_file: bool, // 读取时数据文件缺失则跳过该 key
+    pub max_unsynced_bytes: Option<usize>, // 未持久化的数据达到阈值时强制持久化
+    pub read_only: bool, // 只读模式，不修改数据目录中的任何数据，不支持 B+ 树索引
+    pub hash_keys_in_index: bool, // 索引中只保存 key 的 16 字节 hash，减少长 key 占用的内存
+    pub merge_keep_recent_files: usize, // merge 时跳过最近的若干个旧数据文件
+    pub on_file_rotate: Option<Arc<dyn Fn(u32, u32) + Send + Sync>>, // 活跃文件切换时的回调，参数为封存的文件 id 和新的文件 id
+    pub collect_read_stats: bool, // 统计读取活跃文件和旧数据文件的次数
+    pub file_suffix: String, // 数据文件的后缀
+    pub lock_file_name: String, // 数据目录中文件锁的文件名
+    pub max_open_files: usize, // 同时打开的旧数据文件数量上限，超过时关闭最近最少使用的文件，0 表示不限制
+    pub rebuild_index_on_corruption: bool, // B+ 树索引文件损坏时删除索引文件，从数据文件中重建索引
+    pub error_on_data_file_size_shrink: bool, // 数据文件大小比之前使用的小时返回错误，否则只给出警告
+    pub sync_on_drop: bool, // Drop 时是否关闭存储引擎并持久化数据，关闭时只释放文件锁
+    pub archive_reader: Option<ArchiveReader>, // 读取压缩归档的数据文件，只能用于只读模式
+    pub reuse_read_buffers: bool, // 读取数据时复用每个线程缓存的缓冲区，减少内存分配
+    pub merge_file_size: Option<u64>, // merge 后数据文件的大小，None 表示和 data_file_size 相同
+    pub max_record_size: Option<u64>, // 单条日志记录编码后的最大字节数，None 表示不限制
+    pub group_commit_window: Option<Duration>, // 事务提交时等待该时间后合并多个事务的 sync，None 表示每个事务单独 sync
+    pub on_corruption: CorruptionPolicy, // 启动加载索引时遇到损坏数据的处理方式
+    pub shard_fn: Option<ShardFn>, // 计算 key 所属的写入分段，None 表示按 key 的 hash 分段
+    pub separate_values_over: Option<usize>, // value 超过该长度时写入单独的 blob 文件，None 表示不分离
+    pub create_if_missing: bool, // 数据目录中没有数据时新建存储引擎，关闭时返回 DatabaseNotFound
+    pub error_if_exists: bool, // 数据目录中已经有数据时返回 DatabaseAlreadyExists
+    pub initial_file_id: u32, // 新建存储引擎时第一个活跃文件的 id
+    pub io_retry: Option<RetryPolicy>, // 写入和持久化数据文件失败时的重试策略，None 表示不重试
+    pub merge_policy: Option<Arc<dyn MergePolicy>>, // merge 的触发策略，None 表示按 data_file_merge_ratio 判断
+    pub key_normalizer: Option<KeyNormalizer>, // 写入和读取前对 key 进行转换，数据文件和索引中保存转换后的 key
+    pub merge_throughput_limit: Option<u64>, // merge 每秒最多处理的旧数据文件字节数，None 表示不限制
+    pub on_merge_progress: Option<Arc<dyn Fn(u64, u64) + Send + Sync>>, // merge 进度回调，参数为已经处理和总共需要处理的字节数
+    pub write_buffer_size: usize, // 活跃文件的写缓冲区大小，缓冲的数据达到该大小时一次写入文件，0 表示不缓冲
+}
+
+/// 读取压缩归档的数据文件，参数为数据文件的路径，返回解压后的完整文件内容
+pub type ArchiveReader = Arc<dyn Fn(&Path) -> std::io::Result<Vec<u8>> + Send + Sync>;
+
+/// 计算 key 所属的写入分段，返回值对分段数量取模，同一个函数对同一个 key 必须始终返回相同的值
+pub type ShardFn = Arc<dyn Fn(&[u8]) -> usize + Send + Sync>;
+
+/// 将 key 转换为保存时使用的形式，例如转换为小写实现不区分大小写的查找，同一个 key 必须始终转换为相同的结果
+pub type KeyNormalizer = Arc<dyn Fn(&[u8]) -> Vec<u8> + Send + Sync>;
+
+impl Options {
+    /// 创建配置项的构建器，未设置的配置项使用默认值
+    pub fn builder() -> OptionsBuilder {
+        OptionsBuilder::default()
+    }
+
+    // 配置了任意一种持久化策略时，新建数据文件后也要持久化所在的目录
+    pub(crate) fn sync_dir_enabled(&self) -> bool {
+        self.sync_writes || self.bytes_per_sync > 0 || self.max_unsynced_bytes.is_some()
+    }
+}
+
+/// 存储引擎配置项的构建器，在 build 时校验配置项
+///
+/// ```
+/// use bitcask::options::{IndexType, Options};
+///
+/// let opts = Options::builder()
+///     .dir_path(std::env::temp_dir().join("bitcask-rs-builder"))
+///     .data_file_size(64 * 1024 * 1024)
+///     .index_type(IndexType::BTree)
+///     .build()
+///     .expect("invalid options");
+/// assert_eq!(opts.data_file_size, 64 * 1024 * 1024);
+///
+/// // 不合法的配置项在 build 时返回错误
+/// assert!(Options::builder().data_file_size(0).build().is_err());
+/// ```
+#[derive(Clone, Default)]
+pub struct OptionsBuilder {
+    options: Options,
+}
+
+impl OptionsBuilder {
+    pub fn dir_path(mut self, dir_path: impl Into<PathBuf>) -> Self {
+        self.options.dir_path = dir_path.into();
+        self
+    }
+
+    pub fn data_file_size(mut self, data_file_size: u64) -> Self {
+        self.options.data_file_size = data_file_size;
+        self
+    }
+
+    /// 写入数据后是否持久化
+    /// 配置了任意一种持久化策略（sync_writes、bytes_per_sync、max_unsynced_bytes）时，
+    /// 新建数据文件和 merge 移动文件之后还会持久化所在的目录，保证崩溃后新建的文件不会丢失
+    pub fn sync_writes(mut self, sync_writes: bool) -> Self {
+        self.options.sync_writes = sync_writes;
+        self
+    }
+
+    pub fn bytes_per_sync(mut self, bytes_per_sync: usize) -> Self {
+        self.options.bytes_per_sync = bytes_per_sync;
+        self
+    }
+
+    pub fn index_type(mut self, index_type: IndexType) -> Self {
+        self.options.index_type = index_type;
+        self
+    }
+
+    pub fn mmap_at_startup(mut self, mmap_at_startup: bool) -> Self {
+        self.options.mmap_at_startup = mmap_at_startup;
+        self
+    }
+
+    pub fn data_file_merge_ratio(mut self, data_file_merge_ratio: f32) -> Self {
+        self.options.data_file_merge_ratio = data_file_merge_ratio;
+        self
+    }
+
+    pub fn skip_missing_data_file(mut self, skip_missing_data_file: bool) -> Self {
+        self.options.skip_missing_data_file = skip_missing_data_file;
+        self
+    }
+
+    pub fn max_unsynced_bytes(mut self, max_unsynced_bytes: Option<usize>) -> Self {
+        self.options.max_unsynced_bytes = max_unsynced_bytes;
+        self
+    }
+
+    /// 只读模式，不支持 B+ 树索引
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.options.read_only = read_only;
+        self
+    }
+
+    pub fn hash_keys_in_index(mut self, hash_keys_in_index: bool) -> Self {
+        self.options.hash_keys_in_index = hash_keys_in_index;
+        self
+    }
+
+    pub fn merge_keep_recent_files(mut self, merge_keep_recent_files: usize) -> Self {
+        self.options.merge_keep_recent_files = merge_keep_recent_files;
+        self
+    }
+
+    pub fn on_file_rotate<F>(mut self, on_file_rotate: F) -> Self
+    where
+        F: Fn(u32, u32) + Send + Sync + 'static,
+    {
+        self.options.on_file_rotate = Some(Arc::new(on_file_rotate));
+        self
+    }
+
+    pub fn collect_read_stats(mut self, collect_read_stats: bool) -> Self {
+        self.options.collect_read_stats = collect_read_stats;
+        self
+    }
+
+    pub fn file_suffix(mut self, file_suffix: impl Into<String>) -> Self {
+        self.options.file_suffix = file_suffix.into();
+        self
+    }
+
+    pub fn lock_file_name(mut self, lock_file_name: impl Into<String>) -> Self {
+        self.options.lock_file_name = lock_file_name.into();
+        self
+    }
+
+    pub fn max_open_files(mut self, max_open_files: usize) -> Self {
+        self.options.max_open_files = max_open_files;
+        self
+    }
+
+    pub fn rebuild_index_on_corruption(mut self, rebuild_index_on_corruption: bool) -> Self {
+        self.options.rebuild_index_on_corruption = rebuild_index_on_corruption;
+        self
+    }
+
+    pub fn error_on_data_file_size_shrink(mut self, error_on_data_file_size_shrink: bool) -> Self {
+        self.options.error_on_data_file_size_shrink = error_on_data_file_size_shrink;
+        self
+    }
+
+    pub fn sync_on_drop(mut self, sync_on_drop: bool) -> Self {
+        self.options.sync_on_drop = sync_on_drop;
+        self
+    }
+
+    /// 从压缩归档的快照中读取数据，例如 gzip 压缩的数据文件，不需要先解压到磁盘上
+    /// 每个数据文件第一次被访问时调用 archive_reader 将整个文件解压到内存中；
+    /// 只能和 read_only 一起使用，写入、删除、merge 等修改数据的操作都会返回 ReadOnlyMode，
+    /// 数据文件的后缀通过 file_suffix 设置，例如 ".data.gz"
+    pub fn archive_reader<F>(mut self, archive_reader: F) -> Self
+    where
+        F: Fn(&Path) -> std::io::Result<Vec<u8>> + Send + Sync + 'static,
+    {
+        self.options.archive_reader = Some(Arc::new(archive_reader));
+        self
+    }
+
+    /// merge 后数据文件的大小，默认和 data_file_size 相同
+    /// merge 后的数据不会再修改，可以使用更大的文件减少数据文件的数量和打开的文件描述符；
+    /// 不能小于 data_file_size，否则 merge 后的文件 id 可能和之后写入的数据文件冲突
+    pub fn merge_file_size(mut self, merge_file_size: Option<u64>) -> Self {
+        self.options.merge_file_size = merge_file_size;
+        self
+    }
+
+    /// 单条日志记录编码后的最大字节数，包括 header、key 和 value，默认不限制
+    /// 超过时写入返回 RecordTooLarge，不会写入数据也不会切换活跃文件
+    pub fn max_record_size(mut self, max_record_size: Option<u64>) -> Self {
+        self.options.max_record_size = max_record_size;
+        self
+    }
+
+    /// 开启事务的组提交，默认关闭
+    /// 开启后 WriteBatch 提交时写完数据即释放提交锁，等待 window 时间后再持久化，
+    /// 同一时间窗口内并发提交的多个事务共享一次 sync；每个事务的序列号和事务完成标识不变，
+    /// 提交仍然在持久化之后才更新索引并返回
+    pub fn group_commit_window(mut self, group_commit_window: Option<Duration>) -> Self {
+        self.options.group_commit_window = group_commit_window;
+        self
+    }
+
+    /// 启动加载索引时遇到损坏数据（CRC 校验失败、长度损坏）的处理方式，默认返回错误
+    pub fn on_corruption(mut self, on_corruption: CorruptionPolicy) -> Self {
+        self.options.on_corruption = on_corruption;
+        self
+    }
+
+    /// 自定义 key 到写入分段的映射，默认使用 key 的 FNV hash，结果与平台和版本无关
+    /// 同一分段的 key 串行写入，不同分段之间可以并发；例如按前缀分段可以让相关的 key
+    /// 以及同一个事务中的 key 落在同一个分段上。不能和 hash_keys_in_index 一起使用
+    pub fn shard_fn<F>(mut self, shard_fn: F) -> Self
+    where
+        F: Fn(&[u8]) -> usize + Send + Sync + 'static,
+    {
+        self.options.shard_fn = Some(Arc::new(shard_fn));
+        self
+    }
+
+    /// value 超过该长度时写入单独的 blob 文件，数据文件中只保存 blob 位置，默认不分离
+    /// merge 只重写数据文件，不会移动 blob 中的大 value，减少写放大；覆盖写入和删除后 blob 中的旧 value
+    /// 通过 Engine::gc_blobs 回收。已经分离的 value 在关闭该配置项后仍然可以正常读取
+    pub fn separate_values_over(mut self, separate_values_over: Option<usize>) -> Self {
+        self.options.separate_values_over = separate_values_over;
+        self
+    }
+
+    /// 数据目录不存在或者其中没有数据文件时是否新建存储引擎，默认开启
+    /// 关闭后只能打开已经存在的存储引擎，否则返回 DatabaseNotFound，也不会创建数据目录
+    pub fn create_if_missing(mut self, create_if_missing: bool) -> Self {
+        self.options.create_if_missing = create_if_missing;
+        self
+    }
+
+    /// 数据目录中已经有数据文件时是否返回 DatabaseAlreadyExists，默认关闭，Engine::create 会开启
+    pub fn error_if_exists(mut self, error_if_exists: bool) -> Self {
+        self.options.error_if_exists = error_if_exists;
+        self
+    }
+
+    /// 新建存储引擎时第一个活跃文件的 id，默认为 0
+    /// 只在数据目录中没有数据文件时生效，之后的文件 id 从该值开始递增，清空存储引擎后也从该值重新开始；
+    /// 便于工具将多个数据目录中的文件拼接在一起时避免文件 id 冲突
+    pub fn initial_file_id(mut self, initial_file_id: u32) -> Self {
+        self.options.initial_file_id = initial_file_id;
+        self
+    }
+
+    /// 写入和持久化数据文件失败时的重试策略，默认不重试
+    /// 适合网络文件系统上偶发的暂时性错误，磁盘空间不足等无法通过重试恢复的错误直接返回
+    pub fn io_retry(mut self, io_retry: Option<RetryPolicy>) -> Self {
+        self.options.io_retry = io_retry;
+        self
+    }
+
+    /// 自定义 merge 的触发策略和 merge 的范围，默认使用 RatioMergePolicy，
+    /// 按 data_file_merge_ratio 和 merge_keep_recent_files 判断
+    /// 策略可以根据每个数据文件中可以回收的数据量决定是否 merge，例如单个文件中的无效数据过多时提前 merge
+    pub fn merge_policy<P>(mut self, merge_policy: P) -> Self
+    where
+        P: MergePolicy + 'static,
+    {
+        self.options.merge_policy = Some(Arc::new(merge_policy));
+        self
+    }
+
+    /// put、get、delete 以及事务中的写入在使用 key 之前先经过该函数转换，数据文件和索引中保存的是转换后的 key，
+    /// 因此迭代器返回的也是转换后的 key，按前缀或范围迭代时需要传入转换后的 key。
+    /// 不支持修改已有数据库的转换函数，之前写入的 key 不会重新转换，可能再也无法通过 get 找到
+    pub fn key_normalizer<F>(mut self, key_normalizer: F) -> Self
+    where
+        F: Fn(&[u8]) -> Vec<u8> + Send + Sync + 'static,
+    {
+        self.options.key_normalizer = Some(Arc::new(key_normalizer));
+        self
+    }
+
+    /// merge 每秒最多处理的旧数据文件字节数，默认不限制
+    /// 超过限制时 merge 线程休眠，避免 merge 占满磁盘 IO 影响前台的读写，必须大于 0
+    pub fn merge_throughput_limit(mut self, merge_throughput_limit: Option<u64>) -> Self {
+        self.options.merge_throughput_limit = merge_throughput_limit;
+        self
+    }
+
+    /// merge 进度回调，参数为已经处理和总共需要处理的旧数据文件字节数
+    /// 在 merge 线程中调用，每处理一段数据以及处理完成时调用一次，回调中不能再次 merge
+    pub fn on_merge_progress<F>(mut self, on_merge_progress: F) -> Self
+    where
+        F: Fn(u64, u64) + Send + Sync + 'static,
+    {
+        self.options.on_merge_progress = Some(Arc::new(on_merge_progress));
+        self
+    }
+
+    /// 活跃文件的写缓冲区大小，默认为 0，每次写入都直接写入文件
+    /// 开启后写入的数据先保存在内存中，达到该大小、sync、切换活跃文件或者关闭时一次写入文件，减少写入的系统调用次数；
+    /// 缓冲区中的数据可以正常读取，但进程崩溃时会丢失，需要持久化的数据仍然通过 sync_writes 或 sync 保证
+    pub fn write_buffer_size(mut self, write_buffer_size: usize) -> Self {
+        self.options.write_buffer_size = write_buffer_size;
+        self
+    }
+
+    /// 读取数据文件时复用缓冲区，默认开启
+    /// 每个线程缓存少量读取 header 和 key/value 的缓冲区，读取时不需要每次重新分配；
+    /// 缓冲区只在线程内部使用，并发读取时不需要加锁
+    pub fn reuse_read_buffers(mut self, reuse_read_buffers: bool) -> Self {
+        self.options.reuse_read_buffers = reuse_read_buffers;
+        self
+    }
+
+    /// 校验并返回配置项
+    pub fn build(self) -> Result<Options, Errors> {
+        if let Some(e) = check_options(&self.options) {
+            return Err(e);
+        }
+        Ok(self.options)
+    }
 }
 
 #[derive(Clone, PartialEq)]
@@ -18,6 +362,29 @@ pub enum IndexType {
     BPlusTree,
 }
 
+/// 写入和持久化数据文件失败时的重试策略
+///
+/// sync 失败后重试成功并不能保证之前写入的数据已经持久化，部分文件系统在 sync 失败时会丢弃未写回的数据，
+/// 只在确定错误是暂时性的（例如网络文件系统的连接抖动）时开启
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// 最多执行的次数，包括第一次，不大于 1 时不重试
+    pub attempts: usize,
+    /// 每次重试之前等待的时间
+    pub backoff: Duration,
+}
+
+/// 启动加载索引时遇到损坏数据的处理方式
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CorruptionPolicy {
+    /// 返回错误，存储引擎无法打开；长度损坏的数据和文件末尾没写完整的数据无法区分，仍然按读到文件末尾处理
+    Fail,
+    /// 跳过损坏的数据，继续加载之后的数据，数据文件保持不变，跳过的数据在 merge 时被清理
+    SkipRecord,
+    /// 忽略损坏的数据及其之后的所有数据，并将数据文件截断到损坏的位置，只读模式下不截断
+    Truncate,
+}
+
 impl Default for Options {
     fn default() -> Self {
         Self {
@@ -28,16 +395,74 @@ impl Default for Options {
             index_type: IndexType::SkipList,
             mmap_at_startup: true,
             data_file_merge_ratio: 0.5,
+            skip_missing_data_file: false,
+            max_unsynced_bytes: None,
+            read_only: false,
+            hash_keys_in_index: false,
+            merge_keep_recent_files: 0,
+            on_file_rotate: None,
+            collect_read_stats: false,
+            file_suffix: DATA_FILE_NAME_SUFFIX.to_string(),
+            lock_file_name: FILE_LOCK_NAME.to_string(),
+            max_open_files: 0,
+            rebuild_index_on_corruption: false,
+            error_on_data_file_size_shrink: false,
+            sync_on_drop: true,
+            archive_reader: None,
+            reuse_read_buffers: true,
+            merge_file_size: None,
+            max_record_size: None,
+            group_commit_window: None,
+            on_corruption: CorruptionPolicy::Fail,
+            shard_fn: None,
+            separate_values_over: None,
+            create_if_missing: true,
+            error_if_exists: false,
+            initial_file_id: 0,
+            io_retry: None,
+            merge_policy: None,
+            key_normalizer: None,
+            merge_throughput_limit: None,
+            on_merge_progress: None,
+            write_buffer_size: 0,
         }
     }
 }
 
 /// 索引迭代器配置项
+///
+/// ```
+/// use bitcask::options::IteratorOptions;
+///
+/// let opts = IteratorOptions::new().prefix("user:").reverse(true);
+/// assert_eq!(opts.prefix, b"user:".to_vec());
+/// assert!(opts.reverse);
+/// ```
+#[derive(Clone, Debug, PartialEq)]
 pub struct IteratorOptions {
     pub prefix: Vec<u8>,
     pub reverse: bool,
 }
 
+impl IteratorOptions {
+    /// 使用默认值创建迭代器配置项，再通过链式调用修改需要的配置项
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 只遍历以 prefix 开头的 key
+    pub fn prefix(mut self, prefix: impl Into<Vec<u8>>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+
+    /// 是否反向遍历
+    pub fn reverse(mut self, reverse: bool) -> Self {
+        self.reverse = reverse;
+        self
+    }
+}
+
 impl Default for IteratorOptions {
     fn default() -> Self {
         Self {
@@ -67,3 +492,26 @@ pub enum IOType {
     FileIO,
     MMapIO,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_iterator_options_builder() {
+        assert_eq!(IteratorOptions::new(), IteratorOptions::default());
+
+        let opts = IteratorOptions::new().prefix("aa").reverse(true);
+        let expected = IteratorOptions {
+            prefix: "aa".as_bytes().to_vec(),
+            reverse: true,
+        };
+        assert_eq!(opts, expected);
+
+        // 在已有配置项的基础上修改
+        let forward = opts.clone().reverse(false);
+        assert_eq!(forward.prefix, opts.prefix);
+        assert!(!forward.reverse);
+        assert!(opts.reverse);
+    }
+}
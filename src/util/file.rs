@@ -1,4 +1,7 @@
-use std::{fs, io, path::PathBuf};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
 
 /// 磁盘数据目录的大小
 pub fn dir_disk_size(dir_path: PathBuf) -> u64 {
@@ -8,6 +11,20 @@ pub fn dir_disk_size(dir_path: PathBuf) -> u64 {
     0
 }
 
+/// 数据目录中以指定后缀结尾的文件的总大小，不包含子目录，用于只统计数据文件的大小
+pub fn dir_disk_size_with_suffix(dir_path: PathBuf, suffix: &str) -> u64 {
+    let dir = match fs::read_dir(dir_path) {
+        Ok(dir) => dir,
+        Err(_) => return 0,
+    };
+    dir.flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().ends_with(suffix))
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|meta| meta.is_file())
+        .map(|meta| meta.len())
+        .sum()
+}
+
 /// 获取空闲磁盘空间大小
 pub fn available_disk_size() -> u64 {
     if let Ok(size) = fs2::available_space(PathBuf::from("/")) {
@@ -16,6 +33,18 @@ pub fn available_disk_size() -> u64 {
     0 
 }
 
+/// 持久化目录本身，只持久化文件内容时，崩溃后目录中新建或重命名的文件可能丢失
+#[cfg(unix)]
+pub fn sync_dir(dir_path: &Path) -> io::Result<()> {
+    fs::File::open(dir_path)?.sync_all()
+}
+
+/// 其他平台无法打开目录进行持久化，不做处理
+#[cfg(not(unix))]
+pub fn sync_dir(_dir_path: &Path) -> io::Result<()> {
+    Ok(())
+}
+
 /// 拷贝数据目录
 pub fn copy_dir(src: PathBuf, dest: PathBuf, exclude: &[&str]) -> io::Result<()> {
     if !dest.exists() {
@@ -46,3 +75,30 @@ fn test_available_disk_size() {
     let size = available_disk_size();
     assert!(size > 0);
 }
+
+#[test]
+fn test_dir_disk_size_with_suffix() {
+    let dir_path = PathBuf::from("/tmp/bitcask-rs-dir-size-suffix");
+    fs::create_dir_all(dir_path.join("sub")).unwrap();
+    fs::write(dir_path.join("000000000.data"), [0u8; 100]).unwrap();
+    fs::write(dir_path.join("000000001.data"), [0u8; 50]).unwrap();
+    fs::write(dir_path.join("hint-index"), [0u8; 10]).unwrap();
+    fs::write(dir_path.join("sub").join("000000002.data"), [0u8; 10]).unwrap();
+    assert_eq!(150, dir_disk_size_with_suffix(dir_path.clone(), ".data"));
+
+    fs::remove_dir_all(&dir_path).unwrap();
+    assert_eq!(0, dir_disk_size_with_suffix(dir_path, ".data"));
+}
+
+#[test]
+fn test_sync_dir() {
+    let dir_path = PathBuf::from("/tmp/bitcask-rs-sync-dir");
+    fs::create_dir_all(&dir_path).unwrap();
+    fs::write(dir_path.join("000000000.data"), b"data").unwrap();
+    assert!(sync_dir(&dir_path).is_ok());
+
+    // 目录不存在时返回错误
+    fs::remove_dir_all(&dir_path).unwrap();
+    #[cfg(unix)]
+    assert!(sync_dir(&dir_path).is_err());
+}
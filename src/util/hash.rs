@@ -0,0 +1,22 @@
+const FNV_OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+const FNV_PRIME: u128 = 0x0000000001000000000000000000013b;
+
+/// 计算 key 的 16 字节 hash（FNV-1a 128 位），结果与平台和版本无关
+pub fn key_hash(key: &[u8]) -> [u8; 16] {
+    let mut hash = FNV_OFFSET_BASIS;
+    for b in key {
+        hash ^= *b as u128;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash.to_be_bytes()
+}
+
+#[test]
+fn test_key_hash() {
+    let hash1 = key_hash(b"bitcask-rs-key");
+    let hash2 = key_hash(b"bitcask-rs-key");
+    let hash3 = key_hash(b"bitcask-rs-key-2");
+    assert_eq!(hash1, hash2);
+    assert_ne!(hash1, hash3);
+    assert_eq!(key_hash(b""), FNV_OFFSET_BASIS.to_be_bytes());
+}
@@ -1,2 +1,3 @@
 pub mod rand_kv;
-pub mod file;
\ No newline at end of file
+pub mod file;
+pub mod hash;
\ No newline at end of file